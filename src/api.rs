@@ -0,0 +1,626 @@
+//! resource oriented JSON API, nested under `/api/v1`
+
+use crate::auth::{self, Role, Token};
+use crate::db::{
+    self, Article, ArticleCursor, ArticleFilter, ArticleListing, ArticleSort, Revision, Snapshot,
+};
+use crate::declared::{self, Declared};
+use crate::diff::Paragraph;
+use crate::events::Event;
+use crate::http::{self, State};
+use crate::import::{self, Format};
+use crate::media::{self, Media, MediaChange};
+use crate::metadata::PageMetadata;
+use crate::watchlist::Watchlist;
+use crate::{mime, scraper, warc, wayback};
+
+use tide::{prelude::*, Request, Response, Result, StatusCode};
+
+#[derive(Deserialize)]
+struct NewArticle {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    limit: Option<i32>,
+    cursor: Option<String>,
+    #[serde(default)]
+    sort: ArticleSort,
+}
+
+#[derive(Serialize)]
+struct Articles {
+    articles: Vec<ArticleListing>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    format: Option<Format>,
+}
+
+/// `?articles=1,2` selects articles, all are exported without
+#[derive(Deserialize)]
+struct WarcQuery {
+    articles: Option<String>,
+    #[serde(default)]
+    gzip: bool,
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct NewWatchlist {
+    name: String,
+    /// only admins may create watchlists for others
+    user_id: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct UserQuery {
+    user_id: Option<i32>,
+}
+
+/// an article by id, or by url which gets tracked if it isn't yet
+#[derive(Deserialize)]
+struct WatchlistArticle {
+    article_id: Option<i32>,
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WatchlistSource {
+    host: String,
+}
+
+#[derive(Serialize)]
+struct WatchlistWithEntries {
+    #[serde(flatten)]
+    watchlist: Watchlist,
+    articles: Vec<Article>,
+    sources: Vec<String>,
+}
+
+/// the revision with its stats, the paragraphs of its stored diff and what happened to
+/// the media of the article
+#[derive(Serialize)]
+struct RevisionWithDiff {
+    #[serde(flatten)]
+    revision: Revision,
+    paragraphs: Vec<Paragraph>,
+    media: Vec<MediaChange>,
+}
+
+/// the metadata of both snapshots of a revision and the paragraphs of their diff
+#[derive(Serialize)]
+struct RevisionMetadata {
+    previous: PageMetadata,
+    current: PageMetadata,
+    paragraphs: Vec<Paragraph>,
+}
+
+/// with what the publisher declares about modifications, see `declared`, and what the
+/// page says about its article, see `metadata`
+#[derive(Serialize)]
+struct SnapshotWithFulltext {
+    #[serde(flatten)]
+    snapshot: Snapshot,
+    fulltext: String,
+    declared: Declared,
+    metadata: PageMetadata,
+    media: Vec<Media>,
+}
+
+pub fn server(state: State) -> tide::Server<State> {
+    let mut api = tide::with_state(state);
+    api.at("/articles").get(get_articles).post(insert_article);
+    api.at("/articles/import").post(import_articles);
+    api.at("/articles/:id")
+        .get(get_article)
+        .delete(delete_article);
+    api.at("/articles/:id/snapshots").get(get_snapshots);
+    api.at("/articles/:id/wayback").post(import_wayback);
+    api.at("/snapshots/:id").get(get_snapshot);
+    api.at("/revisions/:id").get(get_revision);
+    api.at("/revisions/:id/diff").get(get_revision_diff);
+    api.at("/revisions/:id/metadata").get(get_revision_metadata);
+    api.at("/warc").get(export_warc).post(import_warc);
+    api.at("/blobs/:sha256").get(get_blob);
+    api.at("/users")
+        .with(auth::Guard::admin())
+        .get(get_users)
+        .post(insert_user);
+    api.at("/watchlists")
+        .get(get_watchlists)
+        .post(insert_watchlist);
+    api.at("/watchlists/:id")
+        .get(get_watchlist)
+        .delete(delete_watchlist);
+    api.at("/watchlists/:id/articles")
+        .post(add_watchlist_article);
+    api.at("/watchlists/:id/articles/:article_id")
+        .delete(remove_watchlist_article);
+    api.at("/watchlists/:id/sources").post(add_watchlist_source);
+    api.at("/watchlists/:id/sources/:host")
+        .delete(remove_watchlist_source);
+    api.with(tide::utils::After(http::problem_details));
+    api.with(auth::Guard::editor());
+    api
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Result<Response> {
+    Ok(Response::builder(status)
+        .body(serde_json::to_string(body)?)
+        .content_type(mime::json())
+        .build())
+}
+
+fn error(status: StatusCode, message: impl std::fmt::Display) -> tide::Error {
+    tide::Error::from_str(status, message.to_string())
+}
+
+fn param_id(req: &Request<State>) -> Result<i32> {
+    req.param("id")
+        .map_err(|_| error(StatusCode::BadRequest, "id must be a number"))
+}
+
+fn body_error(err: tide::Error) -> tide::Error {
+    error(StatusCode::BadRequest, err)
+}
+
+/// admins may change every watchlist, everybody else only the ones of their token's user
+fn owned_watchlist(req: &Request<State>, watchlist: &Watchlist) -> Result<()> {
+    match req.ext::<Token>() {
+        Some(token) if token.role == Role::Admin => Ok(()),
+        Some(token) if token.user_id == Some(watchlist.user_id) => Ok(()),
+        _ => Err(error(
+            StatusCode::Forbidden,
+            format!(
+                "watchlist {} belongs to someone else",
+                watchlist.watchlist_id
+            ),
+        )),
+    }
+}
+
+/// `?limit=&sort=created|checked|changed|revisions` plus the fields of `ArticleFilter`
+async fn get_articles(req: Request<State>) -> Result<Response> {
+    let query: ListQuery = req
+        .query()
+        .map_err(|err| error(StatusCode::BadRequest, err))?;
+    let filter: ArticleFilter = req
+        .query()
+        .map_err(|err| error(StatusCode::BadRequest, err))?;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(str::parse::<ArticleCursor>)
+        .transpose()?;
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+
+    let mut provider = req.state().acquire().await?;
+    let articles = provider
+        .list_articles(&filter, query.sort, cursor, limit)
+        .await?;
+    let next_cursor = if articles.len() as i32 == limit {
+        articles
+            .last()
+            .map(|a| ArticleCursor::after(query.sort, a).to_string())
+    } else {
+        None
+    };
+    json(
+        StatusCode::Ok,
+        &Articles {
+            articles,
+            next_cursor,
+        },
+    )
+}
+
+async fn insert_article(mut req: Request<State>) -> Result<Response> {
+    let article: NewArticle = req
+        .body_json()
+        .await
+        .map_err(|err| error(StatusCode::BadRequest, err))?;
+    db::http_url(&article.url).map_err(|err| error(StatusCode::BadRequest, err))?;
+
+    let mut provider = req.state().acquire().await?;
+    if provider.get_article(&article.url).await.is_ok() {
+        return Err(error(
+            StatusCode::Conflict,
+            format!("{} is already tracked", article.url),
+        ));
+    }
+    let article = provider.insert_article(&article.url).await?;
+    req.state().events.publish(Event::ArticleInserted {
+        article: article.clone(),
+    });
+
+    let mut res = json(StatusCode::Created, &article)?;
+    res.insert_header(
+        "Location",
+        format!("/api/v1/articles/{}", article.article_id),
+    );
+    Ok(res)
+}
+
+/// the body is read by `?format=lines|csv|json` or its content type,
+/// answers with a result for every line
+async fn import_articles(mut req: Request<State>) -> Result<Response> {
+    let query: ImportQuery = req.query()?;
+    let format = query.format.unwrap_or_else(|| {
+        req.content_type()
+            .map(|mime| Format::from_content_type(mime.essence()))
+            .unwrap_or(Format::Lines)
+    });
+    let body = req.body_string().await.map_err(body_error)?;
+    let entries = import::parse(&body, format).map_err(|err| error(StatusCode::BadRequest, err))?;
+
+    let mut tx = req.state().begin().await?;
+    let report = import::import(&mut *tx, entries).await?;
+    tx.commit().await?;
+    let mut provider = req.state().acquire().await?;
+    for line in &report.lines {
+        if let (import::Status::Added, Some(article_id)) = (line.status, line.article_id) {
+            let article = provider.get_article_by_id(article_id).await?;
+            req.state()
+                .events
+                .publish(Event::ArticleInserted { article });
+        }
+    }
+    json(StatusCode::Ok, &report)
+}
+
+async fn export_warc(req: Request<State>) -> Result<Response> {
+    let query: WarcQuery = req
+        .query()
+        .map_err(|err| error(StatusCode::BadRequest, err))?;
+    let article_ids = query
+        .articles
+        .map(|ids| {
+            ids.split(',')
+                .map(|id| id.trim().parse::<i32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(|_| error(StatusCode::BadRequest, "articles must be numbers"))?;
+
+    let mut provider = req.state().acquire().await?;
+    let records =
+        warc::export(&mut *provider, article_ids.as_deref(), scraper::timestamp()).await?;
+    let name = if query.gzip {
+        "propaganda.warc.gz"
+    } else {
+        "propaganda.warc"
+    };
+    Ok(Response::builder(StatusCode::Ok)
+        .body(warc::write(&records, query.gzip))
+        .content_type(mime::warc())
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", name),
+        )
+        .build())
+}
+
+/// plain or gzipped, answers with what became of the response records
+async fn import_warc(mut req: Request<State>) -> Result<Response> {
+    let body = req.body_bytes().await.map_err(body_error)?;
+    let records = warc::parse(&body).map_err(|err| error(StatusCode::BadRequest, err))?;
+
+    let mut tx = req.state().begin().await?;
+    let report = warc::import(&mut *tx, &records).await?;
+    tx.commit().await?;
+    for article in &report.articles {
+        req.state().events.publish(Event::ArticleInserted {
+            article: article.clone(),
+        });
+    }
+    json(StatusCode::Ok, &report)
+}
+
+async fn get_article(req: Request<State>) -> Result<Response> {
+    let article_id = param_id(&req)?;
+    if let Some(article) = req.state().cache.article(article_id) {
+        return json(StatusCode::Ok, &*article);
+    }
+    let mut provider = req.state().acquire().await?;
+    let article = provider.get_article_by_id(article_id).await?;
+    req.state().cache.put_article(article.clone());
+    json(StatusCode::Ok, &article)
+}
+
+async fn delete_article(req: Request<State>) -> Result<Response> {
+    let article_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    provider.get_article_by_id(article_id).await?;
+    provider.delete_article(article_id).await?;
+    req.state().cache.forget_article(article_id);
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn get_snapshots(req: Request<State>) -> Result<Response> {
+    let article_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    provider.get_article_by_id(article_id).await?;
+    let snapshots = provider
+        .get_snaphot_metadatas_from_article(article_id)
+        .await?;
+    json(StatusCode::Ok, &snapshots)
+}
+
+/// a CDX index in the body limits the import to its captures,
+/// without one the index of the configured wayback server is used
+async fn import_wayback(mut req: Request<State>) -> Result<Response> {
+    let article_id = param_id(&req)?;
+    let index = req.body_string().await.map_err(body_error)?;
+    let wayback = wayback::Wayback::new(&req.state().wayback_url);
+
+    let mut provider = req.state().acquire().await?;
+    let article = provider.get_article_by_id(article_id).await?;
+    let captures = if index.trim().is_empty() {
+        wayback
+            .get_index(&article.url)
+            .await
+            .map_err(|err| error(StatusCode::BadGateway, err))?
+    } else {
+        wayback::parse_index(&index).map_err(|err| error(StatusCode::BadRequest, err))?
+    };
+    drop(provider);
+    let report = wayback::import(req.state(), &wayback, &article, captures).await?;
+    json(StatusCode::Ok, &report)
+}
+
+async fn get_snapshot(req: Request<State>) -> Result<Response> {
+    let snapshot_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    let snapshot = provider.get_snaphot(snapshot_id).await?;
+    let fulltext = req.state().cache.fulltext(&snapshot);
+    let url = provider.get_article_by_id(snapshot.article_id).await?.url;
+    let declared = declared::extract(&url, &snapshot.html);
+    let metadata = PageMetadata::of_snapshot(snapshot.page_metadata.as_deref(), &snapshot.html);
+    let media = media::of_snapshot(snapshot.page_media.as_deref(), &snapshot.html);
+    json(
+        StatusCode::Ok,
+        &SnapshotWithFulltext {
+            snapshot,
+            fulltext,
+            declared,
+            metadata,
+            media,
+        },
+    )
+}
+
+async fn get_revision(req: Request<State>) -> Result<Response> {
+    let revision_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    json(StatusCode::Ok, &provider.get_revision(revision_id).await?)
+}
+
+/// as stored at insert time, without diffing the snapshots again
+async fn get_revision_diff(req: Request<State>) -> Result<Response> {
+    let revision_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    let revision = provider.get_revision(revision_id).await?;
+    let paragraphs = scraper::revision_diff(&mut *provider, &revision)
+        .await?
+        .paragraphs;
+    // a revision from before diffs were stored got its stats just now
+    let revision = provider.get_revision(revision_id).await?;
+    let previous = media::of_stored_snapshot(&mut *provider, revision.previous_snapshot_id).await?;
+    let current = media::of_stored_snapshot(&mut *provider, revision.snapshot_id).await?;
+    json(
+        StatusCode::Ok,
+        &RevisionWithDiff {
+            revision,
+            paragraphs,
+            media: media::diff(&previous, &current),
+        },
+    )
+}
+
+/// a downloaded image by the `sha256` of its media, see `media::download`
+async fn get_blob(req: Request<State>) -> Result<Response> {
+    let sha256: String = req.param("sha256")?;
+    let blobs = req
+        .state()
+        .blobs
+        .as_ref()
+        .ok_or_else(|| error(StatusCode::NotFound, "images are not downloaded"))?;
+    let bytes = blobs
+        .get(&sha256)
+        .await?
+        .ok_or_else(|| error(StatusCode::NotFound, format!("blob {} not found", sha256)))?;
+    Ok(Response::builder(StatusCode::Ok)
+        .content_type(mime::image(&bytes))
+        // content addressed, a blob never changes
+        .header("Cache-Control", "public, max-age=31536000, immutable")
+        .body(bytes)
+        .build())
+}
+
+async fn get_revision_metadata(req: Request<State>) -> Result<Response> {
+    let revision_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    let revision = provider.get_revision(revision_id).await?;
+    let metadata = |snapshot: Snapshot| {
+        PageMetadata::of_snapshot(snapshot.page_metadata.as_deref(), &snapshot.html)
+    };
+    let previous = metadata(provider.get_snaphot(revision.previous_snapshot_id).await?);
+    let current = metadata(provider.get_snaphot(revision.snapshot_id).await?);
+    let paragraphs = previous.diff(&current).paragraphs;
+    json(
+        StatusCode::Ok,
+        &RevisionMetadata {
+            previous,
+            current,
+            paragraphs,
+        },
+    )
+}
+
+async fn get_users(req: Request<State>) -> Result<Response> {
+    let mut provider = req.state().acquire().await?;
+    json(StatusCode::Ok, &provider.get_users().await?)
+}
+
+async fn insert_user(mut req: Request<State>) -> Result<Response> {
+    let user: NewUser = req.body_json().await.map_err(body_error)?;
+    let mut provider = req.state().acquire().await?;
+    let user = provider
+        .insert_user(&user.name, scraper::timestamp())
+        .await?;
+    json(StatusCode::Created, &user)
+}
+
+/// `?user_id=` restricts to the watchlists of a user
+async fn get_watchlists(req: Request<State>) -> Result<Response> {
+    let query: UserQuery = req.query()?;
+    let mut provider = req.state().acquire().await?;
+    json(
+        StatusCode::Ok,
+        &provider.get_watchlists(query.user_id).await?,
+    )
+}
+
+async fn insert_watchlist(mut req: Request<State>) -> Result<Response> {
+    let new: NewWatchlist = req.body_json().await.map_err(body_error)?;
+    let token = req
+        .ext::<Token>()
+        .cloned()
+        .ok_or_else(|| error(StatusCode::Unauthorized, "api token required"))?;
+    let user_id = match (new.user_id, token.user_id) {
+        (Some(user_id), _) if token.role == Role::Admin => user_id,
+        (Some(user_id), Some(own)) if user_id == own => user_id,
+        (None, Some(own)) => own,
+        (Some(_), _) => {
+            return Err(error(
+                StatusCode::Forbidden,
+                "only admins create watchlists for others",
+            ))
+        }
+        (None, None) => {
+            return Err(error(
+                StatusCode::BadRequest,
+                format!("token {} belongs to no user, pass a user_id", token.name),
+            ))
+        }
+    };
+
+    let mut provider = req.state().acquire().await?;
+    provider.get_user(user_id).await?;
+    let watchlist = provider
+        .insert_watchlist(user_id, &new.name, scraper::timestamp())
+        .await?;
+
+    let mut res = json(StatusCode::Created, &watchlist)?;
+    res.insert_header(
+        "Location",
+        format!("/api/v1/watchlists/{}", watchlist.watchlist_id),
+    );
+    Ok(res)
+}
+
+async fn get_watchlist(req: Request<State>) -> Result<Response> {
+    let watchlist_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    let watchlist = provider.get_watchlist(watchlist_id).await?;
+    let articles = provider.get_watchlist_articles(watchlist_id).await?;
+    let sources = provider.get_watchlist_sources(watchlist_id).await?;
+    json(
+        StatusCode::Ok,
+        &WatchlistWithEntries {
+            watchlist,
+            articles,
+            sources,
+        },
+    )
+}
+
+async fn delete_watchlist(req: Request<State>) -> Result<Response> {
+    let watchlist_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    let watchlist = provider.get_watchlist(watchlist_id).await?;
+    owned_watchlist(&req, &watchlist)?;
+    provider.delete_watchlist(watchlist_id).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn add_watchlist_article(mut req: Request<State>) -> Result<Response> {
+    let entry: WatchlistArticle = req.body_json().await.map_err(body_error)?;
+    let watchlist_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    let watchlist = provider.get_watchlist(watchlist_id).await?;
+    owned_watchlist(&req, &watchlist)?;
+
+    let article = match (entry.article_id, entry.url) {
+        (Some(article_id), _) => provider.get_article_by_id(article_id).await?,
+        (None, Some(url)) => {
+            db::http_url(&url).map_err(|err| error(StatusCode::BadRequest, err))?;
+            let existed = provider.get_article(&url).await.is_ok();
+            let article = provider.insert_article(&url).await?;
+            if !existed {
+                req.state().events.publish(Event::ArticleInserted {
+                    article: article.clone(),
+                });
+            }
+            article
+        }
+        (None, None) => {
+            return Err(error(
+                StatusCode::BadRequest,
+                "pass an article_id or an url",
+            ))
+        }
+    };
+    provider
+        .add_watchlist_article(watchlist_id, article.article_id, scraper::timestamp())
+        .await?;
+    json(StatusCode::Ok, &article)
+}
+
+async fn remove_watchlist_article(req: Request<State>) -> Result<Response> {
+    let watchlist_id = param_id(&req)?;
+    let article_id: i32 = req
+        .param("article_id")
+        .map_err(|_| error(StatusCode::BadRequest, "article_id must be a number"))?;
+    let mut provider = req.state().acquire().await?;
+    let watchlist = provider.get_watchlist(watchlist_id).await?;
+    owned_watchlist(&req, &watchlist)?;
+    provider
+        .remove_watchlist_article(watchlist_id, article_id)
+        .await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn add_watchlist_source(mut req: Request<State>) -> Result<Response> {
+    let source: WatchlistSource = req.body_json().await.map_err(body_error)?;
+    let watchlist_id = param_id(&req)?;
+    let mut provider = req.state().acquire().await?;
+    let watchlist = provider.get_watchlist(watchlist_id).await?;
+    owned_watchlist(&req, &watchlist)?;
+    provider
+        .add_watchlist_source(watchlist_id, &source.host, scraper::timestamp())
+        .await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn remove_watchlist_source(req: Request<State>) -> Result<Response> {
+    let watchlist_id = param_id(&req)?;
+    let host: String = req
+        .param("host")
+        .map_err(|_| error(StatusCode::BadRequest, "host is missing"))?;
+    let mut provider = req.state().acquire().await?;
+    let watchlist = provider.get_watchlist(watchlist_id).await?;
+    owned_watchlist(&req, &watchlist)?;
+    provider
+        .remove_watchlist_source(watchlist_id, &host)
+        .await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
@@ -0,0 +1,294 @@
+//! API tokens with roles, only the sha256 of a token is stored
+//!
+//! clients send `Authorization: Bearer <token>`, browsers may use basic auth
+//! with the token as password, for changes only from pages of the server itself
+
+use crate::db::{self, DbError, DbResult, VoidResult};
+use crate::http::State;
+use async_trait::async_trait;
+use mockall::automock;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqliteQueryAs;
+use tide::{Request, Response, StatusCode};
+
+/// ordered, every role may do what the ones before it may do
+#[derive(
+    sqlx::Type,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    ReadOnly,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read_only",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> DbResult<Self> {
+        match s {
+            "read_only" => Ok(Role::ReadOnly),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            _ => Err(DbError::InvalidInput(format!(
+                "role {} is not one of read_only, editor, admin",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, serde::Serialize)]
+pub struct Token {
+    pub token_id: i32,
+    pub name: String,
+    pub role: Role,
+    /// the owner of watchlists created with this token
+    pub user_id: Option<i32>,
+    pub created_at: i32,
+    pub revoked_at: Option<i32>,
+}
+
+#[automock]
+#[async_trait]
+pub trait ProvideTokens {
+    async fn insert_token(
+        &mut self,
+        name: &str,
+        role: Role,
+        user_id: Option<i32>,
+        token_hash: &str,
+        created_at: i32,
+    ) -> DbResult<Token>;
+    /// revoked tokens are not found
+    async fn get_token(&mut self, token_hash: &str) -> DbResult<Token>;
+    async fn get_tokens(&mut self) -> DbResult<Vec<Token>>;
+    async fn revoke_token(&mut self, token_id: i32, revoked_at: i32) -> DbResult<()>;
+}
+
+#[async_trait]
+impl ProvideTokens for sqlx::SqliteConnection {
+    async fn insert_token(
+        &mut self,
+        name: &str,
+        role: Role,
+        user_id: Option<i32>,
+        token_hash: &str,
+        created_at: i32,
+    ) -> DbResult<Token> {
+        sqlx::query_as(
+            r"
+            INSERT INTO tokens ( name, role, user_id, token_hash, created_at )
+            VALUES ( $1, $2, $3, $4, $5 );
+            SELECT token_id, name, role, user_id, created_at, revoked_at
+            FROM tokens WHERE token_id = last_insert_rowid() ;",
+        )
+        .bind(name)
+        .bind(role)
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(created_at)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_token(&mut self, token_hash: &str) -> DbResult<Token> {
+        sqlx::query_as(
+            r"
+            SELECT token_id, name, role, user_id, created_at, revoked_at
+            FROM tokens WHERE token_hash = $1 AND revoked_at IS NULL",
+        )
+        .bind(token_hash)
+        .fetch_one(self)
+        .await
+        .or_not_found("token")
+    }
+
+    async fn get_tokens(&mut self) -> DbResult<Vec<Token>> {
+        sqlx::query_as(
+            r"
+            SELECT token_id, name, role, user_id, created_at, revoked_at
+            FROM tokens ORDER BY token_id",
+        )
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn revoke_token(&mut self, token_id: i32, revoked_at: i32) -> DbResult<()> {
+        let revoked = sqlx::query(
+            r"
+            UPDATE tokens SET revoked_at = $1 WHERE token_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(revoked_at)
+        .bind(token_id)
+        .execute(self)
+        .await?;
+        if revoked == 0 {
+            return Err(DbError::NotFound(format!(
+                "active token {} not found",
+                token_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 32 random bytes as hex, shown once when minted
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// returns the plain token next to the stored one
+pub async fn mint<P: ProvideTokens + Send + ?Sized>(
+    provider: &mut P,
+    name: &str,
+    role: Role,
+    user_id: Option<i32>,
+    created_at: i32,
+) -> DbResult<(String, Token)> {
+    let plain = generate_token();
+    let token = provider
+        .insert_token(name, role, user_id, &hash_token(&plain), created_at)
+        .await?;
+    Ok((plain, token))
+}
+
+enum Credentials {
+    Bearer(String),
+    /// the password, browsers send it along with requests of any site
+    Basic(String),
+}
+
+fn credentials(req: &Request<State>) -> Option<Credentials> {
+    let header = req.header("Authorization")?.last().as_str().trim();
+    if let Some(token) = header.strip_prefix("Bearer ") {
+        return Some(Credentials::Bearer(token.trim().to_owned()));
+    }
+    let basic = base64::decode(header.strip_prefix("Basic ")?.trim()).ok()?;
+    let basic = String::from_utf8(basic).ok()?;
+    basic
+        .split_once(':')
+        .map(|(_, password)| Credentials::Basic(password.to_owned()))
+}
+
+/// whether the `Origin`, or else the `Referer`, browsers send is the server itself,
+/// so a form of another site can't change anything with the basic credentials of a user
+fn same_origin(req: &Request<State>) -> bool {
+    let origin = req.header("Origin").or_else(|| req.header("Referer"));
+    origin.is_some_and(|origin| {
+        let host = db::url_host(origin.last().as_str());
+        !host.is_empty() && host == db::url_host(req.url().as_str())
+    })
+}
+
+/// role needed for safe methods and for everything else,
+/// `ReadOnly` reads are open to anyone while `State::public_read` is set
+///
+/// handlers find the checked `Token` in the request extensions
+#[derive(Debug, Clone, Copy)]
+pub struct Guard {
+    pub read: Role,
+    pub write: Role,
+}
+
+impl Guard {
+    /// anyone may read, editors may change
+    pub fn editor() -> Self {
+        Self {
+            read: Role::ReadOnly,
+            write: Role::Editor,
+        }
+    }
+
+    pub fn admin() -> Self {
+        Self {
+            read: Role::Admin,
+            write: Role::Admin,
+        }
+    }
+}
+
+fn denied(status: StatusCode, message: &str) -> Response {
+    let mut res = Response::new(status);
+    if status == StatusCode::Unauthorized {
+        res.insert_header(
+            "WWW-Authenticate",
+            r#"Bearer realm="propaganda", Basic realm="propaganda""#,
+        );
+    }
+    res.set_error(tide::Error::from_str(status, message.to_owned()));
+    res
+}
+
+#[async_trait]
+impl tide::Middleware<State> for Guard {
+    async fn handle(&self, mut req: Request<State>, next: tide::Next<'_, State>) -> tide::Result {
+        let required = if req.method().is_safe() {
+            self.read
+        } else {
+            self.write
+        };
+        if required == Role::ReadOnly && req.state().public_read {
+            return Ok(next.run(req).await);
+        }
+
+        let plain = match credentials(&req) {
+            Some(Credentials::Bearer(plain)) => plain,
+            Some(Credentials::Basic(_)) if !req.method().is_safe() && !same_origin(&req) => {
+                return Ok(denied(
+                    StatusCode::Forbidden,
+                    "changes with basic auth must come from this server",
+                ))
+            }
+            Some(Credentials::Basic(plain)) => plain,
+            None => return Ok(denied(StatusCode::Unauthorized, "api token required")),
+        };
+        let mut provider = req.state().acquire().await?;
+        let token = match provider.get_token(&hash_token(&plain)).await {
+            Ok(token) => token,
+            Err(DbError::NotFound(_)) => {
+                return Ok(denied(StatusCode::Unauthorized, "invalid api token"))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        drop(provider);
+
+        if token.role < required {
+            return Ok(denied(
+                StatusCode::Forbidden,
+                &format!("token {} may not do this", token.name),
+            ));
+        }
+        req.set_ext(token);
+        Ok(next.run(req).await)
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::*;
-use propaganda::db::ProvideArticles;
+use propaganda::repository::Repository;
 use propaganda::*;
 use xactor::Actor; // propaganda needs to export this
 
@@ -7,31 +7,418 @@ use xactor::Actor; // propaganda needs to export this
 async fn main() -> Result<()> {
     tide::log::with_level(tide::log::LevelFilter::Info);
 
-    let db_path = "sqlite:propaganda.db";
-    let pool = sqlx::SqlitePool::new(&db_path)
-        .await
-        .expect("SqlitePool::new");
+    let config = config::Config::from_env();
+    let repository = Repository::open(&config.database_url).await?;
+    repository.acquire().await?.ensure_created_tables().await?;
 
-    let mut conn = pool.acquire().await?;
-    conn.ensure_created_tables().await?;
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["import", path] => import(&repository, path).await,
+        ["warc", args @ ..] => warc(&repository, args).await,
+        ["wayback", url, index @ ..] => wayback(&config, &repository, url, index).await,
+        ["verify", article_ids @ ..] => {
+            verify(&mut *repository.acquire().await?, article_ids).await
+        }
+        ["evidence", args @ ..] => evidence(&config, &mut *repository.acquire().await?, args).await,
+        [] | ["serve"] => serve(repository, config).await,
+        ["token", args @ ..] => token(&repository, args).await,
+        ["user", args @ ..] => user(&repository, args).await,
+        _ => bail!("{}", USAGE),
+    }
+}
+
+const USAGE: &str = "usage:
+    propaganda [serve]
+    propaganda token mint <name> <read_only|editor|admin> [user]
+    propaganda token list
+    propaganda token revoke <token_id>
+    propaganda user add <name>
+    propaganda user list
+    propaganda import <file.txt|file.csv|file.json|->
+    propaganda warc export <file.warc|file.warc.gz|-> [article_id...]
+    propaganda warc import <file.warc|file.warc.gz|->
+    propaganda wayback <url> [index.cdx|index.json|-]
+    propaganda verify [article_id...]
+    propaganda evidence key <key-file>
+    propaganda evidence export <revision_id> <bundle.tar.gz>
+    propaganda evidence verify <bundle.tar.gz> [public_key]";
+
+async fn token(repository: &Repository, args: &[&str]) -> Result<()> {
+    let mut conn = repository.acquire().await?;
+
+    match args {
+        ["mint", name, role, user @ ..] => {
+            let user_id = match user {
+                [] => None,
+                [user] => Some(conn.get_user_by_name(user).await?.user_id),
+                _ => bail!("{}", USAGE),
+            };
+            let (plain, token) = auth::mint(
+                &mut *conn,
+                name,
+                role.parse()?,
+                user_id,
+                scraper::timestamp(),
+            )
+            .await?;
+            eprintln!(
+                "minted token {} for {} as {:?}",
+                token.token_id, token.name, token.role
+            );
+            println!("{}", plain);
+        }
+        ["list"] => {
+            for token in conn.get_tokens().await? {
+                let revoked = token
+                    .revoked_at
+                    .map(|at| format!(" revoked {}", markup::rfc3339(at)))
+                    .unwrap_or_default();
+                println!(
+                    "{}\t{}\t{:?}\tcreated {}{}",
+                    token.token_id,
+                    token.name,
+                    token.role,
+                    markup::rfc3339(token.created_at),
+                    revoked
+                );
+            }
+        }
+        ["revoke", token_id] => {
+            conn.revoke_token(token_id.parse()?, scraper::timestamp())
+                .await?;
+            eprintln!("revoked token {}", token_id);
+        }
+        _ => bail!("{}", USAGE),
+    }
+    Ok(())
+}
+
+async fn user(repository: &Repository, args: &[&str]) -> Result<()> {
+    let mut conn = repository.acquire().await?;
+
+    match args {
+        ["add", name] => {
+            let user = conn.insert_user(name, scraper::timestamp()).await?;
+            println!("{}", user.user_id);
+        }
+        ["list"] => {
+            for user in conn.get_users().await? {
+                println!("{}\t{}", user.user_id, user.name);
+            }
+        }
+        _ => bail!("{}", USAGE),
+    }
+    Ok(())
+}
+
+/// `-` reads newline separated urls from stdin
+async fn import(repository: &Repository, path: &str) -> Result<()> {
+    let body = if path == "-" {
+        let mut body = String::new();
+        async_std::io::ReadExt::read_to_string(&mut async_std::io::stdin(), &mut body).await?;
+        body
+    } else {
+        async_std::fs::read_to_string(path).await?
+    };
+    let entries = import::parse(&body, import::Format::from_path(path)).map_err(Error::msg)?;
+
+    let mut tx = repository.begin().await?;
+    let report = import::import(&mut *tx, entries).await?;
+    tx.commit().await?;
+    for line in &report.lines {
+        println!(
+            "{}\t{:?}\t{}",
+            line.line,
+            line.status,
+            line.url
+                .as_ref()
+                .or(line.error.as_ref())
+                .unwrap_or(&line.input)
+        );
+    }
+    eprintln!(
+        "{} added, {} duplicate, {} invalid",
+        report.added, report.duplicate, report.invalid
+    );
+    Ok(())
+}
+
+/// `-` writes to stdout or reads from stdin
+async fn warc(repository: &Repository, args: &[&str]) -> Result<()> {
+    match args {
+        ["export", path, article_ids @ ..] => {
+            let article_ids = article_ids
+                .iter()
+                .map(|id| id.parse::<i32>())
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let article_ids = Some(article_ids.as_slice()).filter(|ids| !ids.is_empty());
+            let mut conn = repository.acquire().await?;
+            let records = warc::export(&mut *conn, article_ids, scraper::timestamp()).await?;
+            let bytes = warc::write(&records, path.ends_with(".gz"));
+            if *path == "-" {
+                async_std::io::WriteExt::write_all(&mut async_std::io::stdout(), &bytes).await?;
+            } else {
+                async_std::fs::write(path, bytes).await?;
+            }
+            eprintln!("exported {} snapshots", records.len() - 1);
+        }
+        ["import", path] => {
+            let bytes = if *path == "-" {
+                let mut bytes = vec![];
+                async_std::io::ReadExt::read_to_end(&mut async_std::io::stdin(), &mut bytes)
+                    .await?;
+                bytes
+            } else {
+                async_std::fs::read(path).await?
+            };
+            let records = warc::parse(&bytes).map_err(Error::msg)?;
+            let mut tx = repository.begin().await?;
+            let report = warc::import(&mut *tx, &records).await?;
+            tx.commit().await?;
+            for article in &report.articles {
+                println!("{}\t{}", article.article_id, article.url);
+            }
+            eprintln!(
+                "{} response records, {} snapshots added, {} duplicate, {} skipped",
+                report.records, report.snapshots_added, report.duplicate, report.skipped
+            );
+        }
+        _ => bail!("{}", USAGE),
+    }
+    Ok(())
+}
+
+/// prior captures of an url, which gets tracked if it isn't yet, from the index
+/// of `PROPAGANDA_WAYBACK_URL` or a CDX file
+async fn wayback(
+    config: &config::Config,
+    repository: &Repository,
+    url: &str,
+    index: &[&str],
+) -> Result<()> {
+    let wayback = wayback::Wayback::new(&config.wayback_url);
+    let captures = match index {
+        [] => wayback.get_index(url).await?,
+        ["-"] => {
+            let mut index = String::new();
+            async_std::io::ReadExt::read_to_string(&mut async_std::io::stdin(), &mut index).await?;
+            wayback::parse_index(&index).map_err(Error::msg)?
+        }
+        [path] => {
+            wayback::parse_index(&async_std::fs::read_to_string(path).await?).map_err(Error::msg)?
+        }
+        _ => bail!("{}", USAGE),
+    };
+
+    let mut conn = repository.acquire().await?;
+    let article = match conn.get_article(url).await {
+        Ok(article) => article,
+        Err(db::DbError::NotFound(_)) => conn.insert_article(url).await?,
+        Err(err) => return Err(err.into()),
+    };
+    drop(conn);
+    let report = wayback::import(repository, &wayback, &article, captures).await?;
+    eprintln!(
+        "{} captures of {}: {} snapshots and {} revisions added, {} duplicate, {} skipped, {} failed",
+        report.captures,
+        article.url,
+        report.snapshots_added,
+        report.revisions_added,
+        report.duplicate,
+        report.skipped,
+        report.failed
+    );
+    Ok(())
+}
 
-    let mut server = tide::with_state(pool.clone());
+/// print every integrity violation, fails if there is any
+async fn verify(store: &mut dyn db::Store, article_ids: &[&str]) -> Result<()> {
+    let article_ids = article_ids
+        .iter()
+        .map(|id| id.parse::<i32>())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let article_ids = Some(article_ids.as_slice()).filter(|ids| !ids.is_empty());
 
-    server.at("/get_articles").get(&http::get_articles);
+    let report = provenance::verify(store, article_ids).await?;
+    for finding in &report.findings {
+        println!(
+            "article {}\tsnapshot {}\t{:?}\texpected {}\tfound {}",
+            finding.article_id,
+            finding.snapshot_id,
+            finding.violation,
+            finding.expected,
+            finding.found
+        );
+    }
+    eprintln!(
+        "{} snapshots verified, {} chained, {} without hash",
+        report.snapshots, report.chained, report.unhashed
+    );
+    if !report.findings.is_empty() {
+        bail!("{} integrity violations", report.findings.len());
+    }
+    Ok(())
+}
+
+/// bundles are signed with the key in `PROPAGANDA_SIGNING_KEY`,
+/// verifying needs no database but trusts the key in the bundle unless one is given
+async fn evidence(config: &config::Config, store: &mut dyn db::Store, args: &[&str]) -> Result<()> {
+    match args {
+        ["key", path] => {
+            if async_std::path::Path::new(path).exists().await {
+                bail!("{} exists already", path);
+            }
+            let key = evidence::generate_key();
+            async_std::fs::write(path, hex::encode(key.to_bytes())).await?;
+            println!("{}", hex::encode(key.verifying_key().as_bytes()));
+        }
+        ["export", revision_id, path] => {
+            let key_file = config
+                .signing_key
+                .as_ref()
+                .ok_or_else(|| anyhow!("PROPAGANDA_SIGNING_KEY is not set"))?;
+            let key = evidence::parse_key(&async_std::fs::read_to_string(key_file).await?)?;
+            let bundle =
+                evidence::bundle(store, revision_id.parse()?, &key, scraper::timestamp()).await?;
+            async_std::fs::write(path, bundle).await?;
+            eprintln!("signed by {}", hex::encode(key.verifying_key().as_bytes()));
+        }
+        ["verify", path, trusted @ ..] => {
+            let trusted = match trusted {
+                [] => None,
+                [key] => Some(evidence::parse_public_key(key)?),
+                _ => bail!("{}", USAGE),
+            };
+            let bundle = async_std::fs::read(path).await?;
+            let verification = evidence::verify(&bundle, trusted.as_ref())?;
+            let manifest = &verification.manifest;
+            println!(
+                "revision {} of {}\n{} → {}\nsigned by {} at {}",
+                manifest.revision_id,
+                manifest.url,
+                manifest.previous.fetched_at,
+                manifest.current.fetched_at,
+                manifest.public_key,
+                manifest.created_at
+            );
+            for problem in &verification.problems {
+                println!("{}", problem);
+            }
+            if !verification.problems.is_empty() {
+                bail!("{} does not verify", path);
+            }
+        }
+        _ => bail!("{}", USAGE),
+    }
+    Ok(())
+}
+
+async fn serve(repository: Repository, config: config::Config) -> Result<()> {
+    let events = events::Events::default();
+    let blobs = match &config.blob_dir {
+        Some(dir) => Some(blobs::BlobStore::open(dir).await?),
+        None => None,
+    };
+    let state = http::State {
+        public_read: config.public_read,
+        wayback_url: config.wayback_url.clone(),
+        cache: cache::Cache::new(config.cache_entries),
+        blobs: blobs.clone(),
+        ..http::State::new(repository, events.clone())
+    };
+    let repository = state.repository.clone();
+    let cache = state.cache.clone();
+    let mut server = tide::with_state(state.clone());
+    server.with(http::Cors(config.cors_origins.clone()));
+
+    server.at("/api/v1").nest(api::server(state));
+
+    // reads are guarded only without public_read, webhooks are for admins
+    let editor = auth::Guard::editor;
+    server.at("/").with(editor()).get(ui::articles);
+    server
+        .at("/ui/articles")
+        .with(editor())
+        .get(ui::articles)
+        .post(ui::insert_article);
+    server
+        .at("/ui/articles/:id")
+        .with(editor())
+        .get(ui::article);
+    server.at("/ui/diff").with(editor()).get(ui::diff);
+
+    // the JSON routes answer errors with problem details, the debug index is for browsers
+    let problem_details = || tide::utils::After(http::problem_details);
+    server
+        .at("/get_articles")
+        .with(http::Deprecated("/api/v1/articles"))
+        .with(problem_details())
+        .with(editor())
+        .get(http::get_articles);
     server
         .at("/get_snaphot_metadatas_from_article")
-        .get(&http::get_snaphot_metadatas_from_article);
-    server.at("/insert_article").get(&http::insert_article);
-    server.at("/get_snapshot").get(&http::get_snaphot);
-    server.at("/favicon.ico").get(&favicon);
+        .with(http::Deprecated("/api/v1/articles/{id}/snapshots"))
+        .with(problem_details())
+        .with(editor())
+        .get(http::get_snaphot_metadatas_from_article);
+    server
+        .at("/insert_article")
+        .with(http::Deprecated("/api/v1/articles"))
+        .with(problem_details())
+        // a GET which inserts
+        .with(auth::Guard {
+            read: auth::Role::Editor,
+            write: auth::Role::Editor,
+        })
+        .get(http::insert_article);
+    server
+        .at("/get_snapshot")
+        .with(http::Deprecated("/api/v1/snapshots/{id}"))
+        .with(problem_details())
+        .with(editor())
+        .get(http::get_snaphot);
+    server
+        .at("/changes")
+        .with(problem_details())
+        .with(editor())
+        .get(http::get_changes);
+    server
+        .at("/changes.atom")
+        .with(editor())
+        .get(http::get_changes_atom);
+    server
+        .at("/webhooks")
+        .with(problem_details())
+        .with(auth::Guard::admin())
+        .get(http::get_webhooks)
+        .post(http::insert_webhook);
+    server
+        .at("/webhooks/:id")
+        .with(problem_details())
+        .with(auth::Guard::admin())
+        .delete(http::delete_webhook);
+    server
+        .at("/events")
+        .with(editor())
+        .get(tide::sse::endpoint(http::get_events));
+    server.at("/favicon.ico").get(favicon);
 
     server.with(tide::utils::After(&debug_response_middleware));
 
-    let join_server = async_std::task::spawn(server.clone().listen("localhost:8080"));
-    let addr_scraper = scraper::Scraper::new(pool.clone()).start().await?;
+    let join_server = async_std::task::spawn(server.clone().listen(config.listen.clone()));
+    let mut scraper = scraper::Scraper::new(repository.clone(), events)
+        .with_hash_chain(config.hash_chain)
+        .with_cache(cache);
+    if let Some(blobs) = blobs {
+        scraper = scraper.with_blobs(blobs);
+    }
+    let addr_scraper = scraper.start().await?;
+    let addr_webhooks = webhook::Dispatcher::new(repository).start().await?;
 
     join_server.await?;
     addr_scraper.wait_for_stop().await;
+    addr_webhooks.wait_for_stop().await;
 
     Ok(())
 }
@@ -46,15 +433,13 @@ async fn favicon<R>(_req: R) -> tide::Result {
 /// with some error handling and string matching this could be
 /// a generic interactive HTTP API user interface
 async fn debug_response_middleware(mut res: tide::Response) -> tide::Result {
-    res.append_header("Access-Control-Allow-Origin", "*");
-
-    if res.len().unwrap_or_default() == 0 {
+    if res.len() == Some(0) && res.status() != tide::StatusCode::NoContent {
         res.set_content_type(mime::html());
 
         res.set_body(if let Some(err) = res.error() {
-            format!("<h4>{}</h4>{}", err.to_string(), debug_index())
+            format!("<h4>{}</h4>{}", err, debug_index())
         } else {
-            format!("<h4>{}</h4>{}", res.status().to_string(), debug_index())
+            format!("<h4>{}</h4>{}", res.status(), debug_index())
         });
     }
     Ok(res)
@@ -65,11 +450,44 @@ fn debug_index() -> String {
         format!("<a href={}>{}</a> <span>{}</span>", href, href, desc)
     }
 
-    vec![
-        anchor("get_articles", ""),
-        anchor("get_snaphot_metadatas_from_article", "url"),
-        anchor("insert_article", "url"),
-        anchor("get_snapshot", "id"),
+    [
+        anchor(
+            "api/v1/articles",
+            "GET limit, cursor, sort, q, host, has_changes, created_since, created_until, changed_since, changed_until; POST url",
+        ),
+        anchor(
+            "api/v1/articles/import",
+            "POST urls as lines, csv or json, format",
+        ),
+        anchor("api/v1/articles/{id}", "GET, DELETE"),
+        anchor("api/v1/articles/{id}/snapshots", "GET"),
+        anchor(
+            "api/v1/articles/{id}/wayback",
+            "POST an optional CDX index",
+        ),
+        anchor("api/v1/snapshots/{id}", "GET"),
+        anchor("api/v1/warc", "GET articles, gzip; POST a WARC file"),
+        anchor("api/v1/users", "GET, POST name"),
+        anchor("api/v1/watchlists", "GET user_id, POST name, user_id"),
+        anchor("api/v1/watchlists/{id}", "GET, DELETE"),
+        anchor("api/v1/watchlists/{id}/articles", "POST article_id or url"),
+        anchor("api/v1/watchlists/{id}/sources", "POST host"),
+        anchor("get_articles", "deprecated"),
+        anchor("get_snaphot_metadatas_from_article", "url, deprecated"),
+        anchor("insert_article", "url, deprecated"),
+        anchor("get_snapshot", "id, deprecated"),
+        anchor(
+            "changes",
+            "cursor, limit, url, host, watchlist, min_significance, silent_edit",
+        ),
+        anchor(
+            "changes.atom",
+            "url, host, watchlist, min_significance, silent_edit",
+        ),
+        anchor(
+            "webhooks",
+            "POST url, secret, host, keyword, min_words_changed, min_significance",
+        ),
     ]
     .join("<br />")
 }
@@ -0,0 +1,51 @@
+//! content addressed files, such as the images of articles
+//!
+//! every blob is stored once under the hex sha256 of its bytes, in a directory named
+//! after the first two characters of the hash
+
+use async_std::fs;
+use async_std::path::PathBuf;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    pub async fn open(dir: impl Into<std::path::PathBuf>) -> std::io::Result<Self> {
+        let dir = PathBuf::from(dir.into());
+        fs::create_dir_all(&dir).await?;
+        Ok(Self { dir })
+    }
+
+    /// the hex sha256 the bytes are stored under, existing blobs are not written again
+    pub async fn put(&self, bytes: &[u8]) -> std::io::Result<String> {
+        let sha256 = hex::encode(Sha256::digest(bytes));
+        let path = self.path(&sha256);
+        if !path.exists().await {
+            fs::create_dir_all(path.parent().expect("blob directory")).await?;
+            // complete blobs only, a crash leaves at most a temporary file
+            let temporary = path.with_extension("tmp");
+            fs::write(&temporary, bytes).await?;
+            fs::rename(&temporary, &path).await?;
+        }
+        Ok(sha256)
+    }
+
+    /// none for a hash which is not stored
+    pub async fn get(&self, sha256: &str) -> std::io::Result<Option<Vec<u8>>> {
+        if sha256.len() < 2 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        match fs::read(self.path(sha256)).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(&sha256[..2]).join(sha256)
+    }
+}
@@ -0,0 +1,265 @@
+//! lock-free reads of hot articles, their latest fulltext and computed diffs
+//!
+//! every map is an evmap, handlers read through a read handle of their thread and never
+//! wait for the writer, which publishes each change with `refresh`, the scraper writes
+//! the article and its fulltext whenever it stores a snapshot, handlers fill in what
+//! they missed, a full map evicts a random entry
+
+use crate::db::{Article, Snapshot};
+use crate::diff::DiffSummary;
+use crate::extract;
+use evmap::{ReadHandle, ReadHandleFactory, ShallowCopy, WriteHandle};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// entries of each map unless configured otherwise
+pub const MAX_ENTRIES: usize = 1000;
+
+/// compared by identity, evmap wants values with `Eq` and `Hash` though none is looked up
+#[derive(Debug)]
+pub struct Shared<T>(Arc<T>);
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T> std::ops::Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Eq for Shared<T> {}
+
+impl<T> Hash for Shared<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Arc::as_ptr(&self.0) as usize).hash(state)
+    }
+}
+
+impl<T> ShallowCopy for Shared<T> {
+    unsafe fn shallow_copy(&self) -> ManuallyDrop<Self> {
+        ManuallyDrop::new(Shared(ManuallyDrop::into_inner(self.0.shallow_copy())))
+    }
+}
+
+/// the fulltext of the youngest snapshot of an article
+#[derive(Debug)]
+pub struct Fulltext {
+    pub snapshot_id: i32,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiffView {
+    Inline,
+    SideBySide,
+    Excerpt,
+}
+
+/// from one snapshot to another, snapshots never change so neither does their diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DiffKey {
+    pub from: i32,
+    pub to: i32,
+    pub view: DiffView,
+}
+
+#[derive(Debug)]
+pub struct Diff {
+    pub article_id: i32,
+    pub summary: DiffSummary,
+    pub html: String,
+}
+
+type Map<K, V> = (ReadHandle<K, Shared<V>>, WriteHandle<K, Shared<V>>);
+
+struct Readers {
+    articles: ReadHandle<i32, Shared<Article>>,
+    texts: ReadHandle<i32, Shared<Fulltext>>,
+    diffs: ReadHandle<DiffKey, Shared<Diff>>,
+}
+
+struct Factories {
+    articles: ReadHandleFactory<i32, Shared<Article>>,
+    texts: ReadHandleFactory<i32, Shared<Fulltext>>,
+    diffs: ReadHandleFactory<DiffKey, Shared<Diff>>,
+}
+
+struct Writers {
+    articles: WriteHandle<i32, Shared<Article>>,
+    texts: WriteHandle<i32, Shared<Fulltext>>,
+    diffs: WriteHandle<DiffKey, Shared<Diff>>,
+}
+
+thread_local! {
+    /// read handles are not `Sync`, every thread keeps its own, by the id of their cache
+    static READERS: RefCell<HashMap<usize, Readers>> = RefCell::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// evict a random entry before `key` is added to a full map
+fn make_room<K>(writer: &mut WriteHandle<K, Shared<impl Sized>>, max_entries: usize, key: K)
+where
+    K: Eq + Hash + Clone,
+{
+    if !writer.contains_key(&key) && writer.len() >= max_entries.max(1) {
+        let index = rand::random::<usize>() % writer.len();
+        writer.empty_at_index(index);
+    }
+}
+
+/// shared by the handlers and the scraper, clones are the same cache
+#[derive(Clone)]
+pub struct Cache {
+    id: usize,
+    max_entries: usize,
+    factories: Arc<Factories>,
+    writers: Arc<Mutex<Writers>>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(MAX_ENTRIES)
+    }
+}
+
+impl Cache {
+    /// at most `max_entries` articles, fulltexts and diffs each
+    pub fn new(max_entries: usize) -> Self {
+        let (articles_r, articles): Map<i32, Article> = evmap::new();
+        let (texts_r, texts): Map<i32, Fulltext> = evmap::new();
+        let (diffs_r, diffs): Map<DiffKey, Diff> = evmap::new();
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            max_entries,
+            factories: Arc::new(Factories {
+                articles: articles_r.factory(),
+                texts: texts_r.factory(),
+                diffs: diffs_r.factory(),
+            }),
+            writers: Arc::new(Mutex::new(Writers {
+                articles,
+                texts,
+                diffs,
+            })),
+        }
+    }
+
+    fn read<T>(&self, read: impl FnOnce(&Readers) -> T) -> T {
+        READERS.with(|readers| {
+            let mut readers = readers.borrow_mut();
+            if !readers.contains_key(&self.id) {
+                readers.retain(|_, readers| !readers.articles.is_destroyed());
+                readers.insert(
+                    self.id,
+                    Readers {
+                        articles: self.factories.articles.handle(),
+                        texts: self.factories.texts.handle(),
+                        diffs: self.factories.diffs.handle(),
+                    },
+                );
+            }
+            read(&readers[&self.id])
+        })
+    }
+
+    fn write(&self, write: impl FnOnce(&mut Writers)) {
+        // a writer which panicked left nothing half published, evmap only publishes on refresh
+        let mut writers = self
+            .writers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        write(&mut writers)
+    }
+
+    pub fn article(&self, article_id: i32) -> Option<Shared<Article>> {
+        self.read(|r| r.articles.get_one(&article_id).map(|a| a.clone()))
+    }
+
+    pub fn put_article(&self, article: Article) {
+        let max_entries = self.max_entries;
+        self.write(|w| {
+            make_room(&mut w.articles, max_entries, article.article_id);
+            w.articles
+                .update(article.article_id, Shared(Arc::new(article)))
+                .refresh();
+        });
+    }
+
+    /// the fulltext of the youngest snapshot of the article, as far as the cache knows
+    pub fn latest_fulltext(&self, article_id: i32) -> Option<Shared<Fulltext>> {
+        self.read(|r| r.texts.get_one(&article_id).map(|t| t.clone()))
+    }
+
+    /// a new youngest snapshot of the article replaces the fulltext of the previous one
+    pub fn put_latest_fulltext(&self, article_id: i32, snapshot_id: i32, text: String) {
+        let max_entries = self.max_entries;
+        self.write(|w| {
+            make_room(&mut w.texts, max_entries, article_id);
+            w.texts
+                .update(article_id, Shared(Arc::new(Fulltext { snapshot_id, text })))
+                .refresh();
+        });
+    }
+
+    /// cached if the snapshot is the youngest one of its article, extracted otherwise
+    pub fn fulltext(&self, snapshot: &Snapshot) -> String {
+        match self.latest_fulltext(snapshot.article_id) {
+            Some(latest) if latest.snapshot_id == snapshot.snapshot_id => latest.text.clone(),
+            _ => extract::get_article_fulltext(&snapshot.html),
+        }
+    }
+
+    pub fn diff(&self, key: &DiffKey) -> Option<Shared<Diff>> {
+        self.read(|r| r.diffs.get_one(key).map(|d| d.clone()))
+    }
+
+    pub fn put_diff(&self, key: DiffKey, diff: Diff) -> Shared<Diff> {
+        let max_entries = self.max_entries;
+        let diff = Shared(Arc::new(diff));
+        self.write(|w| {
+            make_room(&mut w.diffs, max_entries, key);
+            w.diffs.update(key, diff.clone()).refresh();
+        });
+        diff
+    }
+
+    /// the article was deleted or its snapshots changed other than by the scraper
+    pub fn forget_article(&self, article_id: i32) {
+        self.write(|w| {
+            w.articles.empty(article_id).refresh();
+            w.texts.empty(article_id).refresh();
+            let keys = w
+                .diffs
+                .map_into::<_, Vec<_>, _>(|key, diffs| match diffs.get_one() {
+                    Some(diff) if diff.article_id == article_id => Some(*key),
+                    _ => None,
+                });
+            for key in keys.into_iter().flatten() {
+                w.diffs.empty(key);
+            }
+            w.diffs.refresh();
+        });
+    }
+
+    /// the numbers of articles, fulltexts and diffs which readers see
+    pub fn sizes(&self) -> (usize, usize, usize) {
+        self.read(|r| (r.articles.len(), r.texts.len(), r.diffs.len()))
+    }
+}
@@ -0,0 +1,54 @@
+//! settings from environment variables, with defaults for local use
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `PROPAGANDA_DATABASE_URL`, `sqlite:`, `postgres:` or `sled:`, see `db::Backend`
+    pub database_url: String,
+    /// `PROPAGANDA_LISTEN`
+    pub listen: String,
+    /// `PROPAGANDA_CORS_ORIGINS`, comma separated, empty allows no cross-origin requests
+    pub cors_origins: Vec<String>,
+    /// `PROPAGANDA_PUBLIC_READ`, set to `false` to require a read_only token for reads
+    pub public_read: bool,
+    /// `PROPAGANDA_WAYBACK_URL`, a server with the CDX and replay API of the wayback machine
+    pub wayback_url: String,
+    /// `PROPAGANDA_HASH_CHAIN`, set to `true` to link fetched snapshots by a hash chain
+    pub hash_chain: bool,
+    /// `PROPAGANDA_SIGNING_KEY`, a file with the hex Ed25519 secret which signs evidence bundles
+    pub signing_key: Option<String>,
+    /// `PROPAGANDA_CACHE_ENTRIES`, articles, fulltexts and diffs each kept in `cache::Cache`
+    pub cache_entries: usize,
+    /// `PROPAGANDA_BLOB_DIR`, a directory to download the images of articles to, see `blobs`
+    pub blob_dir: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+
+        Self {
+            database_url: var("PROPAGANDA_DATABASE_URL")
+                .unwrap_or_else(|| "sqlite:propaganda.db".to_owned()),
+            listen: var("PROPAGANDA_LISTEN").unwrap_or_else(|| "localhost:8080".to_owned()),
+            cors_origins: var("PROPAGANDA_CORS_ORIGINS")
+                .map(|origins| {
+                    origins
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|o| !o.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            public_read: var("PROPAGANDA_PUBLIC_READ").is_none_or(|v| v != "false" && v != "0"),
+            wayback_url: var("PROPAGANDA_WAYBACK_URL")
+                .unwrap_or_else(|| crate::wayback::WAYBACK_URL.to_owned()),
+            hash_chain: var("PROPAGANDA_HASH_CHAIN").is_some_and(|v| v == "true" || v == "1"),
+            signing_key: var("PROPAGANDA_SIGNING_KEY"),
+            cache_entries: var("PROPAGANDA_CACHE_ENTRIES")
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(crate::cache::MAX_ENTRIES),
+            blob_dir: var("PROPAGANDA_BLOB_DIR"),
+        }
+    }
+}
@@ -1,57 +1,658 @@
-use anyhow::*;
+use crate::auth::ProvideTokens;
+use crate::diff::{DiffSummary, Paragraph, TextDiff};
+use crate::media::{self, Media};
+use crate::metadata;
+use crate::provenance;
+use crate::significance::Significance;
+use crate::watchlist::ProvideWatchlists;
+use crate::webhook::ProvideWebhooks;
 use async_trait::async_trait;
 use mockall::automock;
-use sqlx::prelude::*;
+use sqlx::sqlite::SqliteQueryAs;
+use sqlx::Executor;
 
-#[derive(sqlx::FromRow, Debug, serde::Serialize)]
+/// everything the storage can fail with, the http layer maps these to status codes
+#[derive(Debug)]
+pub enum DbError {
+    NotFound(String),
+    Conflict(String),
+    InvalidInput(String),
+    Storage(sqlx::Error),
+}
+
+pub type DbResult<T> = std::result::Result<T, DbError>;
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DbError::NotFound(message)
+            | DbError::Conflict(message)
+            | DbError::InvalidInput(message) => f.write_str(message),
+            DbError::Storage(err) => write!(f, "storage error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbError::Storage(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// sqlite extended result codes of violated UNIQUE and PRIMARY KEY constraints
+/// and the postgres SQLSTATE unique_violation
+const CONSTRAINT_UNIQUE: &[&str] = &["2067", "1555", "23505"];
+
+/// sqlite result codes of a table locked by another connection of the shared cache,
+/// which does not wait for a busy timeout
+const LOCKED: &[&str] = &["6", "262"];
+
+impl DbError {
+    /// another connection of the pool holds a lock, worth another try shortly
+    pub fn is_locked(&self) -> bool {
+        match self {
+            DbError::Storage(sqlx::Error::Database(err)) => {
+                err.code().is_some_and(|code| LOCKED.contains(&code))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// the paragraphs of a stored diff, as JSON in sql databases
+pub(crate) fn encode_paragraphs(paragraphs: &[Paragraph]) -> String {
+    serde_json::to_string(paragraphs).expect("paragraphs serialize")
+}
+
+pub(crate) fn decode_paragraphs(json: &str) -> DbResult<Vec<Paragraph>> {
+    serde_json::from_str(json).map_err(|err| DbError::Storage(sqlx::Error::Decode(err.into())))
+}
+
+impl From<sqlx::Error> for DbError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => DbError::NotFound("not found".to_owned()),
+            sqlx::Error::Database(err)
+                if err
+                    .code()
+                    .is_some_and(|code| CONSTRAINT_UNIQUE.contains(&code)) =>
+            {
+                DbError::Conflict(err.message().to_owned())
+            }
+            err => DbError::Storage(err),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Article {
     pub url: String,
     pub article_id: i32,
+    pub host: String,
+    pub created_at: i32,
     pub updated_at: i32,
 }
 
-#[derive(sqlx::FromRow, Debug, serde::Serialize)]
+/// where the html of a snapshot was fetched from
+#[derive(
+    sqlx::Type, Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[sqlx(rename = "snapshot_source")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotSource {
+    /// by the scraper
+    #[default]
+    Live,
+    /// a response record of an imported WARC file
+    Warc,
+    /// a capture of the wayback machine or another CDX server
+    Wayback,
+}
+
+impl SnapshotSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SnapshotSource::Live => "live",
+            SnapshotSource::Warc => "warc",
+            SnapshotSource::Wayback => "wayback",
+        }
+    }
+}
+
+/// how a snapshot came to be, everything but the html
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    pub source: SnapshotSource,
+    /// the capture or WARC record an imported snapshot was taken from
+    pub source_url: Option<String>,
+    pub request_headers: Vec<(String, String)>,
+    pub response_headers: Vec<(String, String)>,
+    /// the sha256 of the body as received, see `provenance::body_sha256`,
+    /// none for a body which is the html itself
+    pub body_sha256: Option<String>,
+    /// start a hash chain for the article,
+    /// snapshots of an article with a chain always continue it
+    pub chain: bool,
+    /// the media of the html with their downloaded hashes, see `media::download`
+    pub media: Option<Vec<Media>>,
+}
+
+impl Provenance {
+    /// as received, or else of the html
+    pub fn body_sha256(&self, html: &str) -> String {
+        self.body_sha256
+            .clone()
+            .unwrap_or_else(|| provenance::body_sha256(html))
+    }
+
+    /// as downloaded, or else extracted from the html
+    pub fn media(&self, html: &str) -> Vec<Media> {
+        self.media.clone().unwrap_or_else(|| media::extract(html))
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SnapshotMetadata {
     pub article_id: i32,
     pub snapshot_id: i32,
     pub archived_at: i32,
+    pub source: SnapshotSource,
 }
 
-#[derive(sqlx::FromRow, Debug, serde::Serialize)]
+#[derive(sqlx::FromRow, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Snapshot {
     pub article_id: i32,
     pub snapshot_id: i32,
     pub archived_at: i32,
     pub html: String,
+    pub source: SnapshotSource,
+    /// the capture or WARC record an imported snapshot was taken from
+    pub source_url: Option<String>,
+    /// hex sha256 of the response body as received, which is the html unless the body
+    /// was not utf-8
+    pub body_sha256: Option<String>,
+    /// JSON array of `[name, value]` pairs, see `provenance::parse_headers`
+    pub request_headers: Option<String>,
+    pub response_headers: Option<String>,
+    /// see `provenance::chain_sha256`
+    pub chain_sha256: Option<String>,
+    /// JSON of the `metadata::PageMetadata` extracted from the html when it was stored
+    pub page_metadata: Option<String>,
+    /// JSON array of the `media::Media` of the article body
+    pub page_media: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Revision {
+    pub revision_id: i32,
+    pub article_id: i32,
+    pub url: String,
+    pub headline: String,
+    pub previous_snapshot_id: i32,
+    pub previous_archived_at: i32,
+    pub snapshot_id: i32,
+    pub archived_at: i32,
+    pub words_added: i32,
+    pub words_removed: i32,
+    pub paragraphs_added: i32,
+    pub paragraphs_removed: i32,
+    /// see `diff::DiffSummary`, none for revisions from before diffs were stored
+    pub similarity: Option<f64>,
+    /// none for revisions from before they were classified
+    pub significance: Option<Significance>,
+    /// changed without a declared update, see `declared`, none for revisions from before
+    /// declarations were compared
+    pub silent_edit: Option<bool>,
+}
+
+impl Revision {
+    /// the stats of its stored diff
+    pub fn summary(&self) -> DiffSummary {
+        DiffSummary {
+            words_added: self.words_added,
+            words_removed: self.words_removed,
+            paragraphs_added: self.paragraphs_added,
+            paragraphs_removed: self.paragraphs_removed,
+            similarity: self.similarity.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, serde::Serialize)]
+pub struct ArticleListing {
+    pub article_id: i32,
+    pub url: String,
+    pub host: String,
+    pub created_at: i32,
+    pub updated_at: i32,
+    pub headline: String,
+    pub snapshots: i32,
+    pub revisions: i32,
+    pub last_changed_at: Option<i32>,
+}
+
+/// all articles unless restricted by a search term, host, whether they changed,
+/// date ranges of their creation and last change, bounds are inclusive,
+/// or a watchlist with its articles and sources
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct ArticleFilter {
+    pub q: Option<String>,
+    pub host: Option<String>,
+    pub has_changes: Option<bool>,
+    pub created_since: Option<i32>,
+    pub created_until: Option<i32>,
+    pub changed_since: Option<i32>,
+    pub changed_until: Option<i32>,
+    pub watchlist: Option<i32>,
+}
+
+/// always descending, ties are broken by descending article_id
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArticleSort {
+    Created,
+    Checked,
+    #[default]
+    Changed,
+    Revisions,
+}
+
+impl ArticleSort {
+    pub fn key(self, article: &ArticleListing) -> i32 {
+        match self {
+            ArticleSort::Created => article.created_at,
+            ArticleSort::Checked => article.updated_at,
+            ArticleSort::Changed => article.last_changed_at.unwrap_or(-1),
+            ArticleSort::Revisions => article.revisions,
+        }
+    }
+
+    pub(crate) fn column(self) -> &'static str {
+        match self {
+            ArticleSort::Created => "created_at",
+            ArticleSort::Checked => "updated_at",
+            ArticleSort::Changed => "COALESCE(last_changed_at, -1)",
+            ArticleSort::Revisions => "revision_count",
+        }
+    }
+}
+
+/// position after the last listed article, formatted as `sort_key:article_id`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArticleCursor {
+    pub key: i32,
+    pub article_id: i32,
+}
+
+impl ArticleCursor {
+    pub fn after(sort: ArticleSort, article: &ArticleListing) -> Self {
+        Self {
+            key: sort.key(article),
+            article_id: article.article_id,
+        }
+    }
+}
+
+impl std::fmt::Display for ArticleCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.key, self.article_id)
+    }
+}
+
+impl std::str::FromStr for ArticleCursor {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> DbResult<Self> {
+        let invalid = || DbError::InvalidInput(format!("cursor {} is not sort_key:article_id", s));
+        let (key, article_id) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            key: key.parse().map_err(|_| invalid())?,
+            article_id: article_id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// sql condition for articles which are on the watchlist `param` or from one of its sources
+pub(crate) fn in_watchlist(articles: &str, param: &str) -> String {
+    format!(
+        r"(
+            {articles}.article_id IN (
+                SELECT article_id FROM watchlist_articles WHERE watchlist_id = {param}
+            )
+            OR {articles}.host IN (
+                SELECT host FROM watchlist_sources WHERE watchlist_id = {param}
+            )
+        )",
+        articles = articles,
+        param = param
+    )
+}
+
+/// the host of an url including a non-default port, empty for unparsable urls
+pub fn url_host(url: &str) -> String {
+    match surf::url::Url::parse(url) {
+        Ok(url) => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => String::new(),
+        },
+        Err(_) => String::new(),
+    }
+}
+
+/// the url if it parses with an http or https scheme, the only ones tracked and linked,
+/// a `javascript:` or `data:` link would run in the pages of the ui
+pub fn http_url(url: &str) -> Result<surf::url::Url, String> {
+    let url = surf::url::Url::parse(url).map_err(|err| err.to_string())?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!("scheme {} is not http or https", scheme)),
+    }
+}
+
+/// applied in order on top of the initial tables, the count is kept in `PRAGMA user_version`
+const MIGRATIONS: &[&str] = &[
+    r"
+    ALTER TABLE articles ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE articles ADD COLUMN host TEXT NOT NULL DEFAULT '';
+    ALTER TABLE articles ADD COLUMN last_changed_at INTEGER;
+    ALTER TABLE articles ADD COLUMN revision_count INTEGER NOT NULL DEFAULT 0;
+    UPDATE articles SET
+        created_at = COALESCE((
+            SELECT MIN(archived_at) FROM snapshots s WHERE s.article_id = articles.article_id
+        ), updated_at),
+        host = CASE WHEN instr(url, '://') > 0 THEN substr(
+            url,
+            instr(url, '://') + 3,
+            CASE WHEN instr(substr(url, instr(url, '://') + 3), '/') > 0
+            THEN instr(substr(url, instr(url, '://') + 3), '/') - 1
+            ELSE length(url) END
+        ) ELSE '' END,
+        last_changed_at = (
+            SELECT MAX(archived_at) FROM revisions r WHERE r.article_id = articles.article_id
+        ),
+        revision_count = (
+            SELECT COUNT(*) FROM revisions r WHERE r.article_id = articles.article_id
+        );
+    CREATE INDEX articles_host ON articles (host);
+    CREATE INDEX articles_created_at ON articles (created_at, article_id);
+    CREATE INDEX articles_updated_at ON articles (updated_at, article_id);
+    CREATE INDEX articles_last_changed_at ON articles (last_changed_at, article_id);
+    CREATE INDEX articles_revision_count ON articles (revision_count, article_id);
+    CREATE INDEX snapshots_article_id ON snapshots (article_id, archived_at);
+",
+    r"
+    CREATE TABLE tokens (
+        token_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        role TEXT NOT NULL,
+        token_hash TEXT UNIQUE NOT NULL,
+        created_at INTEGER NOT NULL,
+        revoked_at INTEGER
+    );
+",
+    r"
+    CREATE TABLE users (
+        user_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        name TEXT UNIQUE NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    ALTER TABLE tokens ADD COLUMN user_id INTEGER;
+    CREATE TABLE watchlists (
+        watchlist_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        user_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        UNIQUE (user_id, name)
+    );
+    CREATE TABLE watchlist_articles (
+        watchlist_id INTEGER NOT NULL,
+        article_id INTEGER NOT NULL,
+        added_at INTEGER NOT NULL,
+        PRIMARY KEY (watchlist_id, article_id)
+    );
+    CREATE INDEX watchlist_articles_article_id ON watchlist_articles (article_id);
+    CREATE TABLE watchlist_sources (
+        watchlist_id INTEGER NOT NULL,
+        host TEXT NOT NULL,
+        added_at INTEGER NOT NULL,
+        PRIMARY KEY (watchlist_id, host)
+    );
+",
+    r"
+    ALTER TABLE snapshots ADD COLUMN source TEXT NOT NULL DEFAULT 'live';
+    ALTER TABLE snapshots ADD COLUMN source_url TEXT;
+",
+    r"
+    ALTER TABLE snapshots ADD COLUMN body_sha256 TEXT;
+    ALTER TABLE snapshots ADD COLUMN request_headers TEXT;
+    ALTER TABLE snapshots ADD COLUMN response_headers TEXT;
+    ALTER TABLE snapshots ADD COLUMN chain_sha256 TEXT;
+",
+    r"
+    ALTER TABLE articles ADD COLUMN lease_token TEXT;
+    ALTER TABLE articles ADD COLUMN lease_expires_at INTEGER;
+",
+    r"
+    ALTER TABLE revisions ADD COLUMN paragraphs_added INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE revisions ADD COLUMN paragraphs_removed INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE revisions ADD COLUMN similarity REAL;
+    CREATE TABLE revision_diffs (
+        revision_id INTEGER NOT NULL PRIMARY KEY,
+        runs TEXT NOT NULL
+    );
+",
+    // words diffs become paragraphs diffs, recomputed when a revision's diff is asked for
+    r"
+    DELETE FROM revision_diffs;
+    ALTER TABLE revision_diffs RENAME COLUMN runs TO paragraphs;
+",
+    r"
+    ALTER TABLE revisions ADD COLUMN significance INTEGER;
+    ALTER TABLE webhooks ADD COLUMN min_significance INTEGER;
+",
+    r"
+    ALTER TABLE revisions ADD COLUMN silent_edit BOOLEAN;
+",
+    r"
+    ALTER TABLE snapshots ADD COLUMN page_metadata TEXT;
+",
+    r"
+    ALTER TABLE snapshots ADD COLUMN page_media TEXT;
+",
+];
+
+/// all revisions unless restricted to an article url, a host, a watchlist,
+/// a minimum significance or silent edits, revisions from before classification
+/// or the comparison of declarations match neither
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct RevisionFilter {
+    pub url: Option<String>,
+    pub host: Option<String>,
+    pub watchlist: Option<i32>,
+    pub min_significance: Option<Significance>,
+    pub silent_edit: Option<bool>,
+}
+
+/// where a database url points to, by its scheme
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// `sqlite:`
+    Sqlite,
+    /// `postgres:` or `postgresql:`, see `postgres`
+    Postgres,
+    /// `sled:` and the path of the directory, see `sled_store`
+    Sled,
+}
+
+impl Backend {
+    pub fn from_url(url: &str) -> DbResult<Self> {
+        match url.split(':').next() {
+            Some("sqlite") => Ok(Backend::Sqlite),
+            Some("postgres") | Some("postgresql") => Ok(Backend::Postgres),
+            Some("sled") => Ok(Backend::Sled),
+            _ => Err(DbError::InvalidInput(format!(
+                "database url {} is neither sqlite:, postgres: nor sled:",
+                url
+            ))),
+        }
+    }
+}
+
+/// articles, the webhooks their revisions are delivered to, tokens and watchlists,
+/// on any backend
+pub trait Store:
+    ProvideArticles + ProvideWebhooks + ProvideTokens + ProvideWatchlists + Send
+{
+}
+
+impl<T> Store for T where
+    T: ProvideArticles + ProvideWebhooks + ProvideTokens + ProvideWatchlists + Send
+{
+}
+
+/// a migration with the bump of the schema version in one transaction, rolled back when a
+/// statement fails so the connection is not left inside it, a `sqlx::Transaction` would
+/// need a connection of its own
+pub(crate) async fn migrate<C: Executor>(conn: &mut C, migration: &str) -> DbResult<()> {
+    conn.execute("BEGIN").await?;
+    match conn.execute(migration).await {
+        Ok(_) => {
+            conn.execute("COMMIT").await?;
+            Ok(())
+        }
+        Err(err) => {
+            conn.execute("ROLLBACK").await?;
+            Err(err.into())
+        }
+    }
+}
+
+/// the given articles, or all of them in order of creation
+pub async fn get_articles_or_all<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    article_ids: Option<&[i32]>,
+) -> DbResult<Vec<Article>> {
+    let mut articles = vec![];
+    match article_ids {
+        Some(ids) => {
+            for id in ids {
+                articles.push(provider.get_article_by_id(*id).await?);
+            }
+        }
+        None => loop {
+            let page = provider.get_articles(articles.len() as i32, 500).await?;
+            if page.is_empty() {
+                break;
+            }
+            articles.extend(page);
+        },
+    }
+    Ok(articles)
 }
 
 #[automock]
 #[async_trait]
 pub trait ProvideArticles {
-    async fn ensure_created_tables(&mut self) -> Result<()>;
-    async fn get_outdated_articles(&mut self, limit: i32) -> Result<Vec<Article>>;
-    async fn get_articles(&mut self, offset: i32, limit: i32) -> Result<Vec<Article>>;
-    async fn insert_article(&mut self, url: &str) -> Result<Article>;
-    async fn update_article(&mut self, url: &str, updated_at: i32) -> Result<()>;
-    async fn get_article(&mut self, url: &str) -> Result<Article>;
+    async fn ensure_created_tables(&mut self) -> DbResult<()>;
+    async fn get_outdated_articles(&mut self, limit: i32) -> DbResult<Vec<Article>>;
+    /// lease the most outdated article which isn't leased or whose lease expired,
+    /// atomically, so no two workers claim the same article at once
+    async fn claim_article(
+        &mut self,
+        token: &str,
+        now: i32,
+        expires_at: i32,
+    ) -> DbResult<Option<Article>>;
+    /// the leased article was fetched at `updated_at`, false if the lease was lost meanwhile
+    async fn release_article(
+        &mut self,
+        article_id: i32,
+        token: &str,
+        updated_at: i32,
+    ) -> DbResult<bool>;
+    async fn get_articles(&mut self, offset: i32, limit: i32) -> DbResult<Vec<Article>>;
+    async fn insert_article(&mut self, url: &str) -> DbResult<Article>;
+    /// with whether each url was new, repeated urls are not new,
+    /// all or nothing when called inside a transaction
+    async fn insert_articles(&mut self, urls: &[String]) -> DbResult<Vec<(Article, bool)>>;
+    async fn update_article(&mut self, url: &str, updated_at: i32) -> DbResult<()>;
+    async fn get_article(&mut self, url: &str) -> DbResult<Article>;
+    async fn get_article_by_id(&mut self, article_id: i32) -> DbResult<Article>;
+    /// also deletes the snapshots and revisions of the article and removes it from watchlists
+    async fn delete_article(&mut self, article_id: i32) -> DbResult<()>;
+    async fn list_articles(
+        &mut self,
+        filter: &ArticleFilter,
+        sort: ArticleSort,
+        cursor: Option<ArticleCursor>,
+        limit: i32,
+    ) -> DbResult<Vec<ArticleListing>>;
 
     async fn get_snaphot_metadatas_from_article(
         &mut self,
         article_id: i32,
-    ) -> Result<Vec<SnapshotMetadata>>;
-    async fn get_youngest_snaphot(&mut self, article: &Article) -> Result<Option<Snapshot>>;
-    async fn get_snaphot(&mut self, id: i32) -> Result<Snapshot>;
+    ) -> DbResult<Vec<SnapshotMetadata>>;
+    async fn get_youngest_snaphot(&mut self, article: &Article) -> DbResult<Option<Snapshot>>;
+    async fn get_snaphot(&mut self, id: i32) -> DbResult<Snapshot>;
+    /// the `page_media` of the snapshot without its html, see `media::of_stored_snapshot`
+    async fn get_snapshot_media(&mut self, snapshot_id: i32) -> DbResult<Option<String>>;
+    /// like `get_snapshot_media` for the youngest snapshot, none for an article without any
+    async fn get_youngest_snapshot_media(&mut self, article_id: i32) -> DbResult<Option<String>>;
+    /// fetched live, without headers
     async fn insert_snapshot(
         &mut self,
         article: &Article,
         archived_at: i32,
         html: &str,
-    ) -> Result<()>;
+    ) -> DbResult<SnapshotMetadata>;
+    /// also stores the sha256 of the body and links it to the hash chain of the article,
+    /// inside a transaction no other snapshot of the article joins the chain meanwhile
+    async fn insert_snapshot_with_provenance(
+        &mut self,
+        article: &Article,
+        archived_at: i32,
+        html: &str,
+        provenance: &Provenance,
+    ) -> DbResult<SnapshotMetadata>;
+
+    async fn insert_revision(
+        &mut self,
+        previous: &SnapshotMetadata,
+        current: &SnapshotMetadata,
+        headline: &str,
+        diff: &TextDiff,
+        significance: Significance,
+        silent_edit: bool,
+    ) -> DbResult<Revision>;
+    async fn get_revision(&mut self, revision_id: i32) -> DbResult<Revision>;
+    /// the paragraphs diff stored with the revision, none for revisions from before diffs were stored
+    async fn get_revision_diff(&mut self, revision_id: i32) -> DbResult<Option<Vec<Paragraph>>>;
+    /// store the diff of a revision from before diffs were stored, with its stats
+    async fn update_revision_diff(
+        &mut self,
+        revision_id: i32,
+        diff: &TextDiff,
+        significance: Significance,
+    ) -> DbResult<()>;
+    /// also deletes its diff and recounts the changes of its article
+    async fn delete_revision(&mut self, revision_id: i32) -> DbResult<()>;
+    /// newest first, only revisions with a revision_id below the cursor
+    async fn get_revisions(
+        &mut self,
+        filter: &RevisionFilter,
+        cursor: Option<i32>,
+        limit: i32,
+    ) -> DbResult<Vec<Revision>>;
 }
 
 #[async_trait]
 impl ProvideArticles for sqlx::SqliteConnection {
-    async fn ensure_created_tables(&mut self) -> Result<()> {
+    async fn ensure_created_tables(&mut self) -> DbResult<()> {
         sqlx::query(
             r"
             CREATE TABLE IF NOT EXISTS articles (
@@ -65,17 +666,56 @@ impl ProvideArticles for sqlx::SqliteConnection {
                 archived_at INTEGER NOT NULL,
                 html STRING NOT NULL
             );
+            CREATE TABLE IF NOT EXISTS revisions (
+                revision_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                article_id INTEGER NOT NULL,
+                headline TEXT NOT NULL,
+                previous_snapshot_id INTEGER NOT NULL,
+                previous_archived_at INTEGER NOT NULL,
+                snapshot_id INTEGER NOT NULL,
+                archived_at INTEGER NOT NULL,
+                words_added INTEGER NOT NULL,
+                words_removed INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS revisions_article_id ON revisions (article_id);
+            CREATE TABLE IF NOT EXISTS webhooks (
+                webhook_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                url TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                host TEXT,
+                keyword TEXT,
+                min_words_changed INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                delivery_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                webhook_id INTEGER NOT NULL,
+                payload TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at INTEGER,
+                delivered_at INTEGER,
+                last_error TEXT
+            );
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_next_attempt_at
+            ON webhook_deliveries (next_attempt_at);
             ",
         )
-        .execute(self)
-        .await
-        .void()
+        .execute(&mut *self)
+        .await?;
+
+        let (version,): (i32,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&mut *self)
+            .await?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let migration = format!("{} PRAGMA user_version = {};", migration, index + 1);
+            migrate(self, &migration).await?;
+        }
+        Ok(())
     }
 
-    async fn get_outdated_articles(&mut self, limit: i32) -> Result<Vec<Article>> {
+    async fn get_outdated_articles(&mut self, limit: i32) -> DbResult<Vec<Article>> {
         sqlx::query_as::<_, Article>(
             r"
-            SELECT url, article_id, updated_at
+            SELECT url, article_id, host, created_at, updated_at
             FROM articles
             ORDER BY updated_at ASC
             LIMIT $1",
@@ -83,41 +723,108 @@ impl ProvideArticles for sqlx::SqliteConnection {
         .bind(limit)
         .fetch_all(self)
         .await
-        .anyhow()
+        .db()
+    }
+
+    async fn claim_article(
+        &mut self,
+        token: &str,
+        now: i32,
+        expires_at: i32,
+    ) -> DbResult<Option<Article>> {
+        sqlx::query_as(
+            r"
+            UPDATE articles SET lease_token = $1, lease_expires_at = $2
+            WHERE article_id = (
+                SELECT article_id FROM articles
+                WHERE lease_expires_at IS NULL OR lease_expires_at <= $3
+                ORDER BY updated_at ASC, article_id ASC
+                LIMIT 1
+            );
+            SELECT * FROM articles WHERE lease_token = $4 ;",
+        )
+        .bind(token)
+        .bind(expires_at)
+        .bind(now)
+        .bind(token)
+        .fetch_optional(self)
+        .await
+        .db()
+    }
+
+    async fn release_article(
+        &mut self,
+        article_id: i32,
+        token: &str,
+        updated_at: i32,
+    ) -> DbResult<bool> {
+        let released = sqlx::query(
+            r"
+            UPDATE articles SET updated_at = $1, lease_token = NULL, lease_expires_at = NULL
+            WHERE article_id = $2 AND lease_token = $3",
+        )
+        .bind(updated_at)
+        .bind(article_id)
+        .bind(token)
+        .execute(self)
+        .await?;
+        Ok(released > 0)
     }
 
-    async fn get_articles(&mut self, offset: i32, limit: i32) -> Result<Vec<Article>> {
-        // TODO order by created_at
+    async fn get_articles(&mut self, offset: i32, limit: i32) -> DbResult<Vec<Article>> {
         sqlx::query_as::<_, Article>(
             r"
-            SELECT url, article_id, updated_at
+            SELECT url, article_id, host, created_at, updated_at
             FROM articles
-            ORDER BY updated_at ASC
+            ORDER BY created_at ASC, article_id ASC
             LIMIT $1 OFFSET $2",
         )
         .bind(limit)
         .bind(offset)
         .fetch_all(self)
         .await
-        .anyhow()
+        .db()
     }
 
-    async fn insert_article(&mut self, url: &str) -> Result<Article> {
+    async fn insert_article(&mut self, url: &str) -> DbResult<Article> {
         sqlx::query_as(
             r"
-            INSERT OR IGNORE INTO articles ( url, updated_at )
-            VALUES ( $1, $2 );
-            SELECT * FROM articles WHERE url = $3 ;",
+            INSERT OR IGNORE INTO articles ( url, host, created_at, updated_at )
+            VALUES ( $1, $2, $3, $4 );
+            SELECT * FROM articles WHERE url = $5 ;",
         )
         .bind(url)
+        .bind(url_host(url))
+        .bind(crate::scraper::timestamp())
         .bind(0)
         .bind(url)
         .fetch_one(self)
         .await
-        .anyhow()
+        .db()
+    }
+
+    async fn insert_articles(&mut self, urls: &[String]) -> DbResult<Vec<(Article, bool)>> {
+        let created_at = crate::scraper::timestamp();
+        let mut articles = vec![];
+        for url in urls {
+            let added = sqlx::query(
+                r"
+                INSERT OR IGNORE INTO articles ( url, host, created_at, updated_at )
+                VALUES ( $1, $2, $3, $4 )",
+            )
+            .bind(url)
+            .bind(url_host(url))
+            .bind(created_at)
+            .bind(0)
+            .execute(&mut *self)
+            .await?
+                > 0;
+            articles.push((self.get_article(url).await?, added));
+        }
+        Ok(articles)
     }
 
-    async fn update_article(&mut self, url: &str, updated_at: i32) -> Result<()> {
+    async fn update_article(&mut self, url: &str, updated_at: i32) -> DbResult<()> {
         sqlx::query(
             r"
             UPDATE articles SET updated_at=$1 WHERE url=$2",
@@ -129,7 +836,7 @@ impl ProvideArticles for sqlx::SqliteConnection {
         .void()
     }
 
-    async fn get_article(&mut self, url: &str) -> Result<Article> {
+    async fn get_article(&mut self, url: &str) -> DbResult<Article> {
         sqlx::query_as(
             r"
             SELECT * FROM articles WHERE url = $1 LIMIT 1",
@@ -137,13 +844,100 @@ impl ProvideArticles for sqlx::SqliteConnection {
         .bind(url)
         .fetch_one(self)
         .await
-        .anyhow()
+        .or_not_found("article")
+    }
+
+    async fn get_article_by_id(&mut self, article_id: i32) -> DbResult<Article> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM articles WHERE article_id = $1 LIMIT 1",
+        )
+        .bind(article_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("article")
+    }
+
+    async fn delete_article(&mut self, article_id: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            DELETE FROM revision_diffs WHERE revision_id IN (
+                SELECT revision_id FROM revisions WHERE article_id = $1
+            );
+            DELETE FROM revisions WHERE article_id = $2;
+            DELETE FROM snapshots WHERE article_id = $3;
+            DELETE FROM watchlist_articles WHERE article_id = $4;
+            DELETE FROM articles WHERE article_id = $5",
+        )
+        .bind(article_id)
+        .bind(article_id)
+        .bind(article_id)
+        .bind(article_id)
+        .bind(article_id)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn list_articles(
+        &mut self,
+        filter: &ArticleFilter,
+        sort: ArticleSort,
+        cursor: Option<ArticleCursor>,
+        limit: i32,
+    ) -> DbResult<Vec<ArticleListing>> {
+        let query = format!(
+            r"
+            SELECT article_id, url, host, created_at, updated_at,
+                COALESCE((
+                    SELECT headline FROM revisions r WHERE r.article_id = articles.article_id
+                    ORDER BY revision_id DESC LIMIT 1
+                ), '') AS headline,
+                (
+                    SELECT COUNT(*) FROM snapshots s WHERE s.article_id = articles.article_id
+                ) AS snapshots,
+                revision_count AS revisions,
+                last_changed_at
+            FROM articles
+            WHERE ($1 IS NULL OR url LIKE '%' || $1 || '%' OR EXISTS (
+                SELECT 1 FROM revisions r
+                WHERE r.article_id = articles.article_id AND r.headline LIKE '%' || $1 || '%'
+            ))
+            AND ($2 IS NULL OR host = $2)
+            AND ($3 IS NULL OR $3 = (revision_count > 0))
+            AND ($4 IS NULL OR created_at >= $4)
+            AND ($5 IS NULL OR created_at <= $5)
+            AND ($6 IS NULL OR last_changed_at >= $6)
+            AND ($7 IS NULL OR last_changed_at <= $7)
+            AND ($8 IS NULL OR {key} < $8 OR ({key} = $8 AND article_id < $9))
+            AND ($10 IS NULL OR {in_watchlist})
+            ORDER BY {key} DESC, article_id DESC
+            LIMIT $11",
+            key = sort.column(),
+            in_watchlist = in_watchlist("articles", "$10"),
+        );
+
+        sqlx::query_as(&query)
+            .bind(filter.q.clone())
+            .bind(filter.host.clone())
+            .bind(filter.has_changes)
+            .bind(filter.created_since)
+            .bind(filter.created_until)
+            .bind(filter.changed_since)
+            .bind(filter.changed_until)
+            .bind(cursor.map(|c| c.key))
+            .bind(cursor.map(|c| c.article_id))
+            .bind(filter.watchlist)
+            .bind(limit)
+            .fetch_all(self)
+            .await
+            .db()
     }
 
     async fn get_snaphot_metadatas_from_article(
         &mut self,
         article_id: i32,
-    ) -> Result<Vec<SnapshotMetadata>> {
+    ) -> DbResult<Vec<SnapshotMetadata>> {
         sqlx::query_as(
             r"
             SELECT * FROM snapshots WHERE article_id = $1",
@@ -151,10 +945,10 @@ impl ProvideArticles for sqlx::SqliteConnection {
         .bind(article_id)
         .fetch_all(self)
         .await
-        .anyhow()
+        .db()
     }
 
-    async fn get_youngest_snaphot(&mut self, article: &Article) -> Result<Option<Snapshot>> {
+    async fn get_youngest_snaphot(&mut self, article: &Article) -> DbResult<Option<Snapshot>> {
         sqlx::query_as(
             r"
             SELECT * FROM snapshots WHERE article_id = $1 ORDER BY archived_at DESC LIMIT 1",
@@ -162,10 +956,10 @@ impl ProvideArticles for sqlx::SqliteConnection {
         .bind(article.article_id)
         .fetch_optional(self)
         .await
-        .anyhow()
+        .db()
     }
 
-    async fn get_snaphot(&mut self, id: i32) -> Result<Snapshot> {
+    async fn get_snaphot(&mut self, id: i32) -> DbResult<Snapshot> {
         sqlx::query_as(
             r"
             SELECT * FROM snapshots WHERE snapshot_id = $1 LIMIT 1",
@@ -173,7 +967,30 @@ impl ProvideArticles for sqlx::SqliteConnection {
         .bind(id)
         .fetch_one(self)
         .await
-        .anyhow()
+        .or_not_found("snapshot")
+    }
+
+    async fn get_snapshot_media(&mut self, snapshot_id: i32) -> DbResult<Option<String>> {
+        let (media,): (Option<String>,) =
+            sqlx::query_as("SELECT page_media FROM snapshots WHERE snapshot_id = $1")
+                .bind(snapshot_id)
+                .fetch_one(self)
+                .await
+                .or_not_found("snapshot")?;
+        Ok(media)
+    }
+
+    async fn get_youngest_snapshot_media(&mut self, article_id: i32) -> DbResult<Option<String>> {
+        let youngest: Option<(Option<String>,)> = sqlx::query_as(
+            r"
+            SELECT page_media FROM snapshots WHERE article_id = $1
+            ORDER BY archived_at DESC LIMIT 1",
+        )
+        .bind(article_id)
+        .fetch_optional(self)
+        .await
+        .db()?;
+        Ok(youngest.and_then(|(media,)| media))
     }
 
     async fn insert_snapshot(
@@ -181,36 +998,250 @@ impl ProvideArticles for sqlx::SqliteConnection {
         article: &Article,
         archived_at: i32,
         html: &str,
-    ) -> Result<()> {
-        sqlx::query(
+    ) -> DbResult<SnapshotMetadata> {
+        self.insert_snapshot_with_provenance(article, archived_at, html, &Provenance::default())
+            .await
+    }
+
+    async fn insert_snapshot_with_provenance(
+        &mut self,
+        article: &Article,
+        archived_at: i32,
+        html: &str,
+        provenance: &Provenance,
+    ) -> DbResult<SnapshotMetadata> {
+        let previous: Option<(Option<String>,)> = sqlx::query_as(
             r"
-            INSERT INTO snapshots (article_id, archived_at, html)
-            VALUES ( $1, $2, $3 )",
+            SELECT chain_sha256 FROM snapshots
+            WHERE article_id = $1 ORDER BY snapshot_id DESC LIMIT 1",
+        )
+        .bind(article.article_id)
+        .fetch_optional(&mut *self)
+        .await?;
+        let previous = previous.and_then(|(chain,)| chain);
+
+        let body_sha256 = provenance.body_sha256(html);
+        let chain_sha256 = provenance::next_chain_sha256(
+            previous.as_deref(),
+            article.article_id,
+            archived_at,
+            provenance,
+            &body_sha256,
+        );
+
+        sqlx::query_as(
+            r"
+            INSERT INTO snapshots (
+                article_id, archived_at, html, source, source_url,
+                body_sha256, request_headers, response_headers, chain_sha256, page_metadata,
+                page_media
+            )
+            VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11 );
+            SELECT article_id, snapshot_id, archived_at, source
+            FROM snapshots WHERE snapshot_id = last_insert_rowid() ;",
         )
         .bind(article.article_id)
         .bind(archived_at)
         .bind(html)
+        .bind(provenance.source)
+        .bind(provenance.source_url.clone())
+        .bind(body_sha256)
+        .bind(provenance::headers_json(&provenance.request_headers))
+        .bind(provenance::headers_json(&provenance.response_headers))
+        .bind(chain_sha256)
+        .bind(metadata::extract(html).to_json())
+        .bind(media::to_json(&provenance.media(html)))
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn insert_revision(
+        &mut self,
+        previous: &SnapshotMetadata,
+        current: &SnapshotMetadata,
+        headline: &str,
+        diff: &TextDiff,
+        significance: Significance,
+        silent_edit: bool,
+    ) -> DbResult<Revision> {
+        let summary = &diff.summary;
+        sqlx::query_as(
+            r"
+            INSERT INTO revisions (
+                article_id, headline,
+                previous_snapshot_id, previous_archived_at,
+                snapshot_id, archived_at,
+                words_added, words_removed,
+                paragraphs_added, paragraphs_removed, similarity, significance, silent_edit
+            )
+            VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13 );
+            INSERT INTO revision_diffs ( revision_id, paragraphs ) VALUES ( last_insert_rowid(), $14 );
+            UPDATE articles
+            SET revision_count = revision_count + 1,
+                last_changed_at = max(COALESCE(last_changed_at, $15), $15)
+            WHERE article_id = $16;
+            SELECT revisions.*, articles.url
+            FROM revisions JOIN articles USING (article_id)
+            WHERE revision_id = last_insert_rowid() ;",
+        )
+        .bind(current.article_id)
+        .bind(headline)
+        .bind(previous.snapshot_id)
+        .bind(previous.archived_at)
+        .bind(current.snapshot_id)
+        .bind(current.archived_at)
+        .bind(summary.words_added)
+        .bind(summary.words_removed)
+        .bind(summary.paragraphs_added)
+        .bind(summary.paragraphs_removed)
+        .bind(summary.similarity)
+        .bind(significance)
+        .bind(silent_edit)
+        .bind(encode_paragraphs(&diff.paragraphs))
+        .bind(current.archived_at)
+        .bind(current.article_id)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_revision(&mut self, revision_id: i32) -> DbResult<Revision> {
+        sqlx::query_as(
+            r"
+            SELECT revisions.*, articles.url
+            FROM revisions JOIN articles USING (article_id)
+            WHERE revision_id = $1",
+        )
+        .bind(revision_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("revision")
+    }
+
+    async fn get_revision_diff(&mut self, revision_id: i32) -> DbResult<Option<Vec<Paragraph>>> {
+        let (_, paragraphs): (i32, Option<String>) = sqlx::query_as(
+            r"
+            SELECT revision_id, paragraphs
+            FROM revisions LEFT JOIN revision_diffs USING (revision_id)
+            WHERE revision_id = $1",
+        )
+        .bind(revision_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("revision")?;
+        paragraphs.as_deref().map(decode_paragraphs).transpose()
+    }
+
+    async fn update_revision_diff(
+        &mut self,
+        revision_id: i32,
+        diff: &TextDiff,
+        significance: Significance,
+    ) -> DbResult<()> {
+        let summary = &diff.summary;
+        sqlx::query(
+            r"
+            UPDATE revisions
+            SET words_added = $1, words_removed = $2,
+                paragraphs_added = $3, paragraphs_removed = $4, similarity = $5,
+                significance = $6
+            WHERE revision_id = $7;
+            INSERT OR REPLACE INTO revision_diffs ( revision_id, paragraphs )
+            SELECT revision_id, $8 FROM revisions WHERE revision_id = $9",
+        )
+        .bind(summary.words_added)
+        .bind(summary.words_removed)
+        .bind(summary.paragraphs_added)
+        .bind(summary.paragraphs_removed)
+        .bind(summary.similarity)
+        .bind(significance)
+        .bind(revision_id)
+        .bind(encode_paragraphs(&diff.paragraphs))
+        .bind(revision_id)
         .execute(self)
         .await
         .void()
     }
+
+    async fn delete_revision(&mut self, revision_id: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            UPDATE articles
+            SET revision_count = revision_count - 1,
+                last_changed_at = (
+                    SELECT MAX(archived_at) FROM revisions r
+                    WHERE r.article_id = articles.article_id AND r.revision_id <> $1
+                )
+            WHERE article_id = (SELECT article_id FROM revisions WHERE revision_id = $2);
+            DELETE FROM revision_diffs WHERE revision_id = $3;
+            DELETE FROM revisions WHERE revision_id = $4",
+        )
+        .bind(revision_id)
+        .bind(revision_id)
+        .bind(revision_id)
+        .bind(revision_id)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_revisions(
+        &mut self,
+        filter: &RevisionFilter,
+        cursor: Option<i32>,
+        limit: i32,
+    ) -> DbResult<Vec<Revision>> {
+        let query = format!(
+            r"
+            SELECT revisions.*, articles.url
+            FROM revisions JOIN articles USING (article_id)
+            WHERE revision_id < $1
+            AND ($2 IS NULL OR articles.url = $2)
+            AND ($3 IS NULL OR articles.host = $3)
+            AND ($4 IS NULL OR {in_watchlist})
+            AND ($5 IS NULL OR revisions.significance >= $5)
+            AND ($6 IS NULL OR revisions.silent_edit = $6)
+            ORDER BY revision_id DESC
+            LIMIT $7",
+            in_watchlist = in_watchlist("articles", "$4"),
+        );
+
+        sqlx::query_as(&query)
+            .bind(cursor.unwrap_or(i32::MAX))
+            .bind(filter.url.clone())
+            .bind(filter.host.clone())
+            .bind(filter.watchlist)
+            .bind(filter.min_significance)
+            .bind(filter.silent_edit)
+            .bind(limit)
+            .fetch_all(self)
+            .await
+            .db()
+    }
 }
 
-trait VoidResult<T> {
-    fn void(self) -> Result<()>;
-    fn anyhow(self) -> Result<T>;
+pub(crate) trait VoidResult<T> {
+    fn void(self) -> DbResult<()>;
+    fn db(self) -> DbResult<T>;
+    /// names the missing thing instead of sqlx's generic RowNotFound
+    fn or_not_found(self, what: &str) -> DbResult<T>;
 }
 
-impl<T, E> VoidResult<T> for std::result::Result<T, E>
-where
-    E: Send + Sync + std::error::Error + 'static,
-{
-    fn void(self) -> Result<()> {
+impl<T> VoidResult<T> for std::result::Result<T, sqlx::Error> {
+    fn void(self) -> DbResult<()> {
         self?;
         Ok(())
     }
 
-    fn anyhow(self) -> Result<T> {
-        self.map_err(&Error::new)
+    fn db(self) -> DbResult<T> {
+        self.map_err(DbError::from)
+    }
+
+    fn or_not_found(self, what: &str) -> DbResult<T> {
+        match self {
+            Err(sqlx::Error::RowNotFound) => Err(DbError::NotFound(format!("{} not found", what))),
+            result => result.db(),
+        }
     }
 }
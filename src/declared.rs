@@ -0,0 +1,153 @@
+//! what publishers declare about changes to their articles
+//!
+//! a modification time in meta tags or JSON-LD, correction boxes and update notes
+//! by the rule of the site or else a generic heuristic, extracted from the html of a
+//! snapshot, a revision without any new declaration is a silent edit
+
+use crate::db;
+use crate::extract::normalize;
+use crate::metadata;
+
+/// boxes with corrections or update notes, by the class names sites give them
+const NOTE_SELECTORS: &[&str] = &[
+    r#"[class*="correction"]"#,
+    r#"[class*="korrektur"]"#,
+    r#"[class*="update-note"]"#,
+    r#"[class*="editors-note"]"#,
+];
+
+/// paragraphs which start with one of these are notes too, in lower case
+const NOTE_PREFIXES: &[&str] = &[
+    "korrektur",
+    "correction",
+    "update",
+    "aktualisierung",
+    "aktualisiert",
+    "anmerkung der redaktion",
+    "hinweis der redaktion",
+    "editor's note",
+];
+
+/// where the notes of a site are
+struct SiteRule {
+    /// the host or one of its subdomains
+    host: &'static str,
+    /// the article, notes anywhere else belong to teasers of other articles
+    scope: Option<&'static str>,
+    /// boxes which are notes as a whole
+    boxes: &'static [&'static str],
+    /// whether paragraphs which start with one of `NOTE_PREFIXES` are notes
+    prefixed: bool,
+}
+
+/// for the sites the scraper knows, see `extract::get_article_fulltext`
+const SITE_RULES: &[SiteRule] = &[
+    SiteRule {
+        host: "tagesschau.de",
+        scope: Some("div.storywrapper"),
+        boxes: NOTE_SELECTORS,
+        prefixed: true,
+    },
+    // commit messages like "Update the readme" are the article, never notes
+    SiteRule {
+        host: "whatthecommit.com",
+        scope: Some("div#content"),
+        boxes: &[],
+        prefixed: false,
+    },
+];
+
+/// a heuristic for any other site, the boxes and prefixed paragraphs of the whole page
+const GENERIC_RULE: SiteRule = SiteRule {
+    host: "",
+    scope: None,
+    boxes: NOTE_SELECTORS,
+    prefixed: true,
+};
+
+fn site_rule(url: &str) -> &'static SiteRule {
+    let host = db::url_host(url);
+    SITE_RULES
+        .iter()
+        .find(|rule| {
+            host == rule.host
+                || host
+                    .strip_suffix(rule.host)
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+        .unwrap_or(&GENERIC_RULE)
+}
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Declared {
+    /// as published, from JSON-LD `dateModified` or else meta tags
+    pub modified_time: Option<String>,
+    /// texts of correction boxes and update notes, in document order
+    pub notes: Vec<String>,
+}
+
+impl Declared {
+    /// a new modification time or a note which `previous` did not have
+    pub fn declares_update_since(&self, previous: &Declared) -> bool {
+        (self.modified_time.is_some() && self.modified_time != previous.modified_time)
+            || self.notes.iter().any(|note| !previous.notes.contains(note))
+    }
+}
+
+/// of the page at `url`, the modification time is the one of `metadata::extract`
+pub fn extract(url: &str, html: &str) -> Declared {
+    let fragment = scraper::Html::parse_fragment(html);
+    Declared {
+        modified_time: metadata::of_fragment(&fragment).modified_time,
+        notes: notes(site_rule(url), &fragment),
+    }
+}
+
+fn notes(rule: &SiteRule, fragment: &scraper::Html) -> Vec<String> {
+    let text =
+        |element: scraper::ElementRef| normalize(&element.text().collect::<Vec<_>>().join(" "));
+    let mut notes: Vec<String> = vec![];
+    let mut push = |note: String| {
+        // a box may also contain a paragraph which starts like a note
+        if !note.is_empty() && !notes.iter().any(|n| n.contains(&note)) {
+            notes.push(note);
+        }
+    };
+
+    let scopes = match rule.scope {
+        Some(scope) => {
+            let scope = scraper::Selector::parse(scope).expect("scope selector");
+            fragment.select(&scope).collect()
+        }
+        None => vec![fragment.root_element()],
+    };
+    for selector in rule.boxes {
+        let selector = scraper::Selector::parse(selector).expect("note selector");
+        for scope in &scopes {
+            scope.select(&selector).map(text).for_each(&mut push);
+        }
+    }
+    if rule.prefixed {
+        let paragraphs = scraper::Selector::parse("p").expect("paragraph selector");
+        for scope in &scopes {
+            scope
+                .select(&paragraphs)
+                .map(text)
+                .filter(|paragraph| is_note(paragraph))
+                .for_each(&mut push);
+        }
+    }
+    notes
+}
+
+/// starts with a prefix and a word boundary, "Update 14:32" but not "Updated figures"
+fn is_note(paragraph: &str) -> bool {
+    let lower = paragraph.to_lowercase();
+    NOTE_PREFIXES.iter().any(|prefix| {
+        lower.starts_with(prefix)
+            && !lower[prefix.len()..]
+                .chars()
+                .next()
+                .is_some_and(char::is_alphanumeric)
+    })
+}
@@ -0,0 +1,471 @@
+use crate::markup::escape;
+use prettydiff::basic::DiffOp;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DiffSummary {
+    pub words_added: i32,
+    pub words_removed: i32,
+    /// lines of the fulltexts, an edited paragraph is removed and added, moved ones only if edited
+    pub paragraphs_added: i32,
+    pub paragraphs_removed: i32,
+    /// words in both texts relative to the words of each, 1 for equal texts
+    pub similarity: f64,
+}
+
+impl DiffSummary {
+    pub fn is_empty(&self) -> bool {
+        self.words_added == 0 && self.words_removed == 0
+    }
+}
+
+/// a passage of a words diff, serialized as `{"=": "text"}`, `{"-": …}` or `{"+": …}`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Run {
+    #[serde(rename = "=")]
+    Equal(String),
+    #[serde(rename = "-")]
+    Remove(String),
+    #[serde(rename = "+")]
+    Insert(String),
+}
+
+/// a paragraph of a diff, in the order of the new fulltext with removed ones where they were,
+/// serialized as `{"equal": "text"}`, `{"edit": [runs]}`, `{"move": {"from": 0, …}}` and so on
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Paragraph {
+    Equal(String),
+    Insert(String),
+    Remove(String),
+    /// changed in place, the words diff of the old and the new paragraph
+    Edit(Vec<Run>),
+    /// paragraph `from` of the old fulltext is paragraph `to` of the new one, maybe edited too
+    Move {
+        from: usize,
+        to: usize,
+        runs: Vec<Run>,
+    },
+}
+
+/// the paragraphs diff of two fulltexts, computed once and stored with the revision
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TextDiff {
+    pub summary: DiffSummary,
+    pub paragraphs: Vec<Paragraph>,
+}
+
+/// paragraphs with at least this similarity of their words are one paragraph edited
+pub const SIMILAR_PARAGRAPHS: f64 = 0.5;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    equal: i32,
+    added: i32,
+    removed: i32,
+}
+
+impl Counts {
+    fn similarity(self) -> f64 {
+        let words = 2 * self.equal + self.added + self.removed;
+        if words == 0 {
+            1.0
+        } else {
+            f64::from(2 * self.equal) / f64::from(words)
+        }
+    }
+}
+
+fn diff_words(old: &str, new: &str) -> (Vec<Run>, Counts) {
+    let mut counts = Counts::default();
+    let mut runs = vec![];
+
+    for op in prettydiff::diff_words(old, new).diff() {
+        match op {
+            DiffOp::Insert(a) => {
+                counts.added += count_words(a);
+                runs.push(Run::Insert(a.concat()));
+            }
+            DiffOp::Remove(a) => {
+                counts.removed += count_words(a);
+                runs.push(Run::Remove(a.concat()));
+            }
+            DiffOp::Replace(a, b) => {
+                counts.removed += count_words(a);
+                counts.added += count_words(b);
+                runs.push(Run::Remove(a.concat()));
+                runs.push(Run::Insert(b.concat()));
+            }
+            DiffOp::Equal(a) => {
+                counts.equal += count_words(a);
+                runs.push(Run::Equal(a.concat()));
+            }
+        }
+    }
+    (runs, counts)
+}
+
+/// paragraphs which are not in the longest common subsequence of both fulltexts
+/// are matched by equal or similar text, a match within the same hunk of the
+/// subsequence diff is an edit, any other a move, words are compared within matches
+pub fn text_diff(old: &str, new: &str) -> TextDiff {
+    let (old, new) = (paragraphs(old), paragraphs(new));
+
+    // the hunk of each paragraph outside the common subsequence, the old index of each inside
+    let mut old_hunks = vec![None; old.len()];
+    let mut new_hunks = vec![None; new.len()];
+    let mut common = vec![None; new.len()];
+    let (mut i, mut j) = (0, 0);
+    for (hunk, op) in prettydiff::basic::diff(&old, &new).into_iter().enumerate() {
+        let (removed, inserted) = match op {
+            DiffOp::Equal(a) => {
+                for k in 0..a.len() {
+                    common[j + k] = Some(i + k);
+                }
+                i += a.len();
+                j += a.len();
+                continue;
+            }
+            DiffOp::Remove(a) => (a.len(), 0),
+            DiffOp::Insert(b) => (0, b.len()),
+            DiffOp::Replace(a, b) => (a.len(), b.len()),
+        };
+        old_hunks[i..i + removed]
+            .iter_mut()
+            .for_each(|h| *h = Some(hunk));
+        new_hunks[j..j + inserted]
+            .iter_mut()
+            .for_each(|h| *h = Some(hunk));
+        i += removed;
+        j += inserted;
+    }
+
+    // new index → old index, equal paragraphs first, then the most similar ones
+    let unmatched = (0..old.len())
+        .filter(|&i| old_hunks[i].is_some())
+        .collect::<Vec<_>>();
+    let mut equal = HashMap::<&str, VecDeque<usize>>::new();
+    for &i in &unmatched {
+        equal.entry(old[i]).or_default().push_back(i);
+    }
+    let mut matches = HashMap::new();
+    let mut matched = HashSet::new();
+    for j in (0..new.len()).filter(|&j| new_hunks[j].is_some()) {
+        if let Some(i) = equal.get_mut(new[j]).and_then(VecDeque::pop_front) {
+            matches.insert(j, (i, vec![Run::Equal(new[j].to_owned())]));
+            matched.insert(i);
+        }
+    }
+    // only paragraphs which share enough words are diffed word by word
+    let old_bags = unmatched
+        .iter()
+        .map(|&i| (i, Bag::of(old[i])))
+        .collect::<HashMap<_, _>>();
+    for j in (0..new.len()).filter(|&j| new_hunks[j].is_some()) {
+        if matches.contains_key(&j) {
+            continue;
+        }
+        let bag = Bag::of(new[j]);
+        let mut best: Option<(f64, usize, Vec<Run>)> = None;
+        for &i in unmatched.iter().filter(|i| !matched.contains(*i)) {
+            if old_bags[&i].similarity_bound(&bag) < SIMILAR_PARAGRAPHS {
+                continue;
+            }
+            let (runs, counts) = diff_words(old[i], new[j]);
+            let similarity = counts.similarity();
+            if similarity >= SIMILAR_PARAGRAPHS && best.as_ref().is_none_or(|b| similarity > b.0) {
+                best = Some((similarity, i, runs));
+            }
+        }
+        if let Some((_, i, runs)) = best {
+            matched.insert(i);
+            matches.insert(j, (i, runs));
+        }
+    }
+
+    let mut paragraphs = vec![];
+    // old paragraphs before are either in `paragraphs` or matched with a new one
+    let mut next_old = 0;
+    let removed = |until: usize, next_old: usize| {
+        (next_old..until)
+            .filter(|i| !matched.contains(i))
+            .map(|i| Paragraph::Remove(old[i].to_owned()))
+            .collect::<Vec<_>>()
+    };
+    for j in 0..new.len() {
+        if let Some(i) = common[j] {
+            paragraphs.extend(removed(i, next_old));
+            next_old = i + 1;
+            paragraphs.push(Paragraph::Equal(new[j].to_owned()));
+        } else if let Some((i, runs)) = matches.remove(&j) {
+            if old_hunks[i] == new_hunks[j] && i >= next_old {
+                paragraphs.extend(removed(i, next_old));
+                next_old = i + 1;
+                paragraphs.push(Paragraph::Edit(runs));
+            } else {
+                paragraphs.push(Paragraph::Move {
+                    from: i,
+                    to: j,
+                    runs,
+                });
+            }
+        } else {
+            paragraphs.push(Paragraph::Insert(new[j].to_owned()));
+        }
+    }
+    paragraphs.extend(removed(old.len(), next_old));
+
+    TextDiff {
+        summary: summarize_paragraphs(&paragraphs),
+        paragraphs,
+    }
+}
+
+fn summarize_paragraphs(paragraphs: &[Paragraph]) -> DiffSummary {
+    let mut summary = DiffSummary::default();
+    let mut counts = Counts::default();
+
+    for paragraph in paragraphs {
+        match paragraph {
+            Paragraph::Equal(text) => counts.equal += words(text),
+            Paragraph::Insert(text) => {
+                counts.added += words(text);
+                summary.paragraphs_added += 1;
+            }
+            Paragraph::Remove(text) => {
+                counts.removed += words(text);
+                summary.paragraphs_removed += 1;
+            }
+            Paragraph::Edit(runs) | Paragraph::Move { runs, .. } => {
+                for run in runs {
+                    match run {
+                        Run::Equal(text) => counts.equal += words(text),
+                        Run::Insert(text) => counts.added += words(text),
+                        Run::Remove(text) => counts.removed += words(text),
+                    }
+                }
+                if runs.iter().any(|run| !matches!(run, Run::Equal(_))) {
+                    summary.paragraphs_added += 1;
+                    summary.paragraphs_removed += 1;
+                }
+            }
+        }
+    }
+
+    summary.words_added = counts.added;
+    summary.words_removed = counts.removed;
+    summary.similarity = counts.similarity();
+    summary
+}
+
+fn paragraphs(fulltext: &str) -> Vec<&str> {
+    fulltext.lines().filter(|l| !l.trim().is_empty()).collect()
+}
+
+/// prettydiff keeps delimiters as tokens, only count the ones with letters or digits
+fn count_words(tokens: &[&str]) -> i32 {
+    tokens
+        .iter()
+        .filter(|t| t.chars().any(char::is_alphanumeric))
+        .count() as i32
+}
+
+fn words(text: &str) -> i32 {
+    count_words(&prettydiff::text::split_words(text).collect::<Vec<_>>())
+}
+
+/// the words of a paragraph with how often each occurs
+struct Bag<'a> {
+    counts: HashMap<&'a str, i32>,
+    words: i32,
+}
+
+impl<'a> Bag<'a> {
+    fn of(text: &'a str) -> Self {
+        let mut counts = HashMap::new();
+        for token in prettydiff::text::split_words(text) {
+            if token.chars().any(char::is_alphanumeric) {
+                *counts.entry(token).or_insert(0) += 1;
+            }
+        }
+        let words = counts.values().sum();
+        Bag { counts, words }
+    }
+
+    /// the similarity of a words diff if every shared word was kept,
+    /// which it never exceeds
+    fn similarity_bound(&self, other: &Bag) -> f64 {
+        let shared = self
+            .counts
+            .iter()
+            .map(|(word, count)| (*count).min(other.counts.get(word).copied().unwrap_or(0)))
+            .sum::<i32>();
+        Counts {
+            equal: shared,
+            added: other.words - shared,
+            removed: self.words - shared,
+        }
+        .similarity()
+    }
+}
+
+/// the paragraphs as one words diff, one line each, moved ones where they are now
+fn flatten(paragraphs: &[Paragraph]) -> Vec<Run> {
+    let mut runs: Vec<Run> = vec![];
+    let mut push = |run: Run| match (runs.last_mut(), run) {
+        (Some(Run::Equal(a)), Run::Equal(b))
+        | (Some(Run::Insert(a)), Run::Insert(b))
+        | (Some(Run::Remove(a)), Run::Remove(b)) => a.push_str(&b),
+        (_, run) => runs.push(run),
+    };
+
+    for paragraph in paragraphs {
+        match paragraph {
+            Paragraph::Equal(text) => push(Run::Equal(text.clone())),
+            Paragraph::Insert(text) => push(Run::Insert(text.clone())),
+            Paragraph::Remove(text) => push(Run::Remove(text.clone())),
+            Paragraph::Edit(edit) | Paragraph::Move { runs: edit, .. } => {
+                edit.iter().cloned().for_each(&mut push)
+            }
+        }
+        push(Run::Equal("\n".to_owned()));
+    }
+    runs
+}
+
+/// words diff as html with del and ins elements,
+/// unchanged passages are shortened to `context` tokens around the changes
+pub fn html_excerpt(old: &str, new: &str, context: usize) -> String {
+    render_excerpt(&text_diff(old, new).paragraphs, context)
+}
+
+/// like `html_excerpt`, from a stored diff, moved paragraphs are only marked by their edits
+pub fn render_excerpt(paragraphs: &[Paragraph], context: usize) -> String {
+    let runs = flatten(paragraphs);
+    let mut html = String::new();
+
+    for (index, run) in runs.iter().enumerate() {
+        match run {
+            Run::Equal(text) => {
+                let a = prettydiff::text::split_words(text).collect::<Vec<_>>();
+                let head = if index == 0 { 0 } else { context };
+                let tail = if index + 1 == runs.len() { 0 } else { context };
+                if a.len() > head.saturating_add(tail) {
+                    html.push_str(&escape(&a[..head].concat()));
+                    html.push_str(" … ");
+                    html.push_str(&escape(&a[a.len() - tail..].concat()));
+                } else {
+                    html.push_str(&escape(text));
+                }
+            }
+            Run::Insert(text) => push_tagged(&mut html, "ins", text),
+            Run::Remove(text) => push_tagged(&mut html, "del", text),
+        }
+    }
+    html.trim().to_owned()
+}
+
+fn push_tagged(html: &mut String, tag: &str, text: &str) {
+    html.push_str(&format!("<{}>{}</{}>", tag, escape(text), tag));
+}
+
+/// the runs of one side, `old` with del and `new` with ins elements
+fn push_runs(html: &mut String, runs: &[Run], old: bool, new: bool) {
+    for run in runs {
+        match run {
+            Run::Equal(text) => html.push_str(&escape(text)),
+            Run::Remove(text) if old => push_tagged(html, "del", text),
+            Run::Insert(text) if new => push_tagged(html, "ins", text),
+            _ => {}
+        }
+    }
+}
+
+fn push_moved(html: &mut String, title: &str, paragraph: usize) {
+    html.push_str(&format!(
+        r#"<span class="moved" title="{} paragraph {}">"#,
+        title,
+        paragraph + 1
+    ));
+}
+
+/// the complete diff as html, removals and insertions inline,
+/// a moved paragraph where it is now in a span of class moved
+pub fn html_inline(old: &str, new: &str) -> String {
+    render_inline(&text_diff(old, new).paragraphs)
+}
+
+pub fn render_inline(paragraphs: &[Paragraph]) -> String {
+    let mut html = String::new();
+
+    for paragraph in paragraphs {
+        match paragraph {
+            Paragraph::Equal(text) => html.push_str(&escape(text)),
+            Paragraph::Insert(text) => push_tagged(&mut html, "ins", text),
+            Paragraph::Remove(text) => push_tagged(&mut html, "del", text),
+            Paragraph::Edit(runs) => push_runs(&mut html, runs, true, true),
+            Paragraph::Move { from, runs, .. } => {
+                push_moved(&mut html, "moved from", *from);
+                push_runs(&mut html, runs, true, true);
+                html.push_str("</span>");
+            }
+        }
+        html.push('\n');
+    }
+    html.trim().to_owned()
+}
+
+/// old text with del elements and new text with ins elements, for two columns,
+/// a moved paragraph is in both where it was and where it is now
+pub fn html_side_by_side(old: &str, new: &str) -> (String, String) {
+    render_side_by_side(&text_diff(old, new).paragraphs)
+}
+
+pub fn render_side_by_side(paragraphs: &[Paragraph]) -> (String, String) {
+    let moved = paragraphs
+        .iter()
+        .filter_map(|paragraph| match paragraph {
+            Paragraph::Move { from, .. } => Some(*from),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    // all but the moved ones are in the order of the old fulltext already
+    let mut in_place = (0..).filter(|i| !moved.contains(i));
+    let (mut left, mut right) = (vec![], vec![]);
+
+    for paragraph in paragraphs {
+        let (mut old, mut new) = (String::new(), String::new());
+        match paragraph {
+            Paragraph::Equal(text) => {
+                old.push_str(&escape(text));
+                new.push_str(&escape(text));
+            }
+            Paragraph::Insert(text) => push_tagged(&mut new, "ins", text),
+            Paragraph::Remove(text) => push_tagged(&mut old, "del", text),
+            Paragraph::Edit(runs) => {
+                push_runs(&mut old, runs, true, false);
+                push_runs(&mut new, runs, false, true);
+            }
+            Paragraph::Move { from, to, runs } => {
+                push_moved(&mut old, "moved to", *to);
+                push_runs(&mut old, runs, true, false);
+                old.push_str("</span>");
+                left.push((*from, old));
+                push_moved(&mut new, "moved from", *from);
+                push_runs(&mut new, runs, false, true);
+                new.push_str("</span>");
+                right.push(new);
+                continue;
+            }
+        }
+        if !matches!(paragraph, Paragraph::Insert(_)) {
+            left.push((in_place.next().unwrap_or_default(), old));
+        }
+        if !matches!(paragraph, Paragraph::Remove(_)) {
+            right.push(new);
+        }
+    }
+    left.sort_by_key(|(index, _)| *index);
+    let left = left.into_iter().map(|(_, html)| html).collect::<Vec<_>>();
+    (left.join("\n"), right.join("\n"))
+}
@@ -0,0 +1,59 @@
+use crate::db::{Article, Revision};
+use futures::channel::mpsc;
+use std::sync::{Arc, Mutex};
+
+/// how many events a slow subscriber may lag behind before it misses some
+const BUFFER: usize = 64;
+
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    ArticleInserted {
+        article: Article,
+    },
+    Fetched {
+        article_id: i32,
+        url: String,
+        fetched_at: i32,
+        snapshot_id: Option<i32>,
+        error: Option<String>,
+    },
+    Revision {
+        revision: Revision,
+    },
+}
+
+impl Event {
+    /// the SSE event name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ArticleInserted { .. } => "article_inserted",
+            Event::Fetched { .. } => "fetched",
+            Event::Revision { .. } => "revision",
+        }
+    }
+}
+
+/// in-process broadcast, every subscriber gets every event published after subscribing
+#[derive(Clone, Default)]
+pub struct Events {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<Event>>>>,
+}
+
+impl Events {
+    pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+        let (sender, receiver) = mpsc::channel(BUFFER);
+        self.subscribers.lock().expect("subscribers").push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: Event) {
+        self.subscribers
+            .lock()
+            .expect("subscribers")
+            .retain_mut(|subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(err) => !err.is_disconnected(),
+            });
+    }
+}
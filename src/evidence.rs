@@ -0,0 +1,246 @@
+//! signed evidence bundles of a revision
+//!
+//! a `.tar.gz` with both snapshots, their diff and `manifest.json`, which holds the
+//! fetch metadata and the sha256 of every file, `manifest.sig` is the hex Ed25519
+//! signature of the manifest so a bundle can be checked offline
+
+use crate::db::{ProvideArticles, Snapshot};
+use crate::{diff, extract, markup, provenance};
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Read;
+
+pub const FORMAT: &str = "propaganda-evidence/1";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BundledFile {
+    pub name: String,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BundledSnapshot {
+    pub snapshot_id: i32,
+    pub archived_at: i32,
+    pub fetched_at: String,
+    pub source: String,
+    pub source_url: Option<String>,
+    /// as stored, a bundle of a tampered snapshot does not match its file
+    pub body_sha256: Option<String>,
+    pub chain_sha256: Option<String>,
+    pub request_headers: Vec<(String, String)>,
+    pub response_headers: Vec<(String, String)>,
+    pub file: BundledFile,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub created_at: String,
+    /// hex of the key which signed the manifest
+    pub public_key: String,
+    pub article_id: i32,
+    pub url: String,
+    pub revision_id: i32,
+    pub headline: String,
+    pub words_added: i32,
+    pub words_removed: i32,
+    pub previous: BundledSnapshot,
+    pub current: BundledSnapshot,
+    pub diff: BundledFile,
+}
+
+pub fn generate_key() -> SigningKey {
+    SigningKey::generate(&mut rand::rngs::OsRng)
+}
+
+/// a signing key from the hex of its 32 byte secret
+pub fn parse_key(hex: &str) -> Result<SigningKey> {
+    let bytes = hex::decode(hex.trim())?;
+    let secret = <[u8; 32]>::try_from(bytes.as_slice())
+        .map_err(|_| anyhow!("a signing key has 32 bytes, not {}", bytes.len()))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+pub fn parse_public_key(hex: &str) -> Result<VerifyingKey> {
+    let bytes = hex::decode(hex.trim())?;
+    let public = <[u8; 32]>::try_from(bytes.as_slice())
+        .map_err(|_| anyhow!("a public key has 32 bytes, not {}", bytes.len()))?;
+    Ok(VerifyingKey::from_bytes(&public)?)
+}
+
+fn bundled(snapshot: &Snapshot, name: &str) -> BundledSnapshot {
+    BundledSnapshot {
+        snapshot_id: snapshot.snapshot_id,
+        archived_at: snapshot.archived_at,
+        fetched_at: markup::rfc3339(snapshot.archived_at),
+        source: snapshot.source.as_str().to_owned(),
+        source_url: snapshot.source_url.clone(),
+        body_sha256: snapshot.body_sha256.clone(),
+        chain_sha256: snapshot.chain_sha256.clone(),
+        request_headers: provenance::parse_headers(snapshot.request_headers.as_deref()),
+        response_headers: provenance::parse_headers(snapshot.response_headers.as_deref()),
+        file: BundledFile {
+            name: name.to_owned(),
+            sha256: provenance::body_sha256(&snapshot.html),
+        },
+    }
+}
+
+/// the words diff of both fulltexts as a standalone html page
+fn diff_html(headline: &str, previous: &Snapshot, current: &Snapshot) -> String {
+    format!(
+        "<!DOCTYPE html>\n<meta charset=utf-8>\n<title>{}</title>\n<p>{} → {}</p>\n<pre>{}</pre>\n",
+        markup::escape(headline),
+        markup::rfc3339(previous.archived_at),
+        markup::rfc3339(current.archived_at),
+        diff::html_inline(
+            &extract::get_article_fulltext(&previous.html),
+            &extract::get_article_fulltext(&current.html)
+        )
+    )
+}
+
+fn append(
+    archive: &mut tar::Builder<GzEncoder<Vec<u8>>>,
+    name: &str,
+    bytes: &[u8],
+    mtime: i32,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime.max(0) as u64);
+    header.set_cksum();
+    archive.append_data(&mut header, name, bytes)?;
+    Ok(())
+}
+
+/// the bundle of a revision, signed by `key`
+pub async fn bundle<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    revision_id: i32,
+    key: &SigningKey,
+    created_at: i32,
+) -> Result<Vec<u8>> {
+    let revision = provider.get_revision(revision_id).await?;
+    let previous = provider.get_snaphot(revision.previous_snapshot_id).await?;
+    let current = provider.get_snaphot(revision.snapshot_id).await?;
+    let diff = diff_html(&revision.headline, &previous, &current);
+
+    let manifest = Manifest {
+        format: FORMAT.to_owned(),
+        created_at: markup::rfc3339(created_at),
+        public_key: hex::encode(key.verifying_key().as_bytes()),
+        article_id: revision.article_id,
+        url: revision.url.clone(),
+        revision_id: revision.revision_id,
+        headline: revision.headline.clone(),
+        words_added: revision.words_added,
+        words_removed: revision.words_removed,
+        previous: bundled(&previous, "previous.html"),
+        current: bundled(&current, "current.html"),
+        diff: BundledFile {
+            name: "diff.html".to_owned(),
+            sha256: provenance::body_sha256(&diff),
+        },
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let signature = hex::encode(key.sign(&manifest).to_bytes());
+
+    let mut archive = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    append(&mut archive, "manifest.json", &manifest, created_at)?;
+    append(
+        &mut archive,
+        "manifest.sig",
+        signature.as_bytes(),
+        created_at,
+    )?;
+    append(
+        &mut archive,
+        "previous.html",
+        previous.html.as_bytes(),
+        created_at,
+    )?;
+    append(
+        &mut archive,
+        "current.html",
+        current.html.as_bytes(),
+        created_at,
+    )?;
+    append(&mut archive, "diff.html", diff.as_bytes(), created_at)?;
+    Ok(archive.into_inner()?.finish()?)
+}
+
+/// the manifest of a readable bundle and everything that does not add up
+#[derive(Debug)]
+pub struct Verification {
+    pub manifest: Manifest,
+    pub problems: Vec<String>,
+}
+
+/// check the signature, against `trusted` if given, and the hashes of all files
+pub fn verify(bundle: &[u8], trusted: Option<&VerifyingKey>) -> Result<Verification> {
+    let mut files = HashMap::new();
+    for entry in tar::Archive::new(GzDecoder::new(bundle)).entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes)?;
+        files.insert(name, bytes);
+    }
+    let manifest_bytes = files
+        .get("manifest.json")
+        .ok_or_else(|| anyhow!("bundle has no manifest.json"))?;
+    let manifest: Manifest = serde_json::from_slice(manifest_bytes)?;
+    let mut problems = vec![];
+
+    if manifest.format != FORMAT {
+        problems.push(format!("format {} is not {}", manifest.format, FORMAT));
+    }
+    let signer = parse_public_key(&manifest.public_key)?;
+    if trusted.is_some_and(|trusted| *trusted != signer) {
+        problems.push(format!(
+            "signed by {}, not by the trusted key",
+            manifest.public_key
+        ));
+    }
+    let signature = files
+        .get("manifest.sig")
+        .and_then(|sig| hex::decode(String::from_utf8_lossy(sig).trim()).ok())
+        .and_then(|sig| Signature::from_slice(&sig).ok());
+    match signature {
+        Some(signature) if signer.verify(manifest_bytes, &signature).is_ok() => {}
+        Some(_) => problems.push("the signature of manifest.json is invalid".to_owned()),
+        None => problems.push("manifest.sig is missing or unreadable".to_owned()),
+    }
+
+    let bundled = [
+        &manifest.previous.file,
+        &manifest.current.file,
+        &manifest.diff,
+    ];
+    for file in bundled.iter() {
+        match files.get(&file.name) {
+            Some(bytes) if hex::encode(Sha256::digest(bytes)) == file.sha256 => {}
+            Some(_) => problems.push(format!("{} does not match its sha256", file.name)),
+            None => problems.push(format!("{} is missing", file.name)),
+        }
+    }
+    for snapshot in [&manifest.previous, &manifest.current].iter() {
+        if let Some(stored) = &snapshot.body_sha256 {
+            if *stored != snapshot.file.sha256 {
+                problems.push(format!(
+                    "snapshot {} was changed after it was fetched",
+                    snapshot.snapshot_id
+                ));
+            }
+        }
+    }
+
+    Ok(Verification { manifest, problems })
+}
@@ -0,0 +1,60 @@
+/// for tagesschau.de and whatthecommit.com
+///
+/// the first selector which matches anything wins
+pub fn get_article_fulltext(html: &str) -> String {
+    let fragment = scraper::Html::parse_fragment(html);
+    let mut fulltext = String::new();
+
+    for selector in &["div.storywrapper", "div#content > p"] {
+        let selector = scraper::Selector::parse(selector).expect("fulltext selector");
+        let elements: Vec<scraper::ElementRef> = fragment.select(&selector).collect();
+
+        if !elements.is_empty() {
+            for element in elements {
+                for text in element.text() {
+                    let text = text.trim();
+                    if !text.is_empty() {
+                        fulltext.push_str(text);
+                        fulltext.push('\n');
+                    }
+                }
+            }
+
+            break;
+        }
+    }
+    fulltext
+}
+
+pub fn compare_article_fulltext(a: &str, b: &str) -> bool {
+    get_article_fulltext(a) == get_article_fulltext(b)
+}
+
+/// og:title, the first h1 or the title, whatever comes first
+pub fn get_headline(html: &str) -> String {
+    let fragment = scraper::Html::parse_fragment(html);
+
+    let og_title = scraper::Selector::parse(r#"meta[property="og:title"]"#).expect("og:title");
+    if let Some(content) = fragment
+        .select(&og_title)
+        .find_map(|e| e.value().attr("content"))
+    {
+        return content.trim().to_owned();
+    }
+
+    for selector in &["h1", "title"] {
+        let selector = scraper::Selector::parse(selector).expect("headline selector");
+        if let Some(element) = fragment.select(&selector).next() {
+            let text = normalize(&element.text().collect::<Vec<_>>().join(" "));
+            if !text.is_empty() {
+                return text;
+            }
+        }
+    }
+    String::new()
+}
+
+/// runs of whitespace as one space, none around the text
+pub(crate) fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,56 @@
+use crate::db::Revision;
+use crate::markup::{escape, rfc3339};
+
+/// Atom feed with one entry per revision and its html diff excerpt
+pub fn atom(title: &str, self_url: &str, entries: &[(Revision, String)]) -> String {
+    let updated = entries
+        .iter()
+        .map(|(revision, _)| revision.archived_at)
+        .max()
+        .unwrap_or(0);
+
+    let mut xml = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{title}</title>
+<id>{id}</id>
+<link rel="self" href="{id}"/>
+<updated>{updated}</updated>
+<author><name>propaganda</name></author>
+"#,
+        title = escape(title),
+        id = escape(self_url),
+        updated = rfc3339(updated),
+    );
+
+    for (revision, excerpt) in entries {
+        let headline = if revision.headline.is_empty() {
+            &revision.url
+        } else {
+            &revision.headline
+        };
+        let content = format!(
+            "<p>{} words added, {} words removed</p><p>{}</p>",
+            revision.words_added, revision.words_removed, excerpt
+        );
+
+        xml.push_str(&format!(
+            r#"<entry>
+<title>{title}</title>
+<id>tag:propaganda,2020:revision/{id}</id>
+<link href="{url}"/>
+<updated>{updated}</updated>
+<content type="html">{content}</content>
+</entry>
+"#,
+            title = escape(headline),
+            id = revision.revision_id,
+            url = escape(&revision.url),
+            updated = rfc3339(revision.archived_at),
+            content = escape(&content),
+        ));
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
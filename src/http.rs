@@ -1,8 +1,145 @@
-use crate::db::ProvideArticles;
-use crate::mime;
+use crate::blobs::BlobStore;
+use crate::cache::{Cache, DiffKey, DiffView};
+use crate::db::{self, DbError, Revision, RevisionFilter};
+use crate::events::{Event, Events};
+use crate::webhook::NewWebhook;
+use crate::{diff, feed, mime, scraper};
 
-use sqlx::SqlitePool;
-use tide::{prelude::*, Request, Response, Result};
+use crate::repository::Repository;
+use futures::StreamExt;
+use tide::{prelude::*, Request, Response, Result, Status, StatusCode};
+
+/// shared by all handlers, derefs to the repository
+#[derive(Clone)]
+pub struct State {
+    pub repository: Repository,
+    pub events: Events,
+    /// read by the handlers, written by the scraper, see `cache`
+    pub cache: Cache,
+    /// reads need no token, see `auth::Guard`
+    pub public_read: bool,
+    /// where prior captures of articles are imported from, see `wayback::Wayback`
+    pub wayback_url: String,
+    /// the downloaded images served by `/blobs/:sha256`, see `media::download`
+    pub blobs: Option<BlobStore>,
+}
+
+impl State {
+    /// on a pool of any backend, see `Repository`
+    pub fn new(repository: impl Into<Repository>, events: Events) -> Self {
+        Self {
+            repository: repository.into(),
+            events,
+            cache: Cache::default(),
+            public_read: true,
+            wayback_url: crate::wayback::WAYBACK_URL.to_owned(),
+            blobs: None,
+        }
+    }
+}
+
+impl std::ops::Deref for State {
+    type Target = Repository;
+
+    fn deref(&self) -> &Repository {
+        &self.repository
+    }
+}
+
+/// marks a legacy route as deprecated and points to its successor in `/api/v1`
+pub struct Deprecated(pub &'static str);
+
+#[async_trait::async_trait]
+impl<S: Clone + Send + Sync + 'static> tide::Middleware<S> for Deprecated {
+    async fn handle(&self, req: Request<S>, next: tide::Next<'_, S>) -> Result {
+        let mut res = next.run(req).await;
+        res.insert_header("Deprecation", "true");
+        res.insert_header("Link", format!(r#"<{}>; rel="successor-version""#, self.0));
+        Ok(res)
+    }
+}
+
+pub fn db_status(err: &DbError) -> StatusCode {
+    match err {
+        DbError::NotFound(_) => StatusCode::NotFound,
+        DbError::Conflict(_) => StatusCode::Conflict,
+        DbError::InvalidInput(_) => StatusCode::BadRequest,
+        DbError::Storage(_) => StatusCode::InternalServerError,
+    }
+}
+
+/// for handlers which don't go through `problem_details`
+pub fn db_error(err: DbError) -> tide::Error {
+    tide::Error::new(db_status(&err), err)
+}
+
+/// every failed request gets an RFC 7807 problem details body,
+/// `DbError`s which bubbled up with `?` get their proper status code
+///
+/// `{"type": "about:blank", "title": "Not Found", "status": 404, "detail": "article not found"}`
+pub async fn problem_details(mut res: Response) -> Result {
+    if let Some(status) = res
+        .error()
+        .and_then(|err| err.downcast_ref::<DbError>())
+        .map(db_status)
+    {
+        res.set_status(status);
+    }
+
+    let status = res.status();
+    if status.is_client_error() || status.is_server_error() {
+        let detail = match res.error() {
+            Some(err) if status.is_server_error() => {
+                tide::log::error!("{}", err);
+                status.canonical_reason().to_owned()
+            }
+            Some(err) => err.to_string(),
+            None => status.canonical_reason().to_owned(),
+        };
+        res.set_body(json!({
+            "type": "about:blank",
+            "title": status.canonical_reason(),
+            "status": status as u16,
+            "detail": detail,
+        }));
+        res.set_content_type(mime::problem_json());
+    }
+    Ok(res)
+}
+
+/// answers preflight requests and adds `Access-Control-Allow-Origin`
+/// for origins in the allowlist, `*` allows any origin
+pub struct Cors(pub Vec<String>);
+
+#[async_trait::async_trait]
+impl<S: Clone + Send + Sync + 'static> tide::Middleware<S> for Cors {
+    async fn handle(&self, req: Request<S>, next: tide::Next<'_, S>) -> Result {
+        let origin = req
+            .header("Origin")
+            .map(|origin| origin.last().as_str().to_owned())
+            .filter(|origin| self.0.iter().any(|o| o == "*" || o == origin));
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return Ok(next.run(req).await),
+        };
+
+        let mut res = if req.method() == tide::http::Method::Options {
+            let mut res = Response::new(StatusCode::NoContent);
+            res.insert_header("Access-Control-Allow-Methods", "GET, POST, DELETE, OPTIONS");
+            res.insert_header(
+                "Access-Control-Allow-Headers",
+                "Authorization, Content-Type",
+            );
+            res.insert_header("Access-Control-Max-Age", "86400");
+            res
+        } else {
+            next.run(req).await
+        };
+        res.insert_header("Access-Control-Allow-Origin", origin);
+        res.insert_header("Vary", "Origin");
+        Ok(res)
+    }
+}
 
 #[derive(Deserialize)]
 struct UrlQuery {
@@ -14,6 +151,18 @@ struct IdQuery {
     id: i32,
 }
 
+#[derive(Deserialize)]
+struct CursorQuery {
+    cursor: Option<i32>,
+    limit: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct Changes {
+    changes: Vec<Revision>,
+    next_cursor: Option<i32>,
+}
+
 #[derive(Serialize)]
 struct Article2 {
     headline: String,
@@ -28,24 +177,30 @@ struct Article2 {
     firstSnapshotUrl: String,
 }
 
-pub async fn insert_article(req: Request<SqlitePool>) -> Result<Response> {
-    let mut provider = req.state().acquire().await.expect("conn");
+pub async fn insert_article(req: Request<State>) -> Result<Response> {
+    let mut provider = req.state().acquire().await?;
     let query: UrlQuery = req.query()?;
-    provider.insert_article(&query.url).await?;
+    let existed = provider.get_article(&query.url).await.is_ok();
+    let article = provider.insert_article(&query.url).await?;
+    if !existed {
+        req.state()
+            .events
+            .publish(Event::ArticleInserted { article });
+    }
     Ok(Response::new(200))
 }
 
-pub async fn get_articles(req: Request<SqlitePool>) -> Result<Response> {
+pub async fn get_articles(req: Request<State>) -> Result<Response> {
     let mut provider = req.state().acquire().await?;
     let articles = provider.get_articles(0, 100).await?;
 
     Ok(Response::builder(200)
-        .body(serde_json::to_string(&articles).expect("serde_json to_string articles"))
+        .body(serde_json::to_string(&articles)?)
         .content_type(mime::json())
         .build())
 }
 
-pub async fn get_snaphot_metadatas_from_article(req: Request<SqlitePool>) -> Result<Response> {
+pub async fn get_snaphot_metadatas_from_article(req: Request<State>) -> Result<Response> {
     let mut provider = req.state().acquire().await?;
 
     let article_id = if let Ok(query) = req.query::<UrlQuery>() {
@@ -63,46 +218,121 @@ pub async fn get_snaphot_metadatas_from_article(req: Request<SqlitePool>) -> Res
         .build())
 }
 
-pub async fn get_snaphot(req: Request<SqlitePool>) -> Result<Response> {
+pub async fn get_snaphot(req: Request<State>) -> Result<Response> {
     let mut provider = req.state().acquire().await?;
     let query: IdQuery = req.query()?;
     let mut snapshot = provider.get_snaphot(query.id).await?;
 
-    fn get_article_fulltext(html: &str) -> String {
-        let fragment = scraper::Html::parse_fragment(&html);
-        let mut fulltext = String::new();
-        
-        if let Ok(selector) = scraper::Selector::parse("div.storywrapper") {
-            for element in fragment.select(&selector) {
-                for text in element.text() {
-                    let text = text.trim();
-                    if !text.is_empty() {
-                        fulltext.push_str(text);
-                        fulltext.push_str("\n");
-                    }
-                }
-            }
-        } else if let Ok(selector) = scraper::Selector::parse("div#content > p") {
-            for element in fragment.select(&selector) {
-                for text in element.text() {
-                    let text = text.trim();
-                    if !text.is_empty() {
-                        fulltext.push_str(text);
-                        fulltext.push_str("x\n");
-                    }
-                }
+    snapshot.html = req.state().cache.fulltext(&snapshot);
+
+    Ok(Response::builder(200)
+        .body(serde_json::to_string(&snapshot)?)
+        .content_type(mime::json())
+        .build())
+}
+
+pub async fn get_changes(req: Request<State>) -> Result<Response> {
+    let mut provider = req.state().acquire().await?;
+    let query: CursorQuery = req.query()?;
+    let filter: RevisionFilter = req.query()?;
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    let changes = provider.get_revisions(&filter, query.cursor, limit).await?;
+    let next_cursor = if changes.len() as i32 == limit {
+        changes.last().map(|r| r.revision_id)
+    } else {
+        None
+    };
+
+    Ok(Response::builder(200)
+        .body(serde_json::to_string(&Changes {
+            changes,
+            next_cursor,
+        })?)
+        .content_type(mime::json())
+        .build())
+}
+
+pub async fn get_changes_atom(req: Request<State>) -> Result<Response> {
+    let mut provider = req.state().acquire().await?;
+    let filter: RevisionFilter = req.query()?;
+
+    let mut entries = vec![];
+    for revision in provider.get_revisions(&filter, None, 20).await? {
+        let key = DiffKey {
+            from: revision.previous_snapshot_id,
+            to: revision.snapshot_id,
+            view: DiffView::Excerpt,
+        };
+        let cache = &req.state().cache;
+        let excerpt = match cache.diff(&key) {
+            Some(diff) => diff.html.clone(),
+            None => {
+                let stored = scraper::revision_diff(&mut *provider, &revision).await?;
+                let diff = crate::cache::Diff {
+                    article_id: revision.article_id,
+                    html: diff::render_excerpt(&stored.paragraphs, 12),
+                    summary: stored.summary,
+                };
+                cache.put_diff(key, diff).html.clone()
             }
-        } else {
-            fulltext.push_str(html);
-        }
-        fulltext
+        };
+        entries.push((revision, excerpt));
     }
 
-    let fulltext = get_article_fulltext(&snapshot.html);
-    snapshot.html = fulltext;
+    let title = match (&filter.url, &filter.host, filter.watchlist) {
+        (Some(url), _, _) => format!("propaganda: changes of {}", url),
+        (None, Some(host), _) => format!("propaganda: changes on {}", host),
+        (None, None, Some(watchlist_id)) => {
+            let watchlist = provider.get_watchlist(watchlist_id).await?;
+            format!("propaganda: changes on watchlist {}", watchlist.name)
+        }
+        (None, None, None) => "propaganda: changes".to_owned(),
+    };
 
     Ok(Response::builder(200)
-        .body(serde_json::to_string(&snapshot).expect("serde_json to_string snapshot"))
+        .body(feed::atom(&title, req.url().as_str(), &entries))
+        .content_type(mime::atom())
+        .build())
+}
+
+pub async fn get_webhooks(req: Request<State>) -> Result<Response> {
+    let mut provider = req.state().acquire().await?;
+    let webhooks = provider.get_webhooks().await?;
+
+    Ok(Response::builder(200)
+        .body(serde_json::to_string(&webhooks)?)
         .content_type(mime::json())
         .build())
 }
+
+pub async fn insert_webhook(mut req: Request<State>) -> Result<Response> {
+    let webhook: NewWebhook = req.body_json().await?;
+    db::http_url(&webhook.url).map_err(|err| tide::Error::from_str(400, err))?;
+
+    let mut provider = req.state().acquire().await?;
+    let webhook = provider.insert_webhook(&webhook).await?;
+
+    Ok(Response::builder(201)
+        .body(serde_json::to_string(&webhook)?)
+        .content_type(mime::json())
+        .build())
+}
+
+pub async fn delete_webhook(req: Request<State>) -> Result<Response> {
+    let webhook_id: i32 = req.param("id").status(400)?;
+    let mut provider = req.state().acquire().await?;
+    provider.delete_webhook(webhook_id).await?;
+    Ok(Response::new(204))
+}
+
+/// stream article insertions, fetch outcomes and revisions as server-sent events
+pub async fn get_events(req: Request<State>, sender: tide::sse::Sender) -> Result<()> {
+    let mut events = req.state().events.subscribe();
+    while let Some(event) = events.next().await {
+        sender
+            .send(event.name(), serde_json::to_string(&event)?, None)
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! bulk import of article urls from newline separated lists, CSV or JSON
+//!
+//! every input line gets a result, valid urls are canonicalized and
+//! inserted in one transaction
+
+use crate::db::{DbResult, ProvideArticles};
+use surf::url::Url;
+
+/// query parameters which only track where a reader came from
+const TRACKING_PARAMETERS: &[&str] = &["fbclid", "gclid", "dclid", "mc_cid", "mc_eid", "wt_mc"];
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// one url per line, empty lines and lines starting with `#` are skipped
+    Lines,
+    /// the `url` column if the first row names it, the first column otherwise
+    Csv,
+    /// an array of urls or of objects with an `url`
+    Json,
+}
+
+impl Format {
+    pub fn from_content_type(content_type: &str) -> Self {
+        match content_type.split(';').next().unwrap_or("").trim() {
+            "text/csv" => Format::Csv,
+            "application/json" => Format::Json,
+            _ => Format::Lines,
+        }
+    }
+
+    pub fn from_path(path: &str) -> Self {
+        match path.rsplit('.').next() {
+            Some("csv") => Format::Csv,
+            Some("json") => Format::Json,
+            _ => Format::Lines,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Added,
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ImportLine {
+    /// 1-based line of the input, or position in a JSON array
+    pub line: usize,
+    pub input: String,
+    pub status: Status,
+    pub url: Option<String>,
+    pub article_id: Option<i32>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Report {
+    pub added: usize,
+    pub duplicate: usize,
+    pub invalid: usize,
+    pub lines: Vec<ImportLine>,
+}
+
+/// http(s) only, without fragment, tracking parameters and default port,
+/// wayback machine urls are unwrapped to the archived url
+pub fn canonicalize(input: &str) -> Result<String, String> {
+    let mut url = Url::parse(input.trim()).map_err(|err| err.to_string())?;
+
+    if url.host_str() == Some("web.archive.org") {
+        let mut segments = url.path().splitn(4, '/').skip(1);
+        if let (Some("web"), Some(_timestamp), Some(archived)) =
+            (segments.next(), segments.next(), segments.next())
+        {
+            let archived = match url.query() {
+                Some(query) => format!("{}?{}", archived, query),
+                None => archived.to_owned(),
+            };
+            return canonicalize(&archived);
+        }
+    }
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("scheme {} is not http or https", url.scheme()));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err("url has no host".to_owned());
+    }
+
+    url.set_fragment(None);
+    let query = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMETERS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    Ok(url.into())
+}
+
+/// `(line, input)` of every entry, or the reason the whole body is unreadable
+pub fn parse(body: &str, format: Format) -> Result<Vec<(usize, String)>, String> {
+    match format {
+        Format::Lines => Ok(body
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim().to_owned()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .collect()),
+        Format::Csv => {
+            let mut rows = body
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(index, line)| (index + 1, csv_fields(line)));
+            let mut entries = vec![];
+            let mut column = 0;
+            if let Some((line, header)) = rows.next() {
+                match header.iter().position(|f| f.eq_ignore_ascii_case("url")) {
+                    Some(position) => column = position,
+                    None => entries.push((line, header.into_iter().next().unwrap_or_default())),
+                }
+            }
+            entries.extend(rows.map(|(line, mut fields)| {
+                let field = if column < fields.len() {
+                    fields.swap_remove(column)
+                } else {
+                    String::new()
+                };
+                (line, field)
+            }));
+            Ok(entries)
+        }
+        Format::Json => {
+            let values: Vec<serde_json::Value> =
+                serde_json::from_str(body).map_err(|err| err.to_string())?;
+            Ok(values
+                .into_iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    let input = match &value {
+                        serde_json::Value::String(url) => url.clone(),
+                        serde_json::Value::Object(object) => object
+                            .get("url")
+                            .and_then(|url| url.as_str())
+                            .map(str::to_owned)
+                            .unwrap_or_else(|| value.to_string()),
+                        other => other.to_string(),
+                    };
+                    (index + 1, input)
+                })
+                .collect())
+        }
+    }
+}
+
+/// fields of one CSV row, quoted fields may contain commas and `""`
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_owned());
+    fields
+}
+
+/// canonicalize every entry and insert the valid ones at once,
+/// all or nothing when `provider` is a transaction
+pub async fn import<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    entries: Vec<(usize, String)>,
+) -> DbResult<Report> {
+    let mut report = Report::default();
+    let mut urls = vec![];
+
+    for (line, input) in entries {
+        let (status, url, error) = match canonicalize(&input) {
+            Ok(url) => {
+                urls.push(url.clone());
+                (Status::Added, Some(url), None)
+            }
+            Err(error) => (Status::Invalid, None, Some(error)),
+        };
+        report.lines.push(ImportLine {
+            line,
+            input,
+            status,
+            url,
+            article_id: None,
+            error,
+        });
+    }
+
+    let mut inserted = provider.insert_articles(&urls).await?.into_iter();
+    for line in report.lines.iter_mut() {
+        if line.status == Status::Invalid {
+            report.invalid += 1;
+            continue;
+        }
+        if let Some((article, added)) = inserted.next() {
+            line.article_id = Some(article.article_id);
+            if added {
+                report.added += 1;
+            } else {
+                line.status = Status::Duplicate;
+                report.duplicate += 1;
+            }
+        }
+    }
+    Ok(report)
+}
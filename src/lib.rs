@@ -1,4 +1,29 @@
+pub mod api;
+pub mod auth;
+pub mod blobs;
+pub mod cache;
+pub mod config;
 pub mod db;
+pub mod declared;
+pub mod diff;
+pub mod events;
+pub mod evidence;
+pub mod extract;
+pub mod feed;
 pub mod http;
+pub mod import;
+pub mod markup;
+pub mod media;
+pub mod metadata;
 pub mod mime;
+pub mod postgres;
+pub mod provenance;
+pub mod repository;
 pub mod scraper;
+pub mod significance;
+pub mod sled_store;
+pub mod ui;
+pub mod warc;
+pub mod watchlist;
+pub mod wayback;
+pub mod webhook;
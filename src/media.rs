@@ -0,0 +1,334 @@
+//! images and embedded media of the article body
+//!
+//! extracted per snapshot with alt text, caption and credit, which the fulltext drops,
+//! and paired between snapshots into additions, removals, replacements and edits,
+//! images downloaded into a `blobs::BlobStore` get a perceptual hash which tells a
+//! resized copy under another url from a replaced image
+
+use crate::blobs::BlobStore;
+use crate::db::{DbResult, ProvideArticles};
+use crate::extract::normalize;
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+/// the article body, the first selector which matches anything wins, media outside of
+/// it are logos, teasers and ads
+const BODY_SELECTORS: &[&str] = &["div.storywrapper", "div#content", "article", "main"];
+
+/// credits within a figure, by the class names sites give them
+const CREDIT_SELECTORS: &str = r#"[class*="credit"], [class*="copyright"]"#;
+
+/// images larger than this, or which do not declare their length, are not downloaded
+pub const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// a download which takes longer is given up
+pub const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// at most this many of the 64 bits of two perceptual hashes differ for the same image
+pub const SIMILAR_IMAGES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+    /// an iframe, such as a video player or a social media post
+    Embed,
+}
+
+impl MediaKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MediaKind::Image => "image",
+            MediaKind::Video => "video",
+            MediaKind::Audio => "audio",
+            MediaKind::Embed => "embed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Media {
+    pub kind: MediaKind,
+    /// as in the html, lazily loaded images by their `data-src`
+    pub src: String,
+    pub alt: Option<String>,
+    /// the caption of the enclosing figure without its credit
+    pub caption: Option<String>,
+    pub credit: Option<String>,
+    /// hex sha256 of the downloaded bytes in the blob store, see `download`
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// hex dHash of the downloaded image, see `phash`
+    #[serde(default)]
+    pub phash: Option<String>,
+}
+
+impl Media {
+    /// the same image, by perceptual hash in case both have one and by url otherwise
+    pub fn same_as(&self, other: &Media) -> bool {
+        if self.kind != other.kind {
+            return false;
+        }
+        match (self.phash.as_deref(), other.phash.as_deref()) {
+            (Some(a), Some(b)) => distance(a, b).is_some_and(|d| d <= SIMILAR_IMAGES),
+            _ => self.src == other.src,
+        }
+    }
+
+    fn described_as(&self, other: &Media) -> bool {
+        (&self.alt, &self.caption, &self.credit) == (&other.alt, &other.caption, &other.credit)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaChange {
+    Added(Media),
+    Removed(Media),
+    /// another image or media in place of one removed
+    Replaced {
+        from: Media,
+        to: Media,
+    },
+    /// the same image with another alt text, caption or credit
+    Edited {
+        from: Media,
+        to: Media,
+    },
+}
+
+pub fn extract(html: &str) -> Vec<Media> {
+    let fragment = scraper::Html::parse_fragment(html);
+    let media = scraper::Selector::parse("img, video, audio, iframe").expect("media selector");
+    let body = BODY_SELECTORS.iter().find_map(|selector| {
+        let selector = scraper::Selector::parse(selector).expect("body selector");
+        let elements = fragment.select(&selector).collect::<Vec<_>>();
+        Some(elements).filter(|elements| !elements.is_empty())
+    });
+
+    body.unwrap_or_default()
+        .into_iter()
+        .flat_map(|body| body.select(&media))
+        .filter_map(|element| {
+            let (kind, src) = match element.value().name() {
+                "img" => (MediaKind::Image, image_src(element)),
+                "video" => (MediaKind::Video, source_src(element)),
+                "audio" => (MediaKind::Audio, source_src(element)),
+                _ => (MediaKind::Embed, attr(element, "src")),
+            };
+            let (caption, credit) = figure(element);
+            Some(Media {
+                kind,
+                src: src?,
+                alt: attr(element, "alt"),
+                caption,
+                credit,
+                sha256: None,
+                phash: None,
+            })
+        })
+        .collect()
+}
+
+pub fn to_json(media: &[Media]) -> Option<String> {
+    serde_json::to_string(media).ok()
+}
+
+/// stored media, snapshots from before they were stored get theirs extracted now
+pub fn of_snapshot(json: Option<&str>, html: &str) -> Vec<Media> {
+    json.and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_else(|| extract(html))
+}
+
+/// like `of_snapshot`, the html is read only for snapshots from before media were stored
+pub async fn of_stored_snapshot<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    snapshot_id: i32,
+) -> DbResult<Vec<Media>> {
+    let stored = provider.get_snapshot_media(snapshot_id).await?;
+    match stored.and_then(|json| serde_json::from_str(&json).ok()) {
+        Some(media) => Ok(media),
+        None => Ok(extract(&provider.get_snaphot(snapshot_id).await?.html)),
+    }
+}
+
+/// media of the same image are matched in order, edited in case their descriptions
+/// differ, what remains is paired by kind into replacements, added or removed otherwise
+pub fn diff(old: &[Media], new: &[Media]) -> Vec<MediaChange> {
+    let mut matched = vec![false; new.len()];
+    let mut changes = vec![];
+    let mut removed = vec![];
+    for from in old {
+        match (0..new.len()).find(|&i| !matched[i] && from.same_as(&new[i])) {
+            Some(i) => {
+                matched[i] = true;
+                if !from.described_as(&new[i]) {
+                    changes.push(MediaChange::Edited {
+                        from: from.clone(),
+                        to: new[i].clone(),
+                    });
+                }
+            }
+            None => removed.push(from),
+        }
+    }
+
+    let mut added = new
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+        .map(|(media, _)| media)
+        .collect::<Vec<_>>();
+    for from in removed {
+        match added.iter().position(|to| to.kind == from.kind) {
+            Some(i) => changes.push(MediaChange::Replaced {
+                from: from.clone(),
+                to: added.remove(i).clone(),
+            }),
+            None => changes.push(MediaChange::Removed(from.clone())),
+        }
+    }
+    changes.extend(added.into_iter().cloned().map(MediaChange::Added));
+    changes
+}
+
+/// hex of the 64 bit difference hash of the image, none for bytes which are no image
+pub fn phash(bytes: &[u8]) -> Option<String> {
+    let image = image::load_from_memory(bytes)
+        .ok()?
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = image.get_pixel(x, y)[0] < image.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | u64::from(brighter);
+        }
+    }
+    Some(format!("{:016x}", hash))
+}
+
+/// the number of bits two hex perceptual hashes differ in
+pub fn distance(a: &str, b: &str) -> Option<u32> {
+    let (a, b) = (
+        u64::from_str_radix(a, 16).ok()?,
+        u64::from_str_radix(b, 16).ok()?,
+    );
+    Some((a ^ b).count_ones())
+}
+
+/// store the images, relative to the url of the article, in the blob store with their
+/// hashes, an image with the src of one of `previous`, the media of the youngest
+/// snapshot, keeps its hashes without another download, one which fails to download
+/// keeps none
+pub async fn download(blobs: &BlobStore, url: &str, previous: &[Media], media: &mut [Media]) {
+    for media in media.iter_mut().filter(|m| m.kind == MediaKind::Image) {
+        let downloaded = previous
+            .iter()
+            .find(|p| p.kind == media.kind && p.src == media.src && p.sha256.is_some());
+        if let Some(downloaded) = downloaded {
+            media.sha256 = downloaded.sha256.clone();
+            media.phash = downloaded.phash.clone();
+            continue;
+        }
+        let stored = match fetch(url, &media.src).await {
+            Ok(bytes) => blobs
+                .put(&bytes)
+                .await
+                .map(|sha256| (sha256, phash(&bytes)))
+                .map_err(|err| anyhow!(err)),
+            Err(err) => Err(err),
+        };
+        match stored {
+            Ok((sha256, phash)) => {
+                media.sha256 = Some(sha256);
+                media.phash = phash;
+            }
+            Err(err) => tide::log::warn!("image {} of {}: {}", media.src, url, err),
+        }
+    }
+}
+
+async fn fetch(url: &str, src: &str) -> Result<Vec<u8>> {
+    let src = surf::url::Url::parse(url)?.join(src)?;
+    if src.scheme() != "http" && src.scheme() != "https" {
+        return Err(anyhow!("not downloaded"));
+    }
+    let user_agent = concat!("propaganda/", env!("CARGO_PKG_VERSION"));
+    let download = async {
+        let mut res = surf::get(src)
+            .set_header("User-Agent", user_agent)
+            .await
+            .map_err(|err| anyhow!(err))?;
+        if !res.status().is_success() {
+            return Err(anyhow!("status {}", res.status()));
+        }
+        // surf reads the whole body, its declared length is all there is to check beforehand
+        let length = res
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok());
+        match length {
+            Some(length) if length <= MAX_IMAGE_BYTES => {}
+            Some(length) => return Err(anyhow!("{} bytes", length)),
+            None => return Err(anyhow!("no Content-Length")),
+        }
+        Ok(res.body_bytes().await?)
+    };
+    async_std::future::timeout(DOWNLOAD_TIMEOUT, download)
+        .await
+        .map_err(|_| anyhow!("no download within {:?}", DOWNLOAD_TIMEOUT))?
+}
+
+fn attr(element: scraper::ElementRef, name: &str) -> Option<String> {
+    element
+        .value()
+        .attr(name)
+        .map(normalize)
+        .filter(|value| !value.is_empty())
+}
+
+/// lazily loaded images have a placeholder in `src`
+fn image_src(element: scraper::ElementRef) -> Option<String> {
+    let srcset = attr(element, "srcset")
+        .and_then(|srcset| srcset.split_whitespace().next().map(str::to_owned));
+    attr(element, "src")
+        .filter(|src| !src.starts_with("data:"))
+        .or_else(|| attr(element, "data-src"))
+        .or(srcset)
+}
+
+fn source_src(element: scraper::ElementRef) -> Option<String> {
+    let source = scraper::Selector::parse("source[src]").expect("source selector");
+    attr(element, "src").or_else(|| element.select(&source).find_map(|s| attr(s, "src")))
+}
+
+/// caption and credit of the figure around the element
+fn figure(element: scraper::ElementRef) -> (Option<String>, Option<String>) {
+    let figure = match element
+        .ancestors()
+        .filter_map(scraper::ElementRef::wrap)
+        .find(|ancestor| ancestor.value().name() == "figure")
+    {
+        Some(figure) => figure,
+        None => return (None, None),
+    };
+    let figcaption = scraper::Selector::parse("figcaption").expect("figcaption selector");
+    let credits = scraper::Selector::parse(CREDIT_SELECTORS).expect("credit selectors");
+
+    let credit = figure.select(&credits).next().map(text);
+    let caption = figure.select(&figcaption).next().map(|caption| {
+        let caption = text(caption);
+        match &credit {
+            Some(credit) => normalize(&caption.replacen(credit.as_str(), "", 1)),
+            None => caption,
+        }
+    });
+    let non_empty = |text: Option<String>| text.filter(|text| !text.is_empty());
+    (non_empty(caption), non_empty(credit))
+}
+
+fn text(element: scraper::ElementRef) -> String {
+    normalize(&element.text().collect::<Vec<_>>().join(" "))
+}
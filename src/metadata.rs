@@ -0,0 +1,223 @@
+//! what a page says about its article in JSON-LD and OpenGraph
+//!
+//! headline, authors, dates, section and image of the `NewsArticle` a page embeds, or
+//! else of its OpenGraph and `article:` meta tags, stored with every snapshot and
+//! rendered as lines so changes to them diff like the fulltext
+
+use crate::diff::{self, TextDiff};
+use crate::extract::normalize;
+
+/// meta tags with the time of the last modification, the first one present wins
+const MODIFIED_META: &[&str] = &[
+    r#"meta[property="article:modified_time"]"#,
+    r#"meta[property="og:updated_time"]"#,
+    r#"meta[itemprop="dateModified"]"#,
+    r#"meta[name="last-modified"]"#,
+];
+
+/// JSON-LD `@type`s which describe the article itself
+const ARTICLE_TYPES: &[&str] = &[
+    "NewsArticle",
+    "Article",
+    "ReportageNewsArticle",
+    "AnalysisNewsArticle",
+    "OpinionNewsArticle",
+    "BackgroundNewsArticle",
+    "BlogPosting",
+    "LiveBlogPosting",
+];
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PageMetadata {
+    pub headline: Option<String>,
+    pub description: Option<String>,
+    /// names, in the order the page lists them
+    pub authors: Vec<String>,
+    pub section: Option<String>,
+    /// the url of the lead image
+    pub image: Option<String>,
+    /// as published, like `declared::Declared::modified_time`
+    pub published_time: Option<String>,
+    pub modified_time: Option<String>,
+}
+
+impl PageMetadata {
+    /// one `field: value` line per field present, one per author
+    pub fn lines(&self) -> String {
+        let mut lines = vec![];
+        let mut push = |field: &str, value: &Option<String>| {
+            if let Some(value) = value {
+                lines.push(format!("{}: {}", field, value));
+            }
+        };
+        push("headline", &self.headline);
+        push("description", &self.description);
+        for author in &self.authors {
+            push("author", &Some(author.clone()));
+        }
+        push("section", &self.section);
+        push("image", &self.image);
+        push("published", &self.published_time);
+        push("modified", &self.modified_time);
+        lines.join("\n")
+    }
+
+    /// whether anything but the modification time changed, which alone is no revision
+    pub fn changed_since(&self, previous: &PageMetadata) -> bool {
+        self.without_modified_time() != previous.without_modified_time()
+    }
+
+    /// what a revision compares, see `changed_since`
+    pub fn without_modified_time(&self) -> PageMetadata {
+        PageMetadata {
+            modified_time: None,
+            ..self.clone()
+        }
+    }
+
+    /// changes to metadata diff like changes to the fulltext
+    pub fn diff(&self, current: &PageMetadata) -> TextDiff {
+        diff::text_diff(&self.lines(), &current.lines())
+    }
+
+    pub fn to_json(&self) -> Option<String> {
+        serde_json::to_string(self).ok()
+    }
+
+    /// stored metadata, snapshots from before it was stored get theirs extracted now
+    pub fn of_snapshot(json: Option<&str>, html: &str) -> Self {
+        json.and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_else(|| extract(html))
+    }
+}
+
+/// JSON-LD fields win over meta tags, which fill in what JSON-LD lacks
+pub fn extract(html: &str) -> PageMetadata {
+    of_fragment(&scraper::Html::parse_fragment(html))
+}
+
+pub(crate) fn of_fragment(fragment: &scraper::Html) -> PageMetadata {
+    let json_ld = json_ld(fragment).unwrap_or_default();
+    let open_graph = open_graph(fragment);
+    PageMetadata {
+        headline: json_ld.headline.or(open_graph.headline),
+        description: json_ld.description.or(open_graph.description),
+        authors: if json_ld.authors.is_empty() {
+            open_graph.authors
+        } else {
+            json_ld.authors
+        },
+        section: json_ld.section.or(open_graph.section),
+        image: json_ld.image.or(open_graph.image),
+        published_time: json_ld.published_time.or(open_graph.published_time),
+        modified_time: json_ld.modified_time.or(open_graph.modified_time),
+    }
+}
+
+fn json_ld(fragment: &scraper::Html) -> Option<PageMetadata> {
+    let selector =
+        scraper::Selector::parse(r#"script[type="application/ld+json"]"#).expect("json-ld");
+    fragment.select(&selector).find_map(|script| {
+        let json = script.text().collect::<String>();
+        let value: serde_json::Value = serde_json::from_str(&json).ok()?;
+        article(&value).map(|article| PageMetadata {
+            headline: text(article.get("headline")),
+            description: text(article.get("description")),
+            authors: names(article.get("author")),
+            section: text(article.get("articleSection")),
+            image: url(article.get("image")),
+            published_time: text(article.get("datePublished")),
+            modified_time: text(article.get("dateModified")),
+        })
+    })
+}
+
+/// the first object of an article type, JSON-LD nests them in `@graph` and arrays
+fn article(value: &serde_json::Value) -> Option<&serde_json::Map<String, serde_json::Value>> {
+    match value {
+        serde_json::Value::Object(object) => {
+            let is_article = |kind: &serde_json::Value| {
+                kind.as_str()
+                    .is_some_and(|kind| ARTICLE_TYPES.contains(&kind))
+            };
+            let is_article = match object.get("@type") {
+                Some(serde_json::Value::Array(kinds)) => kinds.iter().any(is_article),
+                Some(kind) => is_article(kind),
+                None => false,
+            };
+            if is_article {
+                Some(object)
+            } else {
+                object.values().find_map(article)
+            }
+        }
+        serde_json::Value::Array(values) => values.iter().find_map(article),
+        _ => None,
+    }
+}
+
+/// a string, or the strings of an array joined, `articleSection` may be either
+fn text(value: Option<&serde_json::Value>) -> Option<String> {
+    let text = match value? {
+        serde_json::Value::String(text) => normalize(text),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .filter_map(|value| value.as_str().map(normalize))
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => return None,
+    };
+    Some(text).filter(|text| !text.is_empty())
+}
+
+/// authors are names, `Person` or `Organization` objects with a name, or arrays of these
+fn names(value: Option<&serde_json::Value>) -> Vec<String> {
+    match value {
+        Some(serde_json::Value::Array(values)) => {
+            values.iter().flat_map(|value| names(Some(value))).collect()
+        }
+        Some(serde_json::Value::Object(object)) => text(object.get("name")).into_iter().collect(),
+        Some(value) => text(Some(value)).into_iter().collect(),
+        None => vec![],
+    }
+}
+
+/// images are urls, `ImageObject`s with a url, or arrays of these of which the first counts
+fn url(value: Option<&serde_json::Value>) -> Option<String> {
+    match value? {
+        serde_json::Value::Array(values) => values.iter().find_map(|value| url(Some(value))),
+        serde_json::Value::Object(object) => {
+            url(object.get("url")).or_else(|| url(object.get("contentUrl")))
+        }
+        value => text(Some(value)),
+    }
+}
+
+fn open_graph(fragment: &scraper::Html) -> PageMetadata {
+    let contents = |selector: &str| {
+        let selector = scraper::Selector::parse(selector).expect("meta selector");
+        fragment
+            .select(&selector)
+            .filter_map(|e| e.value().attr("content"))
+            .map(normalize)
+            .filter(|content| !content.is_empty())
+            .collect::<Vec<_>>()
+    };
+    let first = |selector: &str| contents(selector).into_iter().next();
+
+    let mut authors = contents(r#"meta[property="article:author"]"#);
+    if authors.is_empty() {
+        authors = contents(r#"meta[name="author"]"#);
+    }
+    PageMetadata {
+        headline: first(r#"meta[property="og:title"]"#),
+        description: first(r#"meta[property="og:description"]"#)
+            .or_else(|| first(r#"meta[name="description"]"#)),
+        authors,
+        section: first(r#"meta[property="article:section"]"#),
+        image: first(r#"meta[property="og:image"]"#),
+        published_time: first(r#"meta[property="article:published_time"]"#),
+        modified_time: MODIFIED_META.iter().find_map(|selector| first(selector)),
+    }
+}
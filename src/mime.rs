@@ -8,3 +8,22 @@ pub fn html() -> Mime {
 pub fn json() -> Mime {
     Mime::from_str("application/json; charset=utf-8").unwrap()
 }
+
+pub fn atom() -> Mime {
+    Mime::from_str("application/atom+xml; charset=utf-8").unwrap()
+}
+
+pub fn problem_json() -> Mime {
+    Mime::from_str("application/problem+json").unwrap()
+}
+
+pub fn warc() -> Mime {
+    Mime::from_str("application/warc").unwrap()
+}
+
+/// by the magic bytes of the image, a download for bytes which are no image
+pub fn image(bytes: &[u8]) -> Mime {
+    let essence = image::guess_format(bytes)
+        .map_or("application/octet-stream", |format| format.to_mime_type());
+    Mime::from_str(essence).unwrap()
+}
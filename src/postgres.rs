@@ -0,0 +1,1085 @@
+//! the storage on postgres, for deployments with more than one writer
+//!
+//! the schema equals the one on sqlite, ids are identity columns and the
+//! source of a snapshot is an enum, the count of applied migrations is kept
+//! in `schema_version`
+
+use crate::auth::{ProvideTokens, Role, Token};
+use crate::db::{
+    self, decode_paragraphs, encode_paragraphs, Article, ArticleCursor, ArticleFilter,
+    ArticleListing, ArticleSort, DbError, DbResult, Provenance, ProvideArticles, Revision,
+    RevisionFilter, Snapshot, SnapshotMetadata, VoidResult,
+};
+use crate::diff::{Paragraph, TextDiff};
+use crate::media;
+use crate::metadata;
+use crate::provenance;
+use crate::significance::Significance;
+use crate::watchlist::{ProvideWatchlists, User, Watchlist};
+use crate::webhook::{Delivery, NewWebhook, ProvideWebhooks, Webhook};
+use async_trait::async_trait;
+use sqlx::postgres::PgQueryAs;
+use sqlx::Executor;
+use sqlx::PgConnection;
+
+/// applied in order, the first one creates what sqlite has after `db::MIGRATIONS`,
+/// later ones mirror later sqlite migrations
+const MIGRATIONS: &[&str] = &[
+    r"
+    CREATE TABLE articles (
+        article_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        url TEXT UNIQUE NOT NULL,
+        updated_at INTEGER NOT NULL,
+        created_at INTEGER NOT NULL DEFAULT 0,
+        host TEXT NOT NULL DEFAULT '',
+        last_changed_at INTEGER,
+        revision_count INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX articles_host ON articles (host);
+    CREATE INDEX articles_created_at ON articles (created_at, article_id);
+    CREATE INDEX articles_updated_at ON articles (updated_at, article_id);
+    CREATE INDEX articles_last_changed_at ON articles (last_changed_at, article_id);
+    CREATE INDEX articles_revision_count ON articles (revision_count, article_id);
+    CREATE TYPE snapshot_source AS ENUM ('live', 'warc', 'wayback');
+    CREATE TABLE snapshots (
+        article_id INTEGER NOT NULL,
+        snapshot_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        archived_at INTEGER NOT NULL,
+        html TEXT NOT NULL,
+        source snapshot_source NOT NULL DEFAULT 'live',
+        source_url TEXT,
+        body_sha256 TEXT,
+        request_headers TEXT,
+        response_headers TEXT,
+        chain_sha256 TEXT
+    );
+    CREATE INDEX snapshots_article_id ON snapshots (article_id, archived_at);
+    CREATE TABLE revisions (
+        revision_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        article_id INTEGER NOT NULL,
+        headline TEXT NOT NULL,
+        previous_snapshot_id INTEGER NOT NULL,
+        previous_archived_at INTEGER NOT NULL,
+        snapshot_id INTEGER NOT NULL,
+        archived_at INTEGER NOT NULL,
+        words_added INTEGER NOT NULL,
+        words_removed INTEGER NOT NULL
+    );
+    CREATE INDEX revisions_article_id ON revisions (article_id);
+    CREATE TABLE webhooks (
+        webhook_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        url TEXT NOT NULL,
+        secret TEXT NOT NULL,
+        host TEXT,
+        keyword TEXT,
+        min_words_changed INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE webhook_deliveries (
+        delivery_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        webhook_id INTEGER NOT NULL,
+        payload TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        next_attempt_at INTEGER,
+        delivered_at INTEGER,
+        last_error TEXT
+    );
+    CREATE INDEX webhook_deliveries_next_attempt_at ON webhook_deliveries (next_attempt_at);
+    CREATE TABLE users (
+        user_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        name TEXT UNIQUE NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE tokens (
+        token_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        name TEXT NOT NULL,
+        role TEXT NOT NULL,
+        token_hash TEXT UNIQUE NOT NULL,
+        created_at INTEGER NOT NULL,
+        revoked_at INTEGER,
+        user_id INTEGER
+    );
+    CREATE TABLE watchlists (
+        watchlist_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+        user_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        UNIQUE (user_id, name)
+    );
+    CREATE TABLE watchlist_articles (
+        watchlist_id INTEGER NOT NULL,
+        article_id INTEGER NOT NULL,
+        added_at INTEGER NOT NULL,
+        PRIMARY KEY (watchlist_id, article_id)
+    );
+    CREATE INDEX watchlist_articles_article_id ON watchlist_articles (article_id);
+    CREATE TABLE watchlist_sources (
+        watchlist_id INTEGER NOT NULL,
+        host TEXT NOT NULL,
+        added_at INTEGER NOT NULL,
+        PRIMARY KEY (watchlist_id, host)
+    );
+",
+    r"
+    ALTER TABLE articles ADD COLUMN lease_token TEXT;
+    ALTER TABLE articles ADD COLUMN lease_expires_at INTEGER;
+",
+    r"
+    ALTER TABLE revisions ADD COLUMN paragraphs_added INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE revisions ADD COLUMN paragraphs_removed INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE revisions ADD COLUMN similarity DOUBLE PRECISION;
+    CREATE TABLE revision_diffs (
+        revision_id INTEGER PRIMARY KEY REFERENCES revisions ON DELETE CASCADE,
+        runs TEXT NOT NULL
+    );
+",
+    // words diffs become paragraphs diffs, recomputed when a revision's diff is asked for
+    r"
+    DELETE FROM revision_diffs;
+    ALTER TABLE revision_diffs RENAME COLUMN runs TO paragraphs;
+",
+    r"
+    ALTER TABLE revisions ADD COLUMN significance INTEGER;
+    ALTER TABLE webhooks ADD COLUMN min_significance INTEGER;
+",
+    r"
+    ALTER TABLE revisions ADD COLUMN silent_edit BOOLEAN;
+",
+    r"
+    ALTER TABLE snapshots ADD COLUMN page_metadata TEXT;
+",
+    r"
+    ALTER TABLE snapshots ADD COLUMN page_media TEXT;
+",
+];
+
+#[async_trait]
+impl ProvideArticles for PgConnection {
+    async fn ensure_created_tables(&mut self) -> DbResult<()> {
+        self.execute("CREATE TABLE IF NOT EXISTS schema_version ( version INTEGER NOT NULL )")
+            .await?;
+        let version: Option<(i32,)> = sqlx::query_as("SELECT version FROM schema_version")
+            .fetch_optional(&mut *self)
+            .await?;
+        let version = version.map_or(0, |(version,)| version);
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let migration = format!(
+                "{} DELETE FROM schema_version; INSERT INTO schema_version VALUES ({});",
+                migration,
+                index + 1
+            );
+            db::migrate(self, &migration).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_outdated_articles(&mut self, limit: i32) -> DbResult<Vec<Article>> {
+        sqlx::query_as::<_, Article>(
+            r"
+            SELECT url, article_id, host, created_at, updated_at
+            FROM articles
+            ORDER BY updated_at ASC, article_id ASC
+            LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn claim_article(
+        &mut self,
+        token: &str,
+        now: i32,
+        expires_at: i32,
+    ) -> DbResult<Option<Article>> {
+        sqlx::query_as(
+            r"
+            UPDATE articles SET lease_token = $1, lease_expires_at = $2
+            WHERE article_id = (
+                SELECT article_id FROM articles
+                WHERE lease_expires_at IS NULL OR lease_expires_at <= $3
+                ORDER BY updated_at ASC, article_id ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *",
+        )
+        .bind(token)
+        .bind(expires_at)
+        .bind(now)
+        .fetch_optional(self)
+        .await
+        .db()
+    }
+
+    async fn release_article(
+        &mut self,
+        article_id: i32,
+        token: &str,
+        updated_at: i32,
+    ) -> DbResult<bool> {
+        let released = sqlx::query(
+            r"
+            UPDATE articles SET updated_at = $1, lease_token = NULL, lease_expires_at = NULL
+            WHERE article_id = $2 AND lease_token = $3",
+        )
+        .bind(updated_at)
+        .bind(article_id)
+        .bind(token)
+        .execute(self)
+        .await?;
+        Ok(released > 0)
+    }
+
+    async fn get_articles(&mut self, offset: i32, limit: i32) -> DbResult<Vec<Article>> {
+        sqlx::query_as::<_, Article>(
+            r"
+            SELECT url, article_id, host, created_at, updated_at
+            FROM articles
+            ORDER BY created_at ASC, article_id ASC
+            LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn insert_article(&mut self, url: &str) -> DbResult<Article> {
+        sqlx::query(
+            r"
+            INSERT INTO articles ( url, host, created_at, updated_at )
+            VALUES ( $1, $2, $3, 0 )
+            ON CONFLICT (url) DO NOTHING",
+        )
+        .bind(url)
+        .bind(db::url_host(url))
+        .bind(crate::scraper::timestamp())
+        .execute(&mut *self)
+        .await?;
+        self.get_article(url).await
+    }
+
+    async fn insert_articles(&mut self, urls: &[String]) -> DbResult<Vec<(Article, bool)>> {
+        let created_at = crate::scraper::timestamp();
+        let mut articles = vec![];
+        for url in urls {
+            let added = sqlx::query(
+                r"
+                INSERT INTO articles ( url, host, created_at, updated_at )
+                VALUES ( $1, $2, $3, 0 )
+                ON CONFLICT (url) DO NOTHING",
+            )
+            .bind(url)
+            .bind(db::url_host(url))
+            .bind(created_at)
+            .execute(&mut *self)
+            .await?
+                > 0;
+            articles.push((self.get_article(url).await?, added));
+        }
+        Ok(articles)
+    }
+
+    async fn update_article(&mut self, url: &str, updated_at: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            UPDATE articles SET updated_at=$1 WHERE url=$2",
+        )
+        .bind(updated_at)
+        .bind(url)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_article(&mut self, url: &str) -> DbResult<Article> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM articles WHERE url = $1 LIMIT 1",
+        )
+        .bind(url)
+        .fetch_one(self)
+        .await
+        .or_not_found("article")
+    }
+
+    async fn get_article_by_id(&mut self, article_id: i32) -> DbResult<Article> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM articles WHERE article_id = $1 LIMIT 1",
+        )
+        .bind(article_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("article")
+    }
+
+    async fn delete_article(&mut self, article_id: i32) -> DbResult<()> {
+        for table in &["revisions", "snapshots", "watchlist_articles", "articles"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE article_id = $1", table))
+                .bind(article_id)
+                .execute(&mut *self)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn list_articles(
+        &mut self,
+        filter: &ArticleFilter,
+        sort: ArticleSort,
+        cursor: Option<ArticleCursor>,
+        limit: i32,
+    ) -> DbResult<Vec<ArticleListing>> {
+        let query = format!(
+            r"
+            SELECT article_id, url, host, created_at, updated_at,
+                COALESCE((
+                    SELECT headline FROM revisions r WHERE r.article_id = articles.article_id
+                    ORDER BY revision_id DESC LIMIT 1
+                ), '') AS headline,
+                (
+                    SELECT COUNT(*) FROM snapshots s WHERE s.article_id = articles.article_id
+                )::INTEGER AS snapshots,
+                revision_count AS revisions,
+                last_changed_at
+            FROM articles
+            WHERE ($1::TEXT IS NULL OR url ILIKE '%' || $1 || '%' OR EXISTS (
+                SELECT 1 FROM revisions r
+                WHERE r.article_id = articles.article_id AND r.headline ILIKE '%' || $1 || '%'
+            ))
+            AND ($2::TEXT IS NULL OR host = $2)
+            AND ($3::BOOLEAN IS NULL OR $3 = (revision_count > 0))
+            AND ($4::INTEGER IS NULL OR created_at >= $4)
+            AND ($5::INTEGER IS NULL OR created_at <= $5)
+            AND ($6::INTEGER IS NULL OR last_changed_at >= $6)
+            AND ($7::INTEGER IS NULL OR last_changed_at <= $7)
+            AND ($8::INTEGER IS NULL OR {key} < $8 OR ({key} = $8 AND article_id < $9))
+            AND ($10::INTEGER IS NULL OR {in_watchlist})
+            ORDER BY {key} DESC, article_id DESC
+            LIMIT $11",
+            key = sort.column(),
+            in_watchlist = db::in_watchlist("articles", "$10"),
+        );
+
+        sqlx::query_as(&query)
+            .bind(filter.q.clone())
+            .bind(filter.host.clone())
+            .bind(filter.has_changes)
+            .bind(filter.created_since)
+            .bind(filter.created_until)
+            .bind(filter.changed_since)
+            .bind(filter.changed_until)
+            .bind(cursor.map(|c| c.key))
+            .bind(cursor.map(|c| c.article_id))
+            .bind(filter.watchlist)
+            .bind(limit)
+            .fetch_all(self)
+            .await
+            .db()
+    }
+
+    async fn get_snaphot_metadatas_from_article(
+        &mut self,
+        article_id: i32,
+    ) -> DbResult<Vec<SnapshotMetadata>> {
+        sqlx::query_as(
+            r"
+            SELECT article_id, snapshot_id, archived_at, source
+            FROM snapshots WHERE article_id = $1 ORDER BY snapshot_id",
+        )
+        .bind(article_id)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn get_youngest_snaphot(&mut self, article: &Article) -> DbResult<Option<Snapshot>> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM snapshots WHERE article_id = $1
+            ORDER BY archived_at DESC, snapshot_id DESC LIMIT 1",
+        )
+        .bind(article.article_id)
+        .fetch_optional(self)
+        .await
+        .db()
+    }
+
+    async fn get_snaphot(&mut self, id: i32) -> DbResult<Snapshot> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM snapshots WHERE snapshot_id = $1 LIMIT 1",
+        )
+        .bind(id)
+        .fetch_one(self)
+        .await
+        .or_not_found("snapshot")
+    }
+
+    async fn get_snapshot_media(&mut self, snapshot_id: i32) -> DbResult<Option<String>> {
+        let (media,): (Option<String>,) =
+            sqlx::query_as("SELECT page_media FROM snapshots WHERE snapshot_id = $1")
+                .bind(snapshot_id)
+                .fetch_one(self)
+                .await
+                .or_not_found("snapshot")?;
+        Ok(media)
+    }
+
+    async fn get_youngest_snapshot_media(&mut self, article_id: i32) -> DbResult<Option<String>> {
+        let youngest: Option<(Option<String>,)> = sqlx::query_as(
+            r"
+            SELECT page_media FROM snapshots WHERE article_id = $1
+            ORDER BY archived_at DESC, snapshot_id DESC LIMIT 1",
+        )
+        .bind(article_id)
+        .fetch_optional(self)
+        .await
+        .db()?;
+        Ok(youngest.and_then(|(media,)| media))
+    }
+
+    async fn insert_snapshot(
+        &mut self,
+        article: &Article,
+        archived_at: i32,
+        html: &str,
+    ) -> DbResult<SnapshotMetadata> {
+        self.insert_snapshot_with_provenance(article, archived_at, html, &Provenance::default())
+            .await
+    }
+
+    async fn insert_snapshot_with_provenance(
+        &mut self,
+        article: &Article,
+        archived_at: i32,
+        html: &str,
+        provenance: &Provenance,
+    ) -> DbResult<SnapshotMetadata> {
+        // inside a transaction no other snapshot of the article is inserted until it ends
+        sqlx::query("SELECT article_id FROM articles WHERE article_id = $1 FOR UPDATE")
+            .bind(article.article_id)
+            .execute(&mut *self)
+            .await?;
+        let previous: Option<(Option<String>,)> = sqlx::query_as(
+            r"
+            SELECT chain_sha256 FROM snapshots
+            WHERE article_id = $1 ORDER BY snapshot_id DESC LIMIT 1",
+        )
+        .bind(article.article_id)
+        .fetch_optional(&mut *self)
+        .await?;
+        let previous = previous.and_then(|(chain,)| chain);
+
+        let body_sha256 = provenance.body_sha256(html);
+        let chain_sha256 = provenance::next_chain_sha256(
+            previous.as_deref(),
+            article.article_id,
+            archived_at,
+            provenance,
+            &body_sha256,
+        );
+
+        sqlx::query_as(
+            r"
+            INSERT INTO snapshots (
+                article_id, archived_at, html, source, source_url,
+                body_sha256, request_headers, response_headers, chain_sha256, page_metadata,
+                page_media
+            )
+            VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11 )
+            RETURNING article_id, snapshot_id, archived_at, source",
+        )
+        .bind(article.article_id)
+        .bind(archived_at)
+        .bind(html)
+        .bind(provenance.source)
+        .bind(provenance.source_url.clone())
+        .bind(body_sha256)
+        .bind(provenance::headers_json(&provenance.request_headers))
+        .bind(provenance::headers_json(&provenance.response_headers))
+        .bind(chain_sha256)
+        .bind(metadata::extract(html).to_json())
+        .bind(media::to_json(&provenance.media(html)))
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn insert_revision(
+        &mut self,
+        previous: &SnapshotMetadata,
+        current: &SnapshotMetadata,
+        headline: &str,
+        diff: &TextDiff,
+        significance: Significance,
+        silent_edit: bool,
+    ) -> DbResult<Revision> {
+        let summary = &diff.summary;
+        sqlx::query_as(
+            r"
+            WITH inserted AS (
+                INSERT INTO revisions (
+                    article_id, headline,
+                    previous_snapshot_id, previous_archived_at,
+                    snapshot_id, archived_at,
+                    words_added, words_removed,
+                    paragraphs_added, paragraphs_removed, similarity, significance, silent_edit
+                )
+                VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13 )
+                RETURNING *
+            ), stored AS (
+                INSERT INTO revision_diffs ( revision_id, paragraphs )
+                SELECT revision_id, $14 FROM inserted
+            ), updated AS (
+                UPDATE articles
+                SET revision_count = revision_count + 1,
+                    last_changed_at = GREATEST(last_changed_at, $6)
+                WHERE article_id = $1
+                RETURNING url
+            )
+            SELECT inserted.*, updated.url FROM inserted, updated",
+        )
+        .bind(current.article_id)
+        .bind(headline)
+        .bind(previous.snapshot_id)
+        .bind(previous.archived_at)
+        .bind(current.snapshot_id)
+        .bind(current.archived_at)
+        .bind(summary.words_added)
+        .bind(summary.words_removed)
+        .bind(summary.paragraphs_added)
+        .bind(summary.paragraphs_removed)
+        .bind(summary.similarity)
+        .bind(significance)
+        .bind(silent_edit)
+        .bind(encode_paragraphs(&diff.paragraphs))
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_revision(&mut self, revision_id: i32) -> DbResult<Revision> {
+        sqlx::query_as(
+            r"
+            SELECT revisions.*, articles.url
+            FROM revisions JOIN articles USING (article_id)
+            WHERE revision_id = $1",
+        )
+        .bind(revision_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("revision")
+    }
+
+    async fn get_revision_diff(&mut self, revision_id: i32) -> DbResult<Option<Vec<Paragraph>>> {
+        let (_, paragraphs): (i32, Option<String>) = sqlx::query_as(
+            r"
+            SELECT revision_id, paragraphs
+            FROM revisions LEFT JOIN revision_diffs USING (revision_id)
+            WHERE revision_id = $1",
+        )
+        .bind(revision_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("revision")?;
+        paragraphs.as_deref().map(decode_paragraphs).transpose()
+    }
+
+    async fn update_revision_diff(
+        &mut self,
+        revision_id: i32,
+        diff: &TextDiff,
+        significance: Significance,
+    ) -> DbResult<()> {
+        let summary = &diff.summary;
+        sqlx::query(
+            r"
+            WITH updated AS (
+                UPDATE revisions
+                SET words_added = $1, words_removed = $2,
+                    paragraphs_added = $3, paragraphs_removed = $4, similarity = $5,
+                    significance = $6
+                WHERE revision_id = $7
+                RETURNING revision_id
+            )
+            INSERT INTO revision_diffs ( revision_id, paragraphs )
+            SELECT revision_id, $8 FROM updated
+            ON CONFLICT (revision_id) DO UPDATE SET paragraphs = EXCLUDED.paragraphs",
+        )
+        .bind(summary.words_added)
+        .bind(summary.words_removed)
+        .bind(summary.paragraphs_added)
+        .bind(summary.paragraphs_removed)
+        .bind(summary.similarity)
+        .bind(significance)
+        .bind(revision_id)
+        .bind(encode_paragraphs(&diff.paragraphs))
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn delete_revision(&mut self, revision_id: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            UPDATE articles
+            SET revision_count = revision_count - 1,
+                last_changed_at = (
+                    SELECT MAX(archived_at) FROM revisions r
+                    WHERE r.article_id = articles.article_id AND r.revision_id <> $1
+                )
+            WHERE article_id = (SELECT article_id FROM revisions WHERE revision_id = $1)",
+        )
+        .bind(revision_id)
+        .execute(&mut *self)
+        .await?;
+        for table in &["revision_diffs", "revisions"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE revision_id = $1", table))
+                .bind(revision_id)
+                .execute(&mut *self)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn get_revisions(
+        &mut self,
+        filter: &RevisionFilter,
+        cursor: Option<i32>,
+        limit: i32,
+    ) -> DbResult<Vec<Revision>> {
+        let query = format!(
+            r"
+            SELECT revisions.*, articles.url
+            FROM revisions JOIN articles USING (article_id)
+            WHERE revision_id < $1
+            AND ($2::TEXT IS NULL OR articles.url = $2)
+            AND ($3::TEXT IS NULL OR articles.host = $3)
+            AND ($4::INTEGER IS NULL OR {in_watchlist})
+            AND ($5::INTEGER IS NULL OR revisions.significance >= $5)
+            AND ($6::BOOLEAN IS NULL OR revisions.silent_edit = $6)
+            ORDER BY revision_id DESC
+            LIMIT $7",
+            in_watchlist = db::in_watchlist("articles", "$4"),
+        );
+
+        sqlx::query_as(&query)
+            .bind(cursor.unwrap_or(i32::MAX))
+            .bind(filter.url.clone())
+            .bind(filter.host.clone())
+            .bind(filter.watchlist)
+            .bind(filter.min_significance)
+            .bind(filter.silent_edit)
+            .bind(limit)
+            .fetch_all(self)
+            .await
+            .db()
+    }
+}
+
+#[async_trait]
+impl ProvideWebhooks for PgConnection {
+    async fn insert_webhook(&mut self, webhook: &NewWebhook) -> DbResult<Webhook> {
+        sqlx::query_as(
+            r"
+            INSERT INTO webhooks ( url, secret, host, keyword, min_words_changed, min_significance )
+            VALUES ( $1, $2, $3, $4, $5, $6 )
+            RETURNING *",
+        )
+        .bind(&webhook.url)
+        .bind(&webhook.secret)
+        .bind(webhook.host.clone())
+        .bind(webhook.keyword.clone())
+        .bind(webhook.min_words_changed)
+        .bind(webhook.min_significance)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_webhooks(&mut self) -> DbResult<Vec<Webhook>> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM webhooks ORDER BY webhook_id",
+        )
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn delete_webhook(&mut self, webhook_id: i32) -> DbResult<()> {
+        for table in &["webhook_deliveries", "webhooks"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE webhook_id = $1", table))
+                .bind(webhook_id)
+                .execute(&mut *self)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_delivery(&mut self, webhook_id: i32, payload: &str, now: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            INSERT INTO webhook_deliveries ( webhook_id, payload, next_attempt_at )
+            VALUES ( $1, $2, $3 )",
+        )
+        .bind(webhook_id)
+        .bind(payload)
+        .bind(now)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_due_deliveries(&mut self, now: i32, limit: i32) -> DbResult<Vec<Delivery>> {
+        sqlx::query_as(
+            r"
+            SELECT delivery_id, webhook_id, url, secret, payload, attempts
+            FROM webhook_deliveries JOIN webhooks USING (webhook_id)
+            WHERE next_attempt_at <= $1
+            ORDER BY next_attempt_at ASC
+            LIMIT $2",
+        )
+        .bind(now)
+        .bind(limit)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn update_delivery(
+        &mut self,
+        delivery_id: i32,
+        attempts: i32,
+        next_attempt_at: Option<i32>,
+        delivered_at: Option<i32>,
+        last_error: Option<String>,
+    ) -> DbResult<()> {
+        sqlx::query(
+            r"
+            UPDATE webhook_deliveries
+            SET attempts=$1, next_attempt_at=$2, delivered_at=$3, last_error=$4
+            WHERE delivery_id=$5",
+        )
+        .bind(attempts)
+        .bind(next_attempt_at)
+        .bind(delivered_at)
+        .bind(last_error)
+        .bind(delivery_id)
+        .execute(self)
+        .await
+        .void()
+    }
+}
+
+/// a token with its role as text, sqlx takes `Role` for an enum type of postgres
+#[derive(sqlx::FromRow)]
+struct TokenRow {
+    token_id: i32,
+    name: String,
+    role: String,
+    user_id: Option<i32>,
+    created_at: i32,
+    revoked_at: Option<i32>,
+}
+
+impl TokenRow {
+    fn token(self) -> DbResult<Token> {
+        Ok(Token {
+            token_id: self.token_id,
+            name: self.name,
+            role: self.role.parse()?,
+            user_id: self.user_id,
+            created_at: self.created_at,
+            revoked_at: self.revoked_at,
+        })
+    }
+}
+
+#[async_trait]
+impl ProvideTokens for PgConnection {
+    async fn insert_token(
+        &mut self,
+        name: &str,
+        role: Role,
+        user_id: Option<i32>,
+        token_hash: &str,
+        created_at: i32,
+    ) -> DbResult<Token> {
+        sqlx::query_as::<_, TokenRow>(
+            r"
+            INSERT INTO tokens ( name, role, user_id, token_hash, created_at )
+            VALUES ( $1, $2, $3, $4, $5 )
+            RETURNING token_id, name, role, user_id, created_at, revoked_at",
+        )
+        .bind(name)
+        .bind(role.as_str())
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(created_at)
+        .fetch_one(self)
+        .await
+        .db()?
+        .token()
+    }
+
+    async fn get_token(&mut self, token_hash: &str) -> DbResult<Token> {
+        sqlx::query_as::<_, TokenRow>(
+            r"
+            SELECT token_id, name, role, user_id, created_at, revoked_at
+            FROM tokens WHERE token_hash = $1 AND revoked_at IS NULL",
+        )
+        .bind(token_hash)
+        .fetch_one(self)
+        .await
+        .or_not_found("token")?
+        .token()
+    }
+
+    async fn get_tokens(&mut self) -> DbResult<Vec<Token>> {
+        sqlx::query_as::<_, TokenRow>(
+            r"
+            SELECT token_id, name, role, user_id, created_at, revoked_at
+            FROM tokens ORDER BY token_id",
+        )
+        .fetch_all(self)
+        .await
+        .db()?
+        .into_iter()
+        .map(TokenRow::token)
+        .collect()
+    }
+
+    async fn revoke_token(&mut self, token_id: i32, revoked_at: i32) -> DbResult<()> {
+        let revoked = sqlx::query(
+            r"
+            UPDATE tokens SET revoked_at = $1 WHERE token_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(revoked_at)
+        .bind(token_id)
+        .execute(self)
+        .await?;
+        if revoked == 0 {
+            return Err(DbError::NotFound(format!(
+                "active token {} not found",
+                token_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProvideWatchlists for PgConnection {
+    async fn insert_user(&mut self, name: &str, created_at: i32) -> DbResult<User> {
+        if name.trim().is_empty() {
+            return Err(DbError::InvalidInput("user name is empty".to_owned()));
+        }
+        sqlx::query_as(
+            r"
+            INSERT INTO users ( name, created_at )
+            VALUES ( $1, $2 )
+            RETURNING *",
+        )
+        .bind(name.trim())
+        .bind(created_at)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_users(&mut self) -> DbResult<Vec<User>> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM users ORDER BY user_id",
+        )
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn get_user(&mut self, user_id: i32) -> DbResult<User> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM users WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("user")
+    }
+
+    async fn get_user_by_name(&mut self, name: &str) -> DbResult<User> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM users WHERE name = $1",
+        )
+        .bind(name)
+        .fetch_one(self)
+        .await
+        .or_not_found("user")
+    }
+
+    async fn insert_watchlist(
+        &mut self,
+        user_id: i32,
+        name: &str,
+        created_at: i32,
+    ) -> DbResult<Watchlist> {
+        if name.trim().is_empty() {
+            return Err(DbError::InvalidInput("watchlist name is empty".to_owned()));
+        }
+        sqlx::query_as(
+            r"
+            INSERT INTO watchlists ( user_id, name, created_at )
+            VALUES ( $1, $2, $3 )
+            RETURNING *",
+        )
+        .bind(user_id)
+        .bind(name.trim())
+        .bind(created_at)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_watchlists(&mut self, user_id: Option<i32>) -> DbResult<Vec<Watchlist>> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM watchlists
+            WHERE ($1::INTEGER IS NULL OR user_id = $1)
+            ORDER BY watchlist_id",
+        )
+        .bind(user_id)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn get_watchlist(&mut self, watchlist_id: i32) -> DbResult<Watchlist> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM watchlists WHERE watchlist_id = $1",
+        )
+        .bind(watchlist_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("watchlist")
+    }
+
+    async fn delete_watchlist(&mut self, watchlist_id: i32) -> DbResult<()> {
+        for table in &["watchlist_articles", "watchlist_sources", "watchlists"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE watchlist_id = $1", table))
+                .bind(watchlist_id)
+                .execute(&mut *self)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn add_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+        added_at: i32,
+    ) -> DbResult<()> {
+        sqlx::query(
+            r"
+            INSERT INTO watchlist_articles ( watchlist_id, article_id, added_at )
+            VALUES ( $1, $2, $3 )
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(watchlist_id)
+        .bind(article_id)
+        .bind(added_at)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn remove_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+    ) -> DbResult<()> {
+        sqlx::query(
+            r"
+            DELETE FROM watchlist_articles WHERE watchlist_id = $1 AND article_id = $2",
+        )
+        .bind(watchlist_id)
+        .bind(article_id)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_watchlist_articles(&mut self, watchlist_id: i32) -> DbResult<Vec<Article>> {
+        sqlx::query_as(
+            r"
+            SELECT articles.url, articles.article_id, articles.host,
+                articles.created_at, articles.updated_at
+            FROM watchlist_articles JOIN articles USING (article_id)
+            WHERE watchlist_id = $1
+            ORDER BY added_at, article_id",
+        )
+        .bind(watchlist_id)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn add_watchlist_source(
+        &mut self,
+        watchlist_id: i32,
+        host: &str,
+        added_at: i32,
+    ) -> DbResult<()> {
+        let host = host.trim().to_lowercase();
+        if host.is_empty() || host.contains('/') {
+            return Err(DbError::InvalidInput(format!(
+                "source {} is not a host",
+                host
+            )));
+        }
+        sqlx::query(
+            r"
+            INSERT INTO watchlist_sources ( watchlist_id, host, added_at )
+            VALUES ( $1, $2, $3 )
+            ON CONFLICT DO NOTHING",
+        )
+        .bind(watchlist_id)
+        .bind(host)
+        .bind(added_at)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn remove_watchlist_source(&mut self, watchlist_id: i32, host: &str) -> DbResult<()> {
+        sqlx::query(
+            r"
+            DELETE FROM watchlist_sources WHERE watchlist_id = $1 AND host = $2",
+        )
+        .bind(watchlist_id)
+        .bind(host)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_watchlist_sources(&mut self, watchlist_id: i32) -> DbResult<Vec<String>> {
+        let sources: Vec<(String,)> = sqlx::query_as(
+            r"
+            SELECT host FROM watchlist_sources WHERE watchlist_id = $1 ORDER BY host",
+        )
+        .bind(watchlist_id)
+        .fetch_all(self)
+        .await?;
+        Ok(sources.into_iter().map(|(host,)| host).collect())
+    }
+}
@@ -0,0 +1,159 @@
+//! integrity of snapshots
+//!
+//! every snapshot keeps the sha256 of its html, snapshots may also be linked by a
+//! hash chain per article, in insertion order, so edited, reordered or removed
+//! snapshots break the chain of the ones inserted after them
+
+use crate::db::{self, DbResult, Provenance, ProvideArticles, Snapshot, SnapshotSource};
+use sha2::{Digest, Sha256};
+
+/// of the response body as received, before it was decoded into the html
+pub fn body_sha256(body: impl AsRef<[u8]>) -> String {
+    hex::encode(Sha256::digest(body.as_ref()))
+}
+
+/// sha256 of the previous link and the snapshot, a chain starts with an empty previous link
+pub fn chain_sha256(
+    previous: Option<&str>,
+    article_id: i32,
+    archived_at: i32,
+    source: SnapshotSource,
+    body_sha256: &str,
+) -> String {
+    let link = format!(
+        "{}\n{}\n{}\n{}\n{}",
+        previous.unwrap_or_default(),
+        article_id,
+        archived_at,
+        source.as_str(),
+        body_sha256
+    );
+    hex::encode(Sha256::digest(link.as_bytes()))
+}
+
+/// the link of a new snapshot after the `previous` one of the article,
+/// none unless the article has a chain or the provenance starts one
+pub fn next_chain_sha256(
+    previous: Option<&str>,
+    article_id: i32,
+    archived_at: i32,
+    provenance: &Provenance,
+    body_sha256: &str,
+) -> Option<String> {
+    if provenance.chain || previous.is_some() {
+        Some(chain_sha256(
+            previous,
+            article_id,
+            archived_at,
+            provenance.source,
+            body_sha256,
+        ))
+    } else {
+        None
+    }
+}
+
+/// none for no headers
+pub fn headers_json(headers: &[(String, String)]) -> Option<String> {
+    if headers.is_empty() {
+        None
+    } else {
+        serde_json::to_string(headers).ok()
+    }
+}
+
+pub fn parse_headers(json: Option<&str>) -> Vec<(String, String)> {
+    json.and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Violation {
+    /// the html does not match its sha256
+    BodyHash,
+    /// the link does not follow from the previous one and the snapshot
+    Chain,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Finding {
+    pub article_id: i32,
+    pub snapshot_id: i32,
+    pub violation: Violation,
+    pub expected: String,
+    pub found: String,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Report {
+    pub snapshots: usize,
+    /// stored before hashes were kept, nothing to compare with
+    pub unhashed: usize,
+    pub chained: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// the findings of one article's snapshots in insertion order
+fn verify_snapshots(snapshots: &[Snapshot], report: &mut Report) {
+    let mut previous: Option<&str> = None;
+
+    for snapshot in snapshots {
+        report.snapshots += 1;
+        let body = body_sha256(&snapshot.html);
+        let finding = |violation, expected: &str, found: &str| Finding {
+            article_id: snapshot.article_id,
+            snapshot_id: snapshot.snapshot_id,
+            violation,
+            expected: expected.to_owned(),
+            found: found.to_owned(),
+        };
+
+        match &snapshot.body_sha256 {
+            Some(stored) if *stored != body => {
+                report
+                    .findings
+                    .push(finding(Violation::BodyHash, stored, &body))
+            }
+            Some(_) => {}
+            None => report.unhashed += 1,
+        }
+        if let Some(stored) = &snapshot.chain_sha256 {
+            let expected = chain_sha256(
+                previous,
+                snapshot.article_id,
+                snapshot.archived_at,
+                snapshot.source,
+                &body,
+            );
+            if *stored != expected {
+                report
+                    .findings
+                    .push(finding(Violation::Chain, &expected, stored));
+            }
+            report.chained += 1;
+        }
+        previous = snapshot.chain_sha256.as_deref();
+    }
+}
+
+/// recompute the hashes of the snapshots of the given articles, or all
+pub async fn verify<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    article_ids: Option<&[i32]>,
+) -> DbResult<Report> {
+    let mut report = Report::default();
+
+    for article in db::get_articles_or_all(provider, article_ids).await? {
+        let mut metadatas = provider
+            .get_snaphot_metadatas_from_article(article.article_id)
+            .await?;
+        metadatas.sort_by_key(|m| m.snapshot_id);
+        let mut snapshots = vec![];
+        for metadata in metadatas {
+            snapshots.push(provider.get_snaphot(metadata.snapshot_id).await?);
+        }
+        verify_snapshots(&snapshots, &mut report);
+    }
+    Ok(report)
+}
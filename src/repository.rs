@@ -0,0 +1,217 @@
+//! the storage behind a pool, shared by the server, the scraper and the webhook dispatcher
+//!
+//! a connection of the pool derefs to a `db::Store` of whichever backend the pool is on,
+//! a sled store is shared instead, `begin` wraps a connection in a transaction which
+//! rolls back unless committed, on sled it journals its writes and excludes connections
+//! while open, and articles due for a fetch are handed out by lease so concurrent
+//! scrapers never fetch the same article at once
+
+use crate::db::{Article, Backend, DbResult, Store};
+use crate::sled_store::{SledStore, SledTransaction};
+use async_lock::RwLockReadGuardArc;
+use sqlx::pool::PoolConnection;
+use sqlx::{PgConnection, Pool, SqliteConnection};
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+/// about a second, locks of the shared cache are held for single statements
+const LOCKED_RETRIES: u32 = 200;
+const LOCKED_PAUSE: Duration = Duration::from_millis(5);
+
+/// again after a short pause while another connection holds the lock, sqlite only
+async fn retry_locked<T, F, Fut>(mut attempt: F) -> DbResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = DbResult<T>>,
+{
+    for _ in 0..LOCKED_RETRIES {
+        match attempt().await {
+            Err(err) if err.is_locked() => async_std::task::sleep(LOCKED_PAUSE).await,
+            result => return result,
+        }
+    }
+    attempt().await
+}
+
+/// an article claimed for fetching, other workers skip it until `expires_at`
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub article: Article,
+    pub token: String,
+    pub expires_at: i32,
+}
+
+#[derive(Clone)]
+pub enum Repository {
+    Sqlite(Pool<SqliteConnection>),
+    Postgres(Pool<PgConnection>),
+    Sled(Box<SledStore>),
+}
+
+impl From<Pool<SqliteConnection>> for Repository {
+    fn from(pool: Pool<SqliteConnection>) -> Self {
+        Repository::Sqlite(pool)
+    }
+}
+
+impl From<Pool<PgConnection>> for Repository {
+    fn from(pool: Pool<PgConnection>) -> Self {
+        Repository::Postgres(pool)
+    }
+}
+
+impl From<SledStore> for Repository {
+    fn from(store: SledStore) -> Self {
+        Repository::Sled(Box::new(store))
+    }
+}
+
+/// a connection of the pool, returned to it when dropped
+pub enum Connection {
+    Sqlite(PoolConnection<SqliteConnection>),
+    Postgres(PoolConnection<PgConnection>),
+    Sled(SledStore, RwLockReadGuardArc<()>),
+}
+
+impl Deref for Connection {
+    type Target = dyn Store;
+
+    fn deref(&self) -> &(dyn Store + 'static) {
+        match self {
+            Connection::Sqlite(conn) => &**conn,
+            Connection::Postgres(conn) => &**conn,
+            Connection::Sled(store, _) => store,
+        }
+    }
+}
+
+impl DerefMut for Connection {
+    fn deref_mut(&mut self) -> &mut (dyn Store + 'static) {
+        match self {
+            Connection::Sqlite(conn) => &mut **conn,
+            Connection::Postgres(conn) => &mut **conn,
+            Connection::Sled(store, _) => store,
+        }
+    }
+}
+
+/// rolled back when dropped uncommitted
+pub enum Transaction {
+    Sqlite(sqlx::Transaction<PoolConnection<SqliteConnection>>),
+    Postgres(sqlx::Transaction<PoolConnection<PgConnection>>),
+    Sled(SledTransaction),
+}
+
+impl Transaction {
+    pub async fn commit(self) -> DbResult<()> {
+        match self {
+            Transaction::Sqlite(tx) => drop(tx.commit().await?),
+            Transaction::Postgres(tx) => drop(tx.commit().await?),
+            Transaction::Sled(tx) => tx.commit()?,
+        };
+        Ok(())
+    }
+
+    pub async fn rollback(self) -> DbResult<()> {
+        match self {
+            Transaction::Sqlite(tx) => drop(tx.rollback().await?),
+            Transaction::Postgres(tx) => drop(tx.rollback().await?),
+            Transaction::Sled(tx) => tx.rollback()?,
+        };
+        Ok(())
+    }
+}
+
+impl Deref for Transaction {
+    type Target = dyn Store;
+
+    fn deref(&self) -> &(dyn Store + 'static) {
+        match self {
+            Transaction::Sqlite(tx) => &***tx,
+            Transaction::Postgres(tx) => &***tx,
+            Transaction::Sled(tx) => &**tx,
+        }
+    }
+}
+
+impl DerefMut for Transaction {
+    fn deref_mut(&mut self) -> &mut (dyn Store + 'static) {
+        match self {
+            Transaction::Sqlite(tx) => &mut ***tx,
+            Transaction::Postgres(tx) => &mut ***tx,
+            Transaction::Sled(tx) => &mut **tx,
+        }
+    }
+}
+
+impl Repository {
+    /// a pool on the backend of the url's scheme
+    pub async fn open(url: &str) -> DbResult<Self> {
+        Ok(match Backend::from_url(url)? {
+            Backend::Sqlite => Pool::<SqliteConnection>::new(url).await?.into(),
+            Backend::Postgres => Pool::<PgConnection>::new(url).await?.into(),
+            Backend::Sled => SledStore::open(&url["sled:".len()..])?.into(),
+        })
+    }
+
+    pub async fn acquire(&self) -> DbResult<Connection> {
+        Ok(match self {
+            Repository::Sqlite(pool) => Connection::Sqlite(pool.acquire().await?),
+            Repository::Postgres(pool) => Connection::Postgres(pool.acquire().await?),
+            Repository::Sled(store) => {
+                let (store, shared) = store.share().await;
+                Connection::Sled(store, shared)
+            }
+        })
+    }
+
+    /// a transaction on a connection of the pool, rolled back when dropped uncommitted
+    pub async fn begin(&self) -> DbResult<Transaction> {
+        Ok(match self {
+            Repository::Sqlite(pool) => Transaction::Sqlite(pool.begin().await?),
+            Repository::Postgres(pool) => Transaction::Postgres(pool.begin().await?),
+            Repository::Sled(store) => Transaction::Sled(store.begin().await),
+        })
+    }
+
+    /// lease the most outdated article for `lease_seconds`, none if every article is leased
+    pub async fn claim_next_due_article(
+        &self,
+        now: i32,
+        lease_seconds: i32,
+    ) -> DbResult<Option<Lease>> {
+        let token = hex::encode(rand::random::<[u8; 16]>());
+        let expires_at = now.saturating_add(lease_seconds);
+        let article = retry_locked(|| async {
+            self.acquire()
+                .await?
+                .claim_article(&token, now, expires_at)
+                .await
+        })
+        .await?;
+        Ok(article.map(|article| Lease {
+            article,
+            token,
+            expires_at,
+        }))
+    }
+
+    /// a transaction which begins with the end of the lease, the article is due again after
+    /// `fetched_at`, started over while another connection holds the lock,
+    /// false if the lease expired and another worker claimed the article meanwhile
+    pub async fn begin_release(
+        &self,
+        lease: &Lease,
+        fetched_at: i32,
+    ) -> DbResult<(Transaction, bool)> {
+        retry_locked(|| async {
+            let mut tx = self.begin().await?;
+            let released = tx
+                .release_article(lease.article.article_id, &lease.token, fetched_at)
+                .await?;
+            Ok((tx, released))
+        })
+        .await
+    }
+}
@@ -1,4 +1,14 @@
-use crate::db::{ ProvideArticles, Article };
+use crate::blobs::BlobStore;
+use crate::cache::Cache;
+use crate::db::{Article, Provenance, ProvideArticles, Revision, Snapshot, SnapshotMetadata};
+use crate::diff::TextDiff;
+use crate::events::{Event, Events};
+use crate::media::{Media, MediaChange};
+use crate::metadata::PageMetadata;
+use crate::repository::{Lease, Repository};
+use crate::significance::Significance;
+use crate::webhook::{self, ProvideWebhooks};
+use crate::{declared, diff, extract, media, metadata, provenance, significance};
 use anyhow::anyhow;
 use std::time::Duration;
 use xactor::*;
@@ -11,18 +21,56 @@ struct DumpArticleUrls;
 #[derive(Clone, Debug)]
 struct FetchTopArticle;
 
+/// a fetch which takes longer loses its article to the next worker
+pub const LEASE_SECONDS: i32 = 300;
+
 pub struct Scraper {
-    pool: sqlx::SqlitePool,
+    repository: Repository,
+    events: Events,
+    hash_chain: bool,
+    cache: Option<Cache>,
+    blobs: Option<BlobStore>,
+}
+
+/// what `insert_snapshot_and_revision` stored, if anything
+#[derive(Debug, Default)]
+pub struct Inserted {
+    pub snapshot: Option<SnapshotMetadata>,
+    pub revision: Option<Revision>,
 }
 
 impl Scraper {
-    pub fn new(pool: sqlx::SqlitePool) -> Self {
-        Self { pool }
+    pub fn new(repository: Repository, events: Events) -> Self {
+        Self {
+            repository,
+            events,
+            hash_chain: false,
+            cache: None,
+            blobs: None,
+        }
+    }
+
+    /// link the snapshots of every article by a hash chain, see `provenance`
+    pub fn with_hash_chain(mut self, hash_chain: bool) -> Self {
+        self.hash_chain = hash_chain;
+        self
+    }
+
+    /// keep the fetched articles and their latest fulltext up to date in `cache`
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// download the images of fetched articles into `blobs` and hash them, see `media`
+    pub fn with_blobs(mut self, blobs: BlobStore) -> Self {
+        self.blobs = Some(blobs);
+        self
     }
 
     async fn dump_article_urls(&self) -> Result<()> {
         let urls = self
-            .pool
+            .repository
             .acquire()
             .await?
             .get_articles(0, 100)
@@ -36,51 +84,138 @@ impl Scraper {
     }
 
     async fn fetch_top_article(&self) -> Result<()> {
-        let mut conn = self.pool.acquire().await?;
-        if let Some(outdated) = conn.get_outdated_articles(1).await?.get(0) {
-            // TODO saving time as i32 is not good
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            let timestamp = timestamp as i32;
-
-            conn.update_article(&outdated.url, timestamp).await?;
-
-            let html = surf_get_string(&outdated.url).await?;
-
-            let snapshot_is_outdated = conn
-                .get_youngest_snaphot(&outdated)
-                .await?
-                .filter(|s| &s.html == &html)
-                .is_none();
-
-            if snapshot_is_outdated {
-                conn.insert_snapshot(&outdated, timestamp, &html).await?;
-            }
+        let now = timestamp();
+        if let Some(lease) = self
+            .repository
+            .claim_next_due_article(now, LEASE_SECONDS)
+            .await?
+        {
+            self.fetch_article(&lease.article, now, Some(&lease))
+                .await?;
         }
         Ok(())
     }
 
     async fn fetch_whatthecommit(&self) -> Result<()> {
-        let mut conn = self.pool.acquire().await?;
         let url = "http://whatthecommit.com/";
-        let article = conn.insert_article(url).await?;
-        self.insert_snapshot(&mut conn, &article).await?;
+        let article = self.repository.acquire().await?.insert_article(url).await?;
+        self.fetch_article(&article, timestamp(), None).await?;
         Ok(())
     }
 
-    async fn insert_snapshot(&self, provider: &mut sqlx::pool::PoolConnection<sqlx::SqliteConnection>, article: &Article) -> Result<()> {
-        let html = surf_get_string(&article.url).await?;
-        provider.insert_snapshot(article, self.timestamp(), &html).await?;
+    /// fetch and store the article, publish the outcome and a detected revision
+    async fn fetch_article(
+        &self,
+        article: &Article,
+        fetched_at: i32,
+        lease: Option<&Lease>,
+    ) -> Result<()> {
+        let inserted = self.fetch_and_insert(article, fetched_at, lease).await;
+
+        self.events.publish(Event::Fetched {
+            article_id: article.article_id,
+            url: article.url.clone(),
+            fetched_at,
+            snapshot_id: inserted
+                .as_ref()
+                .ok()
+                .and_then(|i| i.snapshot.as_ref())
+                .map(|s| s.snapshot_id),
+            error: inserted.as_ref().err().map(ToString::to_string),
+        });
+
+        if let Some(revision) = inserted?.revision {
+            self.events.publish(Event::Revision { revision });
+        }
         Ok(())
     }
 
-    fn timestamp(&self) -> i32 {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i32
+    /// what was committed, the article is due again and the fulltext of a new snapshot
+    /// replaces the cached one
+    fn cached(
+        &self,
+        article: &Article,
+        fetched_at: i32,
+        released: bool,
+        snapshot: Option<(&SnapshotMetadata, &str)>,
+    ) {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return,
+        };
+        if released {
+            cache.put_article(Article {
+                updated_at: fetched_at,
+                ..article.clone()
+            });
+        }
+        if let Some((snapshot, html)) = snapshot {
+            cache.put_latest_fulltext(
+                article.article_id,
+                snapshot.snapshot_id,
+                extract::get_article_fulltext(html),
+            );
+        }
+    }
+
+    /// the snapshot, its revision and webhook deliveries are stored in one transaction
+    /// together with the end of the lease, a failed fetch only ends the lease,
+    /// nothing is stored once the lease expired and another worker may have claimed it
+    async fn fetch_and_insert(
+        &self,
+        article: &Article,
+        fetched_at: i32,
+        lease: Option<&Lease>,
+    ) -> Result<Inserted> {
+        let mut fetched = fetch(&article.url).await;
+        if let (Some(blobs), Ok((html, provenance))) = (&self.blobs, &mut fetched) {
+            let previous = self
+                .repository
+                .acquire()
+                .await?
+                .get_youngest_snapshot_media(article.article_id)
+                .await?;
+            // media extracted from the html of an older snapshot have no hashes to keep
+            let previous = media::of_snapshot(previous.as_deref(), "");
+            let mut media = media::extract(html);
+            media::download(blobs, &article.url, &previous, &mut media).await;
+            provenance.media = Some(media);
+        }
+        let (mut tx, released) = match lease {
+            Some(lease) => self.repository.begin_release(lease, fetched_at).await?,
+            None => (self.repository.begin().await?, false),
+        };
+        if lease.is_some() && !released {
+            tide::log::warn!("the lease of {} expired during its fetch", article.url);
+            tx.rollback().await?;
+            return Ok(Inserted::default());
+        }
+        let (inserted, html) = match fetched {
+            Ok((html, provenance)) => {
+                let provenance = Provenance {
+                    chain: self.hash_chain,
+                    ..provenance
+                };
+                let inserted = insert_snapshot_and_revision_with_provenance(
+                    &mut *tx,
+                    article,
+                    fetched_at,
+                    &html,
+                    &provenance,
+                )
+                .await?;
+                (inserted, html)
+            }
+            Err(err) => {
+                tx.commit().await?;
+                self.cached(article, fetched_at, released, None);
+                return Err(err);
+            }
+        };
+        tx.commit().await?;
+        let snapshot = inserted.snapshot.as_ref().map(|s| (s, html.as_str()));
+        self.cached(article, fetched_at, released, snapshot);
+        Ok(inserted)
     }
 }
 
@@ -114,43 +249,214 @@ impl Handler<FetchTopArticle> for Scraper {
     }
 }
 
-async fn surf_get_string(uri: impl AsRef<str>) -> Result<String> {
-    surf::url::Url::parse(uri.as_ref())?;
-    surf::get(uri)
-        .recv_string()
+/// seconds since unix epoch
+// TODO saving time as i32 is not good
+pub fn timestamp() -> i32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i32
+}
+
+/// the body with the headers sent and received
+async fn fetch(uri: &str) -> Result<(String, Provenance)> {
+    surf::url::Url::parse(uri)?;
+    let user_agent = concat!("propaganda/", env!("CARGO_PKG_VERSION"));
+    let mut res = surf::get(uri)
+        .set_header("User-Agent", user_agent)
         .await
-        .map_err(|err| anyhow!(err))
+        .map_err(|err| anyhow!(err))?;
+    let response_headers = res
+        .headers()
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+    let body = res.body_bytes().await.map_err(|err| anyhow!(err))?;
+    let body_sha256 = provenance::body_sha256(&body);
+    let html = String::from_utf8(body)?;
+
+    Ok((
+        html,
+        Provenance {
+            request_headers: vec![("User-Agent".to_owned(), user_agent.to_owned())],
+            response_headers,
+            body_sha256: Some(body_sha256),
+            ..Provenance::default()
+        },
+    ))
 }
 
-fn compare_article_fulltext(a: &str, b: &str) -> bool {
-    get_article_fulltext(a) == get_article_fulltext(b)
+/// store a revision from `previous` to the `current` snapshot with that html
+/// in case the article fulltext, its metadata besides the modification time or its media
+/// changed, with the diff of both fulltexts, its significance and whether the publisher
+/// declared the update
+pub async fn insert_revision_if_changed<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    article: &Article,
+    previous: &Snapshot,
+    current: &SnapshotMetadata,
+    html: &str,
+) -> Result<Option<Revision>> {
+    let media = media::of_stored_snapshot(provider, current.snapshot_id).await?;
+    let changes = PageChanges::between(previous, html, media);
+    if extract::compare_article_fulltext(&previous.html, html) && changes.is_empty() {
+        return Ok(None);
+    }
+    let diff = diff::text_diff(
+        &extract::get_article_fulltext(&previous.html),
+        &extract::get_article_fulltext(html),
+    );
+    let previous_headline = extract::get_headline(&previous.html);
+    let silent_edit = !declared::extract(&article.url, html)
+        .declares_update_since(&declared::extract(&article.url, &previous.html));
+    let previous = SnapshotMetadata {
+        article_id: previous.article_id,
+        snapshot_id: previous.snapshot_id,
+        archived_at: previous.archived_at,
+        source: previous.source,
+    };
+    let headline = extract::get_headline(html);
+    let significance =
+        changes.classify(significance::classify(&previous_headline, &headline, &diff));
+    let revision = provider
+        .insert_revision(
+            &previous,
+            current,
+            &headline,
+            &diff,
+            significance,
+            silent_edit,
+        )
+        .await?;
+    Ok(Some(revision))
 }
 
-/// for tagesschau.de
-fn get_article_fulltext(html: &str) -> String {
-    let fragment = scraper::Html::parse_fragment(&html);
-    let mut fulltext = String::new();
-    
-    if let Ok(selector) = scraper::Selector::parse("div.storywrapper") {
-        for element in fragment.select(&selector) {
-            for text in element.text() {
-                let text = text.trim();
-                if !text.is_empty() {
-                    fulltext.push_str(text);
-                    fulltext.push_str("\n");
-                }
-            }
+/// the metadata besides the modification time and the media of two snapshots,
+/// which a revision may change without any change to the fulltext
+struct PageChanges {
+    metadata: (PageMetadata, PageMetadata),
+    media: Vec<MediaChange>,
+}
+
+impl PageChanges {
+    /// to a snapshot with that html and media
+    fn between(previous: &Snapshot, html: &str, media: Vec<Media>) -> Self {
+        let metadata = PageMetadata::of_snapshot(previous.page_metadata.as_deref(), &previous.html);
+        PageChanges {
+            metadata: (
+                metadata.without_modified_time(),
+                metadata::extract(html).without_modified_time(),
+            ),
+            media: media::diff(
+                &media::of_snapshot(previous.page_media.as_deref(), &previous.html),
+                &media,
+            ),
         }
-    } else if let Ok(selector) = scraper::Selector::parse("div#content") {
-        for element in fragment.select(&selector) {
-            for text in element.text() {
-                let text = text.trim();
-                if !text.is_empty() {
-                    fulltext.push_str(text);
-                    fulltext.push_str("\n");
-                }
-            }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.metadata.0 == self.metadata.1 && self.media.is_empty()
+    }
+
+    /// the more significant of these and the changes to the fulltext
+    fn classify(&self, fulltext: Significance) -> Significance {
+        fulltext
+            .max(significance::classify_metadata(
+                &self.metadata.0.diff(&self.metadata.1),
+            ))
+            .max(significance::classify_media(&self.media))
+    }
+}
+
+/// the diff stored with the revision, revisions from before diffs were stored
+/// get theirs computed from both snapshots and stored now, classified too
+pub async fn revision_diff<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    revision: &Revision,
+) -> Result<TextDiff> {
+    if let Some(paragraphs) = provider.get_revision_diff(revision.revision_id).await? {
+        return Ok(TextDiff {
+            summary: revision.summary(),
+            paragraphs,
+        });
+    }
+    let previous = provider.get_snaphot(revision.previous_snapshot_id).await?;
+    let current = provider.get_snaphot(revision.snapshot_id).await?;
+    let diff = diff::text_diff(
+        &extract::get_article_fulltext(&previous.html),
+        &extract::get_article_fulltext(&current.html),
+    );
+    let media = media::of_snapshot(current.page_media.as_deref(), &current.html);
+    let significance =
+        PageChanges::between(&previous, &current.html, media).classify(significance::classify(
+            &extract::get_headline(&previous.html),
+            &revision.headline,
+            &diff,
+        ));
+    provider
+        .update_revision_diff(revision.revision_id, &diff, significance)
+        .await?;
+    Ok(diff)
+}
+
+/// store the html unless it equals the youngest snapshot,
+/// also store a revision in case the article fulltext changed
+/// and queue the matching webhook deliveries for it
+pub async fn insert_snapshot_and_revision<P>(
+    provider: &mut P,
+    article: &Article,
+    archived_at: i32,
+    html: &str,
+) -> Result<Inserted>
+where
+    P: ProvideArticles + ProvideWebhooks + Send + ?Sized,
+{
+    insert_snapshot_and_revision_with_provenance(
+        provider,
+        article,
+        archived_at,
+        html,
+        &Provenance::default(),
+    )
+    .await
+}
+
+/// like `insert_snapshot_and_revision`, for snapshots with headers or from elsewhere
+pub async fn insert_snapshot_and_revision_with_provenance<P>(
+    provider: &mut P,
+    article: &Article,
+    archived_at: i32,
+    html: &str,
+    provenance: &Provenance,
+) -> Result<Inserted>
+where
+    P: ProvideArticles + ProvideWebhooks + Send + ?Sized,
+{
+    let youngest = provider.get_youngest_snaphot(article).await?;
+
+    if let Some(youngest) = &youngest {
+        if youngest.html == html {
+            return Ok(Inserted::default());
+        }
+    }
+
+    let current = provider
+        .insert_snapshot_with_provenance(article, archived_at, html, provenance)
+        .await?;
+    let mut revision = None;
+
+    if let Some(youngest) = youngest {
+        if let Some(inserted) =
+            insert_revision_if_changed(provider, article, &youngest, &current, html).await?
+        {
+            let fulltext = extract::get_article_fulltext(html);
+            webhook::enqueue(provider, &inserted, &fulltext, timestamp()).await?;
+            revision = Some(inserted);
         }
     }
-    fulltext
+
+    Ok(Inserted {
+        snapshot: Some(current),
+        revision,
+    })
 }
@@ -0,0 +1,228 @@
+//! how much a revision matters, from moved punctuation to a rewritten article
+//!
+//! heuristics over the paragraphs diff and both headlines classify each revision when
+//! it is stored, listings, feeds and webhooks may ask for a minimum significance
+
+use crate::diff::{Paragraph, Run, TextDiff};
+use crate::media::{Media, MediaChange};
+
+/// from least to most significant, stored as its number
+#[derive(
+    sqlx::Type,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[repr(i32)]
+#[serde(rename_all = "snake_case")]
+pub enum Significance {
+    /// whitespace, punctuation or markers like "Update 14:32"
+    Cosmetic = 0,
+    /// words with a letter or two changed
+    Typo = 1,
+    /// any other words changed
+    Wording = 2,
+    /// a number changed
+    Numeric = 3,
+    /// words within quotation marks changed
+    Quote = 4,
+    /// paragraphs added, removed or moved
+    Paragraphs = 5,
+    Headline = 6,
+    /// less than `REWRITE_SIMILARITY` of the words remain
+    Rewrite = 7,
+}
+
+impl Significance {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Significance::Cosmetic => "cosmetic",
+            Significance::Typo => "typo",
+            Significance::Wording => "wording",
+            Significance::Numeric => "numeric",
+            Significance::Quote => "quote",
+            Significance::Paragraphs => "paragraphs",
+            Significance::Headline => "headline",
+            Significance::Rewrite => "rewrite",
+        }
+    }
+}
+
+/// below this similarity of words the article was rewritten
+pub const REWRITE_SIMILARITY: f64 = 0.5;
+
+/// words which mark when an article was updated, in lower case
+const MARKERS: &[&str] = &["update", "updated", "aktualisiert", "stand", "uhr"];
+
+const QUOTATION_MARKS: &[char] = &['"', '„', '“', '”', '«', '»'];
+
+/// the most significant change of the revision
+pub fn classify(previous_headline: &str, headline: &str, diff: &TextDiff) -> Significance {
+    if diff.summary.similarity < REWRITE_SIMILARITY {
+        return Significance::Rewrite;
+    }
+    if content_words(previous_headline) != content_words(headline) {
+        return Significance::Headline;
+    }
+    classify_paragraphs(&diff.paragraphs)
+}
+
+/// metadata without its modification time diffs like the fulltext, see
+/// `metadata::PageMetadata::diff`, a swapped author is a change of words
+pub fn classify_metadata(diff: &TextDiff) -> Significance {
+    classify_paragraphs(&diff.paragraphs)
+}
+
+/// images added, removed or replaced count like paragraphs,
+/// edited alt texts, captions and credits like words
+pub fn classify_media(changes: &[MediaChange]) -> Significance {
+    let description = |media: &Media| {
+        [&media.alt, &media.caption, &media.credit]
+            .iter()
+            .filter_map(|text| text.as_deref())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    changes
+        .iter()
+        .map(|change| match change {
+            MediaChange::Edited { from, to } => {
+                classify_change(&description(from), &description(to), false)
+            }
+            _ => Significance::Paragraphs,
+        })
+        .max()
+        .unwrap_or(Significance::Cosmetic)
+}
+
+fn classify_paragraphs(paragraphs: &[Paragraph]) -> Significance {
+    paragraphs
+        .iter()
+        .map(|paragraph| match paragraph {
+            Paragraph::Equal(_) => Significance::Cosmetic,
+            Paragraph::Insert(_) | Paragraph::Remove(_) | Paragraph::Move { .. } => {
+                Significance::Paragraphs
+            }
+            Paragraph::Edit(runs) => classify_edit(runs),
+        })
+        .max()
+        .unwrap_or(Significance::Cosmetic)
+}
+
+fn classify_edit(runs: &[Run]) -> Significance {
+    let side = |old: bool| {
+        runs.iter()
+            .filter_map(|run| match run {
+                Run::Equal(text) => Some(text.as_str()),
+                Run::Remove(text) if old => Some(text.as_str()),
+                Run::Insert(text) if !old => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<String>()
+    };
+    if content_words(&side(true)) == content_words(&side(false)) {
+        return Significance::Cosmetic;
+    }
+
+    let mut significance = Significance::Cosmetic;
+    // the old paragraph up to the change, to tell whether it is within a quote
+    let mut before = String::new();
+    let mut runs = runs.iter().peekable();
+    while let Some(run) = runs.next() {
+        let (mut removed, mut inserted) = (String::new(), String::new());
+        match run {
+            Run::Equal(text) => {
+                before.push_str(text);
+                continue;
+            }
+            Run::Remove(text) => removed.push_str(text),
+            Run::Insert(text) => inserted.push_str(text),
+        }
+        while let Some(run) = runs.next_if(|run| !matches!(run, Run::Equal(_))) {
+            match run {
+                Run::Remove(text) => removed.push_str(text),
+                Run::Insert(text) => inserted.push_str(text),
+                Run::Equal(_) => unreachable!(),
+            }
+        }
+        let quoted = before.matches(QUOTATION_MARKS).count() % 2 == 1;
+        significance = significance.max(classify_change(&removed, &inserted, quoted));
+        before.push_str(&removed);
+    }
+    significance
+}
+
+fn classify_change(removed: &str, inserted: &str, quoted: bool) -> Significance {
+    let (old, new) = (words(removed), words(inserted));
+    if old == new {
+        Significance::Cosmetic
+    } else if quoted || removed.contains(QUOTATION_MARKS) || inserted.contains(QUOTATION_MARKS) {
+        Significance::Quote
+    } else if old
+        .iter()
+        .chain(&new)
+        .any(|word| word.chars().any(|c| c.is_ascii_digit()))
+    {
+        Significance::Numeric
+    } else if old.len() == new.len() && old.iter().zip(&new).all(|(a, b)| is_typo(a, b)) {
+        Significance::Typo
+    } else {
+        Significance::Wording
+    }
+}
+
+fn words(text: &str) -> Vec<&str> {
+    prettydiff::text::split_words(text)
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .collect()
+}
+
+/// the words without update markers, times like 14:32 and dates like 19.10.2026
+fn content_words(text: &str) -> Vec<&str> {
+    text.split_whitespace()
+        .map(|token| token.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|token| !is_marker(token))
+        .flat_map(words)
+        .collect()
+}
+
+fn is_marker(token: &str) -> bool {
+    let numbers = |separator: char, parts: usize| {
+        let numbers = token.split(separator).collect::<Vec<_>>();
+        numbers.len() == parts
+            && numbers
+                .iter()
+                .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    };
+    MARKERS.contains(&token.to_lowercase().as_str()) || numbers(':', 2) || numbers('.', 3)
+}
+
+/// a few letters apart relative to the length of the words, a swap of two counts once
+fn is_typo(a: &str, b: &str) -> bool {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    d[0] = (0..=b.len()).collect();
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    let distance = d[a.len()][b.len()];
+    distance <= 2 && distance * 3 <= a.len().max(b.len())
+}
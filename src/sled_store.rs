@@ -0,0 +1,1568 @@
+//! the storage in a sled directory, for single binary deployments without sqlite
+//!
+//! records are bincode, ids are keys which sort like the numbers, `articles_by_url`
+//! and `articles_by_updated_at` are the secondary indexes of articles, snapshots and
+//! revisions are indexed by article, leases of articles have a tree of their own,
+//! tokens by their hash, users by name and watchlists by user and name, the last id of
+//! every tree is counted in `meta`, and what a `SledTransaction` overwrote is kept in
+//! `journal` until it commits
+
+use crate::auth::{ProvideTokens, Role, Token};
+use crate::db::{
+    self, Article, ArticleCursor, ArticleFilter, ArticleListing, ArticleSort, DbError, DbResult,
+    Provenance, ProvideArticles, Revision, RevisionFilter, Snapshot, SnapshotMetadata,
+};
+use crate::diff::{Paragraph, TextDiff};
+use crate::media;
+use crate::metadata;
+use crate::provenance;
+use crate::significance::Significance;
+use crate::watchlist::{ProvideWatchlists, User, Watchlist};
+use crate::webhook::{Delivery, NewWebhook, ProvideWebhooks, Webhook};
+use async_lock::{RwLock, RwLockReadGuardArc, RwLockWriteGuardArc};
+use async_trait::async_trait;
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+};
+use sled::{Transactional, Tree};
+use std::convert::TryFrom;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+impl From<sled::Error> for DbError {
+    fn from(err: sled::Error) -> Self {
+        DbError::Storage(sqlx::Error::Io(err.into()))
+    }
+}
+
+impl From<bincode::Error> for DbError {
+    fn from(err: bincode::Error) -> Self {
+        DbError::Storage(sqlx::Error::Decode(err))
+    }
+}
+
+impl From<TransactionError<DbError>> for DbError {
+    fn from(err: TransactionError<DbError>) -> Self {
+        match err {
+            TransactionError::Abort(err) => err,
+            TransactionError::Storage(err) => err.into(),
+        }
+    }
+}
+
+/// about a second, sled's background threads hold the lock of a dropped store a little longer
+const LOCKED_RETRIES: u32 = 100;
+const LOCKED_PAUSE: Duration = Duration::from_millis(10);
+
+/// the key of the number of `MIGRATIONS` a store went through in `meta`
+const SCHEMA_VERSION: &[u8] = b"schema_version";
+
+/// changes to trees of existing stores, each runs once like the migrations on sqlite
+const MIGRATIONS: &[fn(&sled::Db) -> sled::Result<()>] = &[
+    // words diffs before paragraphs diffs, recomputed when asked for
+    |db| db.drop_tree("revision_diffs").map(drop),
+];
+
+/// big endian with the sign bit flipped
+fn key(n: i32) -> [u8; 4] {
+    ((n as u32) ^ (1 << 31)).to_be_bytes()
+}
+
+fn pair(first: i32, second: i32) -> [u8; 8] {
+    let mut pair = [0; 8];
+    pair[..4].copy_from_slice(&key(first));
+    pair[4..].copy_from_slice(&key(second));
+    pair
+}
+
+/// the number at the end of a key
+fn last(key: &[u8]) -> i32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&key[key.len() - 4..]);
+    (u32::from_be_bytes(bytes) ^ (1 << 31)) as i32
+}
+
+fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> DbResult<T> {
+    Ok(bincode::deserialize(bytes)?)
+}
+
+fn encode<T: serde::Serialize>(value: &T) -> DbResult<Vec<u8>> {
+    Ok(bincode::serialize(value)?)
+}
+
+fn abort(err: DbError) -> ConflictableTransactionError<DbError> {
+    ConflictableTransactionError::Abort(err)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WebhookRecord {
+    url: String,
+    secret: String,
+    host: Option<String>,
+    keyword: Option<String>,
+    min_words_changed: i32,
+    min_significance: Option<Significance>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DeliveryRecord {
+    webhook_id: i32,
+    payload: String,
+    attempts: i32,
+    next_attempt_at: Option<i32>,
+    delivered_at: Option<i32>,
+    last_error: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TokenRecord {
+    name: String,
+    role: Role,
+    user_id: Option<i32>,
+    token_hash: String,
+    created_at: i32,
+    revoked_at: Option<i32>,
+}
+
+impl TokenRecord {
+    fn token(self, token_id: i32) -> Token {
+        Token {
+            token_id,
+            name: self.name,
+            role: self.role,
+            user_id: self.user_id,
+            created_at: self.created_at,
+            revoked_at: self.revoked_at,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LeaseRecord {
+    token: String,
+    expires_at: i32,
+}
+
+#[derive(Clone)]
+pub struct SledStore {
+    db: sled::Db,
+    /// article_id → `Article`
+    articles: Tree,
+    /// url → article_id
+    articles_by_url: Tree,
+    /// updated_at, article_id → ()
+    articles_by_updated_at: Tree,
+    /// article_id → `LeaseRecord`
+    leases: Tree,
+    /// snapshot_id → `Snapshot`
+    snapshots: Tree,
+    /// article_id, snapshot_id → `SnapshotMetadata`
+    snapshots_by_article: Tree,
+    /// revision_id → `Revision`
+    revisions: Tree,
+    /// article_id, revision_id → ()
+    revisions_by_article: Tree,
+    /// revision_id → the `Paragraph`s of its diff
+    revision_diffs: Tree,
+    /// webhook_id → `WebhookRecord`
+    webhooks: Tree,
+    /// delivery_id → `DeliveryRecord`
+    deliveries: Tree,
+    /// token_id → `TokenRecord`
+    tokens: Tree,
+    /// token_hash → token_id
+    tokens_by_hash: Tree,
+    /// user_id → `User`
+    users: Tree,
+    /// name → user_id
+    users_by_name: Tree,
+    /// watchlist_id → `Watchlist`
+    watchlists: Tree,
+    /// user_id, name → watchlist_id
+    watchlists_by_name: Tree,
+    /// watchlist_id, article_id → added_at
+    watchlist_articles: Tree,
+    /// watchlist_id, host → added_at
+    watchlist_sources: Tree,
+    /// the name of a tree → the last id of its records, see `next_id`,
+    /// and the `SCHEMA_VERSION`
+    meta: Tree,
+    /// the name of a tree, 0, a key → its value before the open transaction first wrote it,
+    /// empty outside of transactions, see `journal`
+    journal: Tree,
+    /// whether writes are journaled, only for the store of a `SledTransaction`
+    journaling: bool,
+    /// shared by connections and held alone by a transaction, so no other write happens
+    /// in between a write and its rollback
+    transactions: Arc<RwLock<()>>,
+    /// held while a snapshot is inserted, so its id and the link of the hash chain it reads
+    /// are not taken by another insert meanwhile, a sled directory is opened by one process
+    inserting: Arc<Mutex<()>>,
+}
+
+impl SledStore {
+    /// every write is flushed before it returns, like a commit on sqlite,
+    /// so there is no flusher thread which keeps the directory locked after a drop,
+    /// the lock of a store dropped a moment ago is awaited though
+    pub fn open(path: &str) -> DbResult<Self> {
+        let config = sled::Config::new().path(path).flush_every_ms(None);
+        for _ in 0..LOCKED_RETRIES {
+            match config.open() {
+                Err(sled::Error::Io(err))
+                    if err.to_string().starts_with("could not acquire lock") =>
+                {
+                    std::thread::sleep(LOCKED_PAUSE)
+                }
+                db => return Self::with_db(db?),
+            }
+        }
+        Self::with_db(config.open()?)
+    }
+
+    /// removed when dropped
+    pub fn temporary() -> DbResult<Self> {
+        Self::with_db(sled::Config::new().temporary(true).open()?)
+    }
+
+    /// a transaction left open by a crash is rolled back before anything else
+    fn with_db(db: sled::Db) -> DbResult<Self> {
+        let journal = db.open_tree("journal")?;
+        Self::undo(&db, &journal)?;
+        let meta = db.open_tree("meta")?;
+        Self::migrate(&db, &meta)?;
+        let store = Self {
+            articles: db.open_tree("articles")?,
+            articles_by_url: db.open_tree("articles_by_url")?,
+            articles_by_updated_at: db.open_tree("articles_by_updated_at")?,
+            leases: db.open_tree("leases")?,
+            snapshots: db.open_tree("snapshots")?,
+            snapshots_by_article: db.open_tree("snapshots_by_article")?,
+            revisions: db.open_tree("revisions")?,
+            revisions_by_article: db.open_tree("revisions_by_article")?,
+            revision_diffs: db.open_tree("revision_paragraphs")?,
+            webhooks: db.open_tree("webhooks")?,
+            deliveries: db.open_tree("deliveries")?,
+            tokens: db.open_tree("tokens")?,
+            tokens_by_hash: db.open_tree("tokens_by_hash")?,
+            users: db.open_tree("users")?,
+            users_by_name: db.open_tree("users_by_name")?,
+            watchlists: db.open_tree("watchlists")?,
+            watchlists_by_name: db.open_tree("watchlists_by_name")?,
+            watchlist_articles: db.open_tree("watchlist_articles")?,
+            watchlist_sources: db.open_tree("watchlist_sources")?,
+            meta,
+            journal,
+            journaling: false,
+            transactions: Arc::default(),
+            inserting: Arc::default(),
+            db,
+        };
+        store.count_existing_ids()?;
+        Ok(store)
+    }
+
+    fn migrate(db: &sled::Db, meta: &Tree) -> DbResult<()> {
+        let version = match meta.get(SCHEMA_VERSION)? {
+            Some(version) => decode::<u32>(&version)?,
+            None => 0,
+        };
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(db)?;
+            meta.insert(SCHEMA_VERSION, encode(&(index as u32 + 1))?)?;
+        }
+        Ok(())
+    }
+
+    /// stores from before the counters handed out ids unique in the whole store,
+    /// their counters start at the largest id of each tree
+    fn count_existing_ids(&self) -> DbResult<()> {
+        for tree in &[
+            &self.articles,
+            &self.snapshots,
+            &self.revisions,
+            &self.webhooks,
+            &self.deliveries,
+        ] {
+            let largest = match tree.last()? {
+                Some((id, _)) => last(&id).max(0),
+                None => 0,
+            };
+            let _ = self.meta.compare_and_swap(
+                tree.name(),
+                None as Option<&[u8]>,
+                Some(&i64::from(largest).to_be_bytes()),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> DbResult<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// the store for a connection, which waits while a transaction is open
+    pub async fn share(&self) -> (SledStore, RwLockReadGuardArc<()>) {
+        (self.clone(), self.transactions.read_arc().await)
+    }
+
+    /// waits until every connection and transaction of the store ended
+    pub async fn begin(&self) -> SledTransaction {
+        let exclusive = self.transactions.write_arc().await;
+        SledTransaction {
+            store: SledStore {
+                journaling: true,
+                ..self.clone()
+            },
+            _exclusive: exclusive,
+            done: false,
+        }
+    }
+
+    /// keep the value of `key` in `tree` for a rollback unless it was kept already,
+    /// before every write to a tree but outside of sled transactions, which wait for it
+    fn journal(&self, tree: &Tree, key: impl AsRef<[u8]>) -> sled::Result<()> {
+        if !self.journaling {
+            return Ok(());
+        }
+        let key = key.as_ref();
+        let entry = [&tree.name()[..], &[0], key].concat();
+        if !self.journal.contains_key(&entry)? {
+            let value = match tree.get(key)? {
+                Some(value) => [&[1], &value[..]].concat(),
+                None => vec![0],
+            };
+            self.journal.insert(entry, value)?;
+        }
+        Ok(())
+    }
+
+    /// write back what the journal kept and empty it
+    fn undo(db: &sled::Db, journal: &Tree) -> DbResult<()> {
+        for entry in journal.iter() {
+            let (entry, value) = entry?;
+            let split = entry.iter().position(|&b| b == 0).unwrap_or(entry.len());
+            let tree = db.open_tree(&entry[..split])?;
+            let key = &entry[(split + 1).min(entry.len())..];
+            match value.split_first() {
+                Some((1, value)) => drop(tree.insert(key, value)?),
+                _ => drop(tree.remove(key)?),
+            }
+        }
+        journal.clear()?;
+        db.flush()?;
+        Ok(())
+    }
+
+    /// unique among the records of `tree`, starts at 1,
+    /// counted in `meta` so ids stay dense across reopening
+    fn next_id(&self, tree: &Tree) -> DbResult<i32> {
+        self.journal(&self.meta, tree.name())?;
+        let counted = |bytes: Option<&[u8]>| {
+            let mut count = [0; 8];
+            if let Some(bytes) = bytes {
+                count.copy_from_slice(bytes);
+            }
+            i64::from_be_bytes(count)
+        };
+        let count = self.meta.update_and_fetch(tree.name(), |count| {
+            Some((counted(count) + 1).to_be_bytes().to_vec())
+        })?;
+        i32::try_from(counted(count.as_deref())).map_err(|_| {
+            DbError::InvalidInput(format!(
+                "the ids of {} are used up",
+                String::from_utf8_lossy(&tree.name())
+            ))
+        })
+    }
+
+    fn all<T: serde::de::DeserializeOwned>(tree: &Tree) -> DbResult<Vec<T>> {
+        tree.iter()
+            .map(|entry| decode(&entry?.1))
+            .collect::<DbResult<Vec<_>>>()
+    }
+
+    /// the ids at the end of the keys with that article_id in front
+    fn ids_of_article(tree: &Tree, article_id: i32) -> DbResult<Vec<i32>> {
+        tree.scan_prefix(key(article_id))
+            .keys()
+            .map(|key| Ok(last(&key?)))
+            .collect()
+    }
+
+    fn article_revisions(&self, article_id: i32) -> DbResult<Vec<Revision>> {
+        Self::ids_of_article(&self.revisions_by_article, article_id)?
+            .into_iter()
+            .map(|id| self.get_revision_sync(id))
+            .collect()
+    }
+
+    fn get_revision_sync(&self, revision_id: i32) -> DbResult<Revision> {
+        match self.revisions.get(key(revision_id))? {
+            Some(bytes) => decode(&bytes),
+            None => Err(DbError::NotFound("revision not found".to_owned())),
+        }
+    }
+
+    fn get_token_by_id(&self, token_id: i32) -> DbResult<Token> {
+        match self.tokens.get(key(token_id))? {
+            Some(bytes) => Ok(decode::<TokenRecord>(&bytes)?.token(token_id)),
+            None => Err(DbError::NotFound("token not found".to_owned())),
+        }
+    }
+
+    /// on the watchlist or from one of its sources
+    fn in_watchlist(&self, watchlist_id: i32, article_id: i32, host: &str) -> DbResult<bool> {
+        Ok(self
+            .watchlist_articles
+            .contains_key(pair(watchlist_id, article_id))?
+            || self
+                .watchlist_sources
+                .contains_key([&key(watchlist_id), host.as_bytes()].concat())?)
+    }
+
+    fn get_article_by_id_sync(&self, article_id: i32) -> DbResult<Article> {
+        match self.articles.get(key(article_id))? {
+            Some(bytes) => decode(&bytes),
+            None => Err(DbError::NotFound("article not found".to_owned())),
+        }
+    }
+
+    fn get_article_sync(&self, url: &str) -> DbResult<Article> {
+        match self.articles_by_url.get(url)? {
+            Some(id) => self.get_article_by_id_sync(last(&id)),
+            None => Err(DbError::NotFound("article not found".to_owned())),
+        }
+    }
+
+    /// insert the urls which are new at once, with whether each one was new
+    fn insert_urls(&self, urls: &[String]) -> DbResult<Vec<(Article, bool)>> {
+        let created_at = crate::scraper::timestamp();
+        let mut ids = vec![];
+        for _ in urls {
+            ids.push(self.next_id(&self.articles)?);
+        }
+        for (url, id) in urls.iter().zip(&ids) {
+            self.journal(&self.articles, key(*id))?;
+            self.journal(&self.articles_by_url, url)?;
+            self.journal(&self.articles_by_updated_at, pair(0, *id))?;
+        }
+
+        let added = (
+            &self.articles,
+            &self.articles_by_url,
+            &self.articles_by_updated_at,
+        )
+            .transaction(|(articles, by_url, by_updated_at)| {
+                let mut added = vec![];
+                for (url, id) in urls.iter().zip(&ids) {
+                    if by_url.get(url.as_bytes())?.is_some() {
+                        added.push(false);
+                        continue;
+                    }
+                    let article = Article {
+                        url: url.clone(),
+                        article_id: *id,
+                        host: db::url_host(url),
+                        created_at,
+                        updated_at: 0,
+                    };
+                    articles.insert(&key(*id), encode(&article).map_err(abort)?)?;
+                    by_url.insert(url.as_bytes(), &key(*id))?;
+                    by_updated_at.insert(&pair(0, *id), &[])?;
+                    added.push(true);
+                }
+                Ok(added)
+            })?;
+        self.flush()?;
+
+        urls.iter()
+            .zip(added)
+            .map(|(url, added)| Ok((self.get_article_sync(url)?, added)))
+            .collect()
+    }
+
+    fn listing(&self, article: Article, revisions: &[Revision]) -> DbResult<ArticleListing> {
+        let snapshots = self
+            .snapshots_by_article
+            .scan_prefix(key(article.article_id))
+            .count();
+        Ok(ArticleListing {
+            article_id: article.article_id,
+            url: article.url,
+            host: article.host,
+            created_at: article.created_at,
+            updated_at: article.updated_at,
+            headline: revisions
+                .last()
+                .map(|r| r.headline.clone())
+                .unwrap_or_default(),
+            snapshots: snapshots as i32,
+            revisions: revisions.len() as i32,
+            last_changed_at: revisions.iter().map(|r| r.archived_at).max(),
+        })
+    }
+}
+
+/// the writes through it are undone unless it commits, also when dropped,
+/// connections of the store wait until it ended
+pub struct SledTransaction {
+    store: SledStore,
+    _exclusive: RwLockWriteGuardArc<()>,
+    done: bool,
+}
+
+impl SledTransaction {
+    pub fn commit(mut self) -> DbResult<()> {
+        self.done = true;
+        self.store.journal.clear()?;
+        self.store.flush()
+    }
+
+    pub fn rollback(mut self) -> DbResult<()> {
+        self.done = true;
+        SledStore::undo(&self.store.db, &self.store.journal)
+    }
+}
+
+impl Drop for SledTransaction {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Err(err) = SledStore::undo(&self.store.db, &self.store.journal) {
+                tide::log::error!("rolling back a dropped transaction failed: {}", err);
+            }
+        }
+    }
+}
+
+impl Deref for SledTransaction {
+    type Target = SledStore;
+
+    fn deref(&self) -> &SledStore {
+        &self.store
+    }
+}
+
+impl DerefMut for SledTransaction {
+    fn deref_mut(&mut self) -> &mut SledStore {
+        &mut self.store
+    }
+}
+
+/// like `LIKE '%q%'` on sqlite, case insensitive
+fn contains(haystack: &str, q: &str) -> bool {
+    haystack.to_lowercase().contains(&q.to_lowercase())
+}
+
+fn listed(
+    filter: &ArticleFilter,
+    sort: ArticleSort,
+    cursor: Option<ArticleCursor>,
+    listing: &ArticleListing,
+    revisions: &[Revision],
+) -> bool {
+    let changed = |check: fn(i32, i32) -> bool, bound: Option<i32>| {
+        bound.is_none_or(|bound| listing.last_changed_at.is_some_and(|at| check(at, bound)))
+    };
+    filter.q.as_ref().is_none_or(|q| {
+        contains(&listing.url, q) || revisions.iter().any(|r| contains(&r.headline, q))
+    }) && filter
+        .host
+        .as_ref()
+        .is_none_or(|host| *host == listing.host)
+        && filter
+            .has_changes
+            .is_none_or(|has_changes| has_changes == (listing.revisions > 0))
+        && filter
+            .created_since
+            .is_none_or(|since| listing.created_at >= since)
+        && filter
+            .created_until
+            .is_none_or(|until| listing.created_at <= until)
+        && changed(|at, since| at >= since, filter.changed_since)
+        && changed(|at, until| at <= until, filter.changed_until)
+        && cursor.is_none_or(|cursor| {
+            let key = sort.key(listing);
+            key < cursor.key || (key == cursor.key && listing.article_id < cursor.article_id)
+        })
+}
+
+#[async_trait]
+impl ProvideArticles for SledStore {
+    async fn ensure_created_tables(&mut self) -> DbResult<()> {
+        Ok(())
+    }
+
+    async fn get_outdated_articles(&mut self, limit: i32) -> DbResult<Vec<Article>> {
+        self.articles_by_updated_at
+            .iter()
+            .keys()
+            .take(limit.max(0) as usize)
+            .map(|key| self.get_article_by_id_sync(last(&key?)))
+            .collect()
+    }
+
+    async fn claim_article(
+        &mut self,
+        token: &str,
+        now: i32,
+        expires_at: i32,
+    ) -> DbResult<Option<Article>> {
+        let lease = encode(&LeaseRecord {
+            token: token.to_owned(),
+            expires_at,
+        })?;
+        for entry in self.articles_by_updated_at.iter().keys() {
+            let article_id = last(&entry?);
+            self.journal(&self.leases, key(article_id))?;
+            let claimed = self.leases.transaction(|leases| {
+                let current = match leases.get(key(article_id))? {
+                    Some(bytes) => Some(decode::<LeaseRecord>(&bytes).map_err(abort)?),
+                    None => None,
+                };
+                if current.is_some_and(|current| current.expires_at > now) {
+                    return Ok(false);
+                }
+                leases.insert(&key(article_id), lease.as_slice())?;
+                Ok(true)
+            })?;
+            if claimed {
+                self.flush()?;
+                return self.get_article_by_id_sync(article_id).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    async fn release_article(
+        &mut self,
+        article_id: i32,
+        token: &str,
+        updated_at: i32,
+    ) -> DbResult<bool> {
+        match self.get_article_by_id_sync(article_id) {
+            Ok(article) => {
+                self.journal(&self.leases, key(article_id))?;
+                self.journal(&self.articles, key(article_id))?;
+                self.journal(
+                    &self.articles_by_updated_at,
+                    pair(article.updated_at, article_id),
+                )?;
+                self.journal(&self.articles_by_updated_at, pair(updated_at, article_id))?;
+            }
+            Err(DbError::NotFound(_)) => (),
+            Err(err) => return Err(err),
+        }
+        let released = (&self.leases, &self.articles, &self.articles_by_updated_at).transaction(
+            |(leases, articles, by_updated_at)| {
+                let leased = match leases.get(key(article_id))? {
+                    Some(bytes) => decode::<LeaseRecord>(&bytes).map_err(abort)?.token == token,
+                    None => false,
+                };
+                let article = match articles.get(key(article_id))? {
+                    Some(bytes) if leased => decode::<Article>(&bytes).map_err(abort)?,
+                    _ => return Ok(false),
+                };
+                let updated = Article {
+                    updated_at,
+                    ..article.clone()
+                };
+                leases.remove(&key(article_id))?;
+                articles.insert(&key(article_id), encode(&updated).map_err(abort)?)?;
+                by_updated_at.remove(&pair(article.updated_at, article_id))?;
+                by_updated_at.insert(&pair(updated_at, article_id), &[])?;
+                Ok(true)
+            },
+        )?;
+        self.flush()?;
+        Ok(released)
+    }
+
+    async fn get_articles(&mut self, offset: i32, limit: i32) -> DbResult<Vec<Article>> {
+        let mut articles = Self::all::<Article>(&self.articles)?;
+        articles.sort_by_key(|a| (a.created_at, a.article_id));
+        Ok(articles
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn insert_article(&mut self, url: &str) -> DbResult<Article> {
+        let mut inserted = self.insert_urls(&[url.to_owned()])?;
+        Ok(inserted.remove(0).0)
+    }
+
+    async fn insert_articles(&mut self, urls: &[String]) -> DbResult<Vec<(Article, bool)>> {
+        self.insert_urls(urls)
+    }
+
+    async fn update_article(&mut self, url: &str, updated_at: i32) -> DbResult<()> {
+        let article = match self.get_article_sync(url) {
+            Ok(article) => article,
+            Err(DbError::NotFound(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let updated = Article {
+            updated_at,
+            ..article.clone()
+        };
+        let bytes = encode(&updated)?;
+        self.journal(&self.articles, key(article.article_id))?;
+        self.journal(
+            &self.articles_by_updated_at,
+            pair(article.updated_at, article.article_id),
+        )?;
+        self.journal(
+            &self.articles_by_updated_at,
+            pair(updated_at, article.article_id),
+        )?;
+        (&self.articles, &self.articles_by_updated_at).transaction(
+            |(articles, by_updated_at)| {
+                articles.insert(&key(article.article_id), bytes.as_slice())?;
+                by_updated_at.remove(&pair(article.updated_at, article.article_id))?;
+                by_updated_at.insert(&pair(updated_at, article.article_id), &[])?;
+                Ok(())
+            },
+        )?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn get_article(&mut self, url: &str) -> DbResult<Article> {
+        self.get_article_sync(url)
+    }
+
+    async fn get_article_by_id(&mut self, article_id: i32) -> DbResult<Article> {
+        self.get_article_by_id_sync(article_id)
+    }
+
+    async fn delete_article(&mut self, article_id: i32) -> DbResult<()> {
+        let article = match self.get_article_by_id_sync(article_id) {
+            Ok(article) => article,
+            Err(DbError::NotFound(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let snapshot_ids = Self::ids_of_article(&self.snapshots_by_article, article_id)?;
+        let revision_ids = Self::ids_of_article(&self.revisions_by_article, article_id)?;
+        let watchlist_entries = self
+            .watchlist_articles
+            .iter()
+            .keys()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |entry| last(entry) == article_id)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let trees = [
+            &self.articles,
+            &self.articles_by_url,
+            &self.articles_by_updated_at,
+            &self.snapshots,
+            &self.snapshots_by_article,
+            &self.revisions,
+            &self.revisions_by_article,
+            &self.leases,
+            &self.revision_diffs,
+            &self.watchlist_articles,
+        ];
+        self.journal(trees[7], key(article_id))?;
+        self.journal(trees[0], key(article_id))?;
+        self.journal(trees[1], &article.url)?;
+        self.journal(trees[2], pair(article.updated_at, article_id))?;
+        for id in &snapshot_ids {
+            self.journal(trees[3], key(*id))?;
+            self.journal(trees[4], pair(article_id, *id))?;
+        }
+        for id in &revision_ids {
+            self.journal(trees[5], key(*id))?;
+            self.journal(trees[6], pair(article_id, *id))?;
+            self.journal(trees[8], key(*id))?;
+        }
+        for entry in &watchlist_entries {
+            self.journal(trees[9], entry)?;
+        }
+        trees[..].transaction(|trees| {
+            trees[7].remove(&key(article_id))?;
+            trees[0].remove(&key(article_id))?;
+            trees[1].remove(article.url.as_bytes())?;
+            trees[2].remove(&pair(article.updated_at, article_id))?;
+            for id in &snapshot_ids {
+                trees[3].remove(&key(*id))?;
+                trees[4].remove(&pair(article_id, *id))?;
+            }
+            for id in &revision_ids {
+                trees[5].remove(&key(*id))?;
+                trees[6].remove(&pair(article_id, *id))?;
+                trees[8].remove(&key(*id))?;
+            }
+            for entry in &watchlist_entries {
+                trees[9].remove(entry)?;
+            }
+            Ok::<_, ConflictableTransactionError<DbError>>(())
+        })?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn list_articles(
+        &mut self,
+        filter: &ArticleFilter,
+        sort: ArticleSort,
+        cursor: Option<ArticleCursor>,
+        limit: i32,
+    ) -> DbResult<Vec<ArticleListing>> {
+        let mut listings = vec![];
+        for article in Self::all::<Article>(&self.articles)? {
+            if let Some(watchlist_id) = filter.watchlist {
+                if !self.in_watchlist(watchlist_id, article.article_id, &article.host)? {
+                    continue;
+                }
+            }
+            let revisions = self.article_revisions(article.article_id)?;
+            let listing = self.listing(article, &revisions)?;
+            if listed(filter, sort, cursor, &listing, &revisions) {
+                listings.push(listing);
+            }
+        }
+        listings.sort_by_key(|l| std::cmp::Reverse((sort.key(l), l.article_id)));
+        listings.truncate(limit.max(0) as usize);
+        Ok(listings)
+    }
+
+    async fn get_snaphot_metadatas_from_article(
+        &mut self,
+        article_id: i32,
+    ) -> DbResult<Vec<SnapshotMetadata>> {
+        self.snapshots_by_article
+            .scan_prefix(key(article_id))
+            .values()
+            .map(|metadata| decode(&metadata?))
+            .collect()
+    }
+
+    async fn get_youngest_snaphot(&mut self, article: &Article) -> DbResult<Option<Snapshot>> {
+        let youngest = self
+            .get_snaphot_metadatas_from_article(article.article_id)
+            .await?
+            .into_iter()
+            .max_by_key(|s| (s.archived_at, s.snapshot_id));
+        match youngest {
+            Some(youngest) => Ok(Some(self.get_snaphot(youngest.snapshot_id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_snapshot_media(&mut self, snapshot_id: i32) -> DbResult<Option<String>> {
+        Ok(self.get_snaphot(snapshot_id).await?.page_media)
+    }
+
+    async fn get_youngest_snapshot_media(&mut self, article_id: i32) -> DbResult<Option<String>> {
+        let youngest = self
+            .get_snaphot_metadatas_from_article(article_id)
+            .await?
+            .into_iter()
+            .max_by_key(|s| (s.archived_at, s.snapshot_id));
+        match youngest {
+            Some(youngest) => self.get_snapshot_media(youngest.snapshot_id).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn get_snaphot(&mut self, id: i32) -> DbResult<Snapshot> {
+        match self.snapshots.get(key(id))? {
+            Some(bytes) => decode(&bytes),
+            None => Err(DbError::NotFound("snapshot not found".to_owned())),
+        }
+    }
+
+    async fn insert_snapshot(
+        &mut self,
+        article: &Article,
+        archived_at: i32,
+        html: &str,
+    ) -> DbResult<SnapshotMetadata> {
+        self.insert_snapshot_with_provenance(article, archived_at, html, &Provenance::default())
+            .await
+    }
+
+    async fn insert_snapshot_with_provenance(
+        &mut self,
+        article: &Article,
+        archived_at: i32,
+        html: &str,
+        provenance: &Provenance,
+    ) -> DbResult<SnapshotMetadata> {
+        let _inserting = self.inserting.lock().expect("inserting");
+        let previous = match self
+            .snapshots_by_article
+            .scan_prefix(key(article.article_id))
+            .keys()
+            .next_back()
+        {
+            Some(previous) => match self.snapshots.get(key(last(&previous?)))? {
+                Some(bytes) => decode::<Snapshot>(&bytes)?.chain_sha256,
+                None => None,
+            },
+            None => None,
+        };
+
+        let body_sha256 = provenance.body_sha256(html);
+        let snapshot = Snapshot {
+            article_id: article.article_id,
+            snapshot_id: self.next_id(&self.snapshots)?,
+            archived_at,
+            html: html.to_owned(),
+            source: provenance.source,
+            source_url: provenance.source_url.clone(),
+            chain_sha256: provenance::next_chain_sha256(
+                previous.as_deref(),
+                article.article_id,
+                archived_at,
+                provenance,
+                &body_sha256,
+            ),
+            body_sha256: Some(body_sha256),
+            request_headers: provenance::headers_json(&provenance.request_headers),
+            response_headers: provenance::headers_json(&provenance.response_headers),
+            page_metadata: metadata::extract(html).to_json(),
+            page_media: media::to_json(&provenance.media(html)),
+        };
+        let metadata = SnapshotMetadata {
+            article_id: snapshot.article_id,
+            snapshot_id: snapshot.snapshot_id,
+            archived_at,
+            source: snapshot.source,
+        };
+
+        let (snapshot_bytes, metadata_bytes) = (encode(&snapshot)?, encode(&metadata)?);
+        self.journal(&self.snapshots, key(metadata.snapshot_id))?;
+        self.journal(
+            &self.snapshots_by_article,
+            pair(metadata.article_id, metadata.snapshot_id),
+        )?;
+        (&self.snapshots, &self.snapshots_by_article).transaction(|(snapshots, by_article)| {
+            snapshots.insert(&key(metadata.snapshot_id), snapshot_bytes.as_slice())?;
+            by_article.insert(
+                &pair(metadata.article_id, metadata.snapshot_id),
+                metadata_bytes.as_slice(),
+            )?;
+            Ok(())
+        })?;
+        self.flush()?;
+        Ok(metadata)
+    }
+
+    async fn insert_revision(
+        &mut self,
+        previous: &SnapshotMetadata,
+        current: &SnapshotMetadata,
+        headline: &str,
+        diff: &TextDiff,
+        significance: Significance,
+        silent_edit: bool,
+    ) -> DbResult<Revision> {
+        let summary = &diff.summary;
+        let article = self.get_article_by_id_sync(current.article_id)?;
+        let revision = Revision {
+            revision_id: self.next_id(&self.revisions)?,
+            article_id: article.article_id,
+            url: article.url,
+            headline: headline.to_owned(),
+            previous_snapshot_id: previous.snapshot_id,
+            previous_archived_at: previous.archived_at,
+            snapshot_id: current.snapshot_id,
+            archived_at: current.archived_at,
+            words_added: summary.words_added,
+            words_removed: summary.words_removed,
+            paragraphs_added: summary.paragraphs_added,
+            paragraphs_removed: summary.paragraphs_removed,
+            similarity: Some(summary.similarity),
+            significance: Some(significance),
+            silent_edit: Some(silent_edit),
+        };
+
+        let bytes = encode(&revision)?;
+        let paragraphs = encode(&diff.paragraphs)?;
+        self.journal(&self.revisions, key(revision.revision_id))?;
+        self.journal(
+            &self.revisions_by_article,
+            pair(revision.article_id, revision.revision_id),
+        )?;
+        self.journal(&self.revision_diffs, key(revision.revision_id))?;
+        (
+            &self.revisions,
+            &self.revisions_by_article,
+            &self.revision_diffs,
+        )
+            .transaction(
+                |(revisions, by_article, diffs)| -> ConflictableTransactionResult<(), DbError> {
+                    revisions.insert(&key(revision.revision_id), bytes.as_slice())?;
+                    by_article.insert(&pair(revision.article_id, revision.revision_id), &[])?;
+                    diffs.insert(&key(revision.revision_id), paragraphs.as_slice())?;
+                    Ok(())
+                },
+            )?;
+        self.flush()?;
+        Ok(revision)
+    }
+
+    async fn get_revision(&mut self, revision_id: i32) -> DbResult<Revision> {
+        self.get_revision_sync(revision_id)
+    }
+
+    async fn get_revision_diff(&mut self, revision_id: i32) -> DbResult<Option<Vec<Paragraph>>> {
+        self.get_revision_sync(revision_id)?;
+        match self.revision_diffs.get(key(revision_id))? {
+            Some(bytes) => decode(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn update_revision_diff(
+        &mut self,
+        revision_id: i32,
+        diff: &TextDiff,
+        significance: Significance,
+    ) -> DbResult<()> {
+        let summary = &diff.summary;
+        let revision = Revision {
+            words_added: summary.words_added,
+            words_removed: summary.words_removed,
+            paragraphs_added: summary.paragraphs_added,
+            paragraphs_removed: summary.paragraphs_removed,
+            similarity: Some(summary.similarity),
+            significance: Some(significance),
+            ..self.get_revision_sync(revision_id)?
+        };
+        let bytes = encode(&revision)?;
+        let paragraphs = encode(&diff.paragraphs)?;
+        self.journal(&self.revisions, key(revision_id))?;
+        self.journal(&self.revision_diffs, key(revision_id))?;
+        (&self.revisions, &self.revision_diffs).transaction(
+            |(revisions, diffs)| -> ConflictableTransactionResult<(), DbError> {
+                revisions.insert(&key(revision_id), bytes.as_slice())?;
+                diffs.insert(&key(revision_id), paragraphs.as_slice())?;
+                Ok(())
+            },
+        )?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn delete_revision(&mut self, revision_id: i32) -> DbResult<()> {
+        let revision = match self.get_revision_sync(revision_id) {
+            Ok(revision) => revision,
+            Err(DbError::NotFound(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        self.journal(&self.revisions, key(revision_id))?;
+        self.journal(
+            &self.revisions_by_article,
+            pair(revision.article_id, revision_id),
+        )?;
+        self.journal(&self.revision_diffs, key(revision_id))?;
+        (
+            &self.revisions,
+            &self.revisions_by_article,
+            &self.revision_diffs,
+        )
+            .transaction(
+                |(revisions, by_article, diffs)| -> ConflictableTransactionResult<(), DbError> {
+                    revisions.remove(&key(revision_id))?;
+                    by_article.remove(&pair(revision.article_id, revision_id))?;
+                    diffs.remove(&key(revision_id))?;
+                    Ok(())
+                },
+            )?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn get_revisions(
+        &mut self,
+        filter: &RevisionFilter,
+        cursor: Option<i32>,
+        limit: i32,
+    ) -> DbResult<Vec<Revision>> {
+        let mut revisions = vec![];
+        for entry in self
+            .revisions
+            .range(..key(cursor.unwrap_or(i32::MAX)))
+            .values()
+            .rev()
+        {
+            if revisions.len() >= limit.max(0) as usize {
+                break;
+            }
+            let revision: Revision = decode(&entry?)?;
+            if filter.url.as_ref().is_none_or(|url| *url == revision.url)
+                && filter
+                    .host
+                    .as_ref()
+                    .is_none_or(|host| *host == db::url_host(&revision.url))
+                && filter.min_significance.is_none_or(|min| {
+                    revision
+                        .significance
+                        .is_some_and(|significance| significance >= min)
+                })
+                && filter
+                    .silent_edit
+                    .is_none_or(|silent| revision.silent_edit == Some(silent))
+                && match filter.watchlist {
+                    Some(watchlist_id) => self.in_watchlist(
+                        watchlist_id,
+                        revision.article_id,
+                        &db::url_host(&revision.url),
+                    )?,
+                    None => true,
+                }
+            {
+                revisions.push(revision);
+            }
+        }
+        Ok(revisions)
+    }
+}
+
+#[async_trait]
+impl ProvideWebhooks for SledStore {
+    async fn insert_webhook(&mut self, webhook: &NewWebhook) -> DbResult<Webhook> {
+        let webhook_id = self.next_id(&self.webhooks)?;
+        let record = WebhookRecord {
+            url: webhook.url.clone(),
+            secret: webhook.secret.clone(),
+            host: webhook.host.clone(),
+            keyword: webhook.keyword.clone(),
+            min_words_changed: webhook.min_words_changed,
+            min_significance: webhook.min_significance,
+        };
+        self.journal(&self.webhooks, key(webhook_id))?;
+        self.webhooks.insert(key(webhook_id), encode(&record)?)?;
+        self.flush()?;
+        Ok(Webhook {
+            webhook_id,
+            url: record.url,
+            secret: record.secret,
+            host: record.host,
+            keyword: record.keyword,
+            min_words_changed: record.min_words_changed,
+            min_significance: record.min_significance,
+        })
+    }
+
+    async fn get_webhooks(&mut self) -> DbResult<Vec<Webhook>> {
+        self.webhooks
+            .iter()
+            .map(|entry| {
+                let (id, bytes) = entry?;
+                let record: WebhookRecord = decode(&bytes)?;
+                Ok(Webhook {
+                    webhook_id: last(&id),
+                    url: record.url,
+                    secret: record.secret,
+                    host: record.host,
+                    keyword: record.keyword,
+                    min_words_changed: record.min_words_changed,
+                    min_significance: record.min_significance,
+                })
+            })
+            .collect()
+    }
+
+    async fn delete_webhook(&mut self, webhook_id: i32) -> DbResult<()> {
+        for entry in self.deliveries.iter() {
+            let (id, bytes) = entry?;
+            if decode::<DeliveryRecord>(&bytes)?.webhook_id == webhook_id {
+                self.journal(&self.deliveries, &id)?;
+                self.deliveries.remove(id)?;
+            }
+        }
+        self.journal(&self.webhooks, key(webhook_id))?;
+        self.webhooks.remove(key(webhook_id))?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn insert_delivery(&mut self, webhook_id: i32, payload: &str, now: i32) -> DbResult<()> {
+        let record = DeliveryRecord {
+            webhook_id,
+            payload: payload.to_owned(),
+            attempts: 0,
+            next_attempt_at: Some(now),
+            delivered_at: None,
+            last_error: None,
+        };
+        let delivery_id = self.next_id(&self.deliveries)?;
+        self.journal(&self.deliveries, key(delivery_id))?;
+        self.deliveries.insert(key(delivery_id), encode(&record)?)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn get_due_deliveries(&mut self, now: i32, limit: i32) -> DbResult<Vec<Delivery>> {
+        let mut due = vec![];
+        for entry in self.deliveries.iter() {
+            let (id, bytes) = entry?;
+            let record: DeliveryRecord = decode(&bytes)?;
+            let next_attempt_at = match record.next_attempt_at {
+                Some(at) if at <= now => at,
+                _ => continue,
+            };
+            if let Some(webhook) = self.webhooks.get(key(record.webhook_id))? {
+                let webhook: WebhookRecord = decode(&webhook)?;
+                let delivery = Delivery {
+                    delivery_id: last(&id),
+                    webhook_id: record.webhook_id,
+                    url: webhook.url,
+                    secret: webhook.secret,
+                    payload: record.payload,
+                    attempts: record.attempts,
+                };
+                due.push((next_attempt_at, delivery));
+            }
+        }
+        due.sort_by_key(|(at, _)| *at);
+        Ok(due
+            .into_iter()
+            .take(limit.max(0) as usize)
+            .map(|(_, delivery)| delivery)
+            .collect())
+    }
+
+    async fn update_delivery(
+        &mut self,
+        delivery_id: i32,
+        attempts: i32,
+        next_attempt_at: Option<i32>,
+        delivered_at: Option<i32>,
+        last_error: Option<String>,
+    ) -> DbResult<()> {
+        if let Some(bytes) = self.deliveries.get(key(delivery_id))? {
+            let record = DeliveryRecord {
+                attempts,
+                next_attempt_at,
+                delivered_at,
+                last_error,
+                ..decode(&bytes)?
+            };
+            self.journal(&self.deliveries, key(delivery_id))?;
+            self.deliveries.insert(key(delivery_id), encode(&record)?)?;
+        }
+        self.flush()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProvideTokens for SledStore {
+    async fn insert_token(
+        &mut self,
+        name: &str,
+        role: Role,
+        user_id: Option<i32>,
+        token_hash: &str,
+        created_at: i32,
+    ) -> DbResult<Token> {
+        let token_id = self.next_id(&self.tokens)?;
+        let record = encode(&TokenRecord {
+            name: name.to_owned(),
+            role,
+            user_id,
+            token_hash: token_hash.to_owned(),
+            created_at,
+            revoked_at: None,
+        })?;
+        self.journal(&self.tokens, key(token_id))?;
+        self.journal(&self.tokens_by_hash, token_hash)?;
+        (&self.tokens, &self.tokens_by_hash).transaction(|(tokens, by_hash)| {
+            if by_hash.get(token_hash)?.is_some() {
+                return Err(abort(DbError::Conflict("token exists".to_owned())));
+            }
+            tokens.insert(&key(token_id), record.as_slice())?;
+            by_hash.insert(token_hash, &key(token_id))?;
+            Ok(())
+        })?;
+        self.flush()?;
+        self.get_token_by_id(token_id)
+    }
+
+    async fn get_token(&mut self, token_hash: &str) -> DbResult<Token> {
+        let token = match self.tokens_by_hash.get(token_hash)? {
+            Some(id) => self.get_token_by_id(last(&id))?,
+            None => return Err(DbError::NotFound("token not found".to_owned())),
+        };
+        if token.revoked_at.is_some() {
+            return Err(DbError::NotFound("token not found".to_owned()));
+        }
+        Ok(token)
+    }
+
+    async fn get_tokens(&mut self) -> DbResult<Vec<Token>> {
+        self.tokens
+            .iter()
+            .map(|entry| {
+                let (id, bytes) = entry?;
+                Ok(decode::<TokenRecord>(&bytes)?.token(last(&id)))
+            })
+            .collect()
+    }
+
+    async fn revoke_token(&mut self, token_id: i32, revoked_at: i32) -> DbResult<()> {
+        self.journal(&self.tokens, key(token_id))?;
+        let revoked = self.tokens.transaction(|tokens| {
+            let record = match tokens.get(key(token_id))? {
+                Some(bytes) => decode::<TokenRecord>(&bytes).map_err(abort)?,
+                None => return Ok(false),
+            };
+            if record.revoked_at.is_some() {
+                return Ok(false);
+            }
+            let record = TokenRecord {
+                revoked_at: Some(revoked_at),
+                ..record
+            };
+            tokens.insert(&key(token_id), encode(&record).map_err(abort)?)?;
+            Ok(true)
+        })?;
+        if !revoked {
+            return Err(DbError::NotFound(format!(
+                "active token {} not found",
+                token_id
+            )));
+        }
+        self.flush()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProvideWatchlists for SledStore {
+    async fn insert_user(&mut self, name: &str, created_at: i32) -> DbResult<User> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(DbError::InvalidInput("user name is empty".to_owned()));
+        }
+        let user = User {
+            user_id: self.next_id(&self.users)?,
+            name: name.to_owned(),
+            created_at,
+        };
+        let bytes = encode(&user)?;
+        self.journal(&self.users, key(user.user_id))?;
+        self.journal(&self.users_by_name, name)?;
+        (&self.users, &self.users_by_name).transaction(|(users, by_name)| {
+            if by_name.get(name)?.is_some() {
+                return Err(abort(DbError::Conflict(format!("user {} exists", name))));
+            }
+            users.insert(&key(user.user_id), bytes.as_slice())?;
+            by_name.insert(name, &key(user.user_id))?;
+            Ok(())
+        })?;
+        self.flush()?;
+        Ok(user)
+    }
+
+    async fn get_users(&mut self) -> DbResult<Vec<User>> {
+        Self::all(&self.users)
+    }
+
+    async fn get_user(&mut self, user_id: i32) -> DbResult<User> {
+        match self.users.get(key(user_id))? {
+            Some(bytes) => decode(&bytes),
+            None => Err(DbError::NotFound("user not found".to_owned())),
+        }
+    }
+
+    async fn get_user_by_name(&mut self, name: &str) -> DbResult<User> {
+        match self.users_by_name.get(name)? {
+            Some(id) => self.get_user(last(&id)).await,
+            None => Err(DbError::NotFound("user not found".to_owned())),
+        }
+    }
+
+    async fn insert_watchlist(
+        &mut self,
+        user_id: i32,
+        name: &str,
+        created_at: i32,
+    ) -> DbResult<Watchlist> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(DbError::InvalidInput("watchlist name is empty".to_owned()));
+        }
+        let watchlist = Watchlist {
+            watchlist_id: self.next_id(&self.watchlists)?,
+            user_id,
+            name: name.to_owned(),
+            created_at,
+        };
+        let bytes = encode(&watchlist)?;
+        let by_name_key = [&key(user_id), name.as_bytes()].concat();
+        self.journal(&self.watchlists, key(watchlist.watchlist_id))?;
+        self.journal(&self.watchlists_by_name, &by_name_key)?;
+        (&self.watchlists, &self.watchlists_by_name).transaction(|(watchlists, by_name)| {
+            if by_name.get(&by_name_key)?.is_some() {
+                return Err(abort(DbError::Conflict(format!(
+                    "watchlist {} exists",
+                    name
+                ))));
+            }
+            watchlists.insert(&key(watchlist.watchlist_id), bytes.as_slice())?;
+            by_name.insert(by_name_key.as_slice(), &key(watchlist.watchlist_id))?;
+            Ok(())
+        })?;
+        self.flush()?;
+        Ok(watchlist)
+    }
+
+    async fn get_watchlists(&mut self, user_id: Option<i32>) -> DbResult<Vec<Watchlist>> {
+        Ok(Self::all::<Watchlist>(&self.watchlists)?
+            .into_iter()
+            .filter(|watchlist| user_id.is_none_or(|user_id| watchlist.user_id == user_id))
+            .collect())
+    }
+
+    async fn get_watchlist(&mut self, watchlist_id: i32) -> DbResult<Watchlist> {
+        match self.watchlists.get(key(watchlist_id))? {
+            Some(bytes) => decode(&bytes),
+            None => Err(DbError::NotFound("watchlist not found".to_owned())),
+        }
+    }
+
+    async fn delete_watchlist(&mut self, watchlist_id: i32) -> DbResult<()> {
+        let watchlist = match self.get_watchlist(watchlist_id).await {
+            Ok(watchlist) => watchlist,
+            Err(DbError::NotFound(_)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let by_name_key = [&key(watchlist.user_id), watchlist.name.as_bytes()].concat();
+        let entries = |tree: &Tree| -> DbResult<Vec<sled::IVec>> {
+            tree.scan_prefix(key(watchlist_id))
+                .keys()
+                .map(|key| Ok(key?))
+                .collect()
+        };
+        let (articles, sources) = (
+            entries(&self.watchlist_articles)?,
+            entries(&self.watchlist_sources)?,
+        );
+        self.journal(&self.watchlists, key(watchlist_id))?;
+        self.journal(&self.watchlists_by_name, &by_name_key)?;
+        for entry in &articles {
+            self.journal(&self.watchlist_articles, entry)?;
+        }
+        for entry in &sources {
+            self.journal(&self.watchlist_sources, entry)?;
+        }
+        (
+            &self.watchlists,
+            &self.watchlists_by_name,
+            &self.watchlist_articles,
+            &self.watchlist_sources,
+        )
+            .transaction(
+                |(watchlists, by_name, watchlist_articles, watchlist_sources)| {
+                    watchlists.remove(&key(watchlist_id))?;
+                    by_name.remove(by_name_key.as_slice())?;
+                    for entry in &articles {
+                        watchlist_articles.remove(entry)?;
+                    }
+                    for entry in &sources {
+                        watchlist_sources.remove(entry)?;
+                    }
+                    Ok::<_, ConflictableTransactionError<DbError>>(())
+                },
+            )?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn add_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+        added_at: i32,
+    ) -> DbResult<()> {
+        // the first one is kept
+        self.journal(&self.watchlist_articles, pair(watchlist_id, article_id))?;
+        let _ = self.watchlist_articles.compare_and_swap(
+            pair(watchlist_id, article_id),
+            None as Option<&[u8]>,
+            Some(encode(&added_at)?),
+        )?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn remove_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+    ) -> DbResult<()> {
+        self.journal(&self.watchlist_articles, pair(watchlist_id, article_id))?;
+        self.watchlist_articles
+            .remove(pair(watchlist_id, article_id))?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn get_watchlist_articles(&mut self, watchlist_id: i32) -> DbResult<Vec<Article>> {
+        let mut added = self
+            .watchlist_articles
+            .scan_prefix(key(watchlist_id))
+            .map(|entry| {
+                let (key, added_at) = entry?;
+                Ok((decode::<i32>(&added_at)?, last(&key)))
+            })
+            .collect::<DbResult<Vec<_>>>()?;
+        added.sort_unstable();
+        added
+            .into_iter()
+            .map(|(_, article_id)| self.get_article_by_id_sync(article_id))
+            .collect()
+    }
+
+    async fn add_watchlist_source(
+        &mut self,
+        watchlist_id: i32,
+        host: &str,
+        added_at: i32,
+    ) -> DbResult<()> {
+        let host = host.trim().to_lowercase();
+        if host.is_empty() || host.contains('/') {
+            return Err(DbError::InvalidInput(format!(
+                "source {} is not a host",
+                host
+            )));
+        }
+        let entry = [&key(watchlist_id), host.as_bytes()].concat();
+        self.journal(&self.watchlist_sources, &entry)?;
+        let _ = self.watchlist_sources.compare_and_swap(
+            entry,
+            None as Option<&[u8]>,
+            Some(encode(&added_at)?),
+        )?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn remove_watchlist_source(&mut self, watchlist_id: i32, host: &str) -> DbResult<()> {
+        let entry = [&key(watchlist_id), host.as_bytes()].concat();
+        self.journal(&self.watchlist_sources, &entry)?;
+        self.watchlist_sources.remove(entry)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    async fn get_watchlist_sources(&mut self, watchlist_id: i32) -> DbResult<Vec<String>> {
+        self.watchlist_sources
+            .scan_prefix(key(watchlist_id))
+            .keys()
+            .map(|key| Ok(String::from_utf8_lossy(&key?[4..]).into_owned()))
+            .collect()
+    }
+}
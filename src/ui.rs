@@ -0,0 +1,411 @@
+//! server-rendered html pages for browsing articles, revisions and diffs
+
+use crate::cache::{Diff, DiffKey, DiffView};
+use crate::db::{self, ArticleCursor, ArticleFilter, ArticleSort, RevisionFilter, Snapshot};
+use crate::events::Event;
+use crate::http::{self, State};
+use crate::markup::{escape, rfc3339};
+use crate::media::{self, Media, MediaChange};
+use crate::metadata::PageMetadata;
+use crate::significance::Significance;
+use crate::{diff, mime, scraper};
+
+use tide::{prelude::*, Redirect, Request, Response, Result, Status};
+
+const PAGE_SIZE: i32 = 50;
+
+const STYLE: &str = r"
+body { font-family: sans-serif; margin: 0 auto; max-width: 70em; padding: 1em; }
+table { border-collapse: collapse; width: 100%; }
+td, th { border-bottom: 1px solid #ddd; padding: .3em; text-align: left; vertical-align: top; }
+del { background: #fdd; color: #900; }
+ins { background: #dfd; color: #060; text-decoration: none; }
+.moved { background: #eef; border-left: 3px solid #88c; }
+.diff { white-space: pre-wrap; line-height: 1.4; }
+.columns { display: flex; gap: 1em; }
+.columns > div { flex: 1; }
+nav form { display: inline; }
+";
+
+#[derive(Deserialize)]
+struct ListQuery {
+    q: Option<String>,
+    host: Option<String>,
+    changed: Option<String>,
+    #[serde(default)]
+    sort: ArticleSort,
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    from: i32,
+    to: i32,
+    mode: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UrlForm {
+    url: String,
+}
+
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_owned)
+}
+
+fn page(title: &str, body: &str) -> Response {
+    Response::builder(200)
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>{title} - propaganda</title><style>{style}</style></head>
+<body>
+<nav><a href="/">articles</a> · <a href="/changes.atom">feed</a>
+<form method="post" action="/ui/articles"><input name="url" placeholder="https://..." size="50"> <button>track article</button></form>
+</nav>
+<h1>{title}</h1>
+{body}
+</body></html>"#,
+            title = escape(title),
+            style = STYLE,
+            body = body,
+        ))
+        .content_type(mime::html())
+        .build()
+}
+
+/// a list item per media change, images linked by their url unless it is not http(s)
+fn media_changes(changes: &[MediaChange]) -> String {
+    let describe = |media: &Media| {
+        let src = escape(&media.src);
+        let mut text = match db::http_url(&media.src) {
+            Ok(_) => format!(
+                r#"{} <a href="{src}">{src}</a>"#,
+                media.kind.as_str(),
+                src = src
+            ),
+            Err(_) => format!("{} {}", media.kind.as_str(), src),
+        };
+        for (name, value) in &[
+            ("alt", &media.alt),
+            ("caption", &media.caption),
+            ("credit", &media.credit),
+        ] {
+            if let Some(value) = value {
+                text.push_str(&format!(" {}: <q>{}</q>", name, escape(value)));
+            }
+        }
+        text
+    };
+    let items = changes
+        .iter()
+        .map(|change| match change {
+            MediaChange::Added(media) => format!("<li><ins>added</ins> {}</li>", describe(media)),
+            MediaChange::Removed(media) => {
+                format!("<li><del>removed</del> {}</li>", describe(media))
+            }
+            MediaChange::Replaced { from, to } => format!(
+                "<li>replaced <del>{}</del> by <ins>{}</ins></li>",
+                describe(from),
+                describe(to)
+            ),
+            MediaChange::Edited { from, to } => format!(
+                "<li>edited <del>{}</del> to <ins>{}</ins></li>",
+                describe(from),
+                describe(to)
+            ),
+        })
+        .collect::<String>();
+    format!("<h2>media</h2><ul>{}</ul>", items)
+}
+
+fn date(timestamp: i32) -> String {
+    rfc3339(timestamp).replace('T', " ").replace('Z', "")
+}
+
+pub async fn articles(req: Request<State>) -> Result<Response> {
+    let query: ListQuery = req.query()?;
+    let filter = ArticleFilter {
+        q: non_empty(&query.q),
+        host: non_empty(&query.host),
+        has_changes: query.changed.as_ref().map(|_| true),
+        ..Default::default()
+    };
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(str::parse::<ArticleCursor>)
+        .transpose()
+        .map_err(http::db_error)?;
+
+    let mut provider = req.state().acquire().await?;
+    let articles = provider
+        .list_articles(&filter, query.sort, cursor, PAGE_SIZE)
+        .await?;
+    let sort_options = [
+        (ArticleSort::Changed, "changed", "last changed"),
+        (ArticleSort::Checked, "checked", "last checked"),
+        (ArticleSort::Created, "created", "newest"),
+        (ArticleSort::Revisions, "revisions", "most revisions"),
+    ]
+    .iter()
+    .map(|(sort, value, label)| {
+        let selected = if *sort == query.sort { " selected" } else { "" };
+        format!(
+            r#"<option value="{}"{}>{}</option>"#,
+            value, selected, label
+        )
+    })
+    .collect::<String>();
+
+    let mut body = format!(
+        r#"<form>
+<input name="q" placeholder="search url or headline" value="{q}">
+<input name="host" placeholder="host" value="{host}">
+<label><input type="checkbox" name="changed"{checked}> only changed</label>
+<select name="sort">{sort_options}</select>
+<button>filter</button>
+</form>
+<table><tr><th>article</th><th>last checked</th><th>last changed</th><th>snapshots</th><th>revisions</th></tr>
+"#,
+        q = escape(filter.q.as_deref().unwrap_or("")),
+        host = escape(filter.host.as_deref().unwrap_or("")),
+        checked = if filter.has_changes.is_some() {
+            " checked"
+        } else {
+            ""
+        },
+        sort_options = sort_options,
+    );
+
+    for article in &articles {
+        let title = if article.headline.is_empty() {
+            &article.url
+        } else {
+            &article.headline
+        };
+        body.push_str(&format!(
+            r#"<tr><td><a href="/ui/articles/{id}">{title}</a><br><small>{url}</small></td><td>{checked}</td><td>{changed}</td><td>{snapshots}</td><td>{revisions}</td></tr>
+"#,
+            id = article.article_id,
+            title = escape(title),
+            url = escape(&article.url),
+            checked = if article.updated_at > 0 { date(article.updated_at) } else { "never".to_owned() },
+            changed = article.last_changed_at.map(date).unwrap_or_default(),
+            snapshots = article.snapshots,
+            revisions = article.revisions,
+        ));
+    }
+    body.push_str("</table>\n");
+
+    if articles.len() as i32 == PAGE_SIZE {
+        if let Some(last) = articles.last() {
+            let mut url = req.url().clone();
+            url.query_pairs_mut()
+                .clear()
+                .extend_pairs(req.url().query_pairs().filter(|(k, _)| k != "cursor"))
+                .append_pair(
+                    "cursor",
+                    &ArticleCursor::after(query.sort, last).to_string(),
+                );
+            body.push_str(&format!(r#"<a href="{}">next</a>"#, escape(url.as_str())));
+        }
+    }
+
+    Ok(page("articles", &body))
+}
+
+pub async fn article(req: Request<State>) -> Result<Response> {
+    let article_id: i32 = req.param("id").status(400)?;
+    let mut provider = req.state().acquire().await?;
+    let article = provider
+        .get_article_by_id(article_id)
+        .await
+        .map_err(http::db_error)?;
+
+    let snapshots = provider
+        .get_snaphot_metadatas_from_article(article_id)
+        .await?;
+    let filter = RevisionFilter {
+        url: Some(article.url.clone()),
+        ..Default::default()
+    };
+    let revisions = provider.get_revisions(&filter, None, 1000).await?;
+
+    let mut body = format!(
+        r#"<p><a href="{url}">{url}</a> · <a href="/changes.atom?url={query}">feed</a></p>
+<h2>revisions</h2>
+<table><tr><th>detected</th><th>headline</th><th>words</th><th>significance</th><th>diff</th></tr>
+"#,
+        url = escape(&article.url),
+        query = escape(
+            &surf::url::form_urlencoded::byte_serialize(article.url.as_bytes()).collect::<String>()
+        ),
+    );
+    for revision in &revisions {
+        body.push_str(&format!(
+            r#"<tr><td>{at}</td><td>{headline}</td><td><ins>+{added}</ins> <del>-{removed}</del></td><td>{significance}{silent}</td><td><a href="/ui/diff?from={from}&amp;to={to}">inline</a> · <a href="/ui/diff?from={from}&amp;to={to}&amp;mode=side">side by side</a></td></tr>
+"#,
+            at = date(revision.archived_at),
+            headline = escape(&revision.headline),
+            added = revision.words_added,
+            removed = revision.words_removed,
+            significance = revision.significance.map_or("", Significance::as_str),
+            silent = if revision.silent_edit == Some(true) {
+                r#" <strong title="changed without a declared update">silent edit</strong>"#
+            } else {
+                ""
+            },
+            from = revision.previous_snapshot_id,
+            to = revision.snapshot_id,
+        ));
+    }
+    body.push_str("</table>\n");
+
+    let options = snapshots
+        .iter()
+        .map(|s| {
+            format!(
+                r#"<option value="{}">{}</option>"#,
+                s.snapshot_id,
+                date(s.archived_at)
+            )
+        })
+        .collect::<String>();
+    body.push_str(&format!(
+        r#"<h2>snapshots</h2>
+<form action="/ui/diff">compare <select name="from">{options}</select> with <select name="to">{options}</select>
+<select name="mode"><option value="inline">inline</option><option value="side">side by side</option></select>
+<button>diff</button></form>
+"#,
+        options = options,
+    ));
+
+    Ok(page(&article.url, &body))
+}
+
+pub async fn diff(req: Request<State>) -> Result<Response> {
+    let query: DiffQuery = req.query()?;
+    let mut provider = req.state().acquire().await?;
+    let from = provider
+        .get_snaphot(query.from)
+        .await
+        .map_err(http::db_error)?;
+    let to = provider
+        .get_snaphot(query.to)
+        .await
+        .map_err(http::db_error)?;
+    let article = provider
+        .get_article_by_id(to.article_id)
+        .await
+        .map_err(http::db_error)?;
+
+    let view = if query.mode.as_deref() == Some("side") {
+        DiffView::SideBySide
+    } else {
+        DiffView::Inline
+    };
+    let key = DiffKey {
+        from: from.snapshot_id,
+        to: to.snapshot_id,
+        view,
+    };
+    let cache = &req.state().cache;
+    let diff = match cache.diff(&key) {
+        Some(diff) => diff,
+        None => {
+            // the diff stored with the revision, snapshots further apart were never diffed
+            let filter = RevisionFilter {
+                url: Some(article.url.clone()),
+                ..RevisionFilter::default()
+            };
+            let revision = provider
+                .get_revisions(&filter, None, i32::MAX)
+                .await?
+                .into_iter()
+                .find(|r| {
+                    r.previous_snapshot_id == from.snapshot_id && r.snapshot_id == to.snapshot_id
+                });
+            let text_diff = match revision {
+                Some(revision) => scraper::revision_diff(&mut *provider, &revision).await?,
+                None => diff::text_diff(&cache.fulltext(&from), &cache.fulltext(&to)),
+            };
+            let html = if view == DiffView::SideBySide {
+                let (left, right) = diff::render_side_by_side(&text_diff.paragraphs);
+                format!(
+                    r#"<div class="columns"><div class="diff">{}</div><div class="diff">{}</div></div>"#,
+                    left, right
+                )
+            } else {
+                format!(
+                    r#"<div class="diff">{}</div>"#,
+                    diff::render_inline(&text_diff.paragraphs)
+                )
+            };
+            // metadata changes below the fulltext, unless only the modification time changed
+            let metadata = |snapshot: &Snapshot| {
+                PageMetadata::of_snapshot(snapshot.page_metadata.as_deref(), &snapshot.html)
+            };
+            let (old_metadata, new_metadata) = (metadata(&from), metadata(&to));
+            let html = if new_metadata.changed_since(&old_metadata) {
+                format!(
+                    r#"{}<h2>metadata</h2><div class="diff">{}</div>"#,
+                    html,
+                    diff::render_inline(&old_metadata.diff(&new_metadata).paragraphs)
+                )
+            } else {
+                html
+            };
+            let stored = |snapshot: &Snapshot| {
+                media::of_snapshot(snapshot.page_media.as_deref(), &snapshot.html)
+            };
+            let changes = media::diff(&stored(&from), &stored(&to));
+            let html = if changes.is_empty() {
+                html
+            } else {
+                html + &media_changes(&changes)
+            };
+            let diff = Diff {
+                article_id: article.article_id,
+                summary: text_diff.summary,
+                html,
+            };
+            cache.put_diff(key, diff)
+        }
+    };
+
+    let body = format!(
+        r#"<p><a href="/ui/articles/{id}">{url}</a></p>
+<p>{from} → {to}: <ins>+{added}</ins> <del>-{removed}</del> words</p>
+"#,
+        id = article.article_id,
+        url = escape(&article.url),
+        from = date(from.archived_at),
+        to = date(to.archived_at),
+        added = diff.summary.words_added,
+        removed = diff.summary.words_removed,
+    ) + &diff.html;
+
+    Ok(page("diff", &body))
+}
+
+pub async fn insert_article(mut req: Request<State>) -> Result<Response> {
+    let form: UrlForm = req.body_form().await?;
+    let url = form.url.trim();
+    db::http_url(url).map_err(|err| tide::Error::from_str(400, err))?;
+
+    let mut provider = req.state().acquire().await?;
+    let existed = provider.get_article(url).await.is_ok();
+    let article = provider.insert_article(url).await?;
+    let location = format!("/ui/articles/{}", article.article_id);
+    if !existed {
+        req.state()
+            .events
+            .publish(Event::ArticleInserted { article });
+    }
+
+    Ok(Redirect::see_other(location).into())
+}
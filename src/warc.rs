@@ -0,0 +1,450 @@
+//! WARC 1.1 export and import of snapshots
+//!
+//! every snapshot becomes a `response` record of its article url dated by
+//! its fetch time, followed by a `request` record if its request headers are known,
+//! a `.warc.gz` holds one gzip member per record
+
+use crate::db::{
+    self, Article, DbError, DbResult, Provenance, ProvideArticles, Snapshot, SnapshotSource,
+};
+use crate::provenance;
+use crate::scraper::insert_snapshot_and_revision_with_provenance;
+use crate::webhook::ProvideWebhooks;
+use flate2::{read::GzDecoder, read::MultiGzDecoder, write::GzEncoder, Compression};
+use rand::RngCore;
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub headers: Vec<(String, String)>,
+    pub block: Vec<u8>,
+}
+
+impl Record {
+    /// the first header of that name, names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = b"WARC/1.1\r\n".to_vec();
+        for (name, value) in &self.headers {
+            bytes.extend(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        bytes.extend(format!("Content-Length: {}\r\n\r\n", self.block.len()).as_bytes());
+        bytes.extend(&self.block);
+        bytes.extend(b"\r\n\r\n");
+        bytes
+    }
+}
+
+/// the decoded payload of an `application/http` response block
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// what an import did with the response records
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Report {
+    pub records: usize,
+    pub snapshots_added: usize,
+    /// a snapshot of the article at that time exists already
+    pub duplicate: usize,
+    /// no http(s) target, no 2xx status or no readable date
+    pub skipped: usize,
+    /// articles which were not tracked before
+    pub articles: Vec<Article>,
+}
+
+fn record_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+pub fn warcinfo(date: i32) -> Record {
+    Record {
+        headers: vec![
+            ("WARC-Type".to_owned(), "warcinfo".to_owned()),
+            ("WARC-Date".to_owned(), crate::markup::rfc3339(date)),
+            ("WARC-Record-ID".to_owned(), record_id()),
+            (
+                "Content-Type".to_owned(),
+                "application/warc-fields".to_owned(),
+            ),
+        ],
+        block: format!(
+            "software: propaganda/{}\r\nformat: WARC File Format 1.1\r\n",
+            env!("CARGO_PKG_VERSION")
+        )
+        .into_bytes(),
+    }
+}
+
+/// headers which described the body on the wire, not the stored html
+const BODY_HEADERS: &[&str] = &["Content-Length", "Content-Encoding", "Transfer-Encoding"];
+
+/// the snapshot as http response with its stored headers, snapshots without
+/// get the ones the html implies
+pub fn response(url: &str, snapshot: &Snapshot) -> Record {
+    let mut headers = provenance::parse_headers(snapshot.response_headers.as_deref());
+    headers.retain(|(name, _)| !BODY_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)));
+    if headers.is_empty() {
+        headers.push((
+            "Content-Type".to_owned(),
+            "text/html; charset=utf-8".to_owned(),
+        ));
+    }
+    headers.push(("Content-Length".to_owned(), snapshot.html.len().to_string()));
+
+    let mut block = b"HTTP/1.1 200 OK\r\n".to_vec();
+    for (name, value) in headers {
+        block.extend(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    block.extend(b"\r\n");
+    block.extend(snapshot.html.as_bytes());
+    let digest = snapshot
+        .body_sha256
+        .clone()
+        .unwrap_or_else(|| provenance::body_sha256(&snapshot.html));
+    Record {
+        headers: vec![
+            ("WARC-Type".to_owned(), "response".to_owned()),
+            ("WARC-Target-URI".to_owned(), url.to_owned()),
+            (
+                "WARC-Date".to_owned(),
+                crate::markup::rfc3339(snapshot.archived_at),
+            ),
+            ("WARC-Record-ID".to_owned(), record_id()),
+            (
+                "WARC-Payload-Digest".to_owned(),
+                format!("sha256:{}", digest),
+            ),
+            (
+                "Content-Type".to_owned(),
+                "application/http; msgtype=response".to_owned(),
+            ),
+        ],
+        block,
+    }
+}
+
+/// the request of a snapshot with stored request headers, next to its `response`
+pub fn request(url: &str, snapshot: &Snapshot, response: &Record) -> Option<Record> {
+    let headers = provenance::parse_headers(snapshot.request_headers.as_deref());
+    if headers.is_empty() {
+        return None;
+    }
+    let url = surf::url::Url::parse(url).ok()?;
+    let target = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+    let mut block = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\n",
+        target,
+        db::url_host(url.as_str())
+    )
+    .into_bytes();
+    for (name, value) in headers {
+        block.extend(format!("{}: {}\r\n", name, value).as_bytes());
+    }
+    block.extend(b"\r\n");
+
+    let mut headers = response.headers.clone();
+    headers.retain(|(name, _)| {
+        ["WARC-Target-URI", "WARC-Date"]
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(name))
+    });
+    headers.insert(0, ("WARC-Type".to_owned(), "request".to_owned()));
+    headers.push(("WARC-Record-ID".to_owned(), record_id()));
+    if let Some(id) = response.header("WARC-Record-ID") {
+        headers.push(("WARC-Concurrent-To".to_owned(), id.to_owned()));
+    }
+    headers.push((
+        "Content-Type".to_owned(),
+        "application/http; msgtype=request".to_owned(),
+    ));
+    Some(Record { headers, block })
+}
+
+/// the given articles, or all, with their snapshots in fetch order
+pub async fn export<P: ProvideArticles + Send + ?Sized>(
+    provider: &mut P,
+    article_ids: Option<&[i32]>,
+    date: i32,
+) -> DbResult<Vec<Record>> {
+    let mut records = vec![warcinfo(date)];
+    for article in db::get_articles_or_all(provider, article_ids).await? {
+        let mut metadatas = provider
+            .get_snaphot_metadatas_from_article(article.article_id)
+            .await?;
+        metadatas.sort_by_key(|m| (m.archived_at, m.snapshot_id));
+        for metadata in metadatas {
+            let snapshot = provider.get_snaphot(metadata.snapshot_id).await?;
+            let response = response(&article.url, &snapshot);
+            let request = request(&article.url, &snapshot, &response);
+            records.push(response);
+            records.extend(request);
+        }
+    }
+    Ok(records)
+}
+
+/// the records one after another, each its own gzip member if asked to
+pub fn write(records: &[Record], gzip: bool) -> Vec<u8> {
+    let mut bytes = vec![];
+    for record in records {
+        if gzip {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            // writing to a vec does not fail
+            let _ = encoder.write_all(&record.to_bytes());
+            bytes.extend(encoder.finish().unwrap_or_default());
+        } else {
+            bytes.extend(record.to_bytes());
+        }
+    }
+    bytes
+}
+
+fn line<'a>(bytes: &'a [u8], at: &mut usize) -> Option<&'a [u8]> {
+    let rest = bytes.get(*at..)?;
+    let end = rest.iter().position(|b| *b == b'\n')?;
+    *at += end + 1;
+    Some(rest[..end].strip_suffix(b"\r").unwrap_or(&rest[..end]))
+}
+
+/// plain or gzipped WARC, or the reason it is unreadable
+pub fn parse(bytes: &[u8]) -> Result<Vec<Record>, String> {
+    let unzipped;
+    let bytes = if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut buffer = vec![];
+        MultiGzDecoder::new(bytes)
+            .read_to_end(&mut buffer)
+            .map_err(|err| err.to_string())?;
+        unzipped = buffer;
+        &unzipped[..]
+    } else {
+        bytes
+    };
+
+    let mut records = vec![];
+    let mut at = 0;
+    while at < bytes.len() {
+        let version = match line(bytes, &mut at) {
+            Some([]) => continue,
+            Some(version) => version,
+            None if bytes[at..].iter().all(u8::is_ascii_whitespace) => break,
+            None => &bytes[at..],
+        };
+        if !version.starts_with(b"WARC/") {
+            return Err(format!("expected a WARC record at byte {}", at));
+        }
+        let mut headers = vec![];
+        loop {
+            let header = line(bytes, &mut at).ok_or("WARC headers end early")?;
+            if header.is_empty() {
+                break;
+            }
+            let header = String::from_utf8_lossy(header);
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| format!("WARC header {} has no value", header))?;
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+        let length = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, value)| value.parse::<usize>().ok())
+            .ok_or("WARC record without Content-Length")?;
+        let block = bytes
+            .get(at..at + length)
+            .ok_or("WARC record is shorter than its Content-Length")?
+            .to_vec();
+        at += length;
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Length"));
+        records.push(Record { headers, block });
+    }
+    Ok(records)
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    loop {
+        let mut at = 0;
+        let size = line(body, &mut at)?;
+        let size = String::from_utf8_lossy(size);
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend(body.get(at..at + size)?);
+        body = body.get(at + size..)?;
+        body = body.strip_prefix(b"\r\n").unwrap_or(body);
+    }
+}
+
+/// status, headers and decoded body of an `application/http` response block
+pub fn http_response(block: &[u8]) -> Option<HttpResponse> {
+    let mut at = 0;
+    let status = String::from_utf8_lossy(line(block, &mut at)?).into_owned();
+    let status = status.split_whitespace().nth(1)?.parse().ok()?;
+    let mut headers = vec![];
+    loop {
+        let header = line(block, &mut at)?;
+        if header.is_empty() {
+            break;
+        }
+        let header = String::from_utf8_lossy(header);
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.to_ascii_lowercase())
+    };
+
+    let mut body = block[at..].to_vec();
+    if header("Transfer-Encoding").is_some_and(|te| te.contains("chunked")) {
+        body = dechunk(&body)?;
+    }
+    if header("Content-Encoding").is_some_and(|ce| ce.contains("gzip")) {
+        let mut decoded = vec![];
+        GzDecoder::new(&body[..]).read_to_end(&mut decoded).ok()?;
+        body = decoded;
+    }
+    Some(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// the header lines of an `application/http` request block
+fn request_headers(block: &[u8]) -> Vec<(String, String)> {
+    let mut at = 0;
+    let mut headers = vec![];
+    line(block, &mut at);
+    while let Some(header) = line(block, &mut at).filter(|header| !header.is_empty()) {
+        let header = String::from_utf8_lossy(header);
+        if let Some((name, value)) = header.split_once(':') {
+            if !name.trim().eq_ignore_ascii_case("Host") {
+                headers.push((name.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+    }
+    headers
+}
+
+/// store every 2xx response as snapshot of its target with the headers of the
+/// response and its request, in fetch order so snapshots newer than the
+/// youngest one also get revisions
+pub async fn import<P>(provider: &mut P, records: &[Record]) -> anyhow::Result<Report>
+where
+    P: ProvideArticles + ProvideWebhooks + Send + ?Sized,
+{
+    let mut report = Report::default();
+    let mut responses = vec![];
+    let requests = records
+        .iter()
+        .filter(|record| record.header("WARC-Type") == Some("request"))
+        .filter_map(|record| Some((record.header("WARC-Concurrent-To")?, record)))
+        .collect::<std::collections::HashMap<_, _>>();
+
+    for record in records {
+        if record.header("WARC-Type") != Some("response") {
+            continue;
+        }
+        report.records += 1;
+        let url = record
+            .header("WARC-Target-URI")
+            .map(|uri| uri.trim_start_matches('<').trim_end_matches('>'))
+            .filter(|uri| uri.starts_with("http://") || uri.starts_with("https://"));
+        let date = record
+            .header("WARC-Date")
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.timestamp() as i32);
+        let response = http_response(&record.block).filter(|response| response.status / 100 == 2);
+        match (url, date, response) {
+            (Some(url), Some(date), Some(response)) => {
+                let id = record.header("WARC-Record-ID");
+                let provenance = Provenance {
+                    source: SnapshotSource::Warc,
+                    source_url: id.map(str::to_owned),
+                    request_headers: id
+                        .and_then(|id| requests.get(id))
+                        .map(|request| request_headers(&request.block))
+                        .unwrap_or_default(),
+                    response_headers: response.headers,
+                    body_sha256: Some(provenance::body_sha256(&response.body)),
+                    chain: false,
+                    media: None,
+                };
+                let html = String::from_utf8_lossy(&response.body).into_owned();
+                responses.push((url.to_owned(), date, html, provenance))
+            }
+            _ => report.skipped += 1,
+        }
+    }
+    responses.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+
+    for (url, archived_at, html, provenance) in responses {
+        let article = match provider.get_article(&url).await {
+            Ok(article) => article,
+            Err(DbError::NotFound(_)) => {
+                let article = provider.insert_article(&url).await?;
+                report.articles.push(article.clone());
+                article
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let snapshots = provider
+            .get_snaphot_metadatas_from_article(article.article_id)
+            .await?;
+        if snapshots.iter().any(|s| s.archived_at == archived_at) {
+            report.duplicate += 1;
+            continue;
+        }
+
+        if snapshots.iter().all(|s| s.archived_at < archived_at) {
+            let inserted = insert_snapshot_and_revision_with_provenance(
+                provider,
+                &article,
+                archived_at,
+                &html,
+                &provenance,
+            )
+            .await?;
+            if inserted.snapshot.is_none() {
+                report.duplicate += 1;
+                continue;
+            }
+        } else {
+            provider
+                .insert_snapshot_with_provenance(&article, archived_at, &html, &provenance)
+                .await?;
+        }
+        report.snapshots_added += 1;
+    }
+    Ok(report)
+}
@@ -0,0 +1,281 @@
+//! users with named lists of articles and whole sources (hosts)
+//!
+//! `ArticleFilter::watchlist` and `RevisionFilter::watchlist` scope listings,
+//! changes and feeds to a list
+
+use crate::db::{Article, DbError, DbResult, VoidResult};
+use async_trait::async_trait;
+use mockall::automock;
+use sqlx::sqlite::SqliteQueryAs;
+
+#[derive(sqlx::FromRow, Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct User {
+    pub user_id: i32,
+    pub name: String,
+    pub created_at: i32,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Watchlist {
+    pub watchlist_id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub created_at: i32,
+}
+
+#[automock]
+#[async_trait]
+pub trait ProvideWatchlists {
+    async fn insert_user(&mut self, name: &str, created_at: i32) -> DbResult<User>;
+    async fn get_users(&mut self) -> DbResult<Vec<User>>;
+    async fn get_user(&mut self, user_id: i32) -> DbResult<User>;
+    async fn get_user_by_name(&mut self, name: &str) -> DbResult<User>;
+
+    async fn insert_watchlist(
+        &mut self,
+        user_id: i32,
+        name: &str,
+        created_at: i32,
+    ) -> DbResult<Watchlist>;
+    /// all watchlists unless restricted to a user
+    async fn get_watchlists(&mut self, user_id: Option<i32>) -> DbResult<Vec<Watchlist>>;
+    async fn get_watchlist(&mut self, watchlist_id: i32) -> DbResult<Watchlist>;
+    /// also forgets its articles and sources, the articles stay tracked
+    async fn delete_watchlist(&mut self, watchlist_id: i32) -> DbResult<()>;
+
+    /// adding an article twice keeps the first one
+    async fn add_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+        added_at: i32,
+    ) -> DbResult<()>;
+    async fn remove_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+    ) -> DbResult<()>;
+    async fn get_watchlist_articles(&mut self, watchlist_id: i32) -> DbResult<Vec<Article>>;
+
+    async fn add_watchlist_source(
+        &mut self,
+        watchlist_id: i32,
+        host: &str,
+        added_at: i32,
+    ) -> DbResult<()>;
+    async fn remove_watchlist_source(&mut self, watchlist_id: i32, host: &str) -> DbResult<()>;
+    async fn get_watchlist_sources(&mut self, watchlist_id: i32) -> DbResult<Vec<String>>;
+}
+
+#[async_trait]
+impl ProvideWatchlists for sqlx::SqliteConnection {
+    async fn insert_user(&mut self, name: &str, created_at: i32) -> DbResult<User> {
+        if name.trim().is_empty() {
+            return Err(DbError::InvalidInput("user name is empty".to_owned()));
+        }
+        sqlx::query_as(
+            r"
+            INSERT INTO users ( name, created_at )
+            VALUES ( $1, $2 );
+            SELECT * FROM users WHERE user_id = last_insert_rowid() ;",
+        )
+        .bind(name.trim())
+        .bind(created_at)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_users(&mut self) -> DbResult<Vec<User>> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM users ORDER BY user_id",
+        )
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn get_user(&mut self, user_id: i32) -> DbResult<User> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM users WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("user")
+    }
+
+    async fn get_user_by_name(&mut self, name: &str) -> DbResult<User> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM users WHERE name = $1",
+        )
+        .bind(name)
+        .fetch_one(self)
+        .await
+        .or_not_found("user")
+    }
+
+    async fn insert_watchlist(
+        &mut self,
+        user_id: i32,
+        name: &str,
+        created_at: i32,
+    ) -> DbResult<Watchlist> {
+        if name.trim().is_empty() {
+            return Err(DbError::InvalidInput("watchlist name is empty".to_owned()));
+        }
+        sqlx::query_as(
+            r"
+            INSERT INTO watchlists ( user_id, name, created_at )
+            VALUES ( $1, $2, $3 );
+            SELECT * FROM watchlists WHERE watchlist_id = last_insert_rowid() ;",
+        )
+        .bind(user_id)
+        .bind(name.trim())
+        .bind(created_at)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_watchlists(&mut self, user_id: Option<i32>) -> DbResult<Vec<Watchlist>> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM watchlists
+            WHERE ($1 IS NULL OR user_id = $1)
+            ORDER BY watchlist_id",
+        )
+        .bind(user_id)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn get_watchlist(&mut self, watchlist_id: i32) -> DbResult<Watchlist> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM watchlists WHERE watchlist_id = $1",
+        )
+        .bind(watchlist_id)
+        .fetch_one(self)
+        .await
+        .or_not_found("watchlist")
+    }
+
+    async fn delete_watchlist(&mut self, watchlist_id: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            DELETE FROM watchlist_articles WHERE watchlist_id = $1;
+            DELETE FROM watchlist_sources WHERE watchlist_id = $2;
+            DELETE FROM watchlists WHERE watchlist_id = $3",
+        )
+        .bind(watchlist_id)
+        .bind(watchlist_id)
+        .bind(watchlist_id)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn add_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+        added_at: i32,
+    ) -> DbResult<()> {
+        sqlx::query(
+            r"
+            INSERT OR IGNORE INTO watchlist_articles ( watchlist_id, article_id, added_at )
+            VALUES ( $1, $2, $3 )",
+        )
+        .bind(watchlist_id)
+        .bind(article_id)
+        .bind(added_at)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn remove_watchlist_article(
+        &mut self,
+        watchlist_id: i32,
+        article_id: i32,
+    ) -> DbResult<()> {
+        sqlx::query(
+            r"
+            DELETE FROM watchlist_articles WHERE watchlist_id = $1 AND article_id = $2",
+        )
+        .bind(watchlist_id)
+        .bind(article_id)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_watchlist_articles(&mut self, watchlist_id: i32) -> DbResult<Vec<Article>> {
+        sqlx::query_as(
+            r"
+            SELECT articles.url, articles.article_id, articles.host,
+                articles.created_at, articles.updated_at
+            FROM watchlist_articles JOIN articles USING (article_id)
+            WHERE watchlist_id = $1
+            ORDER BY added_at, article_id",
+        )
+        .bind(watchlist_id)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn add_watchlist_source(
+        &mut self,
+        watchlist_id: i32,
+        host: &str,
+        added_at: i32,
+    ) -> DbResult<()> {
+        let host = host.trim().to_lowercase();
+        if host.is_empty() || host.contains('/') {
+            return Err(DbError::InvalidInput(format!(
+                "source {} is not a host",
+                host
+            )));
+        }
+        sqlx::query(
+            r"
+            INSERT OR IGNORE INTO watchlist_sources ( watchlist_id, host, added_at )
+            VALUES ( $1, $2, $3 )",
+        )
+        .bind(watchlist_id)
+        .bind(host)
+        .bind(added_at)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn remove_watchlist_source(&mut self, watchlist_id: i32, host: &str) -> DbResult<()> {
+        sqlx::query(
+            r"
+            DELETE FROM watchlist_sources WHERE watchlist_id = $1 AND host = $2",
+        )
+        .bind(watchlist_id)
+        .bind(host)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_watchlist_sources(&mut self, watchlist_id: i32) -> DbResult<Vec<String>> {
+        let sources: Vec<(String,)> = sqlx::query_as(
+            r"
+            SELECT host FROM watchlist_sources WHERE watchlist_id = $1 ORDER BY host",
+        )
+        .bind(watchlist_id)
+        .fetch_all(self)
+        .await?;
+        Ok(sources.into_iter().map(|(host,)| host).collect())
+    }
+}
@@ -0,0 +1,297 @@
+//! prior captures of an article from the wayback machine or another CDX server
+//!
+//! the CDX index lists the captures of an url, `{endpoint}/web/{timestamp}id_/{url}`
+//! replays the original body of one, imported snapshots keep the capture time
+//! and are marked as `SnapshotSource::Wayback`
+
+use crate::db::{Article, Provenance, RevisionFilter, SnapshotSource};
+use crate::provenance;
+use crate::repository::Repository;
+use crate::scraper::insert_revision_if_changed;
+use anyhow::{anyhow, Result};
+
+pub const WAYBACK_URL: &str = "https://web.archive.org";
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Capture {
+    /// `yyyyMMddhhmmss` in UTC
+    pub timestamp: String,
+    pub original: String,
+    /// none for revisits and other captures without a response
+    pub status: Option<u16>,
+    pub digest: Option<String>,
+}
+
+impl Capture {
+    /// the timestamp as seconds since unix epoch
+    pub fn archived_at(&self) -> Option<i32> {
+        chrono::NaiveDateTime::parse_from_str(&self.timestamp, "%Y%m%d%H%M%S")
+            .ok()
+            .map(|at| at.and_utc().timestamp() as i32)
+    }
+}
+
+/// what an import did with the captures of an article
+#[derive(Debug, serde::Serialize)]
+pub struct Report {
+    pub article: Article,
+    pub captures: usize,
+    pub snapshots_added: usize,
+    /// a snapshot at that time exists or the body equals the capture before
+    pub duplicate: usize,
+    /// no 2xx status or an unreadable timestamp
+    pub skipped: usize,
+    /// the body could not be fetched
+    pub failed: usize,
+    pub revisions_added: usize,
+    /// revisions from a stored snapshot to another which now have an imported one between them
+    pub revisions_removed: usize,
+}
+
+/// an index in the JSON output of a CDX server, where the first row names the fields,
+/// or as plain lines, either with a ` CDX` legend or in the default field order
+/// `urlkey timestamp original mimetype statuscode digest length`
+pub fn parse_index(index: &str) -> Result<Vec<Capture>, String> {
+    let rows: Vec<Vec<String>> = if index.trim_start().starts_with('[') {
+        serde_json::from_str(index).map_err(|err| err.to_string())?
+    } else {
+        index
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split_whitespace().map(str::to_owned).collect())
+            .collect()
+    };
+    let mut rows = rows.into_iter().peekable();
+
+    let fields: Vec<String> = match rows.peek().map(Vec::as_slice) {
+        Some([first, legend @ ..]) if first == "CDX" => {
+            let fields = legend
+                .iter()
+                .map(|letter| match letter.as_str() {
+                    "b" => "timestamp",
+                    "a" => "original",
+                    "s" => "statuscode",
+                    "k" => "digest",
+                    _ => "",
+                })
+                .map(str::to_owned)
+                .collect();
+            rows.next();
+            fields
+        }
+        Some(first) if first.iter().any(|field| field == "timestamp") => {
+            rows.next().unwrap_or_default()
+        }
+        _ => [
+            "urlkey",
+            "timestamp",
+            "original",
+            "mimetype",
+            "statuscode",
+            "digest",
+        ]
+        .iter()
+        .map(|field| field.to_string())
+        .collect(),
+    };
+    let column = |name: &str| fields.iter().position(|field| field == name);
+    let (timestamp, original) = match (column("timestamp"), column("original")) {
+        (Some(timestamp), Some(original)) => (timestamp, original),
+        _ => return Err("CDX index has no timestamp or original field".to_owned()),
+    };
+    let (status, digest) = (column("statuscode"), column("digest"));
+
+    rows.enumerate()
+        .map(|(index, row)| {
+            let field = |column: usize| row.get(column).cloned();
+            Ok(Capture {
+                timestamp: field(timestamp)
+                    .ok_or_else(|| format!("CDX row {} has no timestamp", index + 1))?,
+                original: field(original)
+                    .ok_or_else(|| format!("CDX row {} has no original url", index + 1))?,
+                status: status.and_then(field).and_then(|s| s.parse().ok()),
+                digest: digest.and_then(field).filter(|d| d != "-"),
+            })
+        })
+        .collect()
+}
+
+/// a CDX server with the replay api of the wayback machine
+#[derive(Debug, Clone)]
+pub struct Wayback {
+    pub endpoint: String,
+}
+
+impl Wayback {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    pub fn index_url(&self, url: &str) -> String {
+        format!(
+            "{}/cdx/search/cdx?output=json&fl=timestamp,original,statuscode,digest&url={}",
+            self.endpoint,
+            surf::url::form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>()
+        )
+    }
+
+    /// where people can look at the capture
+    pub fn capture_url(&self, capture: &Capture) -> String {
+        format!(
+            "{}/web/{}/{}",
+            self.endpoint, capture.timestamp, capture.original
+        )
+    }
+
+    /// the capture as it was fetched, without the replay banner and rewritten links
+    pub fn body_url(&self, capture: &Capture) -> String {
+        format!(
+            "{}/web/{}id_/{}",
+            self.endpoint, capture.timestamp, capture.original
+        )
+    }
+
+    async fn get(&self, url: &str) -> Result<(Vec<u8>, Vec<(String, String)>)> {
+        let mut res = surf::get(url).await.map_err(|err| anyhow!(err))?;
+        if !res.status().is_success() {
+            return Err(anyhow!("{} answered {}", url, res.status()));
+        }
+        let headers = res
+            .headers()
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        let body = res.body_bytes().await.map_err(|err| anyhow!(err))?;
+        Ok((body, headers))
+    }
+
+    pub async fn get_index(&self, url: &str) -> Result<Vec<Capture>> {
+        let (index, _) = self.get(&self.index_url(url)).await?;
+        parse_index(&String::from_utf8_lossy(&index)).map_err(|err| anyhow!(err))
+    }
+
+    /// the body with the headers of the replay, which include the original ones
+    /// as `x-archive-orig-*`
+    pub async fn get_body(&self, capture: &Capture) -> Result<(String, Provenance)> {
+        let url = self.body_url(capture);
+        let (body, response_headers) = self.get(&url).await?;
+        let provenance = Provenance {
+            source: SnapshotSource::Wayback,
+            source_url: Some(self.capture_url(capture)),
+            request_headers: vec![],
+            response_headers,
+            body_sha256: Some(provenance::body_sha256(&body)),
+            chain: false,
+            media: None,
+        };
+        Ok((String::from_utf8_lossy(&body).into_owned(), provenance))
+    }
+}
+
+/// store the 2xx captures as snapshots of the article, in capture order
+/// skipping repeated bodies, then add revisions around each new snapshot
+/// instead of the ones spanning it, all in one transaction after every body was fetched
+///
+/// imported history is not news, so no webhooks are queued for it
+pub async fn import(
+    repository: &Repository,
+    wayback: &Wayback,
+    article: &Article,
+    mut captures: Vec<Capture>,
+) -> Result<Report> {
+    let mut report = Report {
+        article: article.clone(),
+        captures: captures.len(),
+        snapshots_added: 0,
+        duplicate: 0,
+        skipped: 0,
+        failed: 0,
+        revisions_added: 0,
+        revisions_removed: 0,
+    };
+    captures.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+    let existing = repository
+        .acquire()
+        .await?
+        .get_snaphot_metadatas_from_article(article.article_id)
+        .await?;
+    let mut fetched = vec![];
+    let mut previous_digest = None;
+
+    for capture in captures {
+        let archived_at = match capture.archived_at() {
+            Some(archived_at) if capture.status.is_some_and(|s| s / 100 == 2) => archived_at,
+            _ => {
+                report.skipped += 1;
+                continue;
+            }
+        };
+        if capture.digest.is_some() && capture.digest == previous_digest
+            || existing.iter().any(|s| s.archived_at == archived_at)
+        {
+            previous_digest = capture.digest;
+            report.duplicate += 1;
+            continue;
+        }
+        match wayback.get_body(&capture).await {
+            Ok((html, provenance)) => fetched.push((archived_at, html, provenance)),
+            Err(err) => {
+                tide::log::warn!("capture {} failed: {}", wayback.body_url(&capture), err);
+                report.failed += 1;
+                continue;
+            }
+        }
+        previous_digest = capture.digest;
+    }
+
+    let mut tx = repository.begin().await?;
+    let mut inserted = vec![];
+    for (archived_at, html, provenance) in fetched {
+        let snapshot = tx
+            .insert_snapshot_with_provenance(article, archived_at, &html, &provenance)
+            .await?;
+        inserted.push(snapshot.snapshot_id);
+        report.snapshots_added += 1;
+    }
+
+    let mut timeline = tx
+        .get_snaphot_metadatas_from_article(article.article_id)
+        .await?;
+    timeline.sort_by_key(|s| (s.archived_at, s.snapshot_id));
+    let position = |snapshot_id| timeline.iter().position(|s| s.snapshot_id == snapshot_id);
+    let filter = RevisionFilter {
+        url: Some(article.url.clone()),
+        ..Default::default()
+    };
+    for revision in tx.get_revisions(&filter, None, i32::MAX).await? {
+        if let (Some(previous), Some(current)) = (
+            position(revision.previous_snapshot_id),
+            position(revision.snapshot_id),
+        ) {
+            let between = timeline.get(previous + 1..current).unwrap_or_default();
+            if between.iter().any(|s| inserted.contains(&s.snapshot_id)) {
+                tx.delete_revision(revision.revision_id).await?;
+                report.revisions_removed += 1;
+            }
+        }
+    }
+
+    for pair in timeline.windows(2) {
+        if !inserted.contains(&pair[0].snapshot_id) && !inserted.contains(&pair[1].snapshot_id) {
+            continue;
+        }
+        let previous = tx.get_snaphot(pair[0].snapshot_id).await?;
+        let current = tx.get_snaphot(pair[1].snapshot_id).await?;
+        if insert_revision_if_changed(&mut *tx, article, &previous, &pair[1], &current.html)
+            .await?
+            .is_some()
+        {
+            report.revisions_added += 1;
+        }
+    }
+    tx.commit().await?;
+    Ok(report)
+}
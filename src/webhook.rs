@@ -0,0 +1,337 @@
+use crate::db::{DbResult, Revision, VoidResult};
+use crate::repository::Repository;
+use crate::significance::Significance;
+use anyhow::*;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use mockall::automock;
+use sha2::Sha256;
+use sqlx::sqlite::SqliteQueryAs;
+use std::time::Duration;
+use xactor::{message, Actor, Context, Handler};
+
+/// give up on a delivery after that many failed attempts
+const MAX_ATTEMPTS: i32 = 12;
+
+#[derive(sqlx::FromRow, Debug, serde::Serialize)]
+pub struct Webhook {
+    pub webhook_id: i32,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub host: Option<String>,
+    pub keyword: Option<String>,
+    pub min_words_changed: i32,
+    pub min_significance: Option<Significance>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    pub secret: String,
+    pub host: Option<String>,
+    pub keyword: Option<String>,
+    #[serde(default)]
+    pub min_words_changed: i32,
+    pub min_significance: Option<Significance>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct Delivery {
+    pub delivery_id: i32,
+    pub webhook_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub payload: String,
+    pub attempts: i32,
+}
+
+#[derive(serde::Serialize)]
+struct Payload<'a> {
+    event: &'static str,
+    revision: &'a Revision,
+}
+
+#[automock]
+#[async_trait]
+pub trait ProvideWebhooks {
+    async fn insert_webhook(&mut self, webhook: &NewWebhook) -> DbResult<Webhook>;
+    async fn get_webhooks(&mut self) -> DbResult<Vec<Webhook>>;
+    async fn delete_webhook(&mut self, webhook_id: i32) -> DbResult<()>;
+
+    async fn insert_delivery(&mut self, webhook_id: i32, payload: &str, now: i32) -> DbResult<()>;
+    async fn get_due_deliveries(&mut self, now: i32, limit: i32) -> DbResult<Vec<Delivery>>;
+    /// `next_attempt_at` of None takes the delivery out of the queue
+    async fn update_delivery(
+        &mut self,
+        delivery_id: i32,
+        attempts: i32,
+        next_attempt_at: Option<i32>,
+        delivered_at: Option<i32>,
+        last_error: Option<String>,
+    ) -> DbResult<()>;
+}
+
+#[async_trait]
+impl ProvideWebhooks for sqlx::SqliteConnection {
+    async fn insert_webhook(&mut self, webhook: &NewWebhook) -> DbResult<Webhook> {
+        sqlx::query_as(
+            r"
+            INSERT INTO webhooks ( url, secret, host, keyword, min_words_changed, min_significance )
+            VALUES ( $1, $2, $3, $4, $5, $6 );
+            SELECT * FROM webhooks WHERE webhook_id = last_insert_rowid() ;",
+        )
+        .bind(&webhook.url)
+        .bind(&webhook.secret)
+        .bind(webhook.host.clone())
+        .bind(webhook.keyword.clone())
+        .bind(webhook.min_words_changed)
+        .bind(webhook.min_significance)
+        .fetch_one(self)
+        .await
+        .db()
+    }
+
+    async fn get_webhooks(&mut self) -> DbResult<Vec<Webhook>> {
+        sqlx::query_as(
+            r"
+            SELECT * FROM webhooks ORDER BY webhook_id",
+        )
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn delete_webhook(&mut self, webhook_id: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            DELETE FROM webhook_deliveries WHERE webhook_id = $1;
+            DELETE FROM webhooks WHERE webhook_id = $2",
+        )
+        .bind(webhook_id)
+        .bind(webhook_id)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn insert_delivery(&mut self, webhook_id: i32, payload: &str, now: i32) -> DbResult<()> {
+        sqlx::query(
+            r"
+            INSERT INTO webhook_deliveries ( webhook_id, payload, next_attempt_at )
+            VALUES ( $1, $2, $3 )",
+        )
+        .bind(webhook_id)
+        .bind(payload)
+        .bind(now)
+        .execute(self)
+        .await
+        .void()
+    }
+
+    async fn get_due_deliveries(&mut self, now: i32, limit: i32) -> DbResult<Vec<Delivery>> {
+        sqlx::query_as(
+            r"
+            SELECT delivery_id, webhook_id, url, secret, payload, attempts
+            FROM webhook_deliveries JOIN webhooks USING (webhook_id)
+            WHERE next_attempt_at <= $1
+            ORDER BY next_attempt_at ASC
+            LIMIT $2",
+        )
+        .bind(now)
+        .bind(limit)
+        .fetch_all(self)
+        .await
+        .db()
+    }
+
+    async fn update_delivery(
+        &mut self,
+        delivery_id: i32,
+        attempts: i32,
+        next_attempt_at: Option<i32>,
+        delivered_at: Option<i32>,
+        last_error: Option<String>,
+    ) -> DbResult<()> {
+        sqlx::query(
+            r"
+            UPDATE webhook_deliveries
+            SET attempts=$1, next_attempt_at=$2, delivered_at=$3, last_error=$4
+            WHERE delivery_id=$5",
+        )
+        .bind(attempts)
+        .bind(next_attempt_at)
+        .bind(delivered_at)
+        .bind(last_error)
+        .bind(delivery_id)
+        .execute(self)
+        .await
+        .void()
+    }
+}
+
+impl Webhook {
+    /// whether this webhook wants to hear about the revision
+    pub fn matches(&self, revision: &Revision, fulltext: &str) -> bool {
+        if let Some(host) = &self.host {
+            let url = surf::url::Url::parse(&revision.url);
+            if url.ok().as_ref().and_then(|u| u.host_str()) != Some(host.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(keyword) = &self.keyword {
+            let keyword = keyword.to_lowercase();
+            if !revision.headline.to_lowercase().contains(&keyword)
+                && !fulltext.to_lowercase().contains(&keyword)
+            {
+                return false;
+            }
+        }
+
+        if let Some(min) = self.min_significance {
+            if revision
+                .significance
+                .is_none_or(|significance| significance < min)
+            {
+                return false;
+            }
+        }
+
+        revision.words_added + revision.words_removed >= self.min_words_changed
+    }
+}
+
+/// queue a delivery for every webhook matching the revision
+pub async fn enqueue<P>(
+    provider: &mut P,
+    revision: &Revision,
+    fulltext: &str,
+    now: i32,
+) -> Result<()>
+where
+    P: ProvideWebhooks + Send + ?Sized,
+{
+    let payload = serde_json::to_string(&Payload {
+        event: "revision",
+        revision,
+    })?;
+
+    for webhook in provider.get_webhooks().await? {
+        if webhook.matches(revision, fulltext) {
+            provider
+                .insert_delivery(webhook.webhook_id, &payload, now)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// hex encoded HMAC-SHA256 of the payload, sent as `X-Propaganda-Signature: sha256=...`
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac key");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// POST every due delivery once, failed ones are retried with exponential backoff
+///
+/// returns the number of successful deliveries
+pub async fn deliver_due<P>(provider: &mut P, now: i32) -> Result<usize>
+where
+    P: ProvideWebhooks + Send + ?Sized,
+{
+    let mut delivered = 0;
+
+    for delivery in provider.get_due_deliveries(now, 50).await? {
+        let attempts = delivery.attempts + 1;
+
+        match post(&delivery).await {
+            Ok(()) => {
+                delivered += 1;
+                provider
+                    .update_delivery(delivery.delivery_id, attempts, None, Some(now), None)
+                    .await?;
+            }
+            Err(err) => {
+                tide::log::warn!("webhook delivery {} failed: {}", delivery.delivery_id, err);
+                let next_attempt_at = if attempts < MAX_ATTEMPTS {
+                    Some(now + backoff(attempts))
+                } else {
+                    None
+                };
+                provider
+                    .update_delivery(
+                        delivery.delivery_id,
+                        attempts,
+                        next_attempt_at,
+                        None,
+                        Some(err.to_string()),
+                    )
+                    .await?;
+            }
+        }
+    }
+    Ok(delivered)
+}
+
+/// seconds to wait after the given number of failed attempts, at most six hours
+pub fn backoff(attempts: i32) -> i32 {
+    (30 * 2i32.pow(attempts.clamp(1, 10) as u32 - 1)).min(6 * 60 * 60)
+}
+
+async fn post(delivery: &Delivery) -> Result<()> {
+    let res = surf::post(&delivery.url)
+        .set_header("X-Propaganda-Event", "revision")
+        .set_header("X-Propaganda-Delivery", delivery.delivery_id.to_string())
+        .set_header(
+            "X-Propaganda-Signature",
+            format!("sha256={}", sign(&delivery.secret, &delivery.payload)),
+        )
+        .body_string(delivery.payload.clone())
+        .set_mime(surf::mime::APPLICATION_JSON)
+        .await
+        .map_err(|err| anyhow!(err))?;
+
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} responded {}", delivery.url, res.status()))
+    }
+}
+
+#[message(result = "()")]
+#[derive(Clone, Debug)]
+struct DeliverWebhooks;
+
+/// works through the persistent delivery queue every few seconds
+pub struct Dispatcher {
+    repository: Repository,
+}
+
+impl Dispatcher {
+    pub fn new(repository: Repository) -> Self {
+        Self { repository }
+    }
+}
+
+#[async_trait]
+impl Actor for Dispatcher {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        ctx.send_interval(DeliverWebhooks, Duration::from_secs(10));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Handler<DeliverWebhooks> for Dispatcher {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: DeliverWebhooks) {
+        let result = async {
+            let mut conn = self.repository.acquire().await?;
+            deliver_due(&mut *conn, crate::scraper::timestamp()).await
+        };
+        if let Err(err) = result.await {
+            tide::log::error!("{}", err);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::*;
+use propaganda::auth::{hash_token, ProvideTokens, Role};
+use propaganda::db::ProvideArticles;
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::*;
+use tide::http::{Method, Request, Url};
+
+const TOKEN: &str = "editor-token";
+
+async fn server(name: &str) -> Result<tide::Server<http::State>> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push(name);
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    let mut conn = pool.acquire().await?;
+    conn.ensure_created_tables().await?;
+    conn.insert_token("tests", Role::Editor, None, &hash_token(TOKEN), 0)
+        .await?;
+    let cat = conn.insert_article("https://cats.example/cat.html").await?;
+    let html = |text: &str| format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text);
+    insert_snapshot_and_revision(&mut *conn, &cat, 5, &html("A cat and a mouse")).await?;
+    insert_snapshot_and_revision(&mut *conn, &cat, 8, &html("A cat and two mice")).await?;
+
+    let state = http::State::new(pool, Default::default());
+    let mut server = tide::with_state(state.clone());
+    server.at("/api/v1").nest(api::server(state));
+    server
+        .at("/get_articles")
+        .with(http::Deprecated("/api/v1/articles"))
+        .get(http::get_articles);
+    server
+        .at("/get_snapshot")
+        .with(tide::utils::After(http::problem_details))
+        .get(http::get_snaphot);
+    Ok(server)
+}
+
+async fn request(
+    server: &tide::Server<http::State>,
+    method: Method,
+    url: &str,
+    body: Option<&str>,
+) -> Result<(tide::http::Response, serde_json::Value)> {
+    let mut req = Request::new(method, Url::parse("http://localhost")?.join(url)?);
+    req.insert_header("Authorization", format!("Bearer {}", TOKEN));
+    if let Some(body) = body {
+        req.set_body(body);
+        req.set_content_type(tide::http::mime::JSON);
+    }
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    let body = res.body_string().await.map_err(|e| anyhow!(e))?;
+    let json = serde_json::from_str(&body).unwrap_or(serde_json::Value::Null);
+    Ok((res, json))
+}
+
+#[async_std::test]
+async fn articles_and_snapshots_as_resources() -> Result<()> {
+    let server = server("api-resources.db").await?;
+
+    let (res, article) = request(&server, Method::Get, "/api/v1/articles/1", None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(article["url"], "https://cats.example/cat.html");
+
+    let (res, snapshots) =
+        request(&server, Method::Get, "/api/v1/articles/1/snapshots", None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(snapshots.as_array().map(Vec::len), Some(2));
+
+    let (res, snapshot) = request(&server, Method::Get, "/api/v1/snapshots/2", None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(snapshot["archived_at"], 8);
+    assert_eq!(snapshot["fulltext"], "A cat and two mice\n");
+    assert_eq!(snapshot["metadata"]["authors"], serde_json::json!([]));
+
+    let (res, revision) = request(&server, Method::Get, "/api/v1/revisions/1/diff", None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(revision["words_added"], 2);
+    assert_eq!(revision["similarity"], 0.6);
+    assert_eq!(
+        revision["paragraphs"][0]["edit"][1],
+        serde_json::json!({"-": "a"})
+    );
+    assert_eq!(
+        revision["paragraphs"][0]["edit"][2],
+        serde_json::json!({"+": "two"})
+    );
+    assert_eq!(revision["media"], serde_json::json!([]));
+
+    let (res, metadata) =
+        request(&server, Method::Get, "/api/v1/revisions/1/metadata", None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(metadata["paragraphs"], serde_json::json!([]));
+
+    let body = r#"{"url": "https://dogs.example/dog.html"}"#;
+    let (res, article) = request(&server, Method::Post, "/api/v1/articles", Some(body)).await?;
+    assert_eq!(res.status(), 201);
+    assert_eq!(res["Location"], "/api/v1/articles/2");
+    assert_eq!(article["article_id"], 2);
+
+    let (res, articles) = request(&server, Method::Get, "/api/v1/articles", None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(articles["articles"].as_array().map(Vec::len), Some(2));
+    assert_eq!(articles["next_cursor"], serde_json::Value::Null);
+
+    let (res, page) = request(
+        &server,
+        Method::Get,
+        "/api/v1/articles?sort=created&limit=1",
+        None,
+    )
+    .await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(page["articles"][0]["url"], "https://dogs.example/dog.html");
+    let url = format!(
+        "/api/v1/articles?sort=created&limit=1&cursor={}",
+        page["next_cursor"].as_str().unwrap_or_default()
+    );
+    let (_, page) = request(&server, Method::Get, &url, None).await?;
+    assert_eq!(page["articles"][0]["url"], "https://cats.example/cat.html");
+
+    let (res, articles) = request(
+        &server,
+        Method::Get,
+        "/api/v1/articles?host=dogs.example&has_changes=false",
+        None,
+    )
+    .await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(articles["articles"].as_array().map(Vec::len), Some(1));
+
+    let (res, _) = request(&server, Method::Delete, "/api/v1/articles/1", None).await?;
+    assert_eq!(res.status(), 204);
+    let (res, _) = request(&server, Method::Get, "/api/v1/snapshots/2", None).await?;
+    assert_eq!(res.status(), 404);
+    let (res, _) = request(&server, Method::Get, "/api/v1/articles/1", None).await?;
+    assert_eq!(res.status(), 404);
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn errors_have_status_codes_and_problem_details() -> Result<()> {
+    let server = server("api-errors.db").await?;
+
+    let (res, body) = request(&server, Method::Get, "/api/v1/articles/42", None).await?;
+    assert_eq!(res.status(), 404);
+    assert_eq!(res["Content-Type"], "application/problem+json");
+    assert_eq!(body["status"], 404);
+    assert_eq!(body["title"], "Not Found");
+    assert_eq!(body["detail"], "article not found");
+
+    let (res, body) = request(&server, Method::Delete, "/api/v1/articles/42", None).await?;
+    assert_eq!(res.status(), 404);
+    assert_eq!(body["status"], 404);
+
+    let (res, body) = request(&server, Method::Get, "/api/v1/articles/cat", None).await?;
+    assert_eq!(res.status(), 400);
+    assert_eq!(body["status"], 400);
+
+    let body = r#"{"url": "https://cats.example/cat.html"}"#;
+    let (res, body) = request(&server, Method::Post, "/api/v1/articles", Some(body)).await?;
+    assert_eq!(res.status(), 409);
+    assert_eq!(body["status"], 409);
+
+    let (res, body) = request(&server, Method::Get, "/api/v1/articles?cursor=x", None).await?;
+    assert_eq!(res.status(), 400);
+    assert_eq!(body["detail"], "cursor x is not sort_key:article_id");
+    let (res, _) = request(&server, Method::Get, "/api/v1/articles?sort=x", None).await?;
+    assert_eq!(res.status(), 400);
+
+    let body = r#"{"url": "not a url"}"#;
+    let (res, body) = request(&server, Method::Post, "/api/v1/articles", Some(body)).await?;
+    assert_eq!(res.status(), 400);
+    assert_eq!(body["status"], 400);
+    let body = r#"{"url": "javascript:alert(1)"}"#;
+    let (res, body) = request(&server, Method::Post, "/api/v1/articles", Some(body)).await?;
+    assert_eq!(res.status(), 400);
+    assert_eq!(body["detail"], "scheme javascript is not http or https");
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn legacy_routes_are_deprecated_aliases() -> Result<()> {
+    let server = server("api-legacy.db").await?;
+
+    let (res, articles) = request(&server, Method::Get, "/get_articles", None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res["Deprecation"], "true");
+    assert_eq!(
+        res["Link"],
+        r#"</api/v1/articles>; rel="successor-version""#
+    );
+    assert_eq!(articles[0]["url"], "https://cats.example/cat.html");
+
+    let (res, body) = request(&server, Method::Get, "/get_snapshot?id=42", None).await?;
+    assert_eq!(res.status(), 404);
+    assert_eq!(body["detail"], "snapshot not found");
+
+    Ok(())
+}
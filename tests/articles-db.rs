@@ -1,11 +1,68 @@
 use anyhow::*;
 use propaganda::db::*;
+use propaganda::significance::Significance;
 use sqlx::prelude::*;
 
-#[async_std::test]
-async fn insert_update_and_get_outdated_articles() -> Result<()> {
-    let mut db: sqlx::SqliteConnection = sqlx::SqliteConnection::connect("sqlite::").await?;
+mod common;
+
+/// a fresh database next to `PROPAGANDA_TEST_POSTGRES_URL`, none if that isn't set
+async fn postgres(name: &str) -> Result<Option<sqlx::PgConnection>> {
+    match common::postgres_url(name).await? {
+        Some(url) => Ok(Some(sqlx::PgConnection::connect(&url).await?)),
+        None => Ok(None),
+    }
+}
+
+/// every test on an in-memory sqlite database, a temporary sled one and its own postgres database
+macro_rules! on_every_backend {
+    ($($test:ident),*) => {
+        mod sqlite {
+            use super::*;
+            $(
+                #[async_std::test]
+                async fn $test() -> Result<()> {
+                    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+                    super::$test(&mut db).await
+                }
+            )*
+        }
+
+        mod sled_store {
+            use super::*;
+            $(
+                #[async_std::test]
+                async fn $test() -> Result<()> {
+                    let mut db = propaganda::sled_store::SledStore::temporary()?;
+                    super::$test(&mut db).await
+                }
+            )*
+        }
+
+        mod postgres {
+            use super::*;
+            $(
+                #[async_std::test]
+                async fn $test() -> Result<()> {
+                    match postgres(concat!("propaganda_", stringify!($test))).await? {
+                        Some(mut db) => super::$test(&mut db).await,
+                        None => Ok(()),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+on_every_backend!(
+    insert_update_and_get_outdated_articles,
+    claim_and_release_articles,
+    insert_snapshots_and_get_snapshots,
+    insert_snapshots_and_get_revisions,
+    list_articles_sorted_filtered_and_paginated,
+    missing_rows_and_invalid_input_are_typed_errors
+);
 
+async fn insert_update_and_get_outdated_articles<P: Store>(db: &mut P) -> Result<()> {
     db.ensure_created_tables().await?;
     db.insert_article("article1").await.expect("insert_article");
     db.insert_article("article2").await?;
@@ -25,10 +82,49 @@ async fn insert_update_and_get_outdated_articles() -> Result<()> {
     Ok(())
 }
 
-#[async_std::test]
-async fn insert_snapshots_and_get_snapshots() -> Result<()> {
-    let mut db: sqlx::SqliteConnection = sqlx::SqliteConnection::connect("sqlite::").await?;
+async fn claim_and_release_articles<P: Store>(db: &mut P) -> Result<()> {
+    db.ensure_created_tables().await?;
+    db.insert_article("article1").await?;
+    db.insert_article("article2").await?;
+    db.update_article("article1", 42).await?;
+
+    let first = db
+        .claim_article("a", 100, 400)
+        .await?
+        .expect("claim_article");
+    assert_eq!(first.url, "article2");
+    let second = db
+        .claim_article("b", 100, 400)
+        .await?
+        .expect("claim_article");
+    assert_eq!(second.url, "article1");
+    assert!(db.claim_article("c", 200, 500).await?.is_none());
+
+    assert!(!db.release_article(first.article_id, "b", 300).await?);
+    assert!(db.release_article(first.article_id, "a", 300).await?);
+    assert!(!db.release_article(first.article_id, "a", 300).await?);
+    assert_eq!(db.get_article("article2").await?.updated_at, 300);
+
+    // the lease of "b" expired, "c" takes over the article before "a" is due again
+    let expired = db
+        .claim_article("c", 400, 700)
+        .await?
+        .expect("claim_article");
+    assert_eq!(expired.article_id, second.article_id);
+    assert!(!db.release_article(second.article_id, "b", 450).await?);
+    let due = db
+        .claim_article("d", 400, 700)
+        .await?
+        .expect("claim_article");
+    assert_eq!(due.article_id, first.article_id);
+
+    db.delete_article(due.article_id).await?;
+    assert!(!db.release_article(due.article_id, "d", 500).await?);
 
+    Ok(())
+}
+
+async fn insert_snapshots_and_get_snapshots<P: Store>(db: &mut P) -> Result<()> {
     db.ensure_created_tables().await?;
     let article1 = db.insert_article("article1").await.expect("insert_article");
     let article2 = db.insert_article("article2").await?;
@@ -53,6 +149,269 @@ async fn insert_snapshots_and_get_snapshots() -> Result<()> {
     let snapshot2 = db.get_snaphot(snapshots[1].snapshot_id).await?;
     assert_eq!(snapshot1.html, html1);
     assert_eq!(snapshot2.html, html2);
+    assert_eq!(
+        snapshot2.page_metadata.as_deref(),
+        propaganda::metadata::extract(html2).to_json().as_deref()
+    );
+    assert_eq!(snapshot2.page_media.as_deref(), Some("[]"));
+
+    Ok(())
+}
+
+async fn insert_snapshots_and_get_revisions<P: Store>(db: &mut P) -> Result<()> {
+    use propaganda::scraper::insert_snapshot_and_revision;
+
+    db.ensure_created_tables().await?;
+    let article1 = db.insert_article("article1").await?;
+    let article2 = db.insert_article("article2").await?;
+
+    let html = |headline: &str, text: &str| {
+        format!(
+            "<title>{}</title><div id=content><p>{}</p></div><footer>{}</footer>",
+            headline,
+            text,
+            text.len()
+        )
+    };
+
+    insert_snapshot_and_revision(db, &article1, 5, &html("Cat", "Think about a cat.")).await?;
+    insert_snapshot_and_revision(db, &article2, 6, &html("Dog", "A dog barks.")).await?;
+    insert_snapshot_and_revision(db, &article1, 7, &html("Cat", "Think about a cat.")).await?;
+    insert_snapshot_and_revision(db, &article1, 9, &html("Cats", "Think about two cats.")).await?;
+    insert_snapshot_and_revision(db, &article2, 11, &html("Dog", "A dog sleeps.")).await?;
+
+    assert_eq!(
+        db.get_snaphot_metadatas_from_article(article1.article_id)
+            .await?
+            .len(),
+        2
+    );
+
+    let changes = db
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].url, "article2");
+    assert_eq!(changes[0].previous_archived_at, 6);
+    assert_eq!(changes[0].archived_at, 11);
+    assert_eq!((changes[0].words_added, changes[0].words_removed), (1, 1));
+    assert_eq!(changes[1].url, "article1");
+    assert_eq!(changes[1].headline, "Cats");
+    assert_eq!((changes[1].words_added, changes[1].words_removed), (2, 2));
+    assert_eq!(
+        (changes[0].paragraphs_added, changes[0].paragraphs_removed),
+        (1, 1)
+    );
+    assert_eq!(
+        changes[0].similarity.map(|s| (s * 100.0).round()),
+        Some(67.0)
+    );
+    assert_eq!(changes[0].significance, Some(Significance::Wording));
+    assert_eq!(changes[1].significance, Some(Significance::Headline));
+    let filter = RevisionFilter {
+        min_significance: Some(Significance::Numeric),
+        ..RevisionFilter::default()
+    };
+    let significant = db.get_revisions(&filter, None, 10).await?;
+    assert_eq!(significant.len(), 1);
+    assert_eq!(significant[0].url, "article1");
+    assert_eq!(changes[0].silent_edit, Some(true));
+    let filter = RevisionFilter {
+        silent_edit: Some(false),
+        ..RevisionFilter::default()
+    };
+    assert!(db.get_revisions(&filter, None, 10).await?.is_empty());
+
+    let paragraphs = db
+        .get_revision_diff(changes[0].revision_id)
+        .await?
+        .expect("stored diff");
+    assert_eq!(
+        propaganda::diff::render_inline(&paragraphs),
+        "A dog <del>barks</del><ins>sleeps</ins>."
+    );
+    let diff = propaganda::diff::text_diff("A cat.", "A cat.");
+    db.update_revision_diff(changes[0].revision_id, &diff, Significance::Cosmetic)
+        .await?;
+    assert_eq!(
+        db.get_revision_diff(changes[0].revision_id).await?,
+        Some(diff.paragraphs)
+    );
+    let revision = db.get_revision(changes[0].revision_id).await?;
+    assert_eq!(revision.summary(), diff.summary);
+    assert_eq!(revision.significance, Some(Significance::Cosmetic));
+    assert!(matches!(
+        db.get_revision_diff(4242).await,
+        Err(DbError::NotFound(_))
+    ));
+
+    let page = db
+        .get_revisions(&RevisionFilter::default(), None, 1)
+        .await?;
+    assert_eq!(page.len(), 1);
+    let page = db
+        .get_revisions(&RevisionFilter::default(), Some(page[0].revision_id), 1)
+        .await?;
+    assert_eq!(page[0].url, "article1");
+    let page = db
+        .get_revisions(&RevisionFilter::default(), Some(page[0].revision_id), 1)
+        .await?;
+    assert!(page.is_empty());
+
+    Ok(())
+}
+
+async fn list_articles_sorted_filtered_and_paginated<P: Store>(db: &mut P) -> Result<()> {
+    use propaganda::scraper::insert_snapshot_and_revision;
+
+    db.ensure_created_tables().await?;
+    // migrations are only applied once
+    db.ensure_created_tables().await?;
+    let cat = db.insert_article("https://cats.example/cat.html").await?;
+    let dog = db.insert_article("https://dogs.example/dog.html").await?;
+    let mouse = db.insert_article("https://cats.example/mouse.html").await?;
+    assert_eq!(cat.host, "cats.example");
+    assert!(cat.created_at > 0);
+
+    let html = |text: &str| format!("<div id=content><p>{}</p></div>", text);
+    insert_snapshot_and_revision(db, &cat, 5, &html("A cat")).await?;
+    insert_snapshot_and_revision(db, &cat, 7, &html("A fat cat")).await?;
+    insert_snapshot_and_revision(db, &cat, 9, &html("A fat cat sleeps")).await?;
+    insert_snapshot_and_revision(db, &dog, 6, &html("A dog")).await?;
+    insert_snapshot_and_revision(db, &dog, 8, &html("A dog barks")).await?;
+    db.update_article(&mouse.url, 20).await?;
+
+    let ids = |articles: Vec<ArticleListing>| {
+        articles
+            .into_iter()
+            .map(|a| a.article_id)
+            .collect::<Vec<_>>()
+    };
+    let all = ArticleFilter::default();
+
+    let changed = db
+        .list_articles(&all, ArticleSort::Changed, None, 10)
+        .await?;
+    assert_eq!(changed[0].revisions, 2);
+    assert_eq!(changed[0].last_changed_at, Some(9));
+    assert_eq!(
+        ids(changed),
+        vec![cat.article_id, dog.article_id, mouse.article_id]
+    );
+    let checked = db
+        .list_articles(&all, ArticleSort::Checked, None, 10)
+        .await?;
+    assert_eq!(checked[0].article_id, mouse.article_id);
+    let created = db
+        .list_articles(&all, ArticleSort::Created, None, 10)
+        .await?;
+    assert_eq!(
+        ids(created),
+        vec![mouse.article_id, dog.article_id, cat.article_id]
+    );
+
+    let first = db
+        .list_articles(&all, ArticleSort::Revisions, None, 2)
+        .await?;
+    let cursor = ArticleCursor::after(ArticleSort::Revisions, &first[1]);
+    assert_eq!(cursor.to_string().parse::<ArticleCursor>()?, cursor);
+    let rest = db
+        .list_articles(&all, ArticleSort::Revisions, Some(cursor), 2)
+        .await?;
+    assert_eq!(ids(first), vec![cat.article_id, dog.article_id]);
+    assert_eq!(ids(rest), vec![mouse.article_id]);
+
+    let filter = ArticleFilter {
+        host: Some("cats.example".to_owned()),
+        ..Default::default()
+    };
+    let cats = db
+        .list_articles(&filter, ArticleSort::Changed, None, 10)
+        .await?;
+    assert_eq!(ids(cats), vec![cat.article_id, mouse.article_id]);
+
+    let filter = ArticleFilter {
+        has_changes: Some(false),
+        ..Default::default()
+    };
+    let unchanged = db
+        .list_articles(&filter, ArticleSort::Changed, None, 10)
+        .await?;
+    assert_eq!(ids(unchanged), vec![mouse.article_id]);
+
+    let filter = ArticleFilter {
+        changed_since: Some(6),
+        changed_until: Some(8),
+        ..Default::default()
+    };
+    let between = db
+        .list_articles(&filter, ArticleSort::Changed, None, 10)
+        .await?;
+    assert_eq!(ids(between), vec![dog.article_id]);
+
+    let filter = ArticleFilter {
+        created_since: Some(cat.created_at + 3600),
+        ..Default::default()
+    };
+    assert!(db
+        .list_articles(&filter, ArticleSort::Created, None, 10)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+async fn missing_rows_and_invalid_input_are_typed_errors<P: Store>(db: &mut P) -> Result<()> {
+    db.ensure_created_tables().await?;
+
+    match db.get_article_by_id(42).await {
+        Err(DbError::NotFound(message)) => assert_eq!(message, "article not found"),
+        other => panic!("expected NotFound, got {:?}", other),
+    }
+    match db.get_snaphot(42).await {
+        Err(DbError::NotFound(message)) => assert_eq!(message, "snapshot not found"),
+        other => panic!("expected NotFound, got {:?}", other),
+    }
+    assert!(matches!(
+        "42".parse::<ArticleCursor>(),
+        Err(DbError::InvalidInput(_))
+    ));
+    assert_eq!(
+        Backend::from_url("postgres://localhost/db")?,
+        Backend::Postgres
+    );
+    assert_eq!(Backend::from_url("sqlite::")?, Backend::Sqlite);
+    assert_eq!(Backend::from_url("sled:propaganda.sled")?, Backend::Sled);
+    assert!(matches!(
+        Backend::from_url("mysql://localhost/db"),
+        Err(DbError::InvalidInput(_))
+    ));
+
+    Ok(())
+}
+
+/// a migration which fails halfway leaves neither its changes nor an open transaction
+#[async_std::test]
+async fn failed_migrations_roll_back() -> Result<()> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.execute(
+        r"
+        CREATE TABLE articles (
+            article_id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            url TEXT UNIQUE NOT NULL,
+            updated_at INTEGER NOT NULL
+        );
+        CREATE INDEX articles_host ON articles (url);",
+    )
+    .await?;
 
+    assert!(db.ensure_created_tables().await.is_err());
+    let (version,) = sqlx::query_as::<sqlx::Sqlite, (i32,)>("PRAGMA user_version")
+        .fetch_one(&mut db)
+        .await?;
+    assert_eq!(version, 0);
+    assert!(db.execute("SELECT created_at FROM articles").await.is_err());
+    // not left inside the failed transaction
+    db.execute("BEGIN; ROLLBACK;").await?;
     Ok(())
 }
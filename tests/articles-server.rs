@@ -23,7 +23,7 @@ async fn fun() -> Result<()> {
     conn.insert_article("article1").await?;
     conn.insert_article("article2").await?;
 
-    let mut server = tide::Server::with_state(pool);
+    let mut server = tide::Server::with_state(http::State::new(pool, Default::default()));
 
     server.at("/get-articles").get(&http::get_articles);
 
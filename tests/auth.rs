@@ -0,0 +1,165 @@
+use anyhow::*;
+use propaganda::auth::{self, ProvideTokens, Role};
+use propaganda::db::ProvideArticles;
+use propaganda::*;
+use tide::http::{Method, Request, Url};
+
+async fn server(
+    name: &str,
+    public_read: bool,
+) -> Result<(tide::Server<http::State>, sqlx::SqlitePool)> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push(name);
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    let mut conn = pool.acquire().await?;
+    conn.ensure_created_tables().await?;
+    conn.insert_article("https://cats.example/cat.html").await?;
+
+    let state = http::State {
+        public_read,
+        ..http::State::new(pool.clone(), Default::default())
+    };
+    let mut server = tide::with_state(state.clone());
+    server.with(http::Cors(vec!["https://reader.example".to_owned()]));
+    server.at("/api/v1").nest(api::server(state));
+    server
+        .at("/webhooks")
+        .with(tide::utils::After(http::problem_details))
+        .with(auth::Guard::admin())
+        .get(http::get_webhooks);
+    Ok((server, pool))
+}
+
+async fn request(
+    server: &tide::Server<http::State>,
+    method: Method,
+    url: &str,
+    authorization: Option<String>,
+) -> Result<tide::http::Response> {
+    let mut req = Request::new(method, Url::parse("http://localhost")?.join(url)?);
+    if let Some(authorization) = authorization {
+        req.insert_header("Authorization", authorization);
+    }
+    if method == Method::Post {
+        req.set_body(r#"{"url": "https://dogs.example/dog.html"}"#);
+        req.set_content_type(tide::http::mime::JSON);
+    }
+    server.respond(req).await.map_err(|e| anyhow!(e))
+}
+
+fn bearer(token: &str) -> Option<String> {
+    Some(format!("Bearer {}", token))
+}
+
+#[async_std::test]
+async fn roles_guard_mutating_routes() -> Result<()> {
+    let (server, pool) = server("auth-roles.db", true).await?;
+    let mut conn = pool.acquire().await?;
+    let (reader, _) = auth::mint(&mut *conn, "reader", Role::ReadOnly, None, 1).await?;
+    let (editor, editor_token) = auth::mint(&mut *conn, "editor", Role::Editor, None, 1).await?;
+    let (admin, _) = auth::mint(&mut *conn, "admin", Role::Admin, None, 1).await?;
+    drop(conn);
+
+    let res = request(&server, Method::Get, "/api/v1/articles", None).await?;
+    assert_eq!(res.status(), 200);
+
+    let res = request(&server, Method::Post, "/api/v1/articles", None).await?;
+    assert_eq!(res.status(), 401);
+    assert!(res["WWW-Authenticate"].as_str().contains("Bearer"));
+    let res = request(&server, Method::Post, "/api/v1/articles", bearer("guess")).await?;
+    assert_eq!(res.status(), 401);
+    let res = request(&server, Method::Post, "/api/v1/articles", bearer(&reader)).await?;
+    assert_eq!(res.status(), 403);
+    let res = request(&server, Method::Post, "/api/v1/articles", bearer(&editor)).await?;
+    assert_eq!(res.status(), 201);
+
+    let res = request(&server, Method::Get, "/webhooks", bearer(&editor)).await?;
+    assert_eq!(res.status(), 403);
+    let res = request(&server, Method::Get, "/webhooks", bearer(&admin)).await?;
+    assert_eq!(res.status(), 200);
+
+    // basic auth ignores the user name, changes need to come from the server's own pages
+    let basic = format!("Basic {}", base64::encode(format!("anyone:{}", admin)));
+    let res = request(&server, Method::Get, "/webhooks", Some(basic.clone())).await?;
+    assert_eq!(res.status(), 200);
+    for origin in &[None, Some("https://evil.example"), Some("null")] {
+        let url = Url::parse("http://localhost/api/v1/articles/2")?;
+        let mut req = Request::new(Method::Delete, url);
+        req.insert_header("Authorization", basic.as_str());
+        if let Some(origin) = origin {
+            req.insert_header("Origin", *origin);
+        }
+        let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+        assert_eq!(res.status(), 403);
+    }
+    let mut req = Request::new(
+        Method::Delete,
+        Url::parse("http://localhost/api/v1/articles/2")?,
+    );
+    req.insert_header("Authorization", basic.as_str());
+    req.insert_header("Referer", "http://localhost/ui/articles/2");
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 204);
+
+    let mut conn = pool.acquire().await?;
+    conn.revoke_token(editor_token.token_id, 2).await?;
+    let res = request(&server, Method::Post, "/api/v1/articles", bearer(&editor)).await?;
+    assert_eq!(res.status(), 401);
+    assert!(conn.revoke_token(editor_token.token_id, 3).await.is_err());
+
+    let tokens = conn.get_tokens().await?;
+    assert_eq!(tokens.len(), 3);
+    assert_eq!(tokens[1].role, Role::Editor);
+    assert_eq!(tokens[1].revoked_at, Some(2));
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn reads_need_a_token_unless_public() -> Result<()> {
+    let (server, pool) = server("auth-private.db", false).await?;
+    let mut conn = pool.acquire().await?;
+    let (reader, _) = auth::mint(&mut *conn, "reader", Role::ReadOnly, None, 1).await?;
+
+    let res = request(&server, Method::Get, "/api/v1/articles", None).await?;
+    assert_eq!(res.status(), 401);
+    let res = request(&server, Method::Get, "/api/v1/articles", bearer(&reader)).await?;
+    assert_eq!(res.status(), 200);
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn cors_only_for_allowed_origins() -> Result<()> {
+    let (server, _) = server("auth-cors.db", true).await?;
+
+    let mut req = Request::new(
+        Method::Options,
+        Url::parse("http://localhost/api/v1/articles")?,
+    );
+    req.insert_header("Origin", "https://reader.example");
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 204);
+    assert_eq!(res["Access-Control-Allow-Origin"], "https://reader.example");
+    assert!(res["Access-Control-Allow-Headers"]
+        .as_str()
+        .contains("Authorization"));
+
+    let mut req = Request::new(Method::Get, Url::parse("http://localhost/api/v1/articles")?);
+    req.insert_header("Origin", "https://evil.example");
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    assert!(res.header("Access-Control-Allow-Origin").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn roles_are_ordered_and_parsed() {
+    assert!(Role::ReadOnly < Role::Editor && Role::Editor < Role::Admin);
+    assert_eq!("editor".parse::<Role>().ok(), Some(Role::Editor));
+    assert!("root".parse::<Role>().is_err());
+    assert_ne!(auth::generate_token(), auth::generate_token());
+}
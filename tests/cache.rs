@@ -0,0 +1,111 @@
+use anyhow::*;
+use propaganda::cache::{Cache, Diff, DiffKey, DiffView};
+use propaganda::db::{Article, ProvideArticles};
+use propaganda::diff::DiffSummary;
+use sqlx::prelude::*;
+
+fn article(article_id: i32) -> Article {
+    Article {
+        url: format!("https://news.example/{}", article_id),
+        article_id,
+        host: "news.example".to_owned(),
+        created_at: 0,
+        updated_at: 0,
+    }
+}
+
+fn diff(article_id: i32) -> Diff {
+    Diff {
+        article_id,
+        summary: DiffSummary::default(),
+        html: format!("<p>{}</p>", article_id),
+    }
+}
+
+fn key(from: i32, to: i32) -> DiffKey {
+    DiffKey {
+        from,
+        to,
+        view: DiffView::Inline,
+    }
+}
+
+#[test]
+fn maps_are_bounded_and_articles_forgotten() {
+    let cache = Cache::new(3);
+    for article_id in 1..=10 {
+        cache.put_article(article(article_id));
+    }
+    assert_eq!(cache.sizes().0, 3);
+    assert_eq!(
+        cache.article(10).map(|a| a.url.clone()),
+        Some(article(10).url)
+    );
+
+    cache.put_article(Article {
+        updated_at: 42,
+        ..article(10)
+    });
+    assert_eq!(cache.sizes().0, 3);
+    assert_eq!(cache.article(10).map(|a| a.updated_at), Some(42));
+
+    cache.put_latest_fulltext(10, 5, "A cat".to_owned());
+    cache.put_diff(key(4, 5), diff(10));
+    cache.put_diff(key(6, 7), diff(11));
+    cache.forget_article(10);
+    assert!(cache.article(10).is_none());
+    assert!(cache.latest_fulltext(10).is_none());
+    assert!(cache.diff(&key(4, 5)).is_none());
+    assert_eq!(
+        cache.diff(&key(6, 7)).map(|d| d.html.clone()),
+        Some("<p>11</p>".to_owned())
+    );
+}
+
+#[async_std::test]
+async fn only_the_latest_fulltext_is_cached() -> Result<()> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+    let html = |text: &str| format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text);
+    let previous = db.insert_snapshot(&article, 1, &html("A cat")).await?;
+    let latest = db.insert_snapshot(&article, 2, &html("A dog")).await?;
+    let previous = db.get_snaphot(previous.snapshot_id).await?;
+    let latest = db.get_snaphot(latest.snapshot_id).await?;
+
+    let cache = Cache::default();
+    assert_eq!(cache.fulltext(&latest), "A dog\n");
+    cache.put_latest_fulltext(article.article_id, latest.snapshot_id, "cached".to_owned());
+    assert_eq!(cache.fulltext(&latest), "cached");
+    assert_eq!(cache.fulltext(&previous), "A cat\n");
+
+    Ok(())
+}
+
+#[test]
+fn readers_on_other_threads_see_every_write() {
+    let cache = Cache::new(100);
+    cache.put_article(article(1));
+    let readers = (0..4)
+        .map(|_| {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                let mut seen = 0;
+                while seen < 50 {
+                    let updated_at = cache.article(1).map(|a| a.updated_at).unwrap_or(-1);
+                    assert!(updated_at >= seen, "{} after {}", updated_at, seen);
+                    seen = updated_at;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for updated_at in 1..=50 {
+        cache.put_article(Article {
+            updated_at,
+            ..article(1)
+        });
+    }
+    for reader in readers {
+        reader.join().expect("reader");
+    }
+}
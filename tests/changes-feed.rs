@@ -0,0 +1,74 @@
+use anyhow::*;
+use propaganda::db::ProvideArticles;
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::*;
+use tide::http::{Method, Request, Url};
+
+async fn pool_with_revisions(name: &str) -> Result<sqlx::SqlitePool> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push(name);
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    let mut conn = pool.acquire().await?;
+    conn.ensure_created_tables().await?;
+
+    let cat = conn.insert_article("https://cats.example/cat.html").await?;
+    let dog = conn.insert_article("https://dogs.example/dog.html").await?;
+    let html = |text: &str| format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text);
+
+    insert_snapshot_and_revision(&mut *conn, &cat, 5, &html("A cat & a mouse")).await?;
+    insert_snapshot_and_revision(&mut *conn, &cat, 8, &html("A cat & two mice")).await?;
+    insert_snapshot_and_revision(&mut *conn, &dog, 6, &html("A dog")).await?;
+    insert_snapshot_and_revision(&mut *conn, &dog, 9, &html("A dog barks")).await?;
+
+    Ok(pool)
+}
+
+async fn get(server: &tide::Server<http::State>, url: &str) -> Result<String> {
+    let req = Request::new(Method::Get, Url::parse("http://localhost")?.join(url)?);
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    res.body_string().await.map_err(|e| anyhow!(e))
+}
+
+#[async_std::test]
+async fn changes_as_json_and_atom() -> Result<()> {
+    let pool = pool_with_revisions("changes-feed.db").await?;
+    let mut server = tide::with_state(http::State::new(pool, Default::default()));
+    server.at("/changes").get(http::get_changes);
+    server.at("/changes.atom").get(http::get_changes_atom);
+
+    let changes: serde_json::Value = serde_json::from_str(&get(&server, "/changes").await?)?;
+    let changes = changes["changes"].as_array().expect("changes");
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0]["url"], "https://dogs.example/dog.html");
+    assert_eq!(changes[0]["words_added"], 1);
+
+    let page: serde_json::Value = serde_json::from_str(&get(&server, "/changes?limit=1").await?)?;
+    let cursor = page["next_cursor"].as_i64().expect("next_cursor");
+    let page: serde_json::Value =
+        serde_json::from_str(&get(&server, &format!("/changes?limit=1&cursor={}", cursor)).await?)?;
+    assert_eq!(page["changes"][0]["url"], "https://cats.example/cat.html");
+
+    let changes: serde_json::Value =
+        serde_json::from_str(&get(&server, "/changes?min_significance=rewrite").await?)?;
+    assert_eq!(changes["changes"].as_array().map(Vec::len), Some(0));
+    let feed = get(&server, "/changes.atom?min_significance=headline").await?;
+    assert_eq!(feed.matches("<entry>").count(), 2);
+
+    let feed = get(&server, "/changes.atom").await?;
+    assert_eq!(feed.matches("<entry>").count(), 2);
+    assert!(feed.contains("<title>A dog barks</title>"));
+    assert!(feed.contains("A dog&lt;ins&gt; barks&lt;/ins&gt;"));
+
+    let feed = get(&server, "/changes.atom?host=cats.example").await?;
+    assert_eq!(feed.matches("<entry>").count(), 1);
+    assert!(feed.contains("<title>A cat &amp; two mice</title>"));
+    assert!(feed.contains("&lt;del&gt;a&lt;/del&gt;&lt;ins&gt;two&lt;/ins&gt;"));
+
+    let feed = get(&server, "/changes.atom?url=https://dogs.example/dog.html").await?;
+    assert_eq!(feed.matches("<entry>").count(), 1);
+
+    Ok(())
+}
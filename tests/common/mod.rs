@@ -0,0 +1,27 @@
+//! fixtures shared by the tests
+#![allow(dead_code)]
+
+use anyhow::*;
+use sqlx::prelude::*;
+
+/// the url of a fresh database next to `PROPAGANDA_TEST_POSTGRES_URL`, none if that isn't set
+pub async fn postgres_url(name: &str) -> Result<Option<String>> {
+    let url = match std::env::var("PROPAGANDA_TEST_POSTGRES_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("PROPAGANDA_TEST_POSTGRES_URL is not set, {} skipped", name);
+            return Ok(None);
+        }
+    };
+    let mut admin = sqlx::PgConnection::connect(&url).await?;
+    admin
+        .execute(format!("DROP DATABASE IF EXISTS {}", name).as_str())
+        .await?;
+    admin
+        .execute(format!("CREATE DATABASE {}", name).as_str())
+        .await?;
+
+    let mut url = surf::url::Url::parse(&url)?;
+    url.set_path(name);
+    Ok(Some(url.into()))
+}
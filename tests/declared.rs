@@ -0,0 +1,136 @@
+use anyhow::*;
+use propaganda::db::{ProvideArticles, RevisionFilter};
+use propaganda::declared::{self, Declared};
+use propaganda::scraper::insert_snapshot_and_revision;
+use sqlx::prelude::*;
+
+const URL: &str = "https://news.example/a";
+
+#[test]
+fn modification_times_from_meta_tags_or_json_ld() {
+    let html = r#"<head>
+        <meta property="article:modified_time" content=" 2026-10-19T12:00:00+02:00 ">
+        <script type="application/ld+json">{"dateModified": "2026-10-18"}</script>
+        </head>"#;
+    assert_eq!(
+        declared::extract(URL, html).modified_time.as_deref(),
+        Some("2026-10-19T12:00:00+02:00")
+    );
+
+    let html = r#"<script type="application/ld+json">
+        {"@context": "https://schema.org", "@graph": [
+            {"@type": "WebPage"},
+            {"@type": "NewsArticle", "datePublished": "2026-10-17", "dateModified": "2026-10-18"}
+        ]}</script>"#;
+    assert_eq!(
+        declared::extract(URL, html).modified_time.as_deref(),
+        Some("2026-10-18")
+    );
+    assert_eq!(
+        declared::extract(URL, "<p>nothing</p>"),
+        Declared::default()
+    );
+}
+
+#[test]
+fn correction_boxes_and_update_notes() {
+    let html = r#"<div id=content>
+        <p>Update 14:32: The minister has resigned.</p>
+        <p>Updated figures show a decline.</p>
+        <div class="box box--korrektur"><p>Korrektur: In einer früheren Version
+        stand ein falscher Name.</p></div>
+        </div>"#;
+    assert_eq!(
+        declared::extract(URL, html).notes,
+        vec![
+            "Korrektur: In einer früheren Version stand ein falscher Name.",
+            "Update 14:32: The minister has resigned.",
+        ]
+    );
+}
+
+/// notes of tagesschau.de are in the story, the ones of teasers next to it are not
+#[test]
+fn tagesschau_notes_are_in_the_story() {
+    let html = r#"<div class="storywrapper">
+        <p class="textabsatz">Der Minister ist zurückgetreten.</p>
+        <p class="textabsatz"><strong>Aktualisierung 14:32 Uhr:</strong> Sein Nachfolger
+        steht fest.</p>
+        <div class="infobox korrekturhinweis"><p>In einer früheren Version stand ein
+        falsches Datum.</p></div>
+        </div>
+        <div class="teaser"><p>Update: Wahlen in Thüringen</p>
+        <div class="korrektur">Korrektur zu einem anderen Artikel</div></div>"#;
+    let notes = vec![
+        "In einer früheren Version stand ein falsches Datum.",
+        "Aktualisierung 14:32 Uhr: Sein Nachfolger steht fest.",
+    ];
+    let url = "https://www.tagesschau.de/inland/minister-100.html";
+    assert_eq!(declared::extract(url, html).notes, notes);
+    // the generic heuristic takes the teaser for a note
+    assert_eq!(declared::extract(URL, html).notes.len(), 4);
+}
+
+/// a commit message of whatthecommit.com is the article, whatever it starts with
+#[test]
+fn whatthecommit_has_no_notes() {
+    let html = r#"<div id="content"><p>Update the thing, again
+        </p><p class="permalink"><a href="/abc">permalink</a></p></div>"#;
+    assert!(declared::extract("http://whatthecommit.com/", html)
+        .notes
+        .is_empty());
+    assert_eq!(declared::extract(URL, html).notes.len(), 1);
+}
+
+#[test]
+fn updates_are_new_modification_times_or_notes() {
+    let before = Declared {
+        modified_time: Some("2026-10-18".into()),
+        notes: vec!["Update: more details".into()],
+    };
+    assert!(!before.declares_update_since(&before));
+    assert!(!Declared::default().declares_update_since(&before));
+    assert!(Declared {
+        modified_time: Some("2026-10-19".into()),
+        ..before.clone()
+    }
+    .declares_update_since(&before));
+    assert!(Declared {
+        notes: vec!["Korrektur: falscher Name".into()],
+        ..before.clone()
+    }
+    .declares_update_since(&before));
+}
+
+#[async_std::test]
+async fn revisions_without_declared_updates_are_silent_edits() -> Result<()> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+    let html = |modified: &str, text: &str| {
+        format!(
+            r#"<meta property="article:modified_time" content="{}"><div id=content><p>{}</p></div>"#,
+            modified, text
+        )
+    };
+
+    insert_snapshot_and_revision(&mut db, &article, 5, &html("1", "The mayor lied.")).await?;
+    insert_snapshot_and_revision(&mut db, &article, 6, &html("1", "The mayor erred.")).await?;
+    insert_snapshot_and_revision(&mut db, &article, 7, &html("2", "The mayor was wrong.")).await?;
+
+    let revisions = db
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[0].silent_edit, Some(false));
+    assert_eq!(revisions[1].silent_edit, Some(true));
+
+    let filter = RevisionFilter {
+        silent_edit: Some(true),
+        ..RevisionFilter::default()
+    };
+    let silent = db.get_revisions(&filter, None, 10).await?;
+    assert_eq!(silent.len(), 1);
+    assert_eq!(silent[0].archived_at, 6);
+    Ok(())
+}
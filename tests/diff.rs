@@ -0,0 +1,125 @@
+use propaganda::diff::{self, Paragraph, Run};
+
+#[test]
+fn reordered_paragraphs_are_moves_not_removals() {
+    let old = "First paragraph about cats.\nSecond paragraph about dogs.\nThird one on mice.\n";
+    let new = "Third one on mice.\nFirst paragraph about cats.\nSecond paragraph about dogs.\n";
+    let diff = diff::text_diff(old, new);
+
+    assert_eq!(
+        diff.paragraphs,
+        vec![
+            Paragraph::Move {
+                from: 2,
+                to: 0,
+                runs: vec![Run::Equal("Third one on mice.".to_owned())],
+            },
+            Paragraph::Equal("First paragraph about cats.".to_owned()),
+            Paragraph::Equal("Second paragraph about dogs.".to_owned()),
+        ]
+    );
+    assert!(diff.summary.is_empty());
+    assert_eq!(diff.summary.paragraphs_added, 0);
+    assert_eq!(diff.summary.similarity, 1.0);
+}
+
+#[test]
+fn moved_and_edited_paragraphs_keep_their_words_diff() {
+    let old = "The minister resigned on Monday.\nMarkets were calm.\nMore details later.\n";
+    let new = "Markets were calm.\nMore details later.\nThe minister resigned on Tuesday.\n";
+    let diff = diff::text_diff(old, new);
+
+    assert_eq!(
+        diff.paragraphs[2],
+        Paragraph::Move {
+            from: 0,
+            to: 2,
+            runs: vec![
+                Run::Equal("The minister resigned on ".to_owned()),
+                Run::Remove("Monday".to_owned()),
+                Run::Insert("Tuesday".to_owned()),
+                Run::Equal(".".to_owned()),
+            ],
+        }
+    );
+    assert_eq!(
+        (diff.summary.words_added, diff.summary.words_removed),
+        (1, 1)
+    );
+    assert_eq!(
+        (
+            diff.summary.paragraphs_added,
+            diff.summary.paragraphs_removed
+        ),
+        (1, 1)
+    );
+}
+
+#[test]
+fn dissimilar_paragraphs_are_removed_and_inserted() {
+    let diff = diff::text_diff(
+        "Intro.\nA cat sleeps.\n",
+        "Intro.\nStocks fell sharply today.\n",
+    );
+
+    assert_eq!(
+        diff.paragraphs,
+        vec![
+            Paragraph::Equal("Intro.".to_owned()),
+            Paragraph::Insert("Stocks fell sharply today.".to_owned()),
+            Paragraph::Remove("A cat sleeps.".to_owned()),
+        ]
+    );
+    assert_eq!(
+        (diff.summary.words_added, diff.summary.words_removed),
+        (4, 3)
+    );
+}
+
+/// a rewritten article with one paragraph kept and edited among many new ones
+#[test]
+fn rewrites_find_the_edited_paragraph() {
+    let old = (0..400)
+        .map(|n| format!("Old paragraph number {} on cats.", n))
+        .chain(Some("The minister resigned on Monday.".to_owned()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let new = Some("The minister resigned on Tuesday.".to_owned())
+        .into_iter()
+        .chain((0..400).map(|n| format!("Fresh text {} about dogs.", n)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let diff = diff::text_diff(&old, &new);
+
+    let edits = diff
+        .paragraphs
+        .iter()
+        .filter_map(|p| match p {
+            Paragraph::Edit(runs) => Some(runs),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(edits.len(), 1);
+    assert!(edits[0].contains(&Run::Insert("Tuesday".to_owned())));
+    assert_eq!(diff.summary.paragraphs_added, 401);
+    assert_eq!(diff.summary.paragraphs_removed, 401);
+}
+
+#[test]
+fn html_marks_moves_in_both_columns() {
+    let old = "Alpha beta gamma.\nDelta epsilon.\n";
+    let new = "Delta epsilon.\nAlpha beta gamma.\n";
+    let moved = r#"<span class="moved" title="moved from paragraph 2">Delta epsilon.</span>"#;
+
+    assert_eq!(
+        diff::html_inline(old, new),
+        format!("{}\nAlpha beta gamma.", moved)
+    );
+    let (left, right) = diff::html_side_by_side(old, new);
+    assert_eq!(
+        left,
+        r#"Alpha beta gamma.
+<span class="moved" title="moved to paragraph 1">Delta epsilon.</span>"#
+    );
+    assert_eq!(right, format!("{}\nAlpha beta gamma.", moved));
+}
@@ -0,0 +1,86 @@
+use anyhow::*;
+use async_std::io::prelude::*;
+use async_std::task;
+use futures::StreamExt;
+use propaganda::db::ProvideArticles;
+use propaganda::events::{Event, Events};
+use propaganda::*;
+use tide::http::{Method, Request, Url};
+
+#[async_std::test]
+async fn events_are_broadcast_to_every_subscriber() -> Result<()> {
+    let events = Events::default();
+    let mut first = events.subscribe();
+    let mut second = events.subscribe();
+
+    let mut db: sqlx::SqliteConnection = sqlx::prelude::Connect::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    let article = db.insert_article("article1").await?;
+    events.publish(Event::ArticleInserted { article });
+
+    for receiver in &mut [&mut first, &mut second] {
+        match receiver.next().await {
+            Some(Event::ArticleInserted { article }) => assert_eq!(article.url, "article1"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    drop(first);
+    events.publish(Event::Fetched {
+        article_id: 1,
+        url: "article1".into(),
+        fetched_at: 5,
+        snapshot_id: None,
+        error: Some("timeout".into()),
+    });
+    assert_eq!(second.next().await.map(|e| e.name()), Some("fetched"));
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn inserted_articles_are_streamed_as_server_sent_events() -> Result<()> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push("events.db");
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    pool.acquire().await?.ensure_created_tables().await?;
+
+    let mut server = tide::with_state(http::State::new(pool, Events::default()));
+    server.at("/insert_article").get(http::insert_article);
+    server
+        .at("/events")
+        .get(tide::sse::endpoint(http::get_events));
+
+    let req = Request::new(Method::Get, Url::parse("http://localhost/events")?);
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    let mut lines = async_std::io::BufReader::new(res.take_body()).lines();
+
+    // the subscription happens in the background, keep inserting until it shows up
+    let inserter = task::spawn(async move {
+        for index in 0.. {
+            let url = format!("http://localhost/insert_article?url=article{}", index);
+            let req = Request::new(Method::Get, Url::parse(&url).unwrap());
+            let _: tide::http::Response = server.respond(req).await.unwrap();
+            task::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    });
+
+    let mut event = String::new();
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        if line.starts_with("event:") {
+            event = line;
+        } else if line.starts_with("data:") {
+            assert_eq!(event, "event:article_inserted");
+            assert!(line.contains(r#""type":"article_inserted""#));
+            assert!(line.contains(r#""url":"article"#));
+            break;
+        }
+    }
+    inserter.cancel().await;
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use anyhow::*;
+use propaganda::db::{ProvideArticles, RevisionFilter};
+use propaganda::evidence;
+use propaganda::scraper::insert_snapshot_and_revision;
+use sqlx::prelude::*;
+use std::io::Read;
+
+fn html(text: &str) -> String {
+    format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text)
+}
+
+/// the bundle with one file replaced
+fn rewrite(bundle: &[u8], name: &str, replacement: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bundle));
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        vec![],
+        flate2::Compression::default(),
+    ));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut bytes = vec![];
+        entry.read_to_end(&mut bytes)?;
+        let bytes = if path == name {
+            replacement.to_vec()
+        } else {
+            bytes
+        };
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, path, bytes.as_slice())?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
+
+#[async_std::test]
+async fn bundles_are_signed_and_verified_offline() -> Result<()> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+    insert_snapshot_and_revision(&mut db, &article, 1598788800, &html("A cat and a mouse")).await?;
+    insert_snapshot_and_revision(&mut db, &article, 1598792400, &html("A cat and two mice"))
+        .await?;
+    let revision = db
+        .get_revisions(&RevisionFilter::default(), None, 1)
+        .await?
+        .remove(0);
+
+    let key = evidence::generate_key();
+    let key = evidence::parse_key(&hex::encode(key.to_bytes()))?;
+    let bundle = evidence::bundle(&mut db, revision.revision_id, &key, 1598796000).await?;
+    assert!(db.get_revision(42).await.is_err());
+
+    let verification = evidence::verify(&bundle, Some(&key.verifying_key()))?;
+    assert!(
+        verification.problems.is_empty(),
+        "{:?}",
+        verification.problems
+    );
+    let manifest = verification.manifest;
+    assert_eq!(manifest.format, evidence::FORMAT);
+    assert_eq!(manifest.url, "https://news.example/a");
+    assert_eq!(manifest.created_at, "2020-08-30T14:00:00Z");
+    assert_eq!(manifest.previous.fetched_at, "2020-08-30T12:00:00Z");
+    assert_eq!(manifest.current.file.name, "current.html");
+    assert_eq!(
+        manifest.current.body_sha256.as_ref(),
+        Some(&manifest.current.file.sha256)
+    );
+    assert_eq!((manifest.words_added, manifest.words_removed), (2, 2));
+
+    let other = evidence::generate_key();
+    let verification = evidence::verify(&bundle, Some(&other.verifying_key()))?;
+    assert_eq!(verification.problems.len(), 1);
+    assert!(verification.problems[0].contains("not by the trusted key"));
+
+    let tampered = rewrite(
+        &bundle,
+        "current.html",
+        html("A cat and three mice").as_bytes(),
+    )?;
+    let verification = evidence::verify(&tampered, None)?;
+    assert_eq!(
+        verification.problems,
+        vec!["current.html does not match its sha256".to_owned()]
+    );
+
+    let forged = serde_json::to_vec(&evidence::Manifest {
+        headline: "A dog".to_owned(),
+        ..manifest
+    })?;
+    let tampered = rewrite(&bundle, "manifest.json", &forged)?;
+    let verification = evidence::verify(&tampered, None)?;
+    assert_eq!(
+        verification.problems,
+        vec!["the signature of manifest.json is invalid".to_owned()]
+    );
+
+    assert!(evidence::verify(b"not a bundle", None).is_err());
+    assert!(evidence::parse_key("abcd").is_err());
+
+    Ok(())
+}
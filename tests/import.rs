@@ -0,0 +1,145 @@
+use anyhow::*;
+use propaganda::auth::{hash_token, ProvideTokens, Role};
+use propaganda::db::ProvideArticles;
+use propaganda::import::{self, canonicalize, Format, Status};
+use propaganda::*;
+use sqlx::prelude::*;
+use tide::http::{Method, Request, Url};
+
+#[test]
+fn urls_are_canonicalized() {
+    assert_eq!(
+        canonicalize(" https://Cats.Example:443/cat.html?utm_source=x&page=2&fbclid=y#top ")
+            .as_deref(),
+        Ok("https://cats.example/cat.html?page=2")
+    );
+    assert_eq!(
+        canonicalize(
+            "https://web.archive.org/web/20200102030405/https://cats.example/cat.html?utm_medium=z"
+        )
+        .as_deref(),
+        Ok("https://cats.example/cat.html")
+    );
+    assert!(canonicalize("ftp://cats.example/cat.html").is_err());
+    assert!(canonicalize("cat.html").is_err());
+}
+
+#[test]
+fn lines_csv_and_json_are_parsed() -> Result<()> {
+    let lines = import::parse(
+        "# seeds\nhttps://a.example/\n\nhttps://b.example/\n",
+        Format::Lines,
+    )
+    .map_err(Error::msg)?;
+    assert_eq!(
+        lines,
+        vec![
+            (2, "https://a.example/".to_owned()),
+            (4, "https://b.example/".to_owned())
+        ]
+    );
+
+    let csv = "title,URL\n\"Cats, and dogs\",https://a.example/\nno url\n";
+    let csv = import::parse(csv, Format::Csv).map_err(Error::msg)?;
+    assert_eq!(
+        csv,
+        vec![(2, "https://a.example/".to_owned()), (3, String::new())]
+    );
+    let csv = import::parse("https://a.example/,x\n", Format::Csv).map_err(Error::msg)?;
+    assert_eq!(csv, vec![(1, "https://a.example/".to_owned())]);
+
+    let json = r#"["https://a.example/", {"url": "https://b.example/"}, 42]"#;
+    let json = import::parse(json, Format::Json).map_err(Error::msg)?;
+    assert_eq!(json[1], (2, "https://b.example/".to_owned()));
+    assert_eq!(json[2], (3, "42".to_owned()));
+    assert!(import::parse("{", Format::Json).is_err());
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn import_reports_added_duplicate_and_invalid_lines() -> Result<()> {
+    let mut db: sqlx::SqliteConnection = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    db.insert_article("https://a.example/").await?;
+
+    let entries = import::parse(
+        "https://a.example/#x\nhttps://b.example/\nnot a url\nhttps://b.example/?utm_source=feed\n",
+        Format::Lines,
+    )
+    .map_err(Error::msg)?;
+    let report = import::import(&mut db, entries).await?;
+
+    assert_eq!((report.added, report.duplicate, report.invalid), (1, 2, 1));
+    let statuses = report.lines.iter().map(|l| l.status).collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        vec![
+            Status::Duplicate,
+            Status::Added,
+            Status::Invalid,
+            Status::Duplicate
+        ]
+    );
+    assert_eq!(report.lines[0].article_id, Some(1));
+    assert!(report.lines[1].article_id.is_some());
+    assert_eq!(report.lines[3].article_id, report.lines[1].article_id);
+    assert!(report.lines[2].error.is_some());
+    assert_eq!(db.get_articles(0, 10).await?.len(), 2);
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn import_over_http() -> Result<()> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push("import-http.db");
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    let mut conn = pool.acquire().await?;
+    conn.ensure_created_tables().await?;
+    conn.insert_token("import", Role::Editor, None, &hash_token("editor"), 0)
+        .await?;
+    drop(conn);
+
+    let state = http::State::new(pool, Default::default());
+    let mut events = state.events.subscribe();
+    let mut server = tide::with_state(state.clone());
+    server.at("/api/v1").nest(api::server(state));
+
+    let mut req = Request::new(
+        Method::Post,
+        Url::parse("http://localhost/api/v1/articles/import")?,
+    );
+    req.insert_header("Authorization", "Bearer editor");
+    req.set_body("url\nhttps://a.example/\nmailto:someone@example.com\n");
+    req.set_content_type("text/csv".parse().map_err(|e| anyhow!("{:?}", e))?);
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    let report: serde_json::Value =
+        serde_json::from_str(&res.body_string().await.map_err(|e| anyhow!(e))?)?;
+    assert_eq!(report["added"], 1);
+    assert_eq!(report["invalid"], 1);
+    assert_eq!(report["lines"][0]["line"], 2);
+    assert_eq!(report["lines"][1]["status"], "invalid");
+
+    use futures::StreamExt;
+    match events.next().await {
+        Some(events::Event::ArticleInserted { article }) => {
+            assert_eq!(article.url, "https://a.example/")
+        }
+        other => panic!("expected an inserted article, got {:?}", other),
+    }
+
+    let mut req = Request::new(
+        Method::Post,
+        Url::parse("http://localhost/api/v1/articles/import?format=json")?,
+    );
+    req.insert_header("Authorization", "Bearer editor");
+    req.set_body("not json");
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 400);
+
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use anyhow::*;
+use propaganda::blobs::BlobStore;
+use propaganda::db::{ProvideArticles, RevisionFilter};
+use propaganda::media::{self, Media, MediaChange, MediaKind};
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::significance::Significance;
+use propaganda::{api, http};
+use sqlx::prelude::*;
+use tide::http::{Method, Request, Response, Url};
+
+fn image(src: &str) -> Media {
+    Media {
+        kind: MediaKind::Image,
+        src: src.into(),
+        alt: None,
+        caption: None,
+        credit: None,
+        sha256: None,
+        phash: None,
+    }
+}
+
+fn png(width: u32, height: u32, pixel: impl Fn(u32, u32) -> u8) -> Vec<u8> {
+    let image = image::GrayImage::from_fn(width, height, |x, y| image::Luma([pixel(x, y)]));
+    let mut bytes = std::io::Cursor::new(vec![]);
+    image
+        .write_to(&mut bytes, image::ImageOutputFormat::Png)
+        .expect("png");
+    bytes.into_inner()
+}
+
+#[test]
+fn images_and_media_of_the_article_body() {
+    let html = r#"<img src="/logo.png">
+        <div id=content>
+        <figure><img src="data:image/gif;base64,R0lG" data-src="/cat.jpg" alt=" A  cat ">
+        <figcaption>The cat on the roof. <span class="credit">Photo: Ann</span></figcaption>
+        </figure>
+        <p>Text</p>
+        <video><source src="/cat.mp4"></video>
+        <iframe src="https://video.example/embed/1"></iframe>
+        </div>"#;
+    let media = media::extract(html);
+    assert_eq!(
+        media[0],
+        Media {
+            alt: Some("A cat".into()),
+            caption: Some("The cat on the roof.".into()),
+            credit: Some("Photo: Ann".into()),
+            ..image("/cat.jpg")
+        }
+    );
+    assert_eq!(media[1].kind, MediaKind::Video);
+    assert_eq!(media[1].src, "/cat.mp4");
+    assert_eq!(media[2].kind, MediaKind::Embed);
+    assert_eq!(media.len(), 3);
+    assert!(media::extract("<p>no body</p><img src=/logo.png>").is_empty());
+}
+
+#[test]
+fn changes_pair_media() {
+    let old = vec![
+        image("/a.jpg"),
+        Media {
+            caption: Some("Before".into()),
+            ..image("/b.jpg")
+        },
+        image("/c.jpg"),
+    ];
+    let new = vec![
+        image("/d.jpg"),
+        Media {
+            caption: Some("After".into()),
+            ..image("/b.jpg")
+        },
+    ];
+    let changes = media::diff(&old, &new);
+    assert!(
+        matches!(&changes[0], MediaChange::Edited { to, .. } if to.caption.as_deref() == Some("After"))
+    );
+    assert!(
+        matches!(&changes[1], MediaChange::Replaced { from, to } if from.src == "/a.jpg" && to.src == "/d.jpg")
+    );
+    assert!(matches!(&changes[2], MediaChange::Removed(media) if media.src == "/c.jpg"));
+    assert_eq!(changes.len(), 3);
+    assert_eq!(media::diff(&new, &new), vec![]);
+    assert_eq!(
+        media::diff(&[], &new[..1]),
+        vec![MediaChange::Added(image("/d.jpg"))]
+    );
+}
+
+#[test]
+fn perceptual_hashes_survive_resizing() -> Result<()> {
+    let gradient = |size: u32| png(size, size, move |x, y| ((x + y) * 255 / (2 * size)) as u8);
+    let stripes = png(64, 64, |x, _| if x / 8 % 2 == 0 { 0 } else { 255 });
+    let (large, small, other) = (
+        media::phash(&gradient(64)).unwrap(),
+        media::phash(&gradient(32)).unwrap(),
+        media::phash(&stripes).unwrap(),
+    );
+    assert!(media::distance(&large, &small).unwrap() <= media::SIMILAR_IMAGES);
+    assert!(media::distance(&large, &other).unwrap() > media::SIMILAR_IMAGES);
+    assert_eq!(media::phash(b"not an image"), None);
+
+    // the same image under another url is no change, another under the same url is
+    let hashed = |src: &str, phash: &str| Media {
+        phash: Some(phash.to_owned()),
+        ..image(src)
+    };
+    assert_eq!(
+        media::diff(&[hashed("/a.png", &large)], &[hashed("/b.png", &small)]),
+        vec![]
+    );
+    assert_eq!(
+        media::diff(&[hashed("/a.png", &large)], &[hashed("/a.png", &other)]).len(),
+        1
+    );
+    Ok(())
+}
+
+async fn blobs(name: &str) -> Result<BlobStore> {
+    let mut path = std::env::temp_dir();
+    path.push(name);
+    let _ = async_std::fs::remove_dir_all(&path).await;
+    Ok(BlobStore::open(&path).await?)
+}
+
+#[async_std::test]
+async fn blobs_are_content_addressed() -> Result<()> {
+    let blobs = blobs("media-blobs").await?;
+
+    let sha256 = blobs.put(b"cat").await?;
+    assert_eq!(blobs.put(b"cat").await?, sha256);
+    assert_eq!(blobs.get(&sha256).await?, Some(b"cat".to_vec()));
+    assert_eq!(blobs.get(&"0".repeat(64)).await?, None);
+    assert_eq!(blobs.get("../etc").await?, None);
+    Ok(())
+}
+
+#[async_std::test]
+async fn blobs_are_served() -> Result<()> {
+    let blobs = blobs("media-served-blobs").await?;
+    let png = png(9, 8, |x, _| x as u8 * 20);
+    let sha256 = blobs.put(&png).await?;
+    let pool = sqlx::SqlitePool::new("sqlite::memory:").await?;
+    let server = api::server(http::State {
+        blobs: Some(blobs),
+        ..http::State::new(pool, Default::default())
+    });
+    let get = |sha256: &str| {
+        let url = Url::parse("http://localhost/blobs/").and_then(|url| url.join(sha256));
+        Request::new(Method::Get, url.expect("blob url"))
+    };
+
+    let mut res: Response = server.respond(get(&sha256)).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res["Content-Type"], "image/png");
+    assert_eq!(res.body_bytes().await.map_err(|e| anyhow!(e))?, png);
+
+    let res: Response = server
+        .respond(get(&"0".repeat(64)))
+        .await
+        .map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 404);
+    Ok(())
+}
+
+#[async_std::test]
+async fn unchanged_images_are_not_downloaded_again() -> Result<()> {
+    let blobs = blobs("media-unchanged-blobs").await?;
+    let previous = vec![Media {
+        sha256: Some("ab".into()),
+        phash: Some("cd".into()),
+        ..image("/a.jpg")
+    }];
+
+    // the article is not fetched, its host does not even resolve
+    let mut media = vec![image("/a.jpg"), image("/b.jpg")];
+    media::download(&blobs, "http://news.invalid/a", &previous, &mut media).await;
+    assert_eq!(media[0], previous[0]);
+    assert_eq!(media[1], image("/b.jpg"));
+    Ok(())
+}
+
+#[async_std::test]
+async fn media_changes_are_revisions() -> Result<()> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+    let html = |src: &str, caption: &str| {
+        format!(
+            r#"<div id=content><p>The mayor lied.</p>
+            <figure><img src="{}"><figcaption>{}</figcaption></figure></div>"#,
+            src, caption
+        )
+    };
+
+    insert_snapshot_and_revision(&mut db, &article, 5, &html("/a.jpg", "The mayor")).await?;
+    insert_snapshot_and_revision(&mut db, &article, 6, &html("/b.jpg", "The mayor")).await?;
+    insert_snapshot_and_revision(&mut db, &article, 7, &html("/b.jpg", "A mayor")).await?;
+
+    let revisions = db
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[1].words_added, 0);
+    assert_eq!(revisions[1].significance, Some(Significance::Paragraphs));
+    assert_eq!(revisions[0].significance, Some(Significance::Wording));
+
+    let snapshot = db.get_snaphot(revisions[0].snapshot_id).await?;
+    let stored = media::of_snapshot(snapshot.page_media.as_deref(), "");
+    assert_eq!(stored[0].caption.as_deref(), Some("A mayor"));
+    Ok(())
+}
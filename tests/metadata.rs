@@ -0,0 +1,133 @@
+use anyhow::*;
+use propaganda::db::{ProvideArticles, RevisionFilter};
+use propaganda::diff::Paragraph;
+use propaganda::metadata::{self, PageMetadata};
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::significance::Significance;
+use sqlx::prelude::*;
+
+#[test]
+fn news_articles_from_json_ld() {
+    let html = r#"<head>
+        <meta property="og:title" content="Cats everywhere">
+        <meta property="og:image" content="https://news.example/og.jpg">
+        <script type="application/ld+json">
+        {"@context": "https://schema.org", "@graph": [
+            {"@type": "WebPage", "name": "News"},
+            {"@type": ["NewsArticle"],
+             "headline": "Cats  everywhere, experts say",
+             "author": [{"@type": "Person", "name": "Ann Example"}, "Bob Example"],
+             "articleSection": ["Science", "Animals"],
+             "image": [{"@type": "ImageObject", "url": "https://news.example/cat.jpg"}],
+             "datePublished": "2026-10-18T08:00:00Z",
+             "dateModified": "2026-10-19T09:30:00Z"}
+        ]}</script>
+        </head>"#;
+    assert_eq!(
+        metadata::extract(html),
+        PageMetadata {
+            headline: Some("Cats everywhere, experts say".into()),
+            description: None,
+            authors: vec!["Ann Example".into(), "Bob Example".into()],
+            section: Some("Science, Animals".into()),
+            image: Some("https://news.example/cat.jpg".into()),
+            published_time: Some("2026-10-18T08:00:00Z".into()),
+            modified_time: Some("2026-10-19T09:30:00Z".into()),
+        }
+    );
+}
+
+#[test]
+fn open_graph_fills_in() {
+    let html = r#"<head>
+        <meta property="og:title" content="Dogs too">
+        <meta property="og:description" content="And they bark.">
+        <meta property="article:author" content="Carl Example">
+        <meta property="article:section" content="Animals">
+        <meta property="og:image" content="https://news.example/dog.jpg">
+        <script type="application/ld+json">{"@type": "Article", "headline": "Dogs, too"}</script>
+        </head>"#;
+    let metadata = metadata::extract(html);
+    assert_eq!(metadata.headline.as_deref(), Some("Dogs, too"));
+    assert_eq!(metadata.description.as_deref(), Some("And they bark."));
+    assert_eq!(metadata.authors, vec!["Carl Example"]);
+    assert_eq!(metadata.section.as_deref(), Some("Animals"));
+    assert_eq!(
+        metadata.image.as_deref(),
+        Some("https://news.example/dog.jpg")
+    );
+    assert_eq!(metadata::extract("<p>nothing</p>"), PageMetadata::default());
+}
+
+#[test]
+fn metadata_diffs_by_field() {
+    let before = PageMetadata {
+        headline: Some("Cats".into()),
+        authors: vec!["Ann Example".into()],
+        section: Some("Science".into()),
+        modified_time: Some("1".into()),
+        ..PageMetadata::default()
+    };
+    assert_eq!(
+        before.lines(),
+        "headline: Cats\nauthor: Ann Example\nsection: Science\nmodified: 1"
+    );
+    let after = PageMetadata {
+        authors: vec!["Bob Example".into()],
+        modified_time: Some("2".into()),
+        ..before.clone()
+    };
+    assert!(after.changed_since(&before));
+    assert!(!PageMetadata {
+        modified_time: Some("2".into()),
+        ..before.clone()
+    }
+    .changed_since(&before));
+
+    let diff = before.diff(&after);
+    assert_eq!(
+        diff.paragraphs[0],
+        Paragraph::Equal("headline: Cats".into())
+    );
+    assert!(matches!(&diff.paragraphs[1], Paragraph::Edit(_)));
+    assert_eq!(diff.summary.words_added, 2);
+}
+
+#[async_std::test]
+async fn metadata_changes_are_revisions() -> Result<()> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+    let html = |author: &str, modified: &str| {
+        format!(
+            r#"<meta property="article:author" content="{}">
+            <meta property="article:modified_time" content="{}">
+            <div id=content><p>The mayor lied.</p></div>"#,
+            author, modified
+        )
+    };
+
+    insert_snapshot_and_revision(&mut db, &article, 5, &html("Ann", "1")).await?;
+    insert_snapshot_and_revision(&mut db, &article, 6, &html("Ann", "2")).await?;
+    insert_snapshot_and_revision(&mut db, &article, 7, &html("Bob", "2")).await?;
+
+    let revisions = db
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0].archived_at, 7);
+    assert_eq!(revisions[0].words_added, 0);
+
+    let filter = RevisionFilter {
+        min_significance: Some(Significance::Wording),
+        ..RevisionFilter::default()
+    };
+    let filtered = db.get_revisions(&filter, None, 10).await?;
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(filtered[0].revision_id, revisions[0].revision_id);
+
+    let snapshot = db.get_snaphot(revisions[0].snapshot_id).await?;
+    let stored: PageMetadata = serde_json::from_str(snapshot.page_metadata.as_deref().unwrap())?;
+    assert_eq!(stored.authors, vec!["Bob"]);
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use anyhow::*;
+use propaganda::db::{Provenance, ProvideArticles, SnapshotSource};
+use propaganda::provenance::{self, Violation};
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::warc;
+use sqlx::prelude::*;
+
+fn html(text: &str) -> String {
+    format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text)
+}
+
+async fn memory_db() -> Result<sqlx::SqliteConnection> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    Ok(db)
+}
+
+fn chained() -> Provenance {
+    Provenance {
+        request_headers: vec![("User-Agent".to_owned(), "propaganda".to_owned())],
+        response_headers: vec![
+            ("Content-Type".to_owned(), "text/html".to_owned()),
+            ("ETag".to_owned(), "\"1\"".to_owned()),
+        ],
+        chain: true,
+        ..Provenance::default()
+    }
+}
+
+#[async_std::test]
+async fn snapshots_keep_hashes_headers_and_chain() -> Result<()> {
+    let mut db = memory_db().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+
+    let first = db
+        .insert_snapshot_with_provenance(&article, 1, &html("One"), &chained())
+        .await?;
+    let second = db.insert_snapshot(&article, 2, &html("Two")).await?;
+
+    let first = db.get_snaphot(first.snapshot_id).await?;
+    assert_eq!(first.source, SnapshotSource::Live);
+    assert_eq!(
+        first.body_sha256.as_deref(),
+        Some(provenance::body_sha256(html("One")).as_str())
+    );
+    assert_eq!(
+        provenance::parse_headers(first.response_headers.as_deref()),
+        chained().response_headers
+    );
+    assert_eq!(
+        first.chain_sha256,
+        Some(provenance::chain_sha256(
+            None,
+            article.article_id,
+            1,
+            SnapshotSource::Live,
+            &provenance::body_sha256(html("One"))
+        ))
+    );
+
+    let second = db.get_snaphot(second.snapshot_id).await?;
+    assert_eq!(second.request_headers, None);
+    assert_eq!(
+        second.chain_sha256,
+        Some(provenance::chain_sha256(
+            first.chain_sha256.as_deref(),
+            article.article_id,
+            2,
+            SnapshotSource::Live,
+            &provenance::body_sha256(html("Two"))
+        ))
+    );
+
+    let unchained = db.insert_article("https://news.example/b").await?;
+    let snapshot = db.insert_snapshot(&unchained, 1, &html("Three")).await?;
+    let snapshot = db.get_snaphot(snapshot.snapshot_id).await?;
+    assert!(snapshot.body_sha256.is_some());
+    assert_eq!(snapshot.chain_sha256, None);
+
+    let report = provenance::verify(&mut db, None).await?;
+    assert_eq!(
+        (report.snapshots, report.chained, report.unhashed),
+        (3, 2, 0)
+    );
+    assert!(report.findings.is_empty());
+
+    Ok(())
+}
+
+/// a body which is not utf-8 keeps the hash of its bytes, not of the html decoded from them
+#[async_std::test]
+async fn bodies_are_hashed_as_received() -> Result<()> {
+    let mut db = memory_db().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+    let body = b"<div id=content><p>Gr\xfc\xdfe</p></div>".to_vec();
+    let html = String::from_utf8_lossy(&body).into_owned();
+    let received = Provenance {
+        body_sha256: Some(provenance::body_sha256(&body)),
+        ..chained()
+    };
+
+    let snapshot = db
+        .insert_snapshot_with_provenance(&article, 1, &html, &received)
+        .await?;
+    let snapshot = db.get_snaphot(snapshot.snapshot_id).await?;
+    assert_eq!(snapshot.body_sha256, received.body_sha256);
+    assert_ne!(snapshot.body_sha256, Some(provenance::body_sha256(&html)));
+    assert_eq!(
+        snapshot.chain_sha256,
+        Some(provenance::chain_sha256(
+            None,
+            article.article_id,
+            1,
+            SnapshotSource::Live,
+            &provenance::body_sha256(&body)
+        ))
+    );
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn verify_reports_tampering() -> Result<()> {
+    let mut db = memory_db().await?;
+    let article = db.insert_article("https://news.example/a").await?;
+    let mut ids = vec![];
+    for (at, text) in [(1, "One"), (2, "Two"), (3, "Three"), (4, "Four")] {
+        let snapshot = db
+            .insert_snapshot_with_provenance(&article, at, &html(text), &chained())
+            .await?;
+        ids.push(snapshot.snapshot_id);
+    }
+    insert_snapshot_and_revision(&mut db, &article, 5, &html("Five")).await?;
+
+    sqlx::query("UPDATE snapshots SET html = $1 WHERE snapshot_id = $2")
+        .bind(html("Uno"))
+        .bind(ids[0])
+        .execute(&mut db)
+        .await?;
+    sqlx::query("DELETE FROM snapshots WHERE snapshot_id = $1")
+        .bind(ids[2])
+        .execute(&mut db)
+        .await?;
+    sqlx::query("INSERT INTO snapshots (article_id, archived_at, html) VALUES ($1, 6, 'x')")
+        .bind(article.article_id)
+        .execute(&mut db)
+        .await?;
+
+    let report = provenance::verify(&mut db, Some(&[article.article_id])).await?;
+    assert_eq!((report.snapshots, report.unhashed), (5, 1));
+    let findings = report
+        .findings
+        .iter()
+        .map(|f| (f.snapshot_id, f.violation))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        findings,
+        vec![
+            (ids[0], Violation::BodyHash),
+            (ids[0], Violation::Chain),
+            (ids[3], Violation::Chain),
+        ]
+    );
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn warc_records_keep_their_headers() -> Result<()> {
+    let mut source = memory_db().await?;
+    let article = source
+        .insert_article("https://news.example/a?page=2")
+        .await?;
+    source
+        .insert_snapshot_with_provenance(&article, 1598788800, &html("One"), &chained())
+        .await?;
+
+    let records = warc::export(&mut source, None, 1598796000).await?;
+    let kinds = records
+        .iter()
+        .map(|r| r.header("WARC-Type").unwrap_or_default())
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["warcinfo", "response", "request"]);
+    assert_eq!(
+        records[2].header("WARC-Concurrent-To"),
+        records[1].header("WARC-Record-ID")
+    );
+    assert!(String::from_utf8_lossy(&records[2].block)
+        .starts_with("GET /a?page=2 HTTP/1.1\r\nHost: news.example\r\n"));
+
+    let mut target = memory_db().await?;
+    let report = warc::import(&mut target, &records).await?;
+    assert_eq!(report.snapshots_added, 1);
+
+    let article = target.get_article("https://news.example/a?page=2").await?;
+    let snapshot = target.get_youngest_snaphot(&article).await?;
+    let snapshot = snapshot.ok_or_else(|| anyhow!("no snapshot"))?;
+    assert_eq!(snapshot.source, SnapshotSource::Warc);
+    assert_eq!(
+        snapshot.source_url.as_deref(),
+        records[1].header("WARC-Record-ID")
+    );
+    assert_eq!(
+        provenance::parse_headers(snapshot.request_headers.as_deref()),
+        chained().request_headers
+    );
+    let mut response_headers = chained().response_headers;
+    response_headers.push(("Content-Length".to_owned(), html("One").len().to_string()));
+    assert_eq!(
+        provenance::parse_headers(snapshot.response_headers.as_deref()),
+        response_headers
+    );
+    assert_eq!(
+        snapshot.body_sha256,
+        Some(provenance::body_sha256(html("One")))
+    );
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use anyhow::*;
+use propaganda::db::{DbError, ProvideArticles};
+use propaganda::repository::Repository;
+use propaganda::sled_store::SledStore;
+use std::collections::HashSet;
+
+mod common;
+
+async fn sqlite(name: &str) -> Result<Repository> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push(name);
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    pool.acquire().await?.ensure_created_tables().await?;
+    Ok(Repository::from(pool))
+}
+
+async fn postgres(name: &str) -> Result<Option<Repository>> {
+    match common::postgres_url(name).await? {
+        Some(url) => {
+            let repository = Repository::open(&url).await?;
+            repository.acquire().await?.ensure_created_tables().await?;
+            Ok(Some(repository))
+        }
+        None => Ok(None),
+    }
+}
+
+/// eight workers claim until nothing is due, every article is claimed exactly once
+async fn concurrent_claims(repository: Repository) -> Result<()> {
+    let urls = (0..20)
+        .map(|n| format!("https://news.example/{}", n))
+        .collect::<Vec<_>>();
+    repository.acquire().await?.insert_articles(&urls).await?;
+
+    let workers = (0..8).map(|_| {
+        let repository = repository.clone();
+        async_std::task::spawn(async move {
+            let mut claimed = vec![];
+            while let Some(lease) = repository.claim_next_due_article(100, 300).await? {
+                claimed.push(lease.article.article_id);
+            }
+            Ok::<_, DbError>(claimed)
+        })
+    });
+    let mut claimed = vec![];
+    for worker in futures::future::join_all(workers).await {
+        claimed.extend(worker?);
+    }
+    assert_eq!(claimed.len(), urls.len());
+    assert_eq!(claimed.iter().collect::<HashSet<_>>().len(), urls.len());
+
+    let lease = repository
+        .claim_next_due_article(400, 300)
+        .await?
+        .expect("an expired lease");
+    let (tx, released) = repository.begin_release(&lease, 400).await?;
+    tx.commit().await?;
+    assert!(released);
+    let (tx, released) = repository.begin_release(&lease, 400).await?;
+    tx.rollback().await?;
+    assert!(!released);
+
+    Ok(())
+}
+
+/// a transaction rolled back leaves nothing behind
+async fn transactions_roll_back(repository: Repository) -> Result<()> {
+    let mut tx = repository.begin().await?;
+    tx.insert_article("https://news.example/dropped").await?;
+    tx.rollback().await?;
+
+    let mut tx = repository.begin().await?;
+    tx.insert_article("https://news.example/committed").await?;
+    tx.commit().await?;
+
+    let mut conn = repository.acquire().await?;
+    assert!(conn
+        .get_article("https://news.example/committed")
+        .await
+        .is_ok());
+    assert!(matches!(
+        conn.get_article("https://news.example/dropped").await,
+        Err(DbError::NotFound(_))
+    ));
+    Ok(())
+}
+
+#[async_std::test]
+async fn sqlite_workers_never_claim_the_same_article() -> Result<()> {
+    concurrent_claims(sqlite("repository-claims.sqlite").await?).await
+}
+
+#[async_std::test]
+async fn postgres_workers_never_claim_the_same_article() -> Result<()> {
+    match postgres("propaganda_repository_claims").await? {
+        Some(repository) => concurrent_claims(repository).await,
+        None => Ok(()),
+    }
+}
+
+#[async_std::test]
+async fn sled_workers_never_claim_the_same_article() -> Result<()> {
+    concurrent_claims(Repository::from(SledStore::temporary()?)).await
+}
+
+#[async_std::test]
+async fn sqlite_transactions_roll_back_unless_committed() -> Result<()> {
+    transactions_roll_back(sqlite("repository-transactions.sqlite").await?).await
+}
+
+#[async_std::test]
+async fn postgres_transactions_roll_back_unless_committed() -> Result<()> {
+    match postgres("propaganda_repository_transactions").await? {
+        Some(repository) => transactions_roll_back(repository).await,
+        None => Ok(()),
+    }
+}
+
+#[async_std::test]
+async fn sled_transactions_roll_back_unless_committed() -> Result<()> {
+    transactions_roll_back(Repository::from(SledStore::temporary()?)).await
+}
+
+/// on sled the journal restores what a dropped transaction overwrote, ids included
+#[async_std::test]
+async fn sled_transactions_roll_back_when_dropped() -> Result<()> {
+    let repository = Repository::from(SledStore::temporary()?);
+    let article = repository
+        .acquire()
+        .await?
+        .insert_article("https://news.example/leased")
+        .await?;
+    let lease = repository
+        .claim_next_due_article(100, 300)
+        .await?
+        .expect("a due article");
+
+    let (mut tx, released) = repository.begin_release(&lease, 200).await?;
+    assert!(released);
+    tx.insert_snapshot(&article, 200, "<p>dropped</p>").await?;
+    drop(tx);
+
+    let mut conn = repository.acquire().await?;
+    assert_eq!(
+        conn.get_article_by_id(article.article_id).await?.updated_at,
+        0
+    );
+    assert!(conn
+        .get_snaphot_metadatas_from_article(article.article_id)
+        .await?
+        .is_empty());
+    let snapshot = conn.insert_snapshot(&article, 300, "<p>kept</p>").await?;
+    assert_eq!(snapshot.snapshot_id, 1);
+    drop(conn);
+
+    // the lease is still held
+    assert!(repository.claim_next_due_article(150, 300).await?.is_none());
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use propaganda::diff::text_diff;
+use propaganda::significance::{classify, Significance};
+
+fn significance(old: &str, new: &str) -> Significance {
+    classify("Headline", "Headline", &text_diff(old, new))
+}
+
+#[test]
+fn markers_punctuation_and_whitespace_are_cosmetic() {
+    let old = "Update 14:32 The council met today.\nIt voted on the budget.";
+    assert_eq!(
+        significance(
+            old,
+            "Update 16:05 The council met today.\nIt voted on the budget."
+        ),
+        Significance::Cosmetic
+    );
+    assert_eq!(
+        significance(
+            old,
+            "Update 14:32 The council met today!\nIt voted on the budget."
+        ),
+        Significance::Cosmetic
+    );
+}
+
+#[test]
+fn edits_are_classified_by_their_most_significant_change() {
+    let old = "The mayor said \"we will build 300 homes\" on Monday in the town hall.";
+    assert_eq!(
+        significance(
+            old,
+            "The mayor said \"we will build 300 homes\" on Monday in the tonw hall."
+        ),
+        Significance::Typo
+    );
+    assert_eq!(
+        significance(
+            old,
+            "The mayor said \"we will build 300 homes\" on Tuesday in the town hall."
+        ),
+        Significance::Wording
+    );
+    assert_eq!(
+        significance(
+            old,
+            "The mayor said \"we will build 200 homes\" on Monday in the town hall."
+        ),
+        Significance::Quote
+    );
+    assert_eq!(
+        significance(
+            "The mayor promised 300 homes on Monday in the town hall.",
+            "The mayor promised 200 homes on Monday in the town hall."
+        ),
+        Significance::Numeric
+    );
+}
+
+#[test]
+fn paragraphs_headlines_and_rewrites() {
+    let old = "First paragraph of the report.\nSecond paragraph of the report.";
+    assert_eq!(
+        significance(old, "First paragraph of the report."),
+        Significance::Paragraphs
+    );
+    assert_eq!(
+        significance(
+            old,
+            "Second paragraph of the report.\nFirst paragraph of the report."
+        ),
+        Significance::Paragraphs
+    );
+    assert_eq!(
+        classify("Storm ahead", "Storm passed", &text_diff(old, old)),
+        Significance::Headline
+    );
+    assert_eq!(
+        classify(
+            "Update 14:32: Storm",
+            "Update 15:00: Storm",
+            &text_diff(old, old)
+        ),
+        Significance::Cosmetic
+    );
+    assert_eq!(
+        significance(old, "Something else entirely happened yesterday."),
+        Significance::Rewrite
+    );
+    assert!(Significance::Quote > Significance::Numeric);
+}
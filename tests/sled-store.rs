@@ -0,0 +1,203 @@
+use anyhow::*;
+use propaganda::db::*;
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::sled_store::SledStore;
+use propaganda::watchlist::ProvideWatchlists;
+use propaganda::webhook::{NewWebhook, ProvideWebhooks};
+
+fn html(text: &str) -> String {
+    format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text)
+}
+
+#[async_std::test]
+async fn records_and_indexes_survive_reopening() -> Result<()> {
+    let mut path = std::env::temp_dir();
+    path.push("sled-store");
+    let _ = async_std::fs::remove_dir_all(&path).await;
+    let path = path.display().to_string();
+
+    {
+        let mut db = SledStore::open(&path)?;
+        let cat = db.insert_article("https://cats.example/cat").await?;
+        let dog = db.insert_article("https://dogs.example/dog").await?;
+        assert_eq!(
+            db.insert_article(&cat.url).await?.article_id,
+            cat.article_id
+        );
+        db.update_article(&cat.url, 42).await?;
+        insert_snapshot_and_revision(&mut db, &dog, 5, &html("A dog")).await?;
+        insert_snapshot_and_revision(&mut db, &dog, 7, &html("A dog barks")).await?;
+    }
+
+    let mut db = SledStore::open(&path)?;
+    let outdated = db.get_outdated_articles(10).await?;
+    let urls = outdated.iter().map(|a| a.url.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        urls,
+        vec!["https://dogs.example/dog", "https://cats.example/cat"]
+    );
+    let dog = db.get_article("https://dogs.example/dog").await?;
+    let revisions = db
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0].url, dog.url);
+
+    let inserted = db
+        .insert_articles(&[dog.url.clone(), "https://birds.example/".to_owned()])
+        .await?;
+    let added = inserted.iter().map(|(_, added)| *added).collect::<Vec<_>>();
+    assert_eq!(added, vec![false, true]);
+
+    db.delete_article(dog.article_id).await?;
+    assert!(matches!(
+        db.get_article(&dog.url).await,
+        Err(DbError::NotFound(_))
+    ));
+    assert!(db
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?
+        .is_empty());
+    assert_eq!(db.get_outdated_articles(10).await?.len(), 2);
+
+    Ok(())
+}
+
+/// ids are counted per tree, reopening does not skip any
+#[async_std::test]
+async fn ids_stay_dense_across_reopening() -> Result<()> {
+    let mut path = std::env::temp_dir();
+    path.push("sled-store-ids");
+    let _ = async_std::fs::remove_dir_all(&path).await;
+    let path = path.display().to_string();
+
+    for n in 1..=5 {
+        let mut db = SledStore::open(&path)?;
+        let article = db
+            .insert_article(&format!("https://cats.example/{}", n))
+            .await?;
+        assert_eq!(article.article_id, n);
+        let snapshot = db.insert_snapshot(&article, n, &html("A cat")).await?;
+        assert_eq!(snapshot.snapshot_id, n);
+    }
+    Ok(())
+}
+
+/// concurrent inserts continue the hash chain one after the other, in the order of their ids
+#[async_std::test]
+async fn concurrent_snapshots_keep_one_chain() -> Result<()> {
+    let mut db = SledStore::temporary()?;
+    let article = db.insert_article("https://cats.example/cat").await?;
+    let chained = Provenance {
+        chain: true,
+        ..Provenance::default()
+    };
+
+    let inserts = (0..8).map(|n| {
+        let (mut db, article, chained) = (db.clone(), article.clone(), chained.clone());
+        async_std::task::spawn(async move {
+            for at in 0..5 {
+                db.insert_snapshot_with_provenance(&article, n * 5 + at, &html("A cat"), &chained)
+                    .await?;
+            }
+            Ok::<_, DbError>(())
+        })
+    });
+    for insert in futures::future::join_all(inserts).await {
+        insert?;
+    }
+
+    let report = propaganda::provenance::verify(&mut db, None).await?;
+    assert_eq!((report.snapshots, report.chained), (40, 40));
+    assert!(report.findings.is_empty());
+    Ok(())
+}
+
+#[async_std::test]
+async fn watchlists_scope_articles_and_revisions() -> Result<()> {
+    let mut db = SledStore::temporary()?;
+    let cat = db.insert_article("https://cats.example/cat").await?;
+    let dog = db.insert_article("https://dogs.example/dog").await?;
+    let bird = db.insert_article("https://birds.example/bird").await?;
+    for article in &[&cat, &dog, &bird] {
+        insert_snapshot_and_revision(&mut db, article, 5, &html("A pet")).await?;
+        insert_snapshot_and_revision(&mut db, article, 7, &html("A new pet")).await?;
+    }
+
+    let alice = db.insert_user("alice", 1).await?;
+    assert!(matches!(
+        db.insert_user("alice", 2).await,
+        Err(DbError::Conflict(_))
+    ));
+    let pets = db.insert_watchlist(alice.user_id, "pets", 1).await?;
+    db.add_watchlist_article(pets.watchlist_id, cat.article_id, 2)
+        .await?;
+    db.add_watchlist_source(pets.watchlist_id, "Dogs.example", 3)
+        .await?;
+
+    let filter = ArticleFilter {
+        watchlist: Some(pets.watchlist_id),
+        ..Default::default()
+    };
+    let listed = db
+        .list_articles(&filter, ArticleSort::Created, None, 10)
+        .await?;
+    let mut ids = listed.iter().map(|a| a.article_id).collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![cat.article_id, dog.article_id]);
+    let filter = RevisionFilter {
+        watchlist: Some(pets.watchlist_id),
+        ..Default::default()
+    };
+    assert_eq!(db.get_revisions(&filter, None, 10).await?.len(), 2);
+    assert_eq!(
+        db.get_watchlist_sources(pets.watchlist_id).await?,
+        vec!["dogs.example"]
+    );
+
+    db.delete_article(cat.article_id).await?;
+    assert!(db
+        .get_watchlist_articles(pets.watchlist_id)
+        .await?
+        .is_empty());
+    db.delete_watchlist(pets.watchlist_id).await?;
+    assert!(db.get_watchlists(Some(alice.user_id)).await?.is_empty());
+    assert!(db
+        .get_watchlist_sources(pets.watchlist_id)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn revisions_queue_webhook_deliveries() -> Result<()> {
+    let mut db = SledStore::temporary()?;
+    let hook = db
+        .insert_webhook(&NewWebhook {
+            url: "http://hooks.example/".to_owned(),
+            secret: "secret".to_owned(),
+            keyword: Some("barks".to_owned()),
+            ..Default::default()
+        })
+        .await?;
+
+    let dog = db.insert_article("https://dogs.example/dog").await?;
+    insert_snapshot_and_revision(&mut db, &dog, 5, &html("A dog")).await?;
+    insert_snapshot_and_revision(&mut db, &dog, 7, &html("A dog sleeps")).await?;
+    insert_snapshot_and_revision(&mut db, &dog, 9, &html("A dog barks")).await?;
+
+    let due = db.get_due_deliveries(i32::MAX, 10).await?;
+    assert_eq!(due.len(), 1);
+    assert_eq!((due[0].webhook_id, due[0].attempts), (hook.webhook_id, 0));
+    assert_eq!(due[0].secret, "secret");
+
+    db.update_delivery(due[0].delivery_id, 1, None, Some(10), None)
+        .await?;
+    assert!(db.get_due_deliveries(i32::MAX, 10).await?.is_empty());
+
+    db.delete_webhook(hook.webhook_id).await?;
+    assert!(db.get_webhooks().await?.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use anyhow::*;
+use propaganda::db::ProvideArticles;
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::*;
+use tide::http::{Method, Request, Url};
+
+async fn pool(name: &str) -> Result<sqlx::SqlitePool> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push(name);
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    let mut conn = pool.acquire().await?;
+    conn.ensure_created_tables().await?;
+
+    let cat = conn.insert_article("https://cats.example/cat.html").await?;
+    conn.insert_article("https://dogs.example/dog.html").await?;
+    let html = |text: &str| format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text);
+    insert_snapshot_and_revision(&mut *conn, &cat, 5, &html("A cat and a mouse")).await?;
+    insert_snapshot_and_revision(&mut *conn, &cat, 8, &html("A cat and two mice")).await?;
+    Ok(pool)
+}
+
+async fn server(name: &str) -> Result<tide::Server<http::State>> {
+    Ok(routes(pool(name).await?))
+}
+
+fn routes(pool: sqlx::SqlitePool) -> tide::Server<http::State> {
+    let mut server = tide::with_state(http::State::new(pool, Default::default()));
+    server.at("/").get(ui::articles);
+    server
+        .at("/ui/articles")
+        .get(ui::articles)
+        .post(ui::insert_article);
+    server.at("/ui/articles/:id").get(ui::article);
+    server.at("/ui/diff").get(ui::diff);
+    server
+}
+
+async fn get(server: &tide::Server<http::State>, url: &str) -> Result<(u16, String)> {
+    let req = Request::new(Method::Get, Url::parse("http://localhost")?.join(url)?);
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    let body = res.body_string().await.map_err(|e| anyhow!(e))?;
+    Ok((res.status() as u16, body))
+}
+
+#[async_std::test]
+async fn list_search_and_filter_articles() -> Result<()> {
+    let server = server("ui-list.db").await?;
+
+    let (status, body) = get(&server, "/").await?;
+    assert_eq!(status, 200);
+    assert!(body.contains("A cat and two mice"));
+    assert!(body.contains("https://dogs.example/dog.html"));
+    assert!(body.find("cats.example") < body.find("dogs.example"));
+
+    let (_, body) = get(&server, "/ui/articles?q=mice").await?;
+    assert!(body.contains("cats.example"));
+    assert!(!body.contains("dogs.example"));
+
+    let (_, body) = get(&server, "/ui/articles?host=dogs.example&q=").await?;
+    assert!(!body.contains("cats.example/cat.html"));
+    assert!(body.contains("dogs.example/dog.html"));
+
+    let (_, body) = get(&server, "/ui/articles?changed=on").await?;
+    assert!(body.contains("cats.example/cat.html"));
+    assert!(!body.contains("dogs.example/dog.html"));
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn article_timeline_and_diffs() -> Result<()> {
+    let server = server("ui-article.db").await?;
+
+    let (status, body) = get(&server, "/ui/articles/1").await?;
+    assert_eq!(status, 200);
+    assert!(body.contains("/ui/diff?from=1&amp;to=2"));
+    assert!(body.contains("<ins>+2</ins>"));
+
+    let (status, _) = get(&server, "/ui/articles/42").await?;
+    assert_eq!(status, 404);
+
+    let (_, body) = get(&server, "/ui/diff?from=1&to=2").await?;
+    assert!(body.contains("A cat and <del>a</del><ins>two</ins> <del>mouse</del><ins>mice</ins>"));
+
+    let (_, body) = get(&server, "/ui/diff?from=1&to=2&mode=side").await?;
+    assert!(body.contains(r#"<div class="diff">A cat and <del>a</del> <del>mouse</del>"#));
+    assert!(body.contains(r#"<div class="diff">A cat and <ins>two</ins> <ins>mice</ins>"#));
+
+    Ok(())
+}
+
+/// a new modification time alone is no metadata change worth showing
+#[async_std::test]
+async fn diffs_show_metadata_changes() -> Result<()> {
+    let pool = pool("ui-metadata.db").await?;
+    let mut conn = pool.acquire().await?;
+    let dog = conn.get_article("https://dogs.example/dog.html").await?;
+    let html = |modified: &str, author: &str, text: &str| {
+        format!(
+            r#"<meta property="article:modified_time" content="{}">
+            <meta name="author" content="{}"><div id=content><p>{}</p></div>"#,
+            modified, author, text
+        )
+    };
+    for (at, html) in &[
+        (5, html("1", "Carl", "A dog")),
+        (6, html("2", "Carl", "A dog barks")),
+        (7, html("2", "Dora", "A dog barks loudly")),
+    ] {
+        insert_snapshot_and_revision(&mut *conn, &dog, *at, html).await?;
+    }
+    drop(conn);
+    let server = routes(pool);
+
+    let (_, body) = get(&server, "/ui/diff?from=3&to=4").await?;
+    assert!(!body.contains("<h2>metadata</h2>"));
+    let (_, body) = get(&server, "/ui/diff?from=4&to=5").await?;
+    assert!(body.contains("<h2>metadata</h2>"));
+    assert!(body.contains("<del>Carl</del><ins>Dora</ins>"));
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn add_article_with_form() -> Result<()> {
+    let server = server("ui-form.db").await?;
+
+    let mut req = Request::new(Method::Post, Url::parse("http://localhost/ui/articles")?);
+    req.set_body("url=https%3A%2F%2Fbirds.example%2Fbird.html");
+    req.set_content_type(tide::http::mime::FORM);
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 303);
+    assert_eq!(res["Location"], "/ui/articles/3");
+
+    let (_, body) = get(&server, "/ui/articles/3").await?;
+    assert!(body.contains("https://birds.example/bird.html"));
+
+    Ok(())
+}
+
+/// only http(s) urls are tracked, others would be links which run in the ui
+#[async_std::test]
+async fn reject_urls_which_are_not_http() -> Result<()> {
+    let server = server("ui-form-scheme.db").await?;
+
+    for url in &[
+        "javascript%3Aalert(1)",
+        "data%3Atext%2Fhtml%2C%3Cp%3E",
+        "file%3A%2F%2F%2Fetc",
+    ] {
+        let mut req = Request::new(Method::Post, Url::parse("http://localhost/ui/articles")?);
+        req.set_body(format!("url={}", url));
+        req.set_content_type(tide::http::mime::FORM);
+        let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+        assert_eq!(res.status(), 400);
+    }
+    let (_, body) = get(&server, "/ui/articles").await?;
+    assert!(!body.contains("javascript:"));
+
+    Ok(())
+}
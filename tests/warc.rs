@@ -0,0 +1,194 @@
+use anyhow::*;
+use propaganda::auth::{hash_token, ProvideTokens, Role};
+use propaganda::db::{ProvideArticles, RevisionFilter};
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::warc::{self, Record};
+use propaganda::*;
+use sqlx::prelude::*;
+use std::io::Write;
+use tide::http::{Method, Request, Url};
+
+fn html(text: &str) -> String {
+    format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text)
+}
+
+async fn memory_db() -> Result<sqlx::SqliteConnection> {
+    let mut db = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+    Ok(db)
+}
+
+#[async_std::test]
+async fn export_and_import_round_trip() -> Result<()> {
+    let mut source = memory_db().await?;
+    let cat = source
+        .insert_article("https://cats.example/cat.html")
+        .await?;
+    insert_snapshot_and_revision(&mut source, &cat, 1598788800, &html("A cat and a mouse")).await?;
+    insert_snapshot_and_revision(&mut source, &cat, 1598792400, &html("A cat and two mice"))
+        .await?;
+    source
+        .insert_article("https://dogs.example/dog.html")
+        .await?;
+
+    let records = warc::export(&mut source, None, 1598796000).await?;
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].header("WARC-Type"), Some("warcinfo"));
+    assert_eq!(records[1].header("WARC-Type"), Some("response"));
+    assert_eq!(
+        records[1].header("WARC-Target-URI"),
+        Some("https://cats.example/cat.html")
+    );
+    assert_eq!(records[1].header("WARC-Date"), Some("2020-08-30T12:00:00Z"));
+    assert!(records[1]
+        .header("WARC-Record-ID")
+        .is_some_and(|id| id.starts_with("<urn:uuid:")));
+
+    let bytes = warc::write(&records, true);
+    assert_eq!(&bytes[..2], &[0x1f, 0x8b]);
+    let parsed = warc::parse(&bytes).map_err(Error::msg)?;
+    assert_eq!(parsed, records);
+    assert_eq!(
+        warc::parse(&warc::write(&records, false)).map_err(Error::msg)?,
+        records
+    );
+
+    let mut target = memory_db().await?;
+    let report = warc::import(&mut target, &parsed).await?;
+    assert_eq!((report.records, report.snapshots_added), (2, 2));
+    assert_eq!((report.duplicate, report.skipped), (0, 0));
+    assert_eq!(report.articles.len(), 1);
+
+    let article = target.get_article("https://cats.example/cat.html").await?;
+    let youngest = target.get_youngest_snaphot(&article).await?;
+    let youngest = youngest.ok_or_else(|| anyhow!("no snapshot"))?;
+    assert_eq!(youngest.archived_at, 1598792400);
+    assert_eq!(youngest.html, html("A cat and two mice"));
+    let revisions = target
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(revisions.len(), 1);
+
+    let report = warc::import(&mut target, &parsed).await?;
+    assert_eq!((report.snapshots_added, report.duplicate), (0, 2));
+    assert!(report.articles.is_empty());
+
+    Ok(())
+}
+
+fn foreign_record(kind: &str, uri: &str, block: Vec<u8>) -> Record {
+    Record {
+        headers: vec![
+            ("WARC-Type".to_owned(), kind.to_owned()),
+            ("WARC-Target-URI".to_owned(), format!("<{}>", uri)),
+            ("WARC-Date".to_owned(), "2019-01-02T03:04:05Z".to_owned()),
+        ],
+        block,
+    }
+}
+
+#[async_std::test]
+async fn foreign_records_are_decoded_or_skipped() -> Result<()> {
+    let mut gzipped = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gzipped.write_all(html("Old news").as_bytes())?;
+    let gzipped = gzipped.finish()?;
+    let mut block = format!(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Encoding: gzip\r\n\r\n{:x}\r\n",
+        gzipped.len()
+    )
+    .into_bytes();
+    block.extend(&gzipped);
+    block.extend(b"\r\n0\r\n\r\n");
+
+    let records = vec![
+        foreign_record(
+            "request",
+            "https://news.example/a",
+            b"GET /a HTTP/1.1\r\n\r\n".to_vec(),
+        ),
+        foreign_record("response", "https://news.example/a", block),
+        foreign_record(
+            "response",
+            "https://news.example/gone",
+            b"HTTP/1.1 404 Not Found\r\n\r\n".to_vec(),
+        ),
+        foreign_record(
+            "response",
+            "dns:news.example",
+            b"HTTP/1.1 200 OK\r\n\r\n".to_vec(),
+        ),
+    ];
+
+    let mut db = memory_db().await?;
+    let report = warc::import(&mut db, &records).await?;
+    assert_eq!(
+        (report.records, report.snapshots_added, report.skipped),
+        (3, 1, 2)
+    );
+
+    let article = db.get_article("https://news.example/a").await?;
+    let snapshot = db.get_youngest_snaphot(&article).await?;
+    let snapshot = snapshot.ok_or_else(|| anyhow!("no snapshot"))?;
+    assert_eq!(snapshot.archived_at, 1546398245);
+    assert_eq!(snapshot.html, html("Old news"));
+
+    assert!(warc::parse(b"HTTP/1.1 200 OK\r\n").is_err());
+    assert!(warc::parse(b"WARC/1.1\r\nWARC-Type: response\r\n\r\n").is_err());
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn warc_over_http() -> Result<()> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push("warc-http.db");
+    let _ = async_std::fs::remove_file(&db_path).await;
+
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    let mut conn = pool.acquire().await?;
+    conn.ensure_created_tables().await?;
+    conn.insert_token("warc", Role::Editor, None, &hash_token("editor"), 0)
+        .await?;
+    let cat = conn.insert_article("https://cats.example/cat.html").await?;
+    insert_snapshot_and_revision(&mut *conn, &cat, 5, &html("A cat")).await?;
+    drop(conn);
+
+    let state = http::State::new(pool, Default::default());
+    let mut server = tide::with_state(state.clone());
+    server.at("/api/v1").nest(api::server(state));
+
+    let req = Request::new(
+        Method::Get,
+        Url::parse("http://localhost/api/v1/warc?articles=1")?,
+    );
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(res["Content-Type"], "application/warc");
+    let body = res.body_bytes().await.map_err(|e| anyhow!(e))?;
+    let records = warc::parse(&body).map_err(Error::msg)?;
+    assert_eq!(records.len(), 2);
+
+    let req = Request::new(
+        Method::Get,
+        Url::parse("http://localhost/api/v1/warc?articles=42")?,
+    );
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 404);
+
+    let mut req = Request::new(Method::Post, Url::parse("http://localhost/api/v1/warc")?);
+    req.insert_header("Authorization", "Bearer editor");
+    req.set_body(body);
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    let report: serde_json::Value =
+        serde_json::from_str(&res.body_string().await.map_err(|e| anyhow!(e))?)?;
+    assert_eq!(report["duplicate"], 1);
+
+    let mut req = Request::new(Method::Post, Url::parse("http://localhost/api/v1/warc")?);
+    req.insert_header("Authorization", "Bearer editor");
+    req.set_body("not a warc");
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 400);
+
+    Ok(())
+}
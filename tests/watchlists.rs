@@ -0,0 +1,329 @@
+use anyhow::*;
+use propaganda::auth::{self, Role};
+use propaganda::repository::Repository;
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::sled_store::SledStore;
+use propaganda::*;
+use tide::http::{Method, Request, Url};
+
+mod common;
+
+struct Fixture {
+    server: tide::Server<http::State>,
+    alice: String,
+    bob: String,
+    admin: String,
+}
+
+async fn sqlite(name: &str) -> Result<Repository> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push(name);
+    let _ = async_std::fs::remove_file(&db_path).await;
+    Ok(Repository::open(&format!("sqlite:{}", db_path.display())).await?)
+}
+
+async fn fixture(repository: Repository) -> Result<Fixture> {
+    let mut conn = repository.acquire().await?;
+    conn.ensure_created_tables().await?;
+
+    let html = |text: &str| format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text);
+    for url in &[
+        "https://cats.example/cat.html",
+        "https://dogs.example/dog.html",
+        "https://dogs.example/puppy.html",
+    ] {
+        let article = conn.insert_article(url).await?;
+        insert_snapshot_and_revision(&mut *conn, &article, 5, &html("A pet")).await?;
+        insert_snapshot_and_revision(&mut *conn, &article, 8, &html("A new pet")).await?;
+    }
+
+    let alice = conn.insert_user("alice", 1).await?;
+    let bob = conn.insert_user("bob", 1).await?;
+    let (alice, _) = auth::mint(&mut *conn, "alice", Role::Editor, Some(alice.user_id), 1).await?;
+    let (bob, _) = auth::mint(&mut *conn, "bob", Role::Editor, Some(bob.user_id), 1).await?;
+    let (admin, _) = auth::mint(&mut *conn, "admin", Role::Admin, None, 1).await?;
+    drop(conn);
+
+    let state = http::State::new(repository, Default::default());
+    let mut server = tide::with_state(state.clone());
+    server.at("/api/v1").nest(api::server(state));
+    server.at("/changes").get(http::get_changes);
+    server.at("/changes.atom").get(http::get_changes_atom);
+    Ok(Fixture {
+        server,
+        alice,
+        bob,
+        admin,
+    })
+}
+
+async fn request(
+    server: &tide::Server<http::State>,
+    method: Method,
+    url: &str,
+    token: &str,
+    body: Option<&str>,
+) -> Result<(tide::http::Response, serde_json::Value)> {
+    let mut req = Request::new(method, Url::parse("http://localhost")?.join(url)?);
+    req.insert_header("Authorization", format!("Bearer {}", token));
+    if let Some(body) = body {
+        req.set_body(body);
+        req.set_content_type(tide::http::mime::JSON);
+    }
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    let body = res.body_string().await.map_err(|e| anyhow!(e))?;
+    let json = serde_json::from_str(&body).unwrap_or(serde_json::Value::Null);
+    Ok((res, json))
+}
+
+fn urls(articles: &serde_json::Value, key: &str) -> Vec<String> {
+    let mut urls = articles
+        .as_array()
+        .map(|a| {
+            a.iter()
+                .map(|a| a[key].as_str().unwrap_or_default().to_owned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    urls.sort();
+    urls
+}
+
+#[async_std::test]
+async fn watchlists_scope_articles_and_changes() -> Result<()> {
+    scope_articles_and_changes(fixture(sqlite("watchlists-scope.db").await?).await?).await
+}
+
+#[async_std::test]
+async fn postgres_watchlists_scope_articles_and_changes() -> Result<()> {
+    match common::postgres_url("propaganda_watchlists_scope").await? {
+        Some(url) => {
+            scope_articles_and_changes(fixture(Repository::open(&url).await?).await?).await
+        }
+        None => Ok(()),
+    }
+}
+
+#[async_std::test]
+async fn sled_watchlists_scope_articles_and_changes() -> Result<()> {
+    let repository = Repository::from(SledStore::temporary()?);
+    scope_articles_and_changes(fixture(repository).await?).await
+}
+
+async fn scope_articles_and_changes(f: Fixture) -> Result<()> {
+    let (res, watchlist) = request(
+        &f.server,
+        Method::Post,
+        "/api/v1/watchlists",
+        &f.alice,
+        Some(r#"{"name": "cats and dogs"}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 201);
+    assert_eq!(watchlist["user_id"], 1);
+    let id = watchlist["watchlist_id"].as_i64().unwrap_or_default();
+
+    let articles = format!("/api/v1/watchlists/{}/articles", id);
+    let (res, _) = request(
+        &f.server,
+        Method::Post,
+        &articles,
+        &f.alice,
+        Some(r#"{"article_id": 1}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 200);
+    let (res, article) = request(
+        &f.server,
+        Method::Post,
+        &articles,
+        &f.alice,
+        Some(r#"{"url": "https://birds.example/bird.html"}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(article["article_id"], 4);
+    let (res, _) = request(
+        &f.server,
+        Method::Post,
+        &articles,
+        &f.alice,
+        Some(r#"{"url": "data:text/html,<p>bird</p>"}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 400);
+    let sources = format!("/api/v1/watchlists/{}/sources", id);
+    let (res, _) = request(
+        &f.server,
+        Method::Post,
+        &sources,
+        &f.alice,
+        Some(r#"{"host": "dogs.example"}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 204);
+
+    let (_, watchlist) = request(
+        &f.server,
+        Method::Get,
+        &format!("/api/v1/watchlists/{}", id),
+        &f.alice,
+        None,
+    )
+    .await?;
+    assert_eq!(watchlist["name"], "cats and dogs");
+    assert_eq!(watchlist["sources"][0], "dogs.example");
+    assert_eq!(watchlist["articles"].as_array().map(Vec::len), Some(2));
+
+    let (_, listing) = request(
+        &f.server,
+        Method::Get,
+        &format!("/api/v1/articles?watchlist={}", id),
+        &f.alice,
+        None,
+    )
+    .await?;
+    assert_eq!(
+        urls(&listing["articles"], "url"),
+        vec![
+            "https://birds.example/bird.html",
+            "https://cats.example/cat.html",
+            "https://dogs.example/dog.html",
+            "https://dogs.example/puppy.html",
+        ]
+    );
+
+    let (_, changes) = request(
+        &f.server,
+        Method::Delete,
+        &format!("{}/1", articles),
+        &f.alice,
+        None,
+    )
+    .await?;
+    assert_eq!(changes, serde_json::Value::Null);
+    let (_, changes) = request(
+        &f.server,
+        Method::Get,
+        &format!("/changes?watchlist={}", id),
+        &f.alice,
+        None,
+    )
+    .await?;
+    assert_eq!(
+        urls(&changes["changes"], "url"),
+        vec![
+            "https://dogs.example/dog.html",
+            "https://dogs.example/puppy.html",
+        ]
+    );
+
+    let mut res: tide::http::Response = f
+        .server
+        .respond(Request::new(
+            Method::Get,
+            Url::parse(&format!("http://localhost/changes.atom?watchlist={}", id))?,
+        ))
+        .await
+        .map_err(|e| anyhow!(e))?;
+    let feed = res.body_string().await.map_err(|e| anyhow!(e))?;
+    assert!(feed.contains("changes on watchlist cats and dogs"));
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn only_owners_and_admins_change_watchlists() -> Result<()> {
+    owners_and_admins_change(fixture(sqlite("watchlists-owners.db").await?).await?).await
+}
+
+#[async_std::test]
+async fn postgres_only_owners_and_admins_change_watchlists() -> Result<()> {
+    match common::postgres_url("propaganda_watchlists_owners").await? {
+        Some(url) => owners_and_admins_change(fixture(Repository::open(&url).await?).await?).await,
+        None => Ok(()),
+    }
+}
+
+#[async_std::test]
+async fn sled_only_owners_and_admins_change_watchlists() -> Result<()> {
+    let repository = Repository::from(SledStore::temporary()?);
+    owners_and_admins_change(fixture(repository).await?).await
+}
+
+async fn owners_and_admins_change(f: Fixture) -> Result<()> {
+    let (_, watchlist) = request(
+        &f.server,
+        Method::Post,
+        "/api/v1/watchlists",
+        &f.alice,
+        Some(r#"{"name": "mine"}"#),
+    )
+    .await?;
+    let url = format!("/api/v1/watchlists/{}", watchlist["watchlist_id"]);
+
+    let (res, _) = request(
+        &f.server,
+        Method::Post,
+        &format!("{}/sources", url),
+        &f.bob,
+        Some(r#"{"host": "cats.example"}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 403);
+
+    let (res, _) = request(
+        &f.server,
+        Method::Post,
+        "/api/v1/watchlists",
+        &f.alice,
+        Some(r#"{"name": "mine"}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 409);
+
+    let (res, _) = request(
+        &f.server,
+        Method::Post,
+        "/api/v1/watchlists",
+        &f.bob,
+        Some(r#"{"name": "for alice", "user_id": 1}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 403);
+    let (res, _) = request(
+        &f.server,
+        Method::Post,
+        "/api/v1/watchlists",
+        &f.admin,
+        Some(r#"{"name": "for alice", "user_id": 1}"#),
+    )
+    .await?;
+    assert_eq!(res.status(), 201);
+
+    let (res, lists) = request(
+        &f.server,
+        Method::Get,
+        "/api/v1/watchlists?user_id=1",
+        &f.bob,
+        None,
+    )
+    .await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(lists.as_array().map(Vec::len), Some(2));
+
+    let (res, _) = request(&f.server, Method::Get, "/api/v1/users", &f.bob, None).await?;
+    assert_eq!(res.status(), 403);
+    let (res, users) = request(&f.server, Method::Get, "/api/v1/users", &f.admin, None).await?;
+    assert_eq!(res.status(), 200);
+    assert_eq!(users[1]["name"], "bob");
+
+    let (res, _) = request(&f.server, Method::Delete, &url, &f.bob, None).await?;
+    assert_eq!(res.status(), 403);
+    let (res, _) = request(&f.server, Method::Delete, &url, &f.alice, None).await?;
+    assert_eq!(res.status(), 204);
+    let (res, _) = request(&f.server, Method::Get, &url, &f.alice, None).await?;
+    assert_eq!(res.status(), 404);
+
+    Ok(())
+}
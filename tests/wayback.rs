@@ -0,0 +1,231 @@
+use anyhow::*;
+use propaganda::auth::{hash_token, ProvideTokens, Role};
+use propaganda::db::{ProvideArticles, RevisionFilter, SnapshotSource};
+use propaganda::repository::Repository;
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::wayback::{self, Capture, Wayback};
+use propaganda::*;
+use tide::http::{Method, Request, Url};
+
+mod common;
+
+fn html(text: &str) -> String {
+    format!("<h1>{}</h1><div id=content><p>{}</p></div>", text, text)
+}
+
+const INDEX: &str = r#"[
+    ["timestamp", "original", "statuscode", "digest"],
+    ["20190101000000", "https://news.example/a", "200", "A"],
+    ["20190201000000", "https://news.example/a", "200", "A"],
+    ["20190301000000", "https://news.example/a", "404", "N"],
+    ["20190401000000", "https://news.example/a", "200", "B"],
+    ["20190501000000", "https://news.example/a", "200", "E"]
+]"#;
+
+/// answers the index above and the bodies of its captures, the last one fails
+async fn stub_wayback() -> Result<String> {
+    let mut stub = tide::new();
+    stub.at("/cdx/search/cdx")
+        .get(|_| async { Ok(tide::Response::builder(200).body(INDEX).build()) });
+    stub.at("/web/:capture/*original")
+        .get(|req: tide::Request<()>| async move {
+            let capture = req.param::<String>("capture")?;
+            let body = match capture.as_str() {
+                "20190101000000id_" | "20190201000000id_" => html("Old news"),
+                "20190401000000id_" => html("Older news"),
+                _ => return Ok(tide::Response::new(500)),
+            };
+            Ok(tide::Response::builder(200).body(body).build())
+        });
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let endpoint = format!("http://{}", listener.local_addr()?);
+    async_std::task::spawn(stub.listen(listener));
+    Ok(endpoint)
+}
+
+#[test]
+fn indexes_are_parsed() -> Result<()> {
+    let captures = wayback::parse_index(INDEX).map_err(Error::msg)?;
+    assert_eq!(captures.len(), 5);
+    assert_eq!(
+        captures[2],
+        Capture {
+            timestamp: "20190301000000".to_owned(),
+            original: "https://news.example/a".to_owned(),
+            status: Some(404),
+            digest: Some("N".to_owned()),
+        }
+    );
+    assert_eq!(captures[0].archived_at(), Some(1546300800));
+
+    let lines = "example,news)/a 20190101000000 https://news.example/a text/html 200 A 123\n\
+                 example,news)/a 20190102000000 https://news.example/a warc/revisit - A 45\n";
+    let captures = wayback::parse_index(lines).map_err(Error::msg)?;
+    assert_eq!(captures[1].status, None);
+    assert_eq!(captures[1].digest.as_deref(), Some("A"));
+
+    let legend = " CDX N b a m s k r M S V g\n\
+                  example,news)/a 20190101000000 https://news.example/a text/html 200 A - - 1 0 a.warc.gz\n";
+    let captures = wayback::parse_index(legend).map_err(Error::msg)?;
+    assert_eq!(captures[0].original, "https://news.example/a");
+    assert_eq!(captures[0].status, Some(200));
+
+    assert!(wayback::parse_index(r#"[["urlkey"], ["x"]]"#).is_err());
+    assert!(wayback::parse_index("[").is_err());
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn captures_become_snapshots_before_the_live_ones() -> Result<()> {
+    let endpoint = stub_wayback().await?;
+    let wayback = Wayback::new(&endpoint);
+
+    let mut db_path = std::env::temp_dir();
+    db_path.push("wayback.db");
+    let _ = async_std::fs::remove_file(&db_path).await;
+    let pool = sqlx::SqlitePool::new(&format!("sqlite:{}", db_path.display())).await?;
+    let mut conn = pool.acquire().await?;
+    conn.ensure_created_tables().await?;
+    conn.insert_token("wayback", Role::Editor, None, &hash_token("editor"), 0)
+        .await?;
+    let article = conn.insert_article("https://news.example/a").await?;
+    insert_snapshot_and_revision(&mut *conn, &article, 1600000000, &html("Today's news")).await?;
+
+    let captures = wayback.get_index(&article.url).await?;
+    drop(conn);
+    let repository = Repository::from(pool.clone());
+    let report = wayback::import(&repository, &wayback, &article, captures).await?;
+    let mut conn = repository.acquire().await?;
+    assert_eq!(report.captures, 5);
+    assert_eq!(report.snapshots_added, 2);
+    assert_eq!((report.duplicate, report.skipped, report.failed), (1, 1, 1));
+    assert_eq!(report.revisions_added, 2);
+
+    let mut snapshots = conn
+        .get_snaphot_metadatas_from_article(article.article_id)
+        .await?;
+    snapshots.sort_by_key(|s| s.archived_at);
+    let first = conn.get_snaphot(snapshots[0].snapshot_id).await?;
+    assert_eq!(first.archived_at, 1546300800);
+    assert_eq!(first.source, SnapshotSource::Wayback);
+    assert_eq!(
+        first.source_url,
+        Some(format!(
+            "{}/web/20190101000000/https://news.example/a",
+            endpoint
+        ))
+    );
+    assert_eq!(snapshots[2].source, SnapshotSource::Live);
+
+    let revisions = conn
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(revisions.len(), 2);
+    let listed = conn.get_article_by_id(article.article_id).await?;
+    assert_eq!(listed.url, article.url);
+    drop(conn);
+
+    let state = http::State {
+        wayback_url: endpoint.clone(),
+        ..http::State::new(pool, Default::default())
+    };
+    let mut server = tide::with_state(state.clone());
+    server.at("/api/v1").nest(api::server(state));
+
+    let mut req = Request::new(
+        Method::Post,
+        Url::parse("http://localhost/api/v1/articles/1/wayback")?,
+    );
+    req.insert_header("Authorization", "Bearer editor");
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 200);
+    let report: serde_json::Value =
+        serde_json::from_str(&res.body_string().await.map_err(|e| anyhow!(e))?)?;
+    assert_eq!(report["snapshots_added"], 0);
+    assert_eq!(report["duplicate"], 3);
+
+    let mut req = Request::new(
+        Method::Post,
+        Url::parse("http://localhost/api/v1/articles/1/wayback")?,
+    );
+    req.insert_header("Authorization", "Bearer editor");
+    req.set_body("[");
+    let res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 400);
+
+    Ok(())
+}
+
+/// the revision between two stored snapshots is replaced by two around the capture between them
+async fn spanning_revisions_are_replaced(repository: Repository) -> Result<()> {
+    let wayback = Wayback::new(&stub_wayback().await?);
+    let mut conn = repository.acquire().await?;
+    let article = conn.insert_article("https://news.example/a").await?;
+    insert_snapshot_and_revision(&mut *conn, &article, 1546300800, &html("Old news")).await?;
+    insert_snapshot_and_revision(&mut *conn, &article, 1600000000, &html("Today's news")).await?;
+    let spanning = conn
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    assert_eq!(spanning.len(), 1);
+    drop(conn);
+
+    let captures = wayback::parse_index(INDEX).map_err(Error::msg)?;
+    let between = vec![captures[3].clone()];
+    let report = wayback::import(&repository, &wayback, &article, between).await?;
+    assert_eq!(report.snapshots_added, 1);
+    assert_eq!((report.revisions_removed, report.revisions_added), (1, 2));
+
+    let mut conn = repository.acquire().await?;
+    let revisions = conn
+        .get_revisions(&RevisionFilter::default(), None, 10)
+        .await?;
+    let spans = revisions
+        .iter()
+        .rev()
+        .map(|r| (r.previous_archived_at, r.archived_at))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        spans,
+        vec![(1546300800, 1554076800), (1554076800, 1600000000)]
+    );
+    assert!(matches!(
+        conn.get_revision(spanning[0].revision_id).await,
+        Err(propaganda::db::DbError::NotFound(_))
+    ));
+    let listed = conn
+        .list_articles(&Default::default(), Default::default(), None, 10)
+        .await?;
+    assert_eq!(listed[0].revisions, 2);
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn sqlite_captures_between_snapshots_replace_their_revision() -> Result<()> {
+    let mut db_path = std::env::temp_dir();
+    db_path.push("wayback-between.db");
+    let _ = async_std::fs::remove_file(&db_path).await;
+    let repository = Repository::open(&format!("sqlite:{}", db_path.display())).await?;
+    repository.acquire().await?.ensure_created_tables().await?;
+    spanning_revisions_are_replaced(repository).await
+}
+
+#[async_std::test]
+async fn postgres_captures_between_snapshots_replace_their_revision() -> Result<()> {
+    match common::postgres_url("propaganda_wayback_between").await? {
+        Some(url) => {
+            let repository = Repository::open(&url).await?;
+            repository.acquire().await?.ensure_created_tables().await?;
+            spanning_revisions_are_replaced(repository).await
+        }
+        None => Ok(()),
+    }
+}
+
+#[async_std::test]
+async fn sled_captures_between_snapshots_replace_their_revision() -> Result<()> {
+    let repository = Repository::from(propaganda::sled_store::SledStore::temporary()?);
+    spanning_revisions_are_replaced(repository).await
+}
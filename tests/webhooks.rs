@@ -0,0 +1,138 @@
+use anyhow::*;
+use async_std::sync::{Arc, Mutex};
+use async_std::task;
+use propaganda::db::ProvideArticles;
+use propaganda::scraper::insert_snapshot_and_revision;
+use propaganda::significance::Significance;
+use propaganda::webhook::*;
+use sqlx::prelude::*;
+
+type Received = Arc<Mutex<Vec<(String, String)>>>;
+
+/// stands in for the receiving side, fails the first request
+async fn receiver(addr: &'static str) -> Received {
+    let received = Received::default();
+    let mut server = tide::with_state(received.clone());
+    server
+        .at("/hook")
+        .post(|mut req: tide::Request<Received>| async move {
+            let signature = req
+                .header("X-Propaganda-Signature")
+                .map(|v| v.as_str().to_owned())
+                .unwrap_or_default();
+            let body = req.body_string().await?;
+            let mut received = req.state().lock().await;
+            received.push((signature, body));
+            Ok(tide::Response::new(if received.len() == 1 {
+                500
+            } else {
+                200
+            }))
+        });
+    task::spawn(server.listen(addr));
+    task::sleep(std::time::Duration::from_millis(100)).await;
+    received
+}
+
+#[async_std::test]
+async fn matching_revisions_are_delivered_signed_and_retried() -> Result<()> {
+    let received = receiver("localhost:3028").await;
+
+    let mut db: sqlx::SqliteConnection = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+
+    db.insert_webhook(&NewWebhook {
+        url: "http://localhost:3028/hook".into(),
+        secret: "s3cret".into(),
+        host: Some("cats.example".into()),
+        keyword: Some("MICE".into()),
+        ..NewWebhook::default()
+    })
+    .await?;
+    db.insert_webhook(&NewWebhook {
+        url: "http://localhost:3028/hook".into(),
+        secret: "s3cret".into(),
+        min_words_changed: 100,
+        ..NewWebhook::default()
+    })
+    .await?;
+    db.insert_webhook(&NewWebhook {
+        url: "http://localhost:3028/hook".into(),
+        secret: "s3cret".into(),
+        min_significance: Some(Significance::Quote),
+        ..NewWebhook::default()
+    })
+    .await?;
+
+    let cat = db.insert_article("https://cats.example/cat.html").await?;
+    let dog = db.insert_article("https://dogs.example/dog.html").await?;
+    let html = |text: &str| format!("<div id=content><p>{}</p></div>", text);
+
+    insert_snapshot_and_revision(&mut db, &cat, 5, &html("A cat and a mouse")).await?;
+    insert_snapshot_and_revision(&mut db, &cat, 8, &html("A cat and two mice")).await?;
+    insert_snapshot_and_revision(&mut db, &dog, 6, &html("A dog and a mouse")).await?;
+    insert_snapshot_and_revision(&mut db, &dog, 9, &html("A dog and two mice")).await?;
+
+    let now = propaganda::scraper::timestamp();
+    assert_eq!(deliver_due(&mut db, now).await?, 0);
+    assert_eq!(deliver_due(&mut db, now).await?, 0, "not due yet");
+    assert_eq!(deliver_due(&mut db, now + backoff(1)).await?, 1);
+    assert_eq!(deliver_due(&mut db, now + 10 * backoff(1)).await?, 0);
+
+    let received = received.lock().await;
+    assert_eq!(received.len(), 2);
+    let (signature, body) = &received[1];
+    assert_eq!(signature, &format!("sha256={}", sign("s3cret", body)));
+
+    let payload: serde_json::Value = serde_json::from_str(body)?;
+    assert_eq!(payload["event"], "revision");
+    assert_eq!(payload["revision"]["url"], "https://cats.example/cat.html");
+    assert_eq!(payload["revision"]["words_added"], 2);
+    assert_eq!(payload["revision"]["significance"], "wording");
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn deleted_webhooks_take_their_deliveries() -> Result<()> {
+    let mut db: sqlx::SqliteConnection = sqlx::SqliteConnection::connect("sqlite::").await?;
+    db.ensure_created_tables().await?;
+
+    let hook = db
+        .insert_webhook(&NewWebhook {
+            url: "http://localhost:3029/hook".into(),
+            secret: "s3cret".into(),
+            ..NewWebhook::default()
+        })
+        .await?;
+    db.insert_delivery(hook.webhook_id, "{}", 0).await?;
+    db.delete_webhook(hook.webhook_id).await?;
+
+    assert!(db.get_webhooks().await?.is_empty());
+    assert!(db.get_due_deliveries(i32::MAX, 10).await?.is_empty());
+    Ok(())
+}
+
+#[async_std::test]
+async fn webhooks_are_http_only() -> Result<()> {
+    let pool = sqlx::SqlitePool::new("sqlite::memory:").await?;
+    pool.acquire().await?.ensure_created_tables().await?;
+    let mut server = tide::with_state(propaganda::http::State::new(pool, Default::default()));
+    server
+        .at("/webhooks")
+        .with(tide::utils::After(propaganda::http::problem_details))
+        .post(propaganda::http::insert_webhook);
+
+    let mut req = tide::http::Request::new(
+        tide::http::Method::Post,
+        tide::http::Url::parse("http://localhost/webhooks")?,
+    );
+    req.set_body(r#"{"url": "file:///etc/passwd", "secret": "s3cret"}"#);
+    req.set_content_type(tide::http::mime::JSON);
+    let mut res: tide::http::Response = server.respond(req).await.map_err(|e| anyhow!(e))?;
+    assert_eq!(res.status(), 400);
+    assert_eq!(res["Content-Type"], "application/problem+json");
+    let body = res.body_string().await.map_err(|e| anyhow!(e))?;
+    assert!(body.contains("scheme file is not http or https"));
+    Ok(())
+}